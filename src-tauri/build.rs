@@ -1,3 +1,5 @@
 fn main() {
+    // Only the desktop app bundles the frontend
+    #[cfg(feature = "gui")]
     tauri_build::build()
 }
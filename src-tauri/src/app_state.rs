@@ -1,9 +1,138 @@
-use std::sync::Arc;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
 use tokio::sync::RwLock;
 
-use crate::torrent_management;
+use crate::{
+    config::{Config, SharedConfig},
+    events::event_bus::EventBus,
+    network::{
+        lsd::start_lsd,
+        peer_id::generate_peer_id,
+        peer_listener::{start_peer_listener, start_utp_listener},
+        port_mapping::{GatewaySearch, PortMapper},
+        rate_limit::RateLimits,
+        utp::socket::UtpSocket,
+    },
+    storage::disk_io::DiskIo,
+    streaming,
+    torrent_management::{
+        self,
+        torrent_manager::{start_queue_manager, TorrentManager},
+    },
+};
 
 pub struct AppState {
     pub torrent_manager: Arc<RwLock<torrent_management::torrent_manager::TorrentManager>>,
-    pub async_proc_input_tx: Arc<RwLock<tauri::async_runtime::Sender<String>>>,
+    // None when streaming is disabled in the configuration
+    pub stream_server_addr: Option<SocketAddr>,
+    // None when the listen port could not be bound, we can still connect out to peers
+    pub peer_listener_addr: Option<SocketAddr>,
+    // Listens for uTP peers and makes outgoing uTP connections, None when uTP is disabled
+    pub utp_socket: Option<UtpSocket>,
+    // Keeps the listen port forwarded on the router, None when port mapping is disabled
+    pub port_mapper: Option<PortMapper>,
+    // Reads and writes the data of every torrent
+    pub disk_io: DiskIo,
+    // The configured download and upload limits apply to all torrents together
+    pub rate_limits: RateLimits,
+    pub events: EventBus,
+    pub config: SharedConfig,
+    // Where `set_config` persists changes, None when there is no config directory
+    pub config_path: Option<PathBuf>,
+    // Generated once per session from the configured prefix
+    pub peer_id: String,
+}
+
+impl AppState {
+    // Starts the engine shared by the desktop app and the headless daemon
+    pub async fn new(
+        configuration: Config,
+        config_path: Option<PathBuf>,
+    ) -> Result<AppState, String> {
+        let events = EventBus::new();
+        let streaming_enabled = configuration.streaming_enabled;
+        let streaming_address = configuration.streaming_address.clone();
+        let peer_id = generate_peer_id(&configuration.peer_id_prefix);
+        let listen_port = configuration.listen_port;
+        let ipv6_enabled = configuration.ipv6_enabled;
+        let utp_enabled = configuration.utp_enabled;
+        let lsd_enabled = configuration.lsd_enabled;
+        let port_mapping_enabled = configuration.port_mapping_enabled;
+        // Changing the disk settings needs a restart
+        let disk_io = DiskIo::new(
+            configuration.disk_io_threads,
+            configuration.disk_queue_size,
+            configuration.disk_cache_mb * 1024 * 1024,
+        );
+        let config = Arc::new(RwLock::new(configuration));
+        let rate_limits = RateLimits::new(Arc::clone(&config));
+        let torrent_manager = Arc::new(RwLock::new(TorrentManager::new(
+            events.clone(),
+            Arc::clone(&config),
+        )));
+
+        let stream_server_addr = if streaming_enabled {
+            Some(
+                streaming::server::start_stream_server(torrent_manager.clone(), &streaming_address)
+                    .await?,
+            )
+        } else {
+            None
+        };
+
+        let peer_listener_addr =
+            match start_peer_listener(torrent_manager.clone(), listen_port, ipv6_enabled).await {
+                Ok(address) => Some(address),
+                Err(e) => {
+                    println!("{}", e);
+                    None
+                }
+            };
+
+        // uTP uses the UDP port matching the TCP one
+        let utp_port = peer_listener_addr.map_or(listen_port, |address| address.port());
+        let utp_socket = if utp_enabled {
+            match start_utp_listener(torrent_manager.clone(), utp_port, ipv6_enabled).await {
+                Ok(socket) => Some(socket),
+                Err(e) => {
+                    println!("{}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        // Disabling LSD later only silences it, enabling it needs a restart
+        if lsd_enabled {
+            if let Err(e) = start_lsd(torrent_manager.clone(), utp_port, ipv6_enabled) {
+                println!("{}", e);
+            }
+        }
+
+        start_queue_manager(torrent_manager.clone());
+
+        // TCP and uTP share the port, so one number is mapped for both protocols
+        let port_mapper = match (port_mapping_enabled, peer_listener_addr) {
+            (true, Some(address)) => Some(PortMapper::start(
+                address.port(),
+                GatewaySearch::default(),
+                events.clone(),
+            )),
+            _ => None,
+        };
+
+        Ok(AppState {
+            torrent_manager,
+            stream_server_addr,
+            peer_listener_addr,
+            utp_socket,
+            port_mapper,
+            disk_io,
+            rate_limits,
+            events,
+            config,
+            config_path,
+            peer_id,
+        })
+    }
 }
@@ -0,0 +1,110 @@
+// Headless daemon: runs the torrent engine without a window and exposes the
+// Tauri commands over an authenticated JSON-RPC API.
+use clap::Parser;
+use pirate::{
+    app_state::AppState, config, events::event_bus::forward_events, rpc::server::start_rpc_server,
+    watch::watch_folder::watch_folder,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+#[derive(Parser, Debug)]
+#[command(name = "pirated", version, about = "Pirate BitTorrent daemon")]
+struct DaemonArgs {
+    /// Address of the JSON-RPC API, `rpc_address` from the configuration by default
+    #[arg(long, value_name = "ADDRESS:PORT")]
+    listen: Option<String>,
+    /// Token clients have to send, a random one is generated and printed when unset
+    #[arg(long, env = "PIRATE_RPC_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    /// Directory to add new .torrent and .magnet files from
+    #[arg(long, value_name = "DIRECTORY")]
+    watch: Option<PathBuf>,
+    /// Start torrents added from the watch directory
+    #[arg(long)]
+    watch_start: bool,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = DaemonArgs::parse();
+
+    let config_path = config::config_path();
+    let configuration = match config::Config::load(config_path.as_deref()) {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let state = Arc::new(
+        AppState::new(configuration.clone(), config_path)
+            .await
+            .expect("error while starting the torrent engine"),
+    );
+
+    let token = match args.token {
+        Some(token) if !token.is_empty() => token,
+        _ => {
+            let token: String = rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(32)
+                .map(char::from)
+                .collect();
+            println!("Generated RPC token: {}", token);
+            token
+        }
+    };
+
+    let listen = args.listen.unwrap_or(configuration.rpc_address.clone());
+    let rpc_addr = start_rpc_server(Arc::clone(&state), &listen, token)
+        .await
+        .expect("error while starting the RPC server");
+    println!("JSON-RPC API listening on http://{}/rpc", rpc_addr);
+    if let Some(peer_listener_addr) = state.peer_listener_addr {
+        println!("Accepting peers on {}", peer_listener_addr);
+    }
+    if let Some(utp_socket) = &state.utp_socket {
+        if let Ok(utp_addr) = utp_socket.local_addr() {
+            println!("Accepting uTP peers on {}", utp_addr);
+        }
+    }
+    if let Some(stream_server_addr) = state.stream_server_addr {
+        println!(
+            "Streaming server listening on http://{}",
+            stream_server_addr
+        );
+    }
+
+    // Without a frontend the events are logged as JSON lines instead
+    tokio::spawn(forward_events(
+        state.events.subscribe(),
+        Duration::from_millis(configuration.event_interval_ms),
+        |batch| {
+            for event in batch {
+                if let Ok(line) = serde_json::to_string(&event) {
+                    println!("{}", line);
+                }
+            }
+        },
+    ));
+
+    if let Some(directory) = args.watch.or(configuration.watch_directory.clone()) {
+        let state = Arc::clone(&state);
+        let auto_start = args.watch_start || configuration.watch_auto_start;
+        let interval = Duration::from_millis(configuration.watch_interval_ms);
+        tokio::spawn(async move {
+            watch_folder(&state, directory, auto_start, interval).await;
+        });
+    }
+
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        eprintln!("Failed to listen for shutdown signal: {}", e);
+    }
+
+    println!("Shutting down, stopping all torrents");
+    state.torrent_manager.read().await.stop_all().await;
+    if let Some(port_mapper) = &state.port_mapper {
+        port_mapper.shutdown().await;
+    }
+}
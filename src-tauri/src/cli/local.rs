@@ -0,0 +1,223 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    app_state::AppState,
+    commands::{
+        add_torrent::{self, default_file_path},
+        start_torrent, stop_torrent,
+    },
+    config::Config,
+    network::{metadata_exchange::torrent_from_magnet, peer_id::generate_peer_id},
+    parsing::{magnet::parse_magnet_link, parser::parse_error::parse_bencoded_torrent},
+    storage::{
+        backend::SharedStorage, file_layout::FileLayout, fs_storage::FsStorage,
+        piece_hashes::PieceHashes, verify::verify_pieces,
+    },
+    torrent_management::{torrent_creator::create_torrent, torrent_status::TorrentStatus},
+};
+
+use super::{output, EXIT_FAILURE, EXIT_INTERRUPTED, EXIT_SUCCESS, EXIT_USAGE, EXIT_VERIFY_FAILED};
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+// Commands that run entirely in this process, without a daemon
+
+// Downloads a torrent to completion with an engine of our own, exits once every piece is in
+pub async fn download(configuration: Config, torrent_file: &Path) -> i32 {
+    let state = match AppState::new(configuration, None).await {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("Failed to start the torrent engine: {}", e);
+            return EXIT_FAILURE;
+        }
+    };
+
+    let torrent_hash = match add_torrent::handle(
+        &state,
+        torrent_file.to_string_lossy().to_string(),
+        Default::default(),
+    )
+    .await
+    {
+        Ok(torrent_hash) => torrent_hash,
+        Err(e) => {
+            eprintln!("Failed to add torrent: {}", e);
+            return EXIT_FAILURE;
+        }
+    };
+    if let Err(e) = start_torrent::handle(&state, torrent_hash.clone()).await {
+        eprintln!("Failed to start torrent: {}", e);
+        return EXIT_FAILURE;
+    }
+
+    let mut interval = tokio::time::interval(PROGRESS_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {
+                output::finish_progress_line();
+                eprintln!("Interrupted, stopping torrent");
+                let _ = stop_torrent::handle(&state, torrent_hash).await;
+                return EXIT_INTERRUPTED;
+            }
+            _ = interval.tick() => {
+                let details = state
+                    .torrent_manager
+                    .read()
+                    .await
+                    .get_torrent_details(&torrent_hash)
+                    .await;
+                let summary = match details {
+                    Ok(details) => details.summary,
+                    Err(e) => {
+                        output::finish_progress_line();
+                        eprintln!("{}", e);
+                        return EXIT_FAILURE;
+                    }
+                };
+                output::print_progress_line(&output::download_progress_line(&summary));
+
+                if summary.progress >= 1.0
+                    || matches!(summary.status, TorrentStatus::Completed | TorrentStatus::Seeding)
+                {
+                    output::finish_progress_line();
+                    let _ = stop_torrent::handle(&state, torrent_hash).await;
+                    println!("Downloaded {}", summary.name);
+                    return EXIT_SUCCESS;
+                }
+            }
+        }
+    }
+}
+
+pub async fn create(
+    path: &Path,
+    announce: Option<String>,
+    piece_length: Option<u64>,
+    output_path: Option<PathBuf>,
+) -> i32 {
+    let torrent = create_torrent(path, announce, piece_length, |hashed, total| {
+        output::print_progress_line(&output::progress_bar(hashed as f64 / total as f64));
+    })
+    .await;
+    output::finish_progress_line();
+
+    let torrent = match torrent {
+        Ok(torrent) => torrent,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_FAILURE;
+        }
+    };
+
+    let output_path = output_path.unwrap_or_else(|| {
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        PathBuf::from(format!("{}.torrent", name.to_string_lossy()))
+    });
+    write_torrent_file(&output_path, &torrent)
+}
+
+pub async fn verify(torrent_file: &Path, data: Option<PathBuf>) -> i32 {
+    let torrent_data = match std::fs::read(torrent_file) {
+        Ok(torrent_data) => torrent_data,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", torrent_file.display(), e);
+            return EXIT_FAILURE;
+        }
+    };
+    let mut metadata = match parse_bencoded_torrent(torrent_data) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            eprintln!("Failed to parse metadata: {}", e);
+            return EXIT_FAILURE;
+        }
+    };
+    metadata.file_path = data
+        .unwrap_or_else(|| default_file_path(&torrent_file.to_string_lossy(), &metadata.info.name));
+
+    let piece_hashes = match PieceHashes::from_metadata(&metadata) {
+        Ok(piece_hashes) => piece_hashes,
+        Err(e) => {
+            eprintln!("Failed to read piece hashes: {}", e);
+            return EXIT_FAILURE;
+        }
+    };
+    let layout = FileLayout::from_metadata(&metadata);
+    if piece_hashes.len() != layout.piece_count() {
+        eprintln!("Torrent has a piece count that doesn't match its size");
+        return EXIT_FAILURE;
+    }
+
+    let total = piece_hashes.len();
+    let storage: SharedStorage = Arc::new(FsStorage::new(layout));
+    let verified = verify_pieces(&storage, &piece_hashes, |checked| {
+        output::print_progress_line(&output::progress_bar(checked as f64 / total as f64));
+    })
+    .await;
+    output::finish_progress_line();
+
+    match verified {
+        Ok(verified) => {
+            let valid = verified.count_ones();
+            println!("{} of {} pieces valid", valid, total);
+            if valid == total {
+                EXIT_SUCCESS
+            } else {
+                EXIT_VERIFY_FAILED
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to read data: {}", e);
+            EXIT_FAILURE
+        }
+    }
+}
+
+pub async fn magnet_to_torrent(
+    configuration: &Config,
+    magnet: &str,
+    output_path: Option<PathBuf>,
+) -> i32 {
+    let magnet = match parse_magnet_link(magnet) {
+        Ok(magnet) => magnet,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_USAGE;
+        }
+    };
+
+    let peer_id = generate_peer_id(&configuration.peer_id_prefix);
+    let torrent =
+        match torrent_from_magnet(&magnet, &peer_id, configuration, Default::default()).await {
+            Ok(torrent) => torrent,
+            Err(e) => {
+                eprintln!("{}", e);
+                return EXIT_FAILURE;
+            }
+        };
+
+    let output_path = output_path.unwrap_or_else(|| {
+        let name = magnet
+            .display_name
+            .map(|name| name.replace(std::path::MAIN_SEPARATOR, "_"))
+            .unwrap_or_else(|| hex::encode(magnet.info_hash));
+        PathBuf::from(format!("{}.torrent", name))
+    });
+    write_torrent_file(&output_path, &torrent)
+}
+
+fn write_torrent_file(path: &Path, torrent: &[u8]) -> i32 {
+    match std::fs::write(path, torrent) {
+        Ok(()) => {
+            println!("{}", path.display());
+            EXIT_SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Failed to write {}: {}", path.display(), e);
+            EXIT_FAILURE
+        }
+    }
+}
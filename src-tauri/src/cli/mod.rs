@@ -0,0 +1,151 @@
+pub mod local;
+pub mod output;
+pub mod remote;
+
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::config;
+
+// Exit codes, so scripts can tell failures apart
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_DAEMON_UNAVAILABLE: i32 = 3;
+pub const EXIT_VERIFY_FAILED: i32 = 4;
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+// Subcommands the `pirate` binary recognises before falling back to the GUI
+pub const SUBCOMMANDS: [&str; 10] = [
+    "add",
+    "list",
+    "info",
+    "start",
+    "pause",
+    "remove",
+    "create",
+    "verify",
+    "magnet-to-torrent",
+    "help",
+];
+
+#[derive(Parser, Debug)]
+#[command(name = "pirate", version, about = "Pirate BitTorrent client")]
+pub struct Cli {
+    // Address of a running `pirated`
+    #[arg(long, global = true, env = "PIRATE_RPC_URL")]
+    pub rpc: Option<String>,
+    #[arg(long, global = true, env = "PIRATE_RPC_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Add a .torrent file to the daemon, or download it in the foreground
+    Add {
+        torrent_file: PathBuf,
+        /// Start downloading right away
+        #[arg(long)]
+        start: bool,
+        /// Download to completion in this process instead of using the daemon
+        #[arg(long)]
+        foreground: bool,
+    },
+    /// List the daemon's torrents
+    List,
+    /// Show the details of a torrent
+    Info { torrent_hash: String },
+    /// Start or resume a torrent
+    Start { torrent_hash: String },
+    /// Pause a torrent
+    Pause { torrent_hash: String },
+    /// Remove a torrent from the daemon
+    Remove {
+        torrent_hash: String,
+        /// Also delete the downloaded files
+        #[arg(long)]
+        delete_data: bool,
+    },
+    /// Create a .torrent file from a file or directory
+    Create {
+        path: PathBuf,
+        #[arg(long)]
+        announce: Option<String>,
+        /// Piece length in bytes, picked from the total size when omitted
+        #[arg(long)]
+        piece_length: Option<u64>,
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Check downloaded data against a .torrent file
+    Verify {
+        torrent_file: PathBuf,
+        /// Where the data lives, next to the .torrent file by default
+        #[arg(long)]
+        data: Option<PathBuf>,
+    },
+    /// Fetch the metadata for a magnet link from peers and write a .torrent file
+    MagnetToTorrent {
+        magnet: String,
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+// Whether the arguments ask for the CLI rather than the GUI
+pub fn is_cli_invocation(args: &[String]) -> bool {
+    args.get(1)
+        .map(|arg| SUBCOMMANDS.contains(&arg.as_str()) || arg.starts_with('-'))
+        .unwrap_or(false)
+}
+
+// Runs the parsed command and returns the process exit code
+pub async fn run(cli: Cli) -> i32 {
+    let configuration = match config::Config::load(config::config_path().as_deref()) {
+        Ok(configuration) => configuration,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_FAILURE;
+        }
+    };
+    let rpc_address = cli.rpc.unwrap_or(configuration.rpc_address.clone());
+    let token = cli.token.unwrap_or_default();
+
+    match cli.command {
+        Command::Add {
+            torrent_file,
+            foreground: true,
+            ..
+        } => local::download(configuration, &torrent_file).await,
+        Command::Add {
+            torrent_file,
+            start,
+            ..
+        } => remote::add(&rpc_address, token, &torrent_file, start).await,
+        Command::List => remote::list(&rpc_address, token).await,
+        Command::Info { torrent_hash } => remote::info(&rpc_address, token, &torrent_hash).await,
+        Command::Start { torrent_hash } => {
+            // Starts added torrents too, resuming only works on paused and stopped ones
+            remote::simple(&rpc_address, token, "start_torrent", &torrent_hash).await
+        }
+        Command::Pause { torrent_hash } => {
+            remote::simple(&rpc_address, token, "pause_torrent", &torrent_hash).await
+        }
+        Command::Remove {
+            torrent_hash,
+            delete_data,
+        } => remote::remove(&rpc_address, token, &torrent_hash, delete_data).await,
+        Command::Create {
+            path,
+            announce,
+            piece_length,
+            output,
+        } => local::create(&path, announce, piece_length, output).await,
+        Command::Verify { torrent_file, data } => local::verify(&torrent_file, data).await,
+        Command::MagnetToTorrent { magnet, output } => {
+            local::magnet_to_torrent(&configuration, &magnet, output).await
+        }
+    }
+}
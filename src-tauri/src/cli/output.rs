@@ -0,0 +1,139 @@
+use std::io::Write;
+
+use crate::torrent_management::torrent_snapshot::{TorrentDetails, TorrentSummary};
+
+const PROGRESS_BAR_WIDTH: usize = 30;
+const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+pub fn format_bytes(bytes: u64) -> String {
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+pub fn format_eta(seconds: Option<u64>) -> String {
+    match seconds {
+        Some(seconds) => format!(
+            "{:02}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        ),
+        None => "--:--:--".to_string(),
+    }
+}
+
+// `[#######-------]  45.2%` for a progress between 0.0 and 1.0
+pub fn progress_bar(progress: f64) -> String {
+    let progress = progress.clamp(0.0, 1.0);
+    let filled = (progress * PROGRESS_BAR_WIDTH as f64).round() as usize;
+    format!(
+        "[{}{}] {:5.1}%",
+        "#".repeat(filled),
+        "-".repeat(PROGRESS_BAR_WIDTH - filled),
+        progress * 100.0
+    )
+}
+
+// Redraws a single status line on stderr so stdout stays clean for scripts
+pub fn print_progress_line(line: &str) {
+    let mut stderr = std::io::stderr();
+    let _ = write!(stderr, "\r\x1b[2K{}", line);
+    let _ = stderr.flush();
+}
+
+pub fn finish_progress_line() {
+    eprintln!();
+}
+
+pub fn download_progress_line(summary: &TorrentSummary) -> String {
+    format!(
+        "{} {} / {}  down {}/s  up {}/s  peers {}  eta {}",
+        progress_bar(summary.progress),
+        format_bytes(summary.completed_size),
+        format_bytes(summary.total_size),
+        format_bytes(summary.download_rate),
+        format_bytes(summary.upload_rate),
+        summary.connected_peers,
+        format_eta(summary.eta_seconds)
+    )
+}
+
+pub fn print_torrent_list(torrents: &[TorrentSummary]) {
+    println!(
+        "{:<40}  {:<11}  {:>6}  {:>10}  {:>12}  NAME",
+        "INFO HASH", "STATUS", "DONE", "SIZE", "DOWN"
+    );
+    for torrent in torrents {
+        println!(
+            "{:<40}  {:<11}  {:>5.1}%  {:>10}  {:>10}/s  {}",
+            torrent.info_hash,
+            format!("{:?}", torrent.status).to_lowercase(),
+            torrent.progress * 100.0,
+            format_bytes(torrent.total_size),
+            format_bytes(torrent.download_rate),
+            torrent.name
+        );
+    }
+}
+
+pub fn print_torrent_details(details: &TorrentDetails) {
+    let summary = &details.summary;
+    println!("Name:       {}", summary.name);
+    println!("Info hash:  {}", summary.info_hash);
+    if let Some(info_hash_v2) = &details.info_hash_v2 {
+        println!("v2 hash:    {}", info_hash_v2);
+    }
+    println!("Status:     {:?}", summary.status);
+    println!(
+        "Progress:   {} ({} / {})",
+        progress_bar(summary.progress),
+        format_bytes(summary.completed_size),
+        format_bytes(summary.total_size)
+    );
+    println!(
+        "Transfer:   down {}/s, up {}/s, ratio {:.2}",
+        format_bytes(summary.download_rate),
+        format_bytes(summary.upload_rate),
+        summary.ratio
+    );
+    println!(
+        "Pieces:     {} x {}",
+        details.piece_count,
+        format_bytes(details.piece_length)
+    );
+    println!("ETA:        {}", format_eta(summary.eta_seconds));
+
+    println!("Trackers:");
+    for tracker in &details.trackers {
+        println!("  {}", tracker);
+    }
+    println!("Files:");
+    for file in &details.files {
+        println!(
+            "  {:>3}  {:>5.1}%  {:>10}  {}",
+            file.index,
+            file.progress * 100.0,
+            format_bytes(file.size),
+            file.path
+        );
+    }
+    println!("Peers:");
+    for peer in &details.peers {
+        let state = if peer.connected { "connected" } else { "known" };
+        println!(
+            "  {:<22}  {:<9}  {}",
+            peer.address,
+            state,
+            peer.client.as_deref().unwrap_or("")
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::path::Path;
+
+use crate::{
+    rpc::client::{RpcClient, RpcClientError},
+    torrent_management::torrent_snapshot::{TorrentDetails, TorrentSummary},
+};
+
+use super::{output, EXIT_DAEMON_UNAVAILABLE, EXIT_FAILURE, EXIT_SUCCESS};
+
+// Commands that drive a running daemon over its JSON-RPC API
+
+pub async fn add(address: &str, token: String, torrent_file: &Path, start: bool) -> i32 {
+    // The daemon resolves the path on its side, so don't hand it a relative one
+    let torrent_file = match std::fs::canonicalize(torrent_file) {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", torrent_file.display(), e);
+            return EXIT_FAILURE;
+        }
+    };
+
+    let client = RpcClient::new(address, token);
+    let torrent_hash: String = match call(
+        &client,
+        "add_torrent",
+        json!({ "torrent_file": torrent_file.to_string_lossy() }),
+    )
+    .await
+    {
+        Ok(torrent_hash) => torrent_hash,
+        Err(code) => return code,
+    };
+    println!("{}", torrent_hash);
+
+    if start {
+        if let Err(code) = call::<String>(
+            &client,
+            "start_torrent",
+            json!({ "torrent_hash": torrent_hash }),
+        )
+        .await
+        {
+            return code;
+        }
+    }
+    EXIT_SUCCESS
+}
+
+pub async fn list(address: &str, token: String) -> i32 {
+    let client = RpcClient::new(address, token);
+    match call::<Vec<TorrentSummary>>(&client, "list_torrents", Value::Null).await {
+        Ok(torrents) => {
+            output::print_torrent_list(&torrents);
+            EXIT_SUCCESS
+        }
+        Err(code) => code,
+    }
+}
+
+pub async fn info(address: &str, token: String, torrent_hash: &str) -> i32 {
+    let client = RpcClient::new(address, token);
+    match call::<TorrentDetails>(
+        &client,
+        "get_torrent_details",
+        json!({ "torrent_hash": torrent_hash }),
+    )
+    .await
+    {
+        Ok(details) => {
+            output::print_torrent_details(&details);
+            EXIT_SUCCESS
+        }
+        Err(code) => code,
+    }
+}
+
+// Commands that only take the info hash and answer with a message
+pub async fn simple(address: &str, token: String, method: &str, torrent_hash: &str) -> i32 {
+    let client = RpcClient::new(address, token);
+    match call::<String>(&client, method, json!({ "torrent_hash": torrent_hash })).await {
+        Ok(message) => {
+            println!("{}", message);
+            EXIT_SUCCESS
+        }
+        Err(code) => code,
+    }
+}
+
+pub async fn remove(address: &str, token: String, torrent_hash: &str, delete_data: bool) -> i32 {
+    let client = RpcClient::new(address, token);
+    match call::<String>(
+        &client,
+        "remove_torrent",
+        json!({ "torrent_hash": torrent_hash, "delete_data": delete_data }),
+    )
+    .await
+    {
+        Ok(message) => {
+            println!("{}", message);
+            EXIT_SUCCESS
+        }
+        Err(code) => code,
+    }
+}
+
+// Prints the error and maps it to the exit code to use
+async fn call<T: DeserializeOwned>(
+    client: &RpcClient,
+    method: &str,
+    params: Value,
+) -> Result<T, i32> {
+    client.call(method, params).await.map_err(|e| {
+        eprintln!("{}", e);
+        match e {
+            RpcClientError::Connection(_) | RpcClientError::Unauthorized => EXIT_DAEMON_UNAVAILABLE,
+            RpcClientError::Rpc(_) | RpcClientError::InvalidResponse(_) => EXIT_FAILURE,
+        }
+    })
+}
@@ -1,155 +1,219 @@
+use bitvec::prelude::*;
 use core::sync::atomic::AtomicBool;
-use serde_bencode::{de, value::Value};
-use std::{collections::HashMap, sync::Arc};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 use tokio::sync::RwLock;
 
 use crate::{
     app_state::AppState,
-    config,
-    hash::compute_info_hash,
-    parsing::parser::parse_error::parse_bencoded_torrent,
-    torrent_management::{peers::get_peers, torrent},
+    config::AllocationMode,
+    parsing::parser::{parse_error::parse_bencoded_torrent, torrent_metadata::TorrentMetadata},
+    storage::{
+        backend::{run_blocking, SharedStorage},
+        file_layout::FileLayout,
+        fs_storage::FsStorage,
+        piece_hashes::PieceHashes,
+        verify::verify_pieces,
+    },
+    torrent_management::torrent,
+    tracker::generate_tracker_key,
 };
 
-use super::{
-    all_pieces_downloaded::all_pieces_downloaded, parse_pieces_status::parse_pieces_status,
-};
+use super::all_pieces_downloaded::all_pieces_downloaded;
+
+// Settings of one torrent, whatever is left out comes from the configuration
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AddTorrentOptions {
+    #[serde(alias = "downloadDirectory")]
+    pub download_directory: Option<PathBuf>,
+    #[serde(alias = "allocationMode")]
+    pub allocation_mode: Option<AllocationMode>,
+    pub category: Option<String>,
+    #[serde(alias = "incompleteDirectory")]
+    pub incomplete_directory: Option<PathBuf>,
+    #[serde(alias = "completedDirectory")]
+    pub completed_directory: Option<PathBuf>,
+    #[serde(alias = "partSuffix")]
+    pub part_suffix: Option<bool>,
+    #[serde(alias = "autoManaged")]
+    pub auto_managed: Option<bool>,
+}
 
+#[cfg(feature = "gui")]
 #[tauri::command]
 pub async fn add_torrent(
     state: tauri::State<'_, AppState>,
     torrent_file: String,
+    options: Option<AddTorrentOptions>,
+) -> Result<String, String> {
+    handle(&state, torrent_file, options.unwrap_or_default()).await
+}
+
+pub async fn handle(
+    state: &AppState,
+    torrent_file: String,
+    options: AddTorrentOptions,
 ) -> Result<String, String> {
-    let configuration = config::Config::new();
-    let hash_size = configuration.hash_size;
+    let configuration = state.config.read().await.clone();
     // Prepare a separate lock to ensure atomic operations when updating `torrent_manager` and `pieces_status`.
     let torrent_operation_lock = RwLock::new(());
 
     let torrent_data = std::fs::read(&torrent_file).map_err(|e| e.to_string())?;
 
-    let metadata_result = parse_bencoded_torrent(torrent_data.clone());
-
-    let torrent_data = match de::from_bytes::<Value>(&torrent_data) {
-        Ok(Value::Dict(dict)) => dict,
-        Ok(_) => return Err("Top level bencode should be a dict".into()),
-        Err(e) => return Err(format!("Failed to parse torrent file: {}", e)),
-    };
+    let metadata_result = parse_bencoded_torrent(torrent_data);
 
     // Apply the lock here for atomic operations
     let _guard = torrent_operation_lock.write().await;
 
-    let pieces_status = match torrent_data.get(&b"pieces".to_vec()) {
-        Some(raw_status) => {
-            let raw_status_bytes = convert_to_bytes(raw_status.clone());
-            match parse_pieces_status(&raw_status_bytes) {
-                Ok(status) => Arc::new(RwLock::new(status)),
-                Err(_) => return Err("Failed to parse pieces status".to_string()),
-            }
+    let mut data = metadata_result.map_err(|e| format!("Failed to parse metadata: {}", e))?;
+    if data.info.files.is_none() && data.info.length <= 0 {
+        return Err("Invalid or missing 'length' field in torrent data".to_string());
+    }
+
+    // The v1 hash, or the truncated v2 hash of v2-only torrents
+    let info_hash_array: [u8; 20] = data
+        .info_hash
+        .as_slice()
+        .try_into()
+        .map_err(|_| "Incorrect hash length".to_string())?;
+
+    // Pad files included, they are part of the pieces
+    let total_size = FileLayout::from_metadata(&data).total_length;
+
+    let torrent_hash = hex::encode(info_hash_array);
+    let events = {
+        let torrent_manager = state.torrent_manager.read().await;
+        // Checked again when adding it, this saves announcing and verifying for nothing
+        if torrent_manager.get_torrent(&torrent_hash).is_some() {
+            return Err(format!("Torrent {} was already added", torrent_hash));
         }
-        None => return Err("Failed to find 'pieces' field".to_string()),
+        torrent_manager.events()
     };
+    // Either swarm's hash, and the full v2 hash, find the torrent too
+    let mut aliases: Vec<String> = data.swarm_info_hashes().iter().map(hex::encode).collect();
+    if !data.info_hash_v2.is_empty() {
+        aliases.push(hex::encode(&data.info_hash_v2));
+    }
 
-    let info = match torrent_data.get(&b"info".to_vec()) {
-        Some(info) => info,
-        None => return Err("Failed to find 'info' field".to_string()),
+    data.peer_id = state.peer_id.clone();
+    data.tracker_key = generate_tracker_key();
+    data.file_path = match options
+        .download_directory
+        .or_else(|| configuration.download_directory.clone())
+    {
+        Some(directory) => directory.join(&data.info.name),
+        None => default_file_path(&torrent_file, &data.info.name),
     };
-
-    let info_hash = match compute_info_hash(info) {
-        Ok(hash) => hash,
-        Err(e) => return Err(format!("Failed to compute info hash: {}", e)),
+    // The torrent's own completed directory goes first, then its category's
+    let completed_directory = options
+        .completed_directory
+        .or_else(|| {
+            let category = options.category.as_ref()?;
+            configuration.categories.get(category).cloned()
+        })
+        .or_else(|| configuration.completed_directory.clone());
+    let final_path = match completed_directory {
+        Some(directory) => directory.join(&data.info.name),
+        None => data.file_path.clone(),
     };
-
-    if info_hash.len() != hash_size {
-        return Err("Incorrect hash length".to_string());
-    }
-
-    let info_hash_array: [u8; 20] = {
-        let mut array = [0; 20];
-        for (index, &value) in info_hash.iter().enumerate() {
-            array[index] = value;
+    data.category = options.category;
+
+    // A finished download added again is found where it was moved to. Anything else is
+    // downloaded into the incomplete directory, when there is one, and moved once complete.
+    let finished_layout = FileLayout::from_metadata(&TorrentMetadata {
+        file_path: final_path.clone(),
+        ..data.clone()
+    });
+    let finished: SharedStorage = Arc::new(FsStorage::new(finished_layout));
+    let storage = match run_blocking(&finished, |storage| storage.check_file_sizes()).await {
+        Ok(true) => {
+            data.file_path = final_path;
+            finished
         }
-        array
-    };
-
-    let total_size: u64 = match torrent_data.get(&b"info".to_vec()) {
-        Some(Value::Dict(info_dict)) => {
-            match info_dict.get(&b"length".to_vec()) {
-                Some(Value::Int(length)) => *length as u64, // single-file torrent
-                None => {
-                    // Maybe it's a multi-file torrent
-                    match info_dict.get(&b"files".to_vec()) {
-                        Some(Value::List(files)) => files
-                            .iter()
-                            .map(|file| match file {
-                                Value::Dict(dict) => match dict.get(&b"length".to_vec()) {
-                                    Some(Value::Int(length)) => *length as u64,
-                                    _ => 0,
-                                },
-                                _ => 0,
-                            })
-                            .sum(),
-                        _ => {
-                            return Err(
-                                "Invalid or missing 'files' field in torrent data".to_string()
-                            )
-                        }
-                    }
-                }
-                _ => return Err("Invalid or missing 'length' field in torrent data".to_string()),
+        _ => {
+            if let Some(directory) = options
+                .incomplete_directory
+                .or_else(|| configuration.incomplete_directory.clone())
+            {
+                data.file_path = directory.join(&data.info.name);
             }
+            data.completed_path = Some(final_path).filter(|path| *path != data.file_path);
+            data.part_suffix = options.part_suffix.unwrap_or(configuration.part_suffix);
+            Arc::new(FsStorage::new(FileLayout::from_metadata(&data)))
         }
-        _ => return Err("Invalid or missing 'info' field in torrent data".to_string()),
     };
 
-    let (metadata, peers) = match metadata_result {
-        Ok(data) => {
-            let peers = get_peers(&data).await?;
-            (Arc::new(RwLock::new(data)), peers)
-        }
-        Err(e) => return Err(format!("Failed to parse metadata: {}", e)),
+    // Pieces start out missing, `piece_hashes` is what they are verified against. Files already
+    // there in full are checked so they can be seeded.
+    let piece_hashes = PieceHashes::from_metadata(&data)?;
+    let pieces_status = match run_blocking(&storage, |storage| storage.check_file_sizes()).await {
+        Ok(true) => verify_pieces(&storage, &piece_hashes, |_| ())
+            .await
+            .map_err(|e| format!("Failed to check existing data: {}", e))?,
+        _ => bitvec![u8, Lsb0; 0; piece_hashes.len()],
     };
+    let pieces_status = Arc::new(RwLock::new(pieces_status));
+
+    // Trackers hear about it once the torrent is started, they hand out the peers then
+    let metadata = Arc::new(RwLock::new(data));
 
     let piece_frequency = Arc::new(RwLock::new(HashMap::new()));
-    let piece_hashes = Arc::new(Vec::new());
+    let piece_hashes = Arc::new(piece_hashes);
     let is_downloading = AtomicBool::new(false);
-    let path = torrent_file.clone();
 
     let torrent = torrent::Torrent::new(
         info_hash_array,
         total_size,
-        Arc::new(peers),
+        Arc::new(RwLock::new(Vec::new())),
         metadata,
         pieces_status,
         piece_frequency.clone(),
         piece_hashes.clone(),
         is_downloading,
-        path,
-    );
+        state.disk_io.open(storage),
+        options
+            .allocation_mode
+            .unwrap_or(configuration.allocation_mode),
+        events,
+        state.config.clone(),
+        state.utp_socket.clone(),
+    )
+    .with_rate_limits(state.rate_limits.clone());
+    torrent.set_auto_managed(options.auto_managed.unwrap_or(configuration.auto_managed));
 
     // Then, apply the lock before updating `torrent_manager`.
     let mut torrent_manager = state.torrent_manager.write().await;
-    let torrent_hash = info_hash_array
-        .iter()
-        .map(|byte| format!("{:02x}", byte))
-        .collect::<String>();
     let torrent = Arc::new(RwLock::new(torrent));
-    torrent_manager.add_torrent(torrent_hash, torrent.clone());
+    torrent_manager
+        .add_torrent(torrent_hash.clone(), torrent.clone())
+        .await?;
+    for alias in aliases {
+        torrent_manager.add_alias(alias, &torrent_hash);
+    }
 
     // Release the lock right after the operation completed
     drop(torrent_manager);
 
-    let torrent_guard = torrent.read().await;
-
-    if all_pieces_downloaded(torrent_guard).await {
+    if all_pieces_downloaded(torrent.read().await).await {
         println!("All pieces are downloaded!");
+        // Left behind when we stopped before the completed download was moved
+        torrent.read().await.move_completed_files().await;
     }
 
-    Ok("Torrent added successfully".to_string())
+    // The info hash identifies the torrent in every other command
+    Ok(torrent_hash)
 }
 
-fn convert_to_bytes(value: Value) -> Vec<u8> {
-    match serde_bencode::to_bytes(&value) {
-        Ok(v) => v,
-        Err(e) => panic!("Failed to serialize Value to bytes: {}", e),
-    }
+// Torrents are downloaded next to their torrent file unless told otherwise
+pub fn default_file_path(torrent_file: &str, name: &str) -> PathBuf {
+    let directory = Path::new(torrent_file)
+        .parent()
+        .filter(|directory| !directory.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    directory.join(name)
 }
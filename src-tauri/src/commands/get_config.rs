@@ -0,0 +1,11 @@
+use crate::{app_state::AppState, config::Config};
+
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn get_config(state: tauri::State<'_, AppState>) -> Result<Config, String> {
+    handle(&state).await
+}
+
+pub async fn handle(state: &AppState) -> Result<Config, String> {
+    Ok(state.config.read().await.clone())
+}
@@ -0,0 +1,11 @@
+use crate::{app_state::AppState, storage::disk_io::DiskStats};
+
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn get_disk_stats(state: tauri::State<'_, AppState>) -> Result<DiskStats, String> {
+    handle(&state).await
+}
+
+pub async fn handle(state: &AppState) -> Result<DiskStats, String> {
+    Ok(state.disk_io.stats())
+}
@@ -0,0 +1,17 @@
+use crate::{app_state::AppState, network::port_mapping::PortMappingStatus};
+
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn get_port_mapping(
+    state: tauri::State<'_, AppState>,
+) -> Result<PortMappingStatus, String> {
+    handle(&state).await
+}
+
+pub async fn handle(state: &AppState) -> Result<PortMappingStatus, String> {
+    let port_mapper = state
+        .port_mapper
+        .as_ref()
+        .ok_or("Port mapping is disabled".to_string())?;
+    Ok(port_mapper.status().await)
+}
@@ -0,0 +1,38 @@
+use crate::{app_state::AppState, storage::file_layout::FileLayout, streaming::server::stream_url};
+
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn get_stream_url(
+    state: tauri::State<'_, AppState>,
+    torrent_hash: String,
+    file_index: usize,
+) -> Result<String, String> {
+    handle(&state, torrent_hash, file_index).await
+}
+
+pub async fn handle(
+    state: &AppState,
+    torrent_hash: String,
+    file_index: usize,
+) -> Result<String, String> {
+    let torrent = state
+        .torrent_manager
+        .read()
+        .await
+        .get_torrent(&torrent_hash)
+        .ok_or("Torrent not found".to_string())?;
+
+    let torrent_guard = torrent.read().await;
+    let metadata = torrent_guard.metadata.read().await;
+    if FileLayout::from_metadata(&metadata)
+        .file(file_index)
+        .is_none()
+    {
+        return Err(format!("Torrent has no file with index {}", file_index));
+    }
+
+    let stream_server_addr = state
+        .stream_server_addr
+        .ok_or("Streaming is disabled".to_string())?;
+    Ok(stream_url(&stream_server_addr, &torrent_hash, file_index))
+}
@@ -0,0 +1,15 @@
+use crate::{app_state::AppState, torrent_management::torrent_snapshot::TorrentDetails};
+
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn get_torrent_details(
+    state: tauri::State<'_, AppState>,
+    torrent_hash: String,
+) -> Result<TorrentDetails, String> {
+    handle(&state, torrent_hash).await
+}
+
+pub async fn handle(state: &AppState, torrent_hash: String) -> Result<TorrentDetails, String> {
+    let torrent_manager = state.torrent_manager.read().await;
+    torrent_manager.get_torrent_details(&torrent_hash).await
+}
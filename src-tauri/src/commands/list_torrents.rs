@@ -0,0 +1,14 @@
+use crate::{app_state::AppState, torrent_management::torrent_snapshot::TorrentSummary};
+
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn list_torrents(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<TorrentSummary>, String> {
+    handle(&state).await
+}
+
+pub async fn handle(state: &AppState) -> Result<Vec<TorrentSummary>, String> {
+    let torrent_manager = state.torrent_manager.read().await;
+    Ok(torrent_manager.list_torrents().await)
+}
@@ -1,4 +1,18 @@
 pub mod add_torrent;
 pub mod all_pieces_downloaded;
+pub mod get_config;
+pub mod get_disk_stats;
+pub mod get_port_mapping;
+pub mod get_stream_url;
+pub mod get_torrent_details;
+pub mod list_torrents;
+pub mod move_queue_position;
+pub mod move_storage;
 pub mod parse_pieces_status;
+pub mod pause_torrent;
+pub mod remove_torrent;
+pub mod resume_torrent;
+pub mod set_auto_managed;
+pub mod set_config;
 pub mod start_torrent;
+pub mod stop_torrent;
@@ -0,0 +1,35 @@
+use crate::{
+    app_state::AppState,
+    torrent_management::{queue::QueueMove, torrent_manager::apply_queue_plan},
+};
+
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn move_queue_position(
+    state: tauri::State<'_, AppState>,
+    torrent_hash: String,
+    movement: QueueMove,
+) -> Result<String, String> {
+    handle(&state, torrent_hash, movement).await
+}
+
+// Moves the torrent up, down, to the top or to the bottom of the queue
+pub async fn handle(
+    state: &AppState,
+    torrent_hash: String,
+    movement: QueueMove,
+) -> Result<String, String> {
+    // Starting allocates files, which may take a while, the manager stays usable meanwhile
+    let (plan, events) = {
+        let mut torrent_manager = state.torrent_manager.write().await;
+        (
+            torrent_manager
+                .move_in_queue(&torrent_hash, movement)
+                .await?,
+            torrent_manager.events(),
+        )
+    };
+    // The change itself went through, torrents that fail to start are reported as events
+    let _ = apply_queue_plan(plan, &events).await;
+    Ok("Successfully moved torrent in the queue!".to_string())
+}
@@ -0,0 +1,38 @@
+use crate::{app_state::AppState, storage::backend::MoveConflict};
+
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn move_storage(
+    state: tauri::State<'_, AppState>,
+    torrent_hash: String,
+    destination: String,
+    conflict: Option<MoveConflict>,
+) -> Result<String, String> {
+    handle(&state, torrent_hash, destination, conflict).await
+}
+
+// Moves the torrent's files into the `destination` directory. Nothing is moved when files are
+// already there unless `conflict` says what to do about them.
+pub async fn handle(
+    state: &AppState,
+    torrent_hash: String,
+    destination: String,
+    conflict: Option<MoveConflict>,
+) -> Result<String, String> {
+    let torrent = state
+        .torrent_manager
+        .read()
+        .await
+        .get_torrent(&torrent_hash)
+        .ok_or("Torrent not found".to_string())?;
+
+    // The manager isn't held for the move, copying to another disk can take a while
+    let torrent_guard = torrent.read().await;
+    match torrent_guard
+        .move_storage(destination.as_ref(), conflict.unwrap_or_default())
+        .await
+    {
+        Ok(()) => Ok("Successfully moved torrent!".to_string()),
+        Err(e) => Err(format!("Failed to move the torrent's files: {}", e)),
+    }
+}
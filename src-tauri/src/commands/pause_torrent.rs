@@ -0,0 +1,18 @@
+use crate::app_state::AppState;
+
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn pause_torrent(
+    state: tauri::State<'_, AppState>,
+    torrent_hash: String,
+) -> Result<String, String> {
+    handle(&state, torrent_hash).await
+}
+
+pub async fn handle(state: &AppState, torrent_hash: String) -> Result<String, String> {
+    let torrent_manager = state.torrent_manager.read().await;
+    match torrent_manager.pause_torrent(&torrent_hash).await {
+        Ok(_) => Ok("Successfully paused torrent!".to_string()),
+        Err(e) => Err(e),
+    }
+}
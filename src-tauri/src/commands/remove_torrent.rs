@@ -0,0 +1,26 @@
+use crate::app_state::AppState;
+
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn remove_torrent(
+    state: tauri::State<'_, AppState>,
+    torrent_hash: String,
+    delete_data: bool,
+) -> Result<String, String> {
+    handle(&state, torrent_hash, delete_data).await
+}
+
+pub async fn handle(
+    state: &AppState,
+    torrent_hash: String,
+    delete_data: bool,
+) -> Result<String, String> {
+    let mut torrent_manager = state.torrent_manager.write().await;
+    match torrent_manager
+        .remove_torrent(&torrent_hash, delete_data)
+        .await
+    {
+        Ok(_) => Ok("Successfully removed torrent!".to_string()),
+        Err(e) => Err(e),
+    }
+}
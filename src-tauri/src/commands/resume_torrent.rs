@@ -0,0 +1,23 @@
+use crate::{app_state::AppState, torrent_management::torrent_manager::apply_queue_plan};
+
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn resume_torrent(
+    state: tauri::State<'_, AppState>,
+    torrent_hash: String,
+) -> Result<String, String> {
+    handle(&state, torrent_hash).await
+}
+
+pub async fn handle(state: &AppState, torrent_hash: String) -> Result<String, String> {
+    // Starting allocates files, which may take a while, the manager stays usable meanwhile
+    let (plan, events) = {
+        let torrent_manager = state.torrent_manager.read().await;
+        (
+            torrent_manager.resume_torrent(&torrent_hash).await?,
+            torrent_manager.events(),
+        )
+    };
+    apply_queue_plan(plan, &events).await?;
+    Ok("Successfully resumed torrent!".to_string())
+}
@@ -0,0 +1,31 @@
+use crate::{app_state::AppState, torrent_management::torrent_manager::apply_queue_plan};
+
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn set_auto_managed(
+    state: tauri::State<'_, AppState>,
+    torrent_hash: String,
+    auto_managed: bool,
+) -> Result<String, String> {
+    handle(&state, torrent_hash, auto_managed).await
+}
+
+pub async fn handle(
+    state: &AppState,
+    torrent_hash: String,
+    auto_managed: bool,
+) -> Result<String, String> {
+    // Starting allocates files, which may take a while, the manager stays usable meanwhile
+    let (plan, events) = {
+        let torrent_manager = state.torrent_manager.read().await;
+        (
+            torrent_manager
+                .set_auto_managed(&torrent_hash, auto_managed)
+                .await?,
+            torrent_manager.events(),
+        )
+    };
+    // The change itself went through, torrents that fail to start are reported as events
+    let _ = apply_queue_plan(plan, &events).await;
+    Ok("Successfully updated torrent!".to_string())
+}
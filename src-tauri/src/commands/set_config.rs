@@ -0,0 +1,30 @@
+use serde_json::Value;
+
+use crate::{
+    app_state::AppState,
+    config::{self, Config},
+};
+
+// Applies the settings in `changes` (an object with any subset of the config keys), saves them to
+// the config file and returns the resulting configuration. Addresses and ports the engine has
+// already bound take effect on the next start.
+#[cfg(feature = "gui")]
+#[tauri::command]
+pub async fn set_config(
+    state: tauri::State<'_, AppState>,
+    changes: Value,
+) -> Result<Config, String> {
+    handle(&state, changes).await
+}
+
+pub async fn handle(state: &AppState, changes: Value) -> Result<Config, String> {
+    let mut current = state.config.write().await;
+    let updated = current.with_changes(changes.clone())?;
+
+    if let Some(path) = &state.config_path {
+        config::save_changes(path, &changes)?;
+    }
+
+    *current = updated.clone();
+    Ok(updated)
+}
@@ -1,13 +1,23 @@
-use crate::app_state::AppState;
+use crate::{app_state::AppState, torrent_management::torrent_manager::apply_queue_plan};
 
+#[cfg(feature = "gui")]
 #[tauri::command]
 pub async fn start_torrent(
     state: tauri::State<'_, AppState>,
     torrent_hash: String,
 ) -> Result<String, String> {
-    let torrent_manager = state.torrent_manager.read().await;
-    match torrent_manager.start_torrent(&torrent_hash).await {
-        Ok(_) => Ok("Successfully started torrent!".to_string()),
-        Err(e) => Err(e.to_string()),
-    }
+    handle(&state, torrent_hash).await
+}
+
+pub async fn handle(state: &AppState, torrent_hash: String) -> Result<String, String> {
+    // Starting allocates files, which may take a while, the manager stays usable meanwhile
+    let (plan, events) = {
+        let torrent_manager = state.torrent_manager.read().await;
+        (
+            torrent_manager.start_torrent(&torrent_hash).await?,
+            torrent_manager.events(),
+        )
+    };
+    apply_queue_plan(plan, &events).await?;
+    Ok("Successfully started torrent!".to_string())
 }
@@ -1,20 +1,326 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::sync::RwLock;
+
+// Protocol constants, these are not user configurable
+pub const HASH_SIZE: usize = 20;
+// SHA-256 info hashes and merkle roots of v2 torrents
+pub const V2_HASH_SIZE: usize = 32;
+pub const PEER_SIZE: u16 = 6;
+pub const PEER6_SIZE: usize = 18;
+pub const DEFAULT_PSTR: &str = "BitTorrent protocol";
+
+// Matches the bundle identifier in tauri.conf.json so the GUI, daemon and CLI share one file
+pub const APP_IDENTIFIER: &str = "com.tauri.dev";
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+// `PIRATE_LISTEN_PORT=7000` overrides `listen_port` and so on
+pub const ENV_PREFIX: &str = "PIRATE_";
+
+pub type SharedConfig = Arc<RwLock<Config>>;
+
+// User settings. Layered as defaults, then the TOML file in the app config dir, then
+// `PIRATE_*` environment variables, then runtime updates through `set_config`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
-    pub hash_size: usize,
-    pub peer_size: u16,
-    pub default_pstr: &'static str,
-    pub bittorent_port: String,
-    pub tcp_port: u16,
-    pub array_size: usize,
+    // Port we accept peer connections on and announce to trackers
+    pub listen_port: u16,
+    // Used for tracker urls that don't name a port
+    pub default_tracker_port: u16,
+    // Where new torrents are downloaded, next to the .torrent file when unset
+    pub download_directory: Option<PathBuf>,
+    // Unfinished downloads are kept here instead, then moved to where they belong
+    pub incomplete_directory: Option<PathBuf>,
+    // Where finished downloads are moved, they stay where they were downloaded when unset
+    pub completed_directory: Option<PathBuf>,
+    // Completed directory by category, ahead of `completed_directory`
+    pub categories: BTreeMap<String, PathBuf>,
+    // Name unfinished files `<name>.part`, the suffix goes once the download is complete
+    pub part_suffix: bool,
+    // Bytes per second, 0 means unlimited
+    pub max_download_rate: u64,
+    pub max_upload_rate: u64,
+    pub max_peers_per_torrent: usize,
+    // Peers of a torrent we upload to at once, the others stay choked. 0 means no limit
+    pub upload_slots: usize,
+    // Auto-managed torrents the queue lets run at once, 0 means no limit
+    pub max_active_downloads: usize,
+    pub max_active_seeds: usize,
+    pub max_active_total: usize,
+    // Whether torrents are added auto-managed, started and queued by the queue
+    pub auto_managed: bool,
+    // Torrents running for `slow_torrent_grace_secs` below these bytes per second don't take a
+    // slot, so a stalled download doesn't hold up the queue
+    pub dont_count_slow_torrents: bool,
+    pub slow_download_rate: u64,
+    pub slow_upload_rate: u64,
+    pub slow_torrent_grace_secs: u64,
+    // Listen on IPv6 as well, announce our IPv6 address and accept IPv6 peers
+    pub ipv6_enabled: bool,
+    // Ask trackers for the compact peer list, the dictionary form is understood either way
+    pub compact_peers: bool,
+    // Message stream encryption (MSE/PE) for peer connections
+    pub encryption: EncryptionPolicy,
+    // Accept uTP peers on the listen port and race uTP against TCP when connecting
+    pub utp_enabled: bool,
+    // Download from the HTTP/FTP servers listed in a torrent's `url-list`
+    pub web_seeds_enabled: bool,
+    // Find peers on the LAN through multicast announces (BEP 14), never for private torrents
+    pub lsd_enabled: bool,
+    // Ask the router to forward the listen port, with UPnP, NAT-PMP or PCP
+    pub port_mapping_enabled: bool,
+    // Threads reading and writing torrent data, and how many jobs may wait for them before
+    // peers are slowed down
+    pub disk_io_threads: usize,
+    pub disk_queue_size: usize,
+    // Memory for blocks waiting to be written and pieces read for uploading, split evenly
+    pub disk_cache_mb: usize,
+    // How files of new torrents are created, torrents can be added with another mode
+    pub allocation_mode: AllocationMode,
+    // Client prefix of our peer id, Azureus style
+    pub peer_id_prefix: String,
+    pub streaming_enabled: bool,
+    // Port 0 lets the OS pick a free port for the local streaming server
+    pub streaming_address: String,
+    // How often queued engine events are coalesced and sent to the frontend
+    pub event_interval_ms: u64,
+    pub announce_interval_secs: u64,
+    pub rpc_address: String,
+    // Directory scanned for new .torrent and .magnet files, disabled when unset
+    pub watch_directory: Option<PathBuf>,
+    pub watch_auto_start: bool,
+    pub watch_interval_ms: u64,
 }
-impl Config {
-    pub fn new() -> Config {
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncryptionPolicy {
+    // Plaintext only, encrypted incoming connections are refused
+    Disabled,
+    // Try encryption first and fall back to plaintext, accept both
+    Enabled,
+    // Encrypted connections only
+    Required,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AllocationMode {
+    // Files get their final size up front as sparse files, disk space is used as pieces arrive
+    Sparse,
+    // Disk space is reserved up front, fallocate on Linux, so the download can't run out of it
+    Full,
+    // Files only grow as far as the data written to them
+    Compact,
+}
+
+impl Default for Config {
+    fn default() -> Config {
         Config {
-            hash_size: 20,
-            peer_size: 6,
-            default_pstr: "BitTorrent protocol",
-            bittorent_port: "6881".to_string(),
-            tcp_port: 80,
-            array_size: 20,
+            listen_port: 6881,
+            default_tracker_port: 80,
+            download_directory: None,
+            incomplete_directory: None,
+            completed_directory: None,
+            categories: BTreeMap::new(),
+            part_suffix: false,
+            max_download_rate: 0,
+            max_upload_rate: 0,
+            max_peers_per_torrent: 50,
+            upload_slots: 4,
+            max_active_downloads: 3,
+            max_active_seeds: 5,
+            max_active_total: 8,
+            auto_managed: true,
+            dont_count_slow_torrents: true,
+            slow_download_rate: 2048,
+            slow_upload_rate: 1024,
+            slow_torrent_grace_secs: 60,
+            ipv6_enabled: true,
+            compact_peers: true,
+            encryption: EncryptionPolicy::Enabled,
+            utp_enabled: true,
+            web_seeds_enabled: true,
+            lsd_enabled: true,
+            port_mapping_enabled: true,
+            disk_io_threads: 4,
+            disk_queue_size: 256,
+            disk_cache_mb: 64,
+            allocation_mode: AllocationMode::Sparse,
+            peer_id_prefix: "-PR0001-".to_string(),
+            streaming_enabled: true,
+            streaming_address: "127.0.0.1:0".to_string(),
+            event_interval_ms: 250,
+            announce_interval_secs: 1800,
+            rpc_address: "127.0.0.1:6890".to_string(),
+            watch_directory: None,
+            watch_auto_start: false,
+            watch_interval_ms: 2000,
+        }
+    }
+}
+
+// `config.toml` in the platform's app config directory
+pub fn config_path() -> Option<PathBuf> {
+    dirs_next::config_dir().map(|directory| directory.join(APP_IDENTIFIER).join(CONFIG_FILE_NAME))
+}
+
+impl Config {
+    // Builds the effective configuration from every layer. A missing file is not an error.
+    pub fn load(path: Option<&Path>) -> Result<Config, String> {
+        let mut settings = serde_json::to_value(Config::default()).map_err(|e| e.to_string())?;
+
+        if let Some(path) = path {
+            if let Some(file_settings) = read_file_layer(path)? {
+                merge(&mut settings, file_settings)?;
+            }
+        }
+
+        apply_env_overrides(&mut settings)?;
+
+        let configuration: Config = serde_json::from_value(settings)
+            .map_err(|e| format!("Invalid configuration: {}", e))?;
+        configuration.validate()?;
+        Ok(configuration)
+    }
+
+    // Returns a copy with the keys of `patch` replaced, validated but not saved
+    pub fn with_changes(&self, patch: Value) -> Result<Config, String> {
+        let mut settings = serde_json::to_value(self).map_err(|e| e.to_string())?;
+        merge(&mut settings, patch)?;
+        let configuration: Config = serde_json::from_value(settings)
+            .map_err(|e| format!("Invalid configuration: {}", e))?;
+        configuration.validate()?;
+        Ok(configuration)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.listen_port == 0 {
+            return Err("listen_port must not be 0".to_string());
+        }
+        if self.default_tracker_port == 0 {
+            return Err("default_tracker_port must not be 0".to_string());
+        }
+        if self.max_peers_per_torrent == 0 {
+            return Err("max_peers_per_torrent must be at least 1".to_string());
         }
+        if self.peer_id_prefix.len() > 20 || !self.peer_id_prefix.is_ascii() {
+            return Err("peer_id_prefix must be at most 20 ASCII characters".to_string());
+        }
+        for (name, address) in [
+            ("streaming_address", &self.streaming_address),
+            ("rpc_address", &self.rpc_address),
+        ] {
+            address
+                .parse::<SocketAddr>()
+                .map_err(|_| format!("{} is not a valid socket address: {}", name, address))?;
+        }
+        for (name, value) in [
+            ("disk_io_threads", self.disk_io_threads),
+            ("disk_queue_size", self.disk_queue_size),
+        ] {
+            if value == 0 {
+                return Err(format!("{} must be at least 1", name));
+            }
+        }
+        for (name, value) in [
+            ("event_interval_ms", self.event_interval_ms),
+            ("announce_interval_secs", self.announce_interval_secs),
+            ("watch_interval_ms", self.watch_interval_ms),
+        ] {
+            if value == 0 {
+                return Err(format!("{} must be greater than 0", name));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Writes the keys of `patch` into the config file, keeping whatever else it contains. Only the
+// changed keys are written so environment overrides don't end up persisted.
+pub fn save_changes(path: &Path, patch: &Value) -> Result<(), String> {
+    let mut settings = read_file_layer(path)?.unwrap_or(Value::Object(Default::default()));
+    merge(&mut settings, patch.clone())?;
+
+    // TOML has no null, unset optional settings are simply left out
+    if let Value::Object(map) = &mut settings {
+        map.retain(|_, value| !value.is_null());
+    }
+    let contents = toml::to_string_pretty(&settings).map_err(|e| e.to_string())?;
+
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory)
+            .map_err(|e| format!("Failed to create {}: {}", directory.display(), e))?;
+    }
+    std::fs::write(path, contents).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+fn read_file_layer(path: &Path) -> Result<Option<Value>, String> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    let settings: toml::Value = toml::from_str(&contents)
+        .map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?;
+    serde_json::to_value(settings)
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+// Replaces the known keys of `settings` with those of `layer`, unknown keys are rejected so typos
+// don't go unnoticed
+fn merge(settings: &mut Value, layer: Value) -> Result<(), String> {
+    let (Value::Object(settings), Value::Object(layer)) = (settings, layer) else {
+        return Err("Configuration must be a table of settings".to_string());
+    };
+    let known_keys: Vec<String> = serde_json::to_value(Config::default())
+        .ok()
+        .and_then(|defaults| {
+            defaults
+                .as_object()
+                .map(|map| map.keys().cloned().collect())
+        })
+        .unwrap_or_default();
+
+    for (key, value) in layer {
+        if !known_keys.contains(&key) {
+            return Err(format!("Unknown setting: {}", key));
+        }
+        settings.insert(key, value);
+    }
+    Ok(())
+}
+
+fn apply_env_overrides(settings: &mut Value) -> Result<(), String> {
+    let Value::Object(settings) = settings else {
+        return Ok(());
+    };
+    for (key, value) in settings.iter_mut() {
+        let variable = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+        let Ok(raw) = std::env::var(&variable) else {
+            continue;
+        };
+        // Interpret the text according to the type of the setting it replaces
+        *value = match value {
+            Value::Bool(_) => Value::Bool(
+                raw.parse()
+                    .map_err(|_| format!("{} must be true or false", variable))?,
+            ),
+            Value::Number(_) => Value::Number(
+                raw.parse::<u64>()
+                    .map_err(|_| format!("{} must be a number", variable))?
+                    .into(),
+            ),
+            _ if raw.is_empty() => Value::Null,
+            _ => Value::String(raw),
+        };
     }
+    Ok(())
 }
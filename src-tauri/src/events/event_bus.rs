@@ -0,0 +1,63 @@
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use super::torrent_event::{coalesce_events, TorrentEvent};
+
+const EVENT_BUS_CAPACITY: usize = 1024;
+
+// Cheap to clone handle that `Torrent` and `TorrentManager` publish their events through.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<TorrentEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+        EventBus { sender }
+    }
+
+    pub fn emit(&self, event: TorrentEvent) {
+        // Sending only fails when nobody is listening, which is fine
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<TorrentEvent> {
+        self.sender.subscribe()
+    }
+}
+
+// Collects events from `receiver` and hands them to `emit` in coalesced batches once per `interval`,
+// so a busy swarm does not flood the frontend with one message per piece or rate sample.
+pub async fn forward_events<F>(
+    mut receiver: broadcast::Receiver<TorrentEvent>,
+    interval: Duration,
+    mut emit: F,
+) where
+    F: FnMut(Vec<TorrentEvent>),
+{
+    let mut pending = Vec::new();
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            received = receiver.recv() => match received {
+                Ok(event) => pending.push(event),
+                Err(RecvError::Lagged(skipped)) => {
+                    println!("Event forwarder lagged behind, dropped {} events", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = ticker.tick() => {
+                if !pending.is_empty() {
+                    emit(coalesce_events(std::mem::take(&mut pending)));
+                }
+            }
+        }
+    }
+
+    if !pending.is_empty() {
+        emit(coalesce_events(pending));
+    }
+}
@@ -0,0 +1,110 @@
+use serde::Serialize;
+
+use crate::{
+    network::port_mapping::PortMappingStatus, torrent_management::torrent_status::TorrentStatus,
+};
+
+// Structured events emitted by the engine, serialised as `{ "type": "...", ...fields }` for the frontend.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TorrentEvent {
+    TorrentAdded {
+        info_hash: String,
+    },
+    TorrentRemoved {
+        info_hash: String,
+    },
+    StatusChanged {
+        info_hash: String,
+        status: TorrentStatus,
+    },
+    PieceCompleted {
+        info_hash: String,
+        piece_index: u32,
+    },
+    RateSample {
+        info_hash: String,
+        download_rate: u64,
+        upload_rate: u64,
+        downloaded: u64,
+        uploaded: u64,
+    },
+    PeerConnected {
+        info_hash: String,
+        peer: String,
+        // Client name and version from the peer's handshake, when recognised
+        client: Option<String>,
+    },
+    PeerDisconnected {
+        info_hash: String,
+        peer: String,
+    },
+    TrackerResponse {
+        info_hash: String,
+        tracker: String,
+        peers: usize,
+    },
+    // Bytes of the torrent's files moved so far by `move_storage`
+    MoveProgress {
+        info_hash: String,
+        moved: u64,
+        total: u64,
+    },
+    // The files now live at `path`
+    StorageMoved {
+        info_hash: String,
+        path: String,
+    },
+    Error {
+        info_hash: Option<String>,
+        message: String,
+    },
+    // The listen port was mapped on the router, or the mapping changed or failed
+    PortMappingChanged {
+        status: PortMappingStatus,
+    },
+}
+
+impl TorrentEvent {
+    // Events that only describe the latest state of a torrent, older ones are superseded by newer ones
+    fn superseded_by(&self, other: &TorrentEvent) -> bool {
+        match (self, other) {
+            (
+                TorrentEvent::RateSample { info_hash, .. },
+                TorrentEvent::RateSample {
+                    info_hash: other_hash,
+                    ..
+                },
+            )
+            | (
+                TorrentEvent::StatusChanged { info_hash, .. },
+                TorrentEvent::StatusChanged {
+                    info_hash: other_hash,
+                    ..
+                },
+            )
+            | (
+                TorrentEvent::MoveProgress { info_hash, .. },
+                TorrentEvent::MoveProgress {
+                    info_hash: other_hash,
+                    ..
+                },
+            ) => info_hash == other_hash,
+            (TorrentEvent::PortMappingChanged { .. }, TorrentEvent::PortMappingChanged { .. }) => {
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// Drops rate samples, status changes, move progress and port mapping updates that a later event in the same batch supersedes,
+// keeping every other event in the order it was emitted.
+pub fn coalesce_events(events: Vec<TorrentEvent>) -> Vec<TorrentEvent> {
+    let mut coalesced: Vec<TorrentEvent> = Vec::with_capacity(events.len());
+    for event in events {
+        coalesced.retain(|existing| !existing.superseded_by(&event));
+        coalesced.push(event);
+    }
+    coalesced
+}
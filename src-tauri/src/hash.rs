@@ -1,6 +1,7 @@
 use serde_bencode::ser::to_bytes;
 use serde_bencode::value::Value;
 use sha1::{Digest, Sha1};
+use sha2::Sha256;
 
 pub fn compute_info_hash(info: &Value) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
     let mut hasher = Sha1::new();
@@ -9,3 +10,17 @@ pub fn compute_info_hash(info: &Value) -> Result<Vec<u8>, Box<dyn std::error::Er
     hasher.update(info_bytes);
     Ok(hasher.finalize().to_vec())
 }
+
+// The v2 info hash (BEP 52) is the SHA-256 of the same bencoded dictionary
+pub fn compute_info_hash_v2(info: &Value) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let info_bytes =
+        to_bytes(info).map_err(|e| format!("Failed to serialize info bencode: {}", e))?;
+    Ok(Sha256::digest(info_bytes).to_vec())
+}
+
+// Handshakes, trackers and the DHT only have room for 20 bytes, v2 hashes are cut to fit
+pub fn truncate_info_hash(info_hash_v2: &[u8]) -> [u8; 20] {
+    let mut truncated = [0u8; 20];
+    truncated.copy_from_slice(&info_hash_v2[..20]);
+    truncated
+}
@@ -0,0 +1,15 @@
+pub mod app_state;
+pub mod cli;
+pub mod commands;
+pub mod config;
+pub mod events;
+pub mod hash;
+pub mod message_handling;
+pub mod network;
+pub mod parsing;
+pub mod rpc;
+pub mod storage;
+pub mod streaming;
+pub mod torrent_management;
+pub mod tracker;
+pub mod watch;
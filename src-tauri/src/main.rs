@@ -1,54 +1,83 @@
-use app_state::AppState;
-use std::sync::Arc;
-use tokio::sync::{mpsc, RwLock};
-
-mod app_state;
-mod commands;
-mod config;
-mod hash;
-mod message_handling;
-mod parsing;
-mod torrent_management;
-mod tracker;
-
-use torrent_management::{
-    peers::{self},
-    torrent_manager,
+use clap::Parser;
+use pirate::{
+    app_state::AppState, cli, commands, config, events::event_bus::forward_events,
+    watch::watch_folder::watch_folder,
 };
-
-async fn async_process_model(
-    mut input_rx: mpsc::Receiver<String>,
-    output_tx: mpsc::Sender<String>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    loop {
-        while let Some(input) = input_rx.recv().await {
-            let output = input;
-            output_tx.send(output).await.unwrap();
-        }
-    }
-}
+use std::time::Duration;
+use tauri::Manager;
 
 #[tokio::main]
 async fn main() {
-    let (async_proc_input_tx, async_proc_input_rx) = mpsc::channel(100);
-    let (async_proc_output_tx, _) = mpsc::channel(100);
+    // `pirate <subcommand>` is the command-line interface, no arguments opens the GUI
+    let args: Vec<String> = std::env::args().collect();
+    if cli::is_cli_invocation(&args) {
+        let cli = cli::Cli::parse_from(args);
+        std::process::exit(cli::run(cli).await);
+    }
 
-    let state = AppState {
-        torrent_manager: Arc::new(RwLock::new(torrent_manager::TorrentManager::new())),
-        async_proc_input_tx: Arc::new(RwLock::new(async_proc_input_tx)),
-    };
+    let config_path = config::config_path();
+    let configuration =
+        config::Config::load(config_path.as_deref()).expect("error while loading configuration");
+    let state = AppState::new(configuration.clone(), config_path)
+        .await
+        .expect("error while starting the torrent engine");
+    let events = state.events.clone();
 
-    tokio::spawn(async_process_model(
-        async_proc_input_rx,
-        async_proc_output_tx,
-    ));
+    let event_interval = Duration::from_millis(configuration.event_interval_ms);
+    let watch_directory = configuration.watch_directory.clone();
+    let watch_auto_start = configuration.watch_auto_start;
+    let watch_interval = Duration::from_millis(configuration.watch_interval_ms);
 
     tauri::Builder::default()
+        .setup(move |app| {
+            let app_handle = app.handle();
+            tauri::async_runtime::spawn(forward_events(
+                events.subscribe(),
+                event_interval,
+                move |batch| {
+                    if let Err(e) = app_handle.emit_all("torrent-events", batch) {
+                        println!("Failed to emit torrent events: {}", e);
+                    }
+                },
+            ));
+
+            if let Some(directory) = watch_directory {
+                let app_handle = app.handle();
+                tauri::async_runtime::spawn(async move {
+                    let state = app_handle.state::<AppState>();
+                    watch_folder(state.inner(), directory, watch_auto_start, watch_interval).await;
+                });
+            }
+            Ok(())
+        })
         .manage(state)
         .invoke_handler(tauri::generate_handler![
             commands::add_torrent::add_torrent,
-            commands::start_torrent::start_torrent
+            commands::start_torrent::start_torrent,
+            commands::get_stream_url::get_stream_url,
+            commands::list_torrents::list_torrents,
+            commands::get_torrent_details::get_torrent_details,
+            commands::pause_torrent::pause_torrent,
+            commands::resume_torrent::resume_torrent,
+            commands::stop_torrent::stop_torrent,
+            commands::remove_torrent::remove_torrent,
+            commands::get_config::get_config,
+            commands::get_port_mapping::get_port_mapping,
+            commands::get_disk_stats::get_disk_stats,
+            commands::move_storage::move_storage,
+            commands::move_queue_position::move_queue_position,
+            commands::set_auto_managed::set_auto_managed,
+            commands::set_config::set_config
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app_handle, event| {
+            // Don't leave our port forwarded on the router after we're gone
+            if let tauri::RunEvent::Exit = event {
+                let state = app_handle.state::<AppState>();
+                if let Some(port_mapper) = &state.port_mapper {
+                    tauri::async_runtime::block_on(port_mapper.shutdown());
+                }
+            }
+        });
 }
@@ -1,8 +1,8 @@
-use std::fs::OpenOptions;
-use std::io::{self, Seek, SeekFrom, Write};
-
 use super::message_error::MessageError;
-use crate::torrent_management::{message::Message, peers::Peer, torrent::Torrent};
+use crate::{
+    network::{extension::UT_PEX_ID, pex::parse_pex},
+    torrent_management::{message::Message, peers::Peer, torrent::Torrent},
+};
 
 // Handle the message received accordingly and manipulate the TorrentMetadata.
 pub async fn message_handler(
@@ -10,26 +10,21 @@ pub async fn message_handler(
     peer: &Peer,
     torrent: &mut Torrent,
 ) -> Result<(), MessageError> {
-    let piece_index = match msg {
-        Message::Piece(index, _, _) => index,
-        _ => 0,
-    };
-
     match msg {
         Message::Choke => {
-            println!("Choke message received.");
+            torrent.set_peer_choking(&peer.address, true).await;
             Ok(())
         }
         Message::Unchoke => {
-            println!("Unchoke message received.");
+            torrent.set_peer_choking(&peer.address, false).await;
             Ok(())
         }
         Message::Interested => {
-            println!("Interested message received.");
+            torrent.set_peer_interested(&peer.address, true).await;
             Ok(())
         }
         Message::NotInterested => {
-            println!("NotInterested message received.");
+            torrent.set_peer_interested(&peer.address, false).await;
             Ok(())
         }
         Message::Have => {
@@ -40,78 +35,49 @@ pub async fn message_handler(
             bitfield_handler(body)?;
             Ok(())
         }
-        Message::Request(piece_index) => {
-            let metadata = &*torrent.metadata.read().await;
-            let piece_size = metadata.info.piece_length as usize;
-            let file_path = metadata
-                .file_path
-                .to_str()
-                .ok_or(MessageError::NonUnicodePath)?;
-
-            // Acquire the read lock before checking the piece status
-            let pieces_status = torrent.pieces_status.read().await;
-
-            // Check if piece is already downloaded
-            if pieces_status[piece_index as usize] {
-                println!(
-                    "Piece {} already downloaded. Ignoring request.",
-                    piece_index
-                );
+        Message::Request(piece_index, begin, length) => {
+            // Choked peers only get the pieces of their allowed-fast set
+            if !torrent.may_serve(&peer.address, piece_index).await {
+                torrent
+                    .reject_request(&peer.address, piece_index, begin, length)
+                    .await;
                 return Ok(());
             }
-
-            // Assuming a peer object is available and it has a method to read a piece
-            let piece_data: Vec<u8> = torrent.download_piece_from_peer(&peer, piece_index).await?;
-
-            // Now, save the downloaded piece data to the file
-            let write_result = tokio::task::block_in_place(|| {
-                write_to_file(
-                    piece_index.try_into().unwrap(),
-                    0,
-                    piece_size,
-                    piece_data,
-                    file_path,
-                )
-                .map_err(|_| MessageError::FileIOError)
-            });
-
-            match write_result {
-                Ok(_) => {
-                    // Acquire the write lock before setting the piece status
-                    let mut pieces_status = torrent.pieces_status.write().await;
-                    pieces_status.set(piece_index as usize, true);
-                    if pieces_status.all() {
-                        println!("Download complete!");
-                    }
-                    Ok(())
-                }
-                Err(_) => Err(MessageError::FileIOError),
-            }
+            torrent
+                .serve_request(&peer.address, piece_index, begin, length)
+                .await;
+            Ok(())
         }
         Message::Piece(index, begin, data) => {
-            let metadata = &*torrent.metadata.read().await;
-            let piece_size = metadata.info.piece_length as usize;
-            let file_path = metadata
-                .file_path
-                .to_str()
-                .ok_or(MessageError::NonUnicodePath)?;
-            let write_result = tokio::task::block_in_place(|| {
-                write_to_file(index, begin, piece_size, data, file_path)
-                    .map_err(|_| MessageError::FileIOError)
-            });
-
-            match write_result {
-                Ok(_) => {
-                    // Acquire the write lock before setting the piece status
-                    let mut pieces_status = torrent.pieces_status.write().await;
-                    pieces_status.set(piece_index as usize, true);
-                    if pieces_status.all() {
-                        println!("Download complete!");
-                    }
-                    Ok(())
-                }
-                Err(_) => Err(MessageError::FileIOError),
+            // Pieces we asked for go to the download waiting for them
+            let data = match begin {
+                0 => match torrent.answer_request(&peer.address, index as u32, data) {
+                    Some(data) => data,
+                    None => return Ok(()),
+                },
+                _ => data,
+            };
+            // Verified pieces are never overwritten with unverified data
+            if torrent
+                .pieces_status
+                .read()
+                .await
+                .get(index)
+                .is_none_or(|downloaded| *downloaded)
+            {
+                return Ok(());
+            }
+            // A whole piece can be checked right away, other blocks are only kept in the storage
+            if begin == 0 && torrent.validate_piece(&data, index as u32).await {
+                return match torrent.store_piece(index as u32, &data).await {
+                    true => Ok(()),
+                    false => Err(MessageError::FileIOError),
+                };
             }
+            torrent
+                .write_block(index as u32, begin as u32, data)
+                .await
+                .map_err(|_| MessageError::FileIOError)
         }
         Message::Cancel => {
             println!("Cancel message received.");
@@ -121,30 +87,57 @@ pub async fn message_handler(
             println!("Received KeepAlive");
             Ok(())
         }
+        Message::SuggestPiece(piece_index) => {
+            torrent.suggest_piece(piece_index).await;
+            Ok(())
+        }
+        Message::HaveAll => {
+            torrent.add_seed_availability().await;
+            Ok(())
+        }
+        Message::HaveNone => {
+            println!("HaveNone message received.");
+            Ok(())
+        }
+        Message::RejectRequest(piece_index, begin, _) => {
+            println!(
+                "Request for piece {} at {} rejected by {}",
+                piece_index, begin, peer.address
+            );
+            torrent.fail_request(&peer.address, piece_index, "The peer rejected the request");
+            Ok(())
+        }
+        Message::AllowedFast(piece_index) => {
+            torrent
+                .add_peer_allowed_fast(&peer.address, piece_index)
+                .await;
+            Ok(())
+        }
+        Message::Extended(UT_PEX_ID, payload) => {
+            // We never offered PEX for private torrents, peers from elsewhere aren't allowed
+            if torrent.is_private().await {
+                println!("Ignoring PEX message for a private torrent");
+                return Ok(());
+            }
+            let pex = parse_pex(&payload)?;
+            let ipv6_enabled = torrent.config().read().await.ipv6_enabled;
+            let added = pex
+                .added
+                .into_iter()
+                .filter(|peer| ipv6_enabled || peer.address.is_ipv4())
+                .collect();
+            let new_peers = torrent.add_peers(added).await;
+            println!("Learned {} new peers through PEX", new_peers);
+            Ok(())
+        }
+        Message::Extended(extended_id, _) => {
+            println!("Extension message {} received.", extended_id);
+            Ok(())
+        }
     }
 }
 
-// Writes the received data to a file
-fn write_to_file(
-    index: usize,
-    begin: usize,
-    piece_size: usize,
-    data: Vec<u8>,
-    file_path: &str,
-) -> io::Result<()> {
-    let position = index * piece_size + begin;
-
-    let mut file = OpenOptions::new().write(true).open(file_path)?;
-
-    file.seek(SeekFrom::Start(position as u64))?;
-
-    file.write_all(&data)?;
-
-    Ok(())
-}
-
 fn bitfield_handler(bitfield: Vec<u8>) -> Result<(), MessageError> {
     println!("Bitfield: {:?}", bitfield);
     Ok(())
 }
-
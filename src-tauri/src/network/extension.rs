@@ -0,0 +1,52 @@
+use serde_bencode::value::Value;
+use std::collections::HashMap;
+use tokio::io::AsyncWriteExt;
+
+use crate::message_handling::message_error::MessageError;
+
+use super::peer_stream::PeerStream;
+
+// Extension protocol (BEP 10) message id, the payload starts with the extended message id
+pub const EXTENDED_MESSAGE_ID: u8 = 20;
+pub const EXTENDED_HANDSHAKE_ID: u8 = 0;
+
+// The ids we ask peers to use when sending us extension messages
+pub const UT_METADATA_ID: u8 = 1;
+pub const UT_PEX_ID: u8 = 2;
+
+// Our extended handshake, advertising the extensions we understand and our listen port. PEX is
+// left out for private torrents.
+pub fn extended_handshake(listen_port: u16, private: bool) -> Value {
+    let mut extensions = HashMap::new();
+    extensions.insert(b"ut_metadata".to_vec(), Value::Int(UT_METADATA_ID as i64));
+    if !private {
+        extensions.insert(b"ut_pex".to_vec(), Value::Int(UT_PEX_ID as i64));
+    }
+
+    let mut handshake = HashMap::new();
+    handshake.insert(b"m".to_vec(), Value::Dict(extensions));
+    handshake.insert(b"p".to_vec(), Value::Int(listen_port as i64));
+    Value::Dict(handshake)
+}
+
+// Sends a bencoded dictionary as extension message `extended_id`, followed by `trailer`
+pub async fn send_extended(
+    stream: &mut PeerStream,
+    extended_id: u8,
+    dict: &Value,
+    trailer: &[u8],
+) -> Result<(), MessageError> {
+    let dict_bytes =
+        serde_bencode::to_bytes(dict).map_err(|e| MessageError::ConversionError(e.to_string()))?;
+    let length = 2 + dict_bytes.len() + trailer.len();
+
+    let mut message = Vec::with_capacity(4 + length);
+    message.extend_from_slice(&(length as u32).to_be_bytes());
+    message.push(EXTENDED_MESSAGE_ID);
+    message.push(extended_id);
+    message.extend_from_slice(&dict_bytes);
+    message.extend_from_slice(trailer);
+
+    stream.write_all(&message).await?;
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use bitvec::prelude::{BitVec, Lsb0};
+use sha1::{Digest, Sha1};
+use std::{collections::HashSet, net::IpAddr};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{message_handling::message_error::MessageError, torrent_management::message};
+
+use super::{peer_connection::send_message, peer_stream::PeerStream};
+
+// Fast extension (BEP 6) message ids
+pub const SUGGEST_PIECE_ID: u8 = 13;
+pub const HAVE_ALL_ID: u8 = 14;
+pub const HAVE_NONE_ID: u8 = 15;
+pub const REJECT_REQUEST_ID: u8 = 16;
+pub const ALLOWED_FAST_ID: u8 = 17;
+
+// How many pieces a new peer may request from us while choked
+pub const ALLOWED_FAST_SET_SIZE: usize = 10;
+
+// What we agreed on with a peer that supports the Fast extension
+#[derive(Debug, Clone, Default)]
+pub struct FastPeerState {
+    // Pieces the peer may request while we choke it
+    pub allowed_fast: HashSet<u32>,
+    // Pieces we may request while the peer chokes us
+    pub peer_allowed_fast: HashSet<u32>,
+}
+
+// The canonical allowed-fast set of BEP 6. It only depends on the peer's network and the torrent,
+// so reconnecting from the same network doesn't earn a peer new free pieces.
+pub fn allowed_fast_set(
+    address: IpAddr,
+    info_hash: &[u8; 20],
+    piece_count: u32,
+    size: usize,
+) -> Vec<u32> {
+    let size = size.min(piece_count as usize);
+    let mut seed = match address {
+        IpAddr::V4(address) => (u32::from(address) & 0xFFFFFF00).to_be_bytes().to_vec(),
+        // The BEP only covers IPv4, a /48 is the closest thing to a /24 for IPv6
+        IpAddr::V6(address) => {
+            let mut prefix = address.octets()[..6].to_vec();
+            prefix.resize(16, 0);
+            prefix
+        }
+    };
+    seed.extend_from_slice(info_hash);
+
+    let mut pieces = Vec::with_capacity(size);
+    while pieces.len() < size {
+        seed = Sha1::digest(&seed).to_vec();
+        for chunk in seed.chunks_exact(4) {
+            if pieces.len() >= size {
+                break;
+            }
+            let index =
+                u32::from_be_bytes(chunk.try_into().expect("Chunk of 4 bytes")) % piece_count;
+            if !pieces.contains(&index) {
+                pieces.push(index);
+            }
+        }
+    }
+    pieces
+}
+
+// Announces our pieces, with the short forms when we have all or none of them
+pub async fn send_availability(
+    stream: &mut PeerStream,
+    pieces_status: &BitVec<u8, Lsb0>,
+) -> Result<(), MessageError> {
+    if pieces_status.all() {
+        send_message(stream, HAVE_ALL_ID, &[]).await
+    } else if pieces_status.not_any() {
+        send_message(stream, HAVE_NONE_ID, &[]).await
+    } else {
+        // The wire bitfield has the first piece in the high bit of the first byte
+        let bitfield: Vec<u8> = pieces_status
+            .as_raw_slice()
+            .iter()
+            .map(|byte| byte.reverse_bits())
+            .collect();
+        send_message(stream, message::BITFIELD_ID, &bitfield).await
+    }
+}
+
+pub async fn send_allowed_fast(
+    stream: &mut PeerStream,
+    piece_index: u32,
+) -> Result<(), MessageError> {
+    send_message(stream, ALLOWED_FAST_ID, &piece_index.to_be_bytes()).await
+}
+
+// Tells the peer its request won't be served, echoing the request
+pub async fn send_reject_request<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    index: u32,
+    begin: u32,
+    length: u32,
+) -> Result<(), MessageError> {
+    stream
+        .write_all(&message::Message::RejectRequest(index, begin, length).encode())
+        .await?;
+    Ok(())
+}
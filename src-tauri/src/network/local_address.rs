@@ -0,0 +1,31 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+
+// Any routable address works, connecting a UDP socket sends nothing
+const IPV4_PROBE_ADDRESS: &str = "8.8.8.8:53";
+const IPV6_PROBE_ADDRESS: &str = "[2001:4860:4860::8888]:53";
+
+// The IPv4 address the OS would use for outgoing traffic, usually a private one behind a router
+pub fn local_ipv4_address() -> Option<Ipv4Addr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect(IPV4_PROBE_ADDRESS).ok()?;
+    match socket.local_addr().ok()? {
+        SocketAddr::V4(address) if !address.ip().is_unspecified() => Some(*address.ip()),
+        _ => None,
+    }
+}
+
+// The IPv6 address the OS would use for outgoing traffic, if this host has a usable one.
+// Loopback and link-local addresses are useless to remote peers and are skipped.
+pub fn local_ipv6_address() -> Option<Ipv6Addr> {
+    let socket = UdpSocket::bind("[::]:0").ok()?;
+    socket.connect(IPV6_PROBE_ADDRESS).ok()?;
+    match socket.local_addr().ok()? {
+        SocketAddr::V6(address) if is_reachable_ipv6(address.ip()) => Some(*address.ip()),
+        _ => None,
+    }
+}
+
+fn is_reachable_ipv6(ip: &Ipv6Addr) -> bool {
+    let link_local = (ip.segments()[0] & 0xffc0) == 0xfe80;
+    !ip.is_loopback() && !ip.is_unspecified() && !link_local && ip.to_ipv4_mapped().is_none()
+}
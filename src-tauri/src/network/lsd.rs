@@ -0,0 +1,291 @@
+use rand::{distributions::Alphanumeric, Rng};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    collections::HashMap,
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{net::UdpSocket, sync::RwLock};
+
+use crate::torrent_management::{peers::Peer, torrent_manager::TorrentManager};
+
+// Local Service Discovery (BEP 14) multicast groups
+pub const LSD_PORT: u16 = 6771;
+pub const LSD_IPV4_GROUP: Ipv4Addr = Ipv4Addr::new(239, 192, 152, 143);
+pub const LSD_IPV6_GROUP: Ipv6Addr = Ipv6Addr::new(0xff15, 0, 0, 0, 0, 0, 0xefc0, 0x988f);
+
+// Each torrent is announced this often, new torrents within a check interval
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+// Keeps an announce well inside one unfragmented datagram
+const MAX_HASHES_PER_ANNOUNCE: usize = 20;
+const COOKIE_LENGTH: usize = 8;
+
+// A `BT-SEARCH` announce: someone on the LAN has these torrents and listens on `port`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LsdAnnounce {
+    pub port: u16,
+    pub info_hashes: Vec<[u8; 20]>,
+    pub cookie: Option<String>,
+}
+
+pub fn format_announce(
+    group: SocketAddr,
+    port: u16,
+    info_hashes: &[[u8; 20]],
+    cookie: &str,
+) -> String {
+    let mut announce = format!(
+        "BT-SEARCH * HTTP/1.1\r\nHost: {}\r\nPort: {}\r\n",
+        group, port
+    );
+    for info_hash in info_hashes {
+        announce.push_str(&format!("Infohash: {}\r\n", hex::encode(info_hash)));
+    }
+    announce.push_str(&format!("cookie: {}\r\n\r\n\r\n", cookie));
+    announce
+}
+
+// Header names are case-insensitive, unknown headers and malformed info hashes are skipped
+pub fn parse_announce(datagram: &[u8]) -> Option<LsdAnnounce> {
+    let text = std::str::from_utf8(datagram).ok()?;
+    let mut lines = text.split("\r\n");
+    if !lines.next()?.starts_with("BT-SEARCH * HTTP/1.1") {
+        return None;
+    }
+
+    let mut port = None;
+    let mut info_hashes = Vec::new();
+    let mut cookie = None;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "port" => port = value.parse::<u16>().ok(),
+            "infohash" => {
+                if let Some(info_hash) = hex::decode(value)
+                    .ok()
+                    .and_then(|bytes| <[u8; 20]>::try_from(bytes).ok())
+                {
+                    info_hashes.push(info_hash);
+                }
+            }
+            "cookie" => cookie = Some(value.to_string()),
+            _ => (),
+        }
+    }
+
+    match (port, info_hashes.is_empty()) {
+        (Some(port), false) if port != 0 => Some(LsdAnnounce {
+            port,
+            info_hashes,
+            cookie,
+        }),
+        _ => None,
+    }
+}
+
+// A socket in one of the LSD groups. Several clients on the same host can share the port.
+pub struct LsdSocket {
+    socket: UdpSocket,
+    group: SocketAddr,
+    // Tells our own announces apart when they loop back
+    cookie: String,
+}
+
+impl LsdSocket {
+    // Joins the IPv4 group on `interface`, unspecified lets the OS pick the interface
+    pub fn bind_v4(interface: Ipv4Addr) -> io::Result<LsdSocket> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, LSD_PORT)).into())?;
+        socket.join_multicast_v4(&LSD_IPV4_GROUP, &interface)?;
+        if !interface.is_unspecified() {
+            socket.set_multicast_if_v4(&interface)?;
+        }
+        // Other clients on this machine are LAN peers too
+        socket.set_multicast_loop_v4(true)?;
+        LsdSocket::new(socket, SocketAddr::from((LSD_IPV4_GROUP, LSD_PORT)))
+    }
+
+    // Joins the IPv6 group on the interface with index `interface`, 0 lets the OS pick
+    pub fn bind_v6(interface: u32) -> io::Result<LsdSocket> {
+        let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_only_v6(true)?;
+        socket.set_reuse_address(true)?;
+        socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, LSD_PORT)).into())?;
+        socket.join_multicast_v6(&LSD_IPV6_GROUP, interface)?;
+        if interface != 0 {
+            socket.set_multicast_if_v6(interface)?;
+        }
+        socket.set_multicast_loop_v6(true)?;
+        LsdSocket::new(socket, SocketAddr::from((LSD_IPV6_GROUP, LSD_PORT)))
+    }
+
+    fn new(socket: Socket, group: SocketAddr) -> io::Result<LsdSocket> {
+        socket.set_nonblocking(true)?;
+        let cookie = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(COOKIE_LENGTH)
+            .map(char::from)
+            .collect();
+        Ok(LsdSocket {
+            socket: UdpSocket::from_std(socket.into())?,
+            group,
+            cookie,
+        })
+    }
+
+    pub fn group(&self) -> SocketAddr {
+        self.group
+    }
+
+    // Tells the group we have `info_hashes` and accept peers on `port`
+    pub async fn announce(&self, port: u16, info_hashes: &[[u8; 20]]) -> io::Result<()> {
+        for chunk in info_hashes.chunks(MAX_HASHES_PER_ANNOUNCE) {
+            let announce = format_announce(self.group, port, chunk, &self.cookie);
+            self.socket.send_to(announce.as_bytes(), self.group).await?;
+        }
+        Ok(())
+    }
+
+    // Waits for someone else's announce, returning it with the address of the peer it describes
+    pub async fn receive(&self) -> io::Result<(SocketAddr, LsdAnnounce)> {
+        let mut buffer = vec![0u8; 2048];
+        loop {
+            let (size, from) = self.socket.recv_from(&mut buffer).await?;
+            let Some(announce) = parse_announce(&buffer[..size]) else {
+                continue;
+            };
+            if announce.cookie.as_deref() == Some(self.cookie.as_str()) {
+                continue;
+            }
+            return Ok((SocketAddr::new(from.ip(), announce.port), announce));
+        }
+    }
+}
+
+// Announces our active public torrents on the LAN and hands peers found there to their torrents.
+// The IPv6 group is optional, not every network has IPv6 multicast.
+pub fn start_lsd(
+    torrent_manager: Arc<RwLock<TorrentManager>>,
+    listen_port: u16,
+    ipv6_enabled: bool,
+) -> Result<(), String> {
+    let mut sockets = vec![LsdSocket::bind_v4(Ipv4Addr::UNSPECIFIED)
+        .map_err(|e| format!("Failed to start local service discovery: {}", e))?];
+    if ipv6_enabled {
+        match LsdSocket::bind_v6(0) {
+            Ok(socket) => sockets.push(socket),
+            Err(e) => println!("Local service discovery is IPv4 only: {}", e),
+        }
+    }
+
+    for socket in sockets {
+        let socket = Arc::new(socket);
+        tokio::spawn(receive_announces(
+            Arc::clone(&socket),
+            Arc::clone(&torrent_manager),
+        ));
+        tokio::spawn(announce_torrents(
+            socket,
+            Arc::clone(&torrent_manager),
+            listen_port,
+        ));
+    }
+    Ok(())
+}
+
+async fn announce_torrents(
+    socket: Arc<LsdSocket>,
+    torrent_manager: Arc<RwLock<TorrentManager>>,
+    listen_port: u16,
+) {
+    let mut last_announced: HashMap<[u8; 20], Instant> = HashMap::new();
+    loop {
+        let config = torrent_manager.read().await.config();
+        if config.read().await.lsd_enabled {
+            let due: Vec<[u8; 20]> = lsd_info_hashes(&torrent_manager)
+                .await
+                .into_iter()
+                .filter(|info_hash| {
+                    last_announced
+                        .get(info_hash)
+                        .is_none_or(|announced| announced.elapsed() >= ANNOUNCE_INTERVAL)
+                })
+                .collect();
+            if !due.is_empty() {
+                match socket.announce(listen_port, &due).await {
+                    Ok(()) => {
+                        for info_hash in due {
+                            last_announced.insert(info_hash, Instant::now());
+                        }
+                    }
+                    Err(e) => println!("Failed to announce to {}: {}", socket.group(), e),
+                }
+            }
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+// Swarm hashes of the running torrents that may use LAN peers
+async fn lsd_info_hashes(torrent_manager: &Arc<RwLock<TorrentManager>>) -> Vec<[u8; 20]> {
+    let torrents = torrent_manager.read().await.torrents();
+    let mut info_hashes = Vec::new();
+    for torrent in torrents {
+        let torrent = torrent.read().await;
+        if !torrent.is_active() || torrent.is_private().await {
+            continue;
+        }
+        let swarms = torrent.metadata.read().await.swarm_info_hashes();
+        info_hashes.extend(
+            swarms
+                .into_iter()
+                .filter_map(|info_hash| <[u8; 20]>::try_from(info_hash).ok()),
+        );
+    }
+    info_hashes
+}
+
+async fn receive_announces(socket: Arc<LsdSocket>, torrent_manager: Arc<RwLock<TorrentManager>>) {
+    loop {
+        let (address, announce) = match socket.receive().await {
+            Ok(received) => received,
+            Err(e) => {
+                println!(
+                    "Local service discovery on {} stopped: {}",
+                    socket.group(),
+                    e
+                );
+                return;
+            }
+        };
+        let config = torrent_manager.read().await.config();
+        if !config.read().await.lsd_enabled {
+            continue;
+        }
+
+        for info_hash in announce.info_hashes {
+            let torrent = torrent_manager
+                .read()
+                .await
+                .get_torrent(&hex::encode(info_hash));
+            let Some(torrent) = torrent else {
+                continue;
+            };
+            let torrent = torrent.read().await;
+            if torrent.is_private().await {
+                continue;
+            }
+            println!("Found LAN peer {} through local service discovery", address);
+            torrent
+                .add_local_peer(Peer::new(address).in_swarm(&info_hash))
+                .await;
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use serde::Deserialize;
+use serde_bencode::value::Value;
+use sha1::{Digest, Sha1};
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+use crate::{
+    config::{Config, EncryptionPolicy},
+    message_handling::message_error::MessageError,
+    parsing::{
+        bencode::bencode_value_len,
+        magnet::MagnetLink,
+        parser::torrent_metadata::{TorrentMetadata, TorrentMetadataInfo},
+    },
+    torrent_management::peers::{dedup_peers, get_peers, Peer},
+    tracker::{AnnounceEvent, TransferStats},
+};
+
+use super::{
+    extension::{send_extended, EXTENDED_HANDSHAKE_ID, EXTENDED_MESSAGE_ID, UT_METADATA_ID},
+    peer_connection::{bytes_to_u32, read_n},
+    peer_handshake::{connect_with_handshake, Handshake},
+    peer_stream::PeerStream,
+    utp::socket::UtpSocket,
+};
+
+const METADATA_PIECE_SIZE: usize = 16 * 1024;
+// Refuse absurd sizes so a misbehaving peer can't make us allocate gigabytes
+const MAX_METADATA_SIZE: usize = 16 * 1024 * 1024;
+const PEER_METADATA_TIMEOUT: Duration = Duration::from_secs(20);
+
+const UT_METADATA_REQUEST: i64 = 0;
+const UT_METADATA_DATA: i64 = 1;
+const UT_METADATA_REJECT: i64 = 2;
+
+#[derive(Deserialize)]
+struct ExtendedHandshake {
+    #[serde(default)]
+    m: HashMap<String, Value>,
+    metadata_size: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct MetadataMessage {
+    msg_type: i64,
+    piece: i64,
+}
+
+// Resolves a magnet link into a complete .torrent file by asking its trackers for peers and
+// downloading the info dictionary from them with the metadata extension (BEP 9).
+pub async fn torrent_from_magnet(
+    magnet: &MagnetLink,
+    peer_id: &str,
+    configuration: &Config,
+    utp: Option<&UtpSocket>,
+) -> Result<Vec<u8>, String> {
+    let mut peers: Vec<Peer> = Vec::new();
+    for tracker in &magnet.trackers {
+        let mut metadata = magnet_tracker_metadata(magnet, tracker, peer_id);
+        // Nothing is known about the transfer before the metadata is
+        let transfer = TransferStats::default();
+        match get_peers(
+            &mut metadata,
+            Some(AnnounceEvent::Started),
+            &transfer,
+            configuration,
+        )
+        .await
+        {
+            Ok(announced_peers) => peers.extend(announced_peers),
+            Err(e) => println!("Tracker {} failed: {}", tracker, e),
+        }
+    }
+    dedup_peers(&mut peers);
+
+    if peers.is_empty() {
+        return Err("No peers found for magnet link".to_string());
+    }
+
+    for peer in &peers {
+        let fetch = fetch_metadata(
+            peer,
+            &magnet.info_hash,
+            peer_id,
+            configuration.encryption,
+            utp,
+        );
+        match tokio::time::timeout(PEER_METADATA_TIMEOUT, fetch).await {
+            Ok(Ok(info)) => return Ok(build_torrent_file(&magnet.trackers, &info)),
+            Ok(Err(e)) => println!("Failed to fetch metadata from {}: {}", peer.address, e),
+            Err(_) => println!("Timed out fetching metadata from {}", peer.address),
+        }
+    }
+
+    Err("None of the peers provided the torrent metadata".to_string())
+}
+
+// Downloads the bencoded info dictionary from a single peer and checks it against the info hash
+pub async fn fetch_metadata(
+    peer: &Peer,
+    info_hash: &[u8; 20],
+    peer_id: &str,
+    encryption: EncryptionPolicy,
+    utp: Option<&UtpSocket>,
+) -> Result<Vec<u8>, MessageError> {
+    let handshake =
+        Handshake::new(info_hash.to_vec(), peer_id.to_string()).with_extension_protocol();
+    let (mut stream, remote) = connect_with_handshake(peer, &handshake, encryption, utp).await?;
+    if !remote.supports_extension_protocol() {
+        return Err(MessageError::HandshakeError(
+            "Peer does not support the extension protocol".to_string(),
+        ));
+    }
+
+    let mut our_extensions = HashMap::new();
+    our_extensions.insert(b"ut_metadata".to_vec(), Value::Int(UT_METADATA_ID as i64));
+    let mut handshake_dict = HashMap::new();
+    handshake_dict.insert(b"m".to_vec(), Value::Dict(our_extensions));
+    send_extended(
+        &mut stream,
+        EXTENDED_HANDSHAKE_ID,
+        &Value::Dict(handshake_dict),
+        &[],
+    )
+    .await?;
+
+    let (remote_ut_metadata_id, metadata_size) = loop {
+        let (message_id, payload) = read_frame(&mut stream).await?;
+        if message_id != EXTENDED_MESSAGE_ID || payload.first() != Some(&EXTENDED_HANDSHAKE_ID) {
+            continue;
+        }
+        let remote_handshake: ExtendedHandshake = serde_bencode::from_bytes(&payload[1..])
+            .map_err(|e| MessageError::ConversionError(e.to_string()))?;
+        let ut_metadata_id = match remote_handshake.m.get("ut_metadata") {
+            Some(Value::Int(id)) if *id > 0 => *id as u8,
+            _ => {
+                return Err(MessageError::HandshakeError(
+                    "Peer does not support ut_metadata".to_string(),
+                ))
+            }
+        };
+        let metadata_size = remote_handshake
+            .metadata_size
+            .filter(|&size| size > 0 && size as usize <= MAX_METADATA_SIZE)
+            .ok_or(MessageError::InvalidResponse)? as usize;
+        break (ut_metadata_id, metadata_size);
+    };
+
+    let piece_count = metadata_size.div_ceil(METADATA_PIECE_SIZE);
+    let mut metadata = vec![0u8; metadata_size];
+
+    for piece in 0..piece_count {
+        let mut request = HashMap::new();
+        request.insert(b"msg_type".to_vec(), Value::Int(UT_METADATA_REQUEST));
+        request.insert(b"piece".to_vec(), Value::Int(piece as i64));
+        send_extended(
+            &mut stream,
+            remote_ut_metadata_id,
+            &Value::Dict(request),
+            &[],
+        )
+        .await?;
+
+        loop {
+            let (message_id, payload) = read_frame(&mut stream).await?;
+            if message_id != EXTENDED_MESSAGE_ID || payload.first() != Some(&UT_METADATA_ID) {
+                continue;
+            }
+
+            let body = &payload[1..];
+            let dict_length = bencode_value_len(body).ok_or(MessageError::InvalidResponse)?;
+            let message: MetadataMessage = serde_bencode::from_bytes(&body[..dict_length])
+                .map_err(|e| MessageError::ConversionError(e.to_string()))?;
+
+            match message.msg_type {
+                UT_METADATA_DATA if message.piece as usize == piece => {
+                    let data = &body[dict_length..];
+                    let start = piece * METADATA_PIECE_SIZE;
+                    let end = (start + data.len()).min(metadata_size);
+                    metadata[start..end].copy_from_slice(&data[..end - start]);
+                    break;
+                }
+                UT_METADATA_REJECT => return Err(MessageError::InvalidResponse),
+                _ => continue,
+            }
+        }
+    }
+
+    if Sha1::digest(&metadata).as_slice() != info_hash {
+        return Err(MessageError::HandshakeError(
+            "Metadata does not match the info hash".to_string(),
+        ));
+    }
+
+    Ok(metadata)
+}
+
+// Wraps a raw info dictionary into a .torrent file, keeping the info bytes untouched so the
+// info hash stays the same
+pub fn build_torrent_file(trackers: &[String], info: &[u8]) -> Vec<u8> {
+    let mut torrent = b"d".to_vec();
+    if let Some(announce) = trackers.first() {
+        torrent.extend_from_slice(format!("8:announce{}:", announce.len()).as_bytes());
+        torrent.extend_from_slice(announce.as_bytes());
+    }
+    if trackers.len() > 1 {
+        torrent.extend_from_slice(b"13:announce-listl");
+        for tracker in trackers {
+            torrent.extend_from_slice(format!("l{}:", tracker.len()).as_bytes());
+            torrent.extend_from_slice(tracker.as_bytes());
+            torrent.push(b'e');
+        }
+        torrent.push(b'e');
+    }
+    torrent.extend_from_slice(b"4:info");
+    torrent.extend_from_slice(info);
+    torrent.push(b'e');
+    torrent
+}
+
+// Trackers only need the info hash, our peer id and the announce url to hand out peers
+fn magnet_tracker_metadata(magnet: &MagnetLink, tracker: &str, peer_id: &str) -> TorrentMetadata {
+    TorrentMetadata {
+        info: TorrentMetadataInfo {
+            pieces: Vec::new(),
+            piece_length: 0,
+            length: 0,
+            name: magnet.display_name.clone().unwrap_or_default(),
+            files: None,
+            meta_version: None,
+            file_tree: None,
+            private: None,
+        },
+        info_hash: magnet.info_hash.to_vec(),
+        info_hash_v2: Vec::new(),
+        announce: tracker.to_string(),
+        url_list: Vec::new(),
+        piece_layers: HashMap::new(),
+        file_path: PathBuf::new(),
+        completed_path: None,
+        part_suffix: false,
+        category: None,
+        peer_id: peer_id.to_string(),
+        tracker_key: String::new(),
+        tracker_ids: HashMap::new(),
+    }
+}
+
+// Reads the next non keep-alive message as its id and payload
+async fn read_frame(stream: &mut PeerStream) -> Result<(u8, Vec<u8>), MessageError> {
+    loop {
+        let length = bytes_to_u32(&read_n(stream, 4).await?)?;
+        if length == 0 {
+            continue;
+        }
+        if length as usize > MAX_METADATA_SIZE {
+            return Err(MessageError::InvalidResponse);
+        }
+        let mut message = read_n(stream, length).await?;
+        let payload = message.split_off(1);
+        return Ok((message[0], payload));
+    }
+}
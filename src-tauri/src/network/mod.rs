@@ -1,2 +1,17 @@
+pub mod extension;
+pub mod fast;
+pub mod local_address;
+pub mod lsd;
+pub mod metadata_exchange;
+pub mod mse;
 pub mod peer_connection;
-pub mod peer_handshake;
\ No newline at end of file
+pub mod peer_handshake;
+pub mod peer_id;
+pub mod peer_listener;
+pub mod peer_stream;
+pub mod pex;
+pub mod port_mapping;
+pub mod rate_limit;
+pub mod transport;
+pub mod utp;
+pub mod web_seed;
@@ -0,0 +1,321 @@
+use num_bigint::BigUint;
+use rand::{Rng, RngCore};
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::{config::EncryptionPolicy, message_handling::message_error::MessageError};
+
+use super::{peer_stream::PeerStream, transport::BoxedTransport};
+
+// Message stream encryption (MSE/PE): a Diffie-Hellman exchange followed by RC4, negotiated
+// before the BitTorrent handshake. The torrent's info hash is the shared secret that proves both
+// sides want the same torrent without sending it in the clear.
+
+// 768 bit safe prime and generator fixed by the spec
+const PRIME: &[u8; 192] = b"FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F14374FE1356D6D51C245E485B576625E7EC6F44C42E9A63A36210000000000090563";
+const GENERATOR: u32 = 2;
+const KEY_SIZE: usize = 96;
+const PRIVATE_KEY_SIZE: usize = 20;
+const MAX_PAD_SIZE: usize = 512;
+// Verification constant, eight zero bytes that show the other side derived the same key
+const VC: [u8; 8] = [0; 8];
+// The start of the RC4 keystream is weak and thrown away by both sides
+const KEYSTREAM_DISCARD: usize = 1024;
+
+pub const CRYPTO_PLAINTEXT: u32 = 0x01;
+pub const CRYPTO_RC4: u32 = 0x02;
+
+// Plain RC4, MSE uses one keystream per direction
+#[derive(Clone)]
+pub struct Rc4 {
+    state: [u8; 256],
+    i: u8,
+    j: u8,
+}
+
+impl Rc4 {
+    pub fn new(key: &[u8]) -> Rc4 {
+        let mut state = [0u8; 256];
+        for (index, value) in state.iter_mut().enumerate() {
+            *value = index as u8;
+        }
+        let mut j: u8 = 0;
+        for i in 0..256 {
+            j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+            state.swap(i, j as usize);
+        }
+        Rc4 { state, i: 0, j: 0 }
+    }
+
+    // Encrypts or decrypts `data` in place
+    pub fn apply(&mut self, data: &mut [u8]) {
+        for byte in data.iter_mut() {
+            self.i = self.i.wrapping_add(1);
+            self.j = self.j.wrapping_add(self.state[self.i as usize]);
+            self.state.swap(self.i as usize, self.j as usize);
+            let index = self.state[self.i as usize].wrapping_add(self.state[self.j as usize]);
+            *byte ^= self.state[index as usize];
+        }
+    }
+
+    // Advances the keystream by `count` bytes
+    pub fn skip(&mut self, count: usize) {
+        self.apply(&mut vec![0; count]);
+    }
+}
+
+// What we offer, in `crypto_provide`, for a given policy
+pub fn crypto_provide(policy: EncryptionPolicy) -> u32 {
+    match policy {
+        EncryptionPolicy::Disabled => CRYPTO_PLAINTEXT,
+        EncryptionPolicy::Enabled => CRYPTO_PLAINTEXT | CRYPTO_RC4,
+        EncryptionPolicy::Required => CRYPTO_RC4,
+    }
+}
+
+// A plaintext connection starts with the BitTorrent handshake, anything else is taken to be the
+// public key of an MSE handshake
+pub fn is_plaintext_handshake(first_bytes: &[u8]) -> bool {
+    let pstr = crate::config::DEFAULT_PSTR.as_bytes();
+    first_bytes.len() > pstr.len()
+        && first_bytes[0] as usize == pstr.len()
+        && &first_bytes[1..=pstr.len()] == pstr
+}
+
+struct KeyPair {
+    private: BigUint,
+    public: [u8; KEY_SIZE],
+}
+
+impl KeyPair {
+    fn generate() -> KeyPair {
+        let mut private = [0u8; PRIVATE_KEY_SIZE];
+        rand::thread_rng().fill_bytes(&mut private);
+        let private = BigUint::from_bytes_be(&private);
+        let public = BigUint::from(GENERATOR).modpow(&private, &prime());
+        KeyPair {
+            public: to_key_bytes(&public),
+            private,
+        }
+    }
+
+    fn shared_secret(&self, remote_public: &[u8]) -> [u8; KEY_SIZE] {
+        let remote_public = BigUint::from_bytes_be(remote_public);
+        to_key_bytes(&remote_public.modpow(&self.private, &prime()))
+    }
+}
+
+fn prime() -> BigUint {
+    BigUint::parse_bytes(PRIME, 16).expect("The MSE prime is valid hex")
+}
+
+// Keys are sent as fixed size big endian numbers
+fn to_key_bytes(number: &BigUint) -> [u8; KEY_SIZE] {
+    let bytes = number.to_bytes_be();
+    let mut key = [0u8; KEY_SIZE];
+    key[KEY_SIZE - bytes.len()..].copy_from_slice(&bytes);
+    key
+}
+
+fn hash(parts: &[&[u8]]) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+fn keystream(name: &[u8], secret: &[u8], info_hash: &[u8]) -> Rc4 {
+    let mut rc4 = Rc4::new(&hash(&[name, secret, info_hash]));
+    rc4.skip(KEYSTREAM_DISCARD);
+    rc4
+}
+
+fn random_pad() -> Vec<u8> {
+    let mut rng = rand::thread_rng();
+    let mut pad = vec![0u8; rng.gen_range(0..=MAX_PAD_SIZE)];
+    rng.fill_bytes(&mut pad);
+    pad
+}
+
+fn handshake_error(message: &str) -> MessageError {
+    MessageError::HandshakeError(format!("Encryption: {}", message))
+}
+
+// Exactly one method, and one we offered
+fn check_selection(selected: u32, provided: u32) -> Result<(), MessageError> {
+    if selected.count_ones() == 1 && selected & provided != 0 {
+        Ok(())
+    } else {
+        Err(handshake_error("peer selected a method we did not offer"))
+    }
+}
+
+async fn read_decrypted(
+    stream: &mut BoxedTransport,
+    cipher: &mut Rc4,
+    size: usize,
+) -> Result<Vec<u8>, MessageError> {
+    let mut buffer = vec![0u8; size];
+    stream.read_exact(&mut buffer).await?;
+    cipher.apply(&mut buffer);
+    Ok(buffer)
+}
+
+async fn read_u16(stream: &mut BoxedTransport, cipher: &mut Rc4) -> Result<usize, MessageError> {
+    let bytes = read_decrypted(stream, cipher, 2).await?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]) as usize)
+}
+
+async fn read_u32(stream: &mut BoxedTransport, cipher: &mut Rc4) -> Result<u32, MessageError> {
+    let bytes = read_decrypted(stream, cipher, 4).await?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+// Skips the other side's random padding by reading until `pattern` has been seen
+async fn synchronize(stream: &mut BoxedTransport, pattern: &[u8]) -> Result<(), MessageError> {
+    let mut window = Vec::with_capacity(MAX_PAD_SIZE + pattern.len());
+    let mut byte = [0u8; 1];
+    while window.len() < MAX_PAD_SIZE + pattern.len() {
+        stream.read_exact(&mut byte).await?;
+        window.push(byte[0]);
+        if window.ends_with(pattern) {
+            return Ok(());
+        }
+    }
+    Err(handshake_error("could not synchronize with the peer"))
+}
+
+// Runs the outgoing side of the handshake on a fresh connection. The BitTorrent handshake is sent
+// afterwards through the returned stream rather than as initial payload.
+pub async fn initiate(
+    mut stream: BoxedTransport,
+    info_hash: &[u8],
+    policy: EncryptionPolicy,
+) -> Result<PeerStream, MessageError> {
+    let keys = KeyPair::generate();
+    let mut message = keys.public.to_vec();
+    message.extend(random_pad());
+    stream.write_all(&message).await?;
+
+    let mut remote_public = [0u8; KEY_SIZE];
+    stream.read_exact(&mut remote_public).await?;
+    let secret = keys.shared_secret(&remote_public);
+
+    let mut encrypt = keystream(b"keyA", &secret, info_hash);
+    let mut decrypt = keystream(b"keyB", &secret, info_hash);
+
+    let provided = crypto_provide(policy);
+    let mut message = hash(&[b"req1", &secret]).to_vec();
+    let req2 = hash(&[b"req2", info_hash]);
+    let req3 = hash(&[b"req3", &secret]);
+    message.extend(req2.iter().zip(req3.iter()).map(|(a, b)| a ^ b));
+    let mut negotiation = VC.to_vec();
+    negotiation.extend(provided.to_be_bytes());
+    negotiation.extend(0u16.to_be_bytes()); // no padding
+    negotiation.extend(0u16.to_be_bytes()); // no initial payload
+    encrypt.apply(&mut negotiation);
+    message.extend(negotiation);
+    stream.write_all(&message).await?;
+
+    // The answer starts with the encrypted verification constant, after the peer's padding
+    let mut expected_vc = VC.to_vec();
+    decrypt.apply(&mut expected_vc);
+    synchronize(&mut stream, &expected_vc).await?;
+
+    let selected = read_u32(&mut stream, &mut decrypt).await?;
+    check_selection(selected, provided)?;
+    let pad_size = read_u16(&mut stream, &mut decrypt).await?;
+    if pad_size > MAX_PAD_SIZE {
+        return Err(handshake_error("padding too long"));
+    }
+    read_decrypted(&mut stream, &mut decrypt, pad_size).await?;
+
+    Ok(if selected == CRYPTO_RC4 {
+        PeerStream::encrypted(stream, encrypt, decrypt)
+    } else {
+        PeerStream::plaintext(stream)
+    })
+}
+
+// Runs the incoming side of the handshake. `received` holds the bytes already read to tell the
+// connection apart from a plaintext one, `info_hashes` the torrents the peer may be asking for.
+// Returns the stream and the info hash the peer proved it knows.
+pub async fn accept(
+    mut stream: BoxedTransport,
+    received: Vec<u8>,
+    info_hashes: &[[u8; 20]],
+    policy: EncryptionPolicy,
+) -> Result<(PeerStream, [u8; 20]), MessageError> {
+    if received.len() > KEY_SIZE {
+        return Err(handshake_error("unexpected data before the public key"));
+    }
+    let mut remote_public = received;
+    let already_read = remote_public.len();
+    remote_public.resize(KEY_SIZE, 0);
+    stream
+        .read_exact(&mut remote_public[already_read..])
+        .await?;
+
+    let keys = KeyPair::generate();
+    let mut message = keys.public.to_vec();
+    message.extend(random_pad());
+    stream.write_all(&message).await?;
+    let secret = keys.shared_secret(&remote_public);
+
+    synchronize(&mut stream, &hash(&[b"req1", &secret])).await?;
+
+    // req2 ^ req3 tells us which torrent without revealing its info hash
+    let mut obfuscated = [0u8; 20];
+    stream.read_exact(&mut obfuscated).await?;
+    let req3 = hash(&[b"req3", &secret]);
+    let info_hash = info_hashes
+        .iter()
+        .find(|info_hash| {
+            let req2 = hash(&[b"req2", info_hash.as_slice()]);
+            req2.iter()
+                .zip(req3.iter())
+                .map(|(a, b)| a ^ b)
+                .eq(obfuscated.iter().copied())
+        })
+        .copied()
+        .ok_or_else(|| handshake_error("unknown info hash"))?;
+
+    let mut decrypt = keystream(b"keyA", &secret, &info_hash);
+    let mut encrypt = keystream(b"keyB", &secret, &info_hash);
+
+    if read_decrypted(&mut stream, &mut decrypt, VC.len()).await? != VC {
+        return Err(handshake_error("invalid verification constant"));
+    }
+    let provided = read_u32(&mut stream, &mut decrypt).await?;
+    let pad_size = read_u16(&mut stream, &mut decrypt).await?;
+    if pad_size > MAX_PAD_SIZE {
+        return Err(handshake_error("padding too long"));
+    }
+    read_decrypted(&mut stream, &mut decrypt, pad_size).await?;
+    let initial_payload_size = read_u16(&mut stream, &mut decrypt).await?;
+    let initial_payload = read_decrypted(&mut stream, &mut decrypt, initial_payload_size).await?;
+
+    // Prefer RC4 whenever the peer offers it
+    let selected = crypto_provide(policy) & provided;
+    let selected = if selected & CRYPTO_RC4 != 0 {
+        CRYPTO_RC4
+    } else if selected & CRYPTO_PLAINTEXT != 0 {
+        CRYPTO_PLAINTEXT
+    } else {
+        return Err(handshake_error("no common encryption method"));
+    };
+
+    let mut answer = VC.to_vec();
+    answer.extend(selected.to_be_bytes());
+    answer.extend(0u16.to_be_bytes()); // no padding
+    encrypt.apply(&mut answer);
+    stream.write_all(&answer).await?;
+
+    let stream = if selected == CRYPTO_RC4 {
+        PeerStream::encrypted(stream, encrypt, decrypt)
+    } else {
+        PeerStream::plaintext(stream)
+    };
+    Ok((stream.with_buffered(initial_payload), info_hash))
+}
@@ -1,69 +1,42 @@
 use byteorder::{BigEndian, ReadBytesExt};
-use std::error::Error;
-use std::sync::Arc;
-use tokio::net::TcpStream;
-use tokio::{io::AsyncReadExt, sync::RwLock};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, WriteHalf};
 
-use super::{peer_handshake::initiate_handshake, peers};
-use crate::torrent_management::message;
-use crate::{
-    message_handling::{message_error::MessageError, message_handling::message_handler},
-    parsing::parser::torrent_metadata::TorrentMetadata,
-};
+use super::peer_stream::PeerStream;
+use crate::message_handling::{message_error::MessageError, message_handling::message_handler};
+use crate::torrent_management::{message, peers, torrent::Torrent};
 
-// Initiates connections to each peer in the given peer list,
-// and attempts to start downloading metadata from them.
-pub async fn connect_to_peers(
-    peers: &Arc<Vec<peers::Peer>>,
-    metadata: Arc<RwLock<TorrentMetadata>>,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let tasks: Vec<_> = peers
-        .iter()
-        .map(|peer| {
-            let metadata = Arc::clone(&metadata);
-            let peer = peer.clone(); // Clone the peer to move ownership into the async block.
-            spawn_peer_handling_task(peer.clone(), metadata)
-        })
-        .collect();
+// What a registered connection sends through, its read half belongs to the task reading it
+pub type PeerWriter = WriteHalf<PeerStream>;
 
-    let _ = futures::future::join_all(tasks).await;
-    Ok(())
-}
-
-async fn spawn_peer_handling_task(peer: peers::Peer, metadata: Arc<RwLock<TorrentMetadata>>) {
-    let handshake_metadata = metadata.clone();
-    let mut handshake_metadata_guard = handshake_metadata.write().await;
-    let mut stream = match initiate_handshake(&peer, &handshake_metadata_guard).await {
-        Ok(stream) => {
-            println!("TcpStream connected!");
-            stream
-        }
-        Err(e) => {
-            println!("Error: {}", e);
-            return;
-        }
-    };
-
-    let bitfield = match receive_message(&mut stream).await {
-        Ok(m) => m,
-        Err(e) => {
-            println!("Error: {:?}", e);
-            return;
-        }
-    };
-
-    match message_handler(bitfield, &mut *handshake_metadata_guard, 0).await {
-        Ok(()) => {
-            println!("Message handled successfully");
-        }
-        Err(e) => {
-            println!("Error: {:?}", e);
+// Reads the peer's messages for as long as the connection lasts and hands each to the message
+// handler. Messages we don't know are skipped, anything else that goes wrong ends the connection.
+pub async fn read_messages<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    peer: &peers::Peer,
+    torrent: &mut Torrent,
+) -> Result<(), MessageError> {
+    loop {
+        let message = match receive_message(reader).await {
+            Ok(message) => message,
+            Err(MessageError::UnknownMessage) => continue,
+            Err(e) => return Err(e),
+        };
+        // Not reading on holds the peer back through TCP flow control
+        if let message::Message::Piece(_, _, data) = &message {
+            torrent
+                .rate_limits()
+                .download
+                .acquire(data.len() as u64)
+                .await;
         }
-    };
+        message_handler(message, peer, torrent).await?;
+    }
 }
 
 // Handles the reception of any message from the peer
-async fn receive_message(stream: &mut TcpStream) -> Result<message::Message, MessageError> {
+pub async fn receive_message<R: AsyncRead + Unpin>(
+    stream: &mut R,
+) -> Result<message::Message, MessageError> {
     let message_size = bytes_to_u32(&read_n(stream, 4).await?)?;
 
     if message_size > 0 {
@@ -75,16 +48,34 @@ async fn receive_message(stream: &mut TcpStream) -> Result<message::Message, Mes
     }
 }
 
-fn bytes_to_u32(bytes: &[u8]) -> Result<u32, MessageError> {
+pub fn bytes_to_u32(bytes: &[u8]) -> Result<u32, MessageError> {
     let mut rdr = std::io::Cursor::new(bytes);
     ReadBytesExt::read_u32::<BigEndian>(&mut rdr)
         .map_err(|e| MessageError::ConversionError(e.to_string()))
 }
 
-pub async fn read_n(stream: &mut TcpStream, nbytes: u32) -> Result<Vec<u8>, MessageError> {
+pub async fn read_n<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    nbytes: u32,
+) -> Result<Vec<u8>, MessageError> {
     let mut buffer = vec![0; nbytes as usize];
     match stream.read_exact(&mut buffer).await {
         Ok(_) => Ok(buffer),
         Err(e) => Err(MessageError::ConversionError(e.to_string())),
     }
 }
+
+// Sends a message with the given id and payload
+pub async fn send_message<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    message_id: u8,
+    payload: &[u8],
+) -> Result<(), MessageError> {
+    let mut message = Vec::with_capacity(5 + payload.len());
+    message.extend_from_slice(&(1 + payload.len() as u32).to_be_bytes());
+    message.push(message_id);
+    message.extend_from_slice(payload);
+
+    stream.write_all(&message).await?;
+    Ok(())
+}
@@ -1,31 +1,89 @@
-use std::net::{SocketAddr, SocketAddrV4};
-
-use tokio::{io::AsyncWriteExt, net::TcpStream};
+use tokio::io::AsyncWriteExt;
 
 use crate::{
-    config, message_handling::message_error::MessageError,
+    config::{self, EncryptionPolicy},
+    message_handling::message_error::MessageError,
     parsing::parser::torrent_metadata::TorrentMetadata,
+    torrent_management::peers,
+};
+
+use super::{
+    mse,
+    peer_connection::read_n,
+    peer_id::{identify_client, ClientInfo},
+    peer_stream::PeerStream,
+    transport::{connect_transport, BoxedTransport},
+    utp::socket::UtpSocket,
 };
 
-use super::{peer_connection::read_n, peers};
+// Reserved bit advertising the extension protocol (BEP 10), 20th bit from the right
+pub const EXTENSION_PROTOCOL_BIT: (usize, u8) = (5, 0x10);
+// Reserved bit advertising the Fast extension (BEP 6), third bit from the right
+pub const FAST_EXTENSION_BIT: (usize, u8) = (7, 0x04);
 
 // Struct representing the handshake process with a specific peer.
-struct Handshake {
-    pstr: String,
-    info_hash: Vec<u8>, //pass info metadata to extract these fields - string may not be correct. Also pstr should maybe be initialized with a constant..?
-    peer_id: String,
+pub struct Handshake {
+    pub pstr: String,
+    pub reserved: [u8; 8],
+    pub info_hash: Vec<u8>,
+    pub peer_id: String,
+}
+
+// What the remote side told us about itself in its handshake
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteHandshake {
+    pub reserved: [u8; 8],
+    pub info_hash: Vec<u8>,
+    pub peer_id: Vec<u8>,
+}
+
+impl RemoteHandshake {
+    pub fn client(&self) -> Option<ClientInfo> {
+        identify_client(&self.peer_id)
+    }
+
+    pub fn supports_extension_protocol(&self) -> bool {
+        let (byte, mask) = EXTENSION_PROTOCOL_BIT;
+        self.reserved[byte] & mask != 0
+    }
+
+    pub fn supports_fast_extension(&self) -> bool {
+        let (byte, mask) = FAST_EXTENSION_BIT;
+        self.reserved[byte] & mask != 0
+    }
 }
 
 impl Handshake {
+    pub fn new(info_hash: Vec<u8>, peer_id: String) -> Self {
+        Handshake {
+            pstr: config::DEFAULT_PSTR.to_string(),
+            reserved: [0u8; 8],
+            info_hash,
+            peer_id,
+        }
+    }
+
+    pub fn with_extension_protocol(mut self) -> Self {
+        let (byte, mask) = EXTENSION_PROTOCOL_BIT;
+        self.reserved[byte] |= mask;
+        self
+    }
+
+    pub fn with_fast_extension(mut self) -> Self {
+        let (byte, mask) = FAST_EXTENSION_BIT;
+        self.reserved[byte] |= mask;
+        self
+    }
+
     // Converts Handshake instance into a byte vector representation
     // The vector is used to send handshake messages over the network
-    fn to_bytes(&self) -> Vec<u8> {
+    pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes: Vec<u8> = Vec::new();
         bytes.push(self.pstr.len() as u8); // length of protocol id
         bytes.append(&mut self.pstr.as_bytes().to_vec()); // protocol id
-        bytes.append(&mut vec![0u8; 8]); // 8 bytes used to indicate extensions we dont support yet
-        bytes.append(&mut self.info_hash.clone()); // 8 bytes used to indicate extensions we dont support yet
-        bytes.append(&mut self.peer_id.as_bytes().to_vec()); // 8 bytes used to indicate extensions we dont support yet
+        bytes.extend_from_slice(&self.reserved); // 8 bytes used to indicate supported extensions
+        bytes.append(&mut self.info_hash.clone()); // 20 byte info hash of the torrent
+        bytes.append(&mut self.peer_id.as_bytes().to_vec()); // 20 byte peer id
         bytes
     }
 }
@@ -34,58 +92,97 @@ impl Handshake {
 pub async fn initiate_handshake(
     peer: &peers::Peer,
     metadata: &TorrentMetadata,
-) -> Result<tokio::net::TcpStream, std::io::Error> {
-    let socket = SocketAddr::from(SocketAddrV4::new(peer.ip, peer.port));
-
-    // Use tokio TcpStream to asynchronously establish a connection
-    let tcp_stream_result = tokio::net::TcpStream::connect(socket).await;
-
-    match tcp_stream_result {
-        Ok(mut tcp_stream) => {
-            let configuration = config::Config::new();
-            let default_pstr = configuration.default_pstr;
-            let handshake = Handshake {
-                pstr: default_pstr.to_string(),
-                info_hash: metadata.info_hash.to_owned(),
-                peer_id: metadata.peer_id.to_owned(),
-            };
-            let handshake_bytes = handshake.to_bytes().clone();
-            AsyncWriteExt::write_all(&mut tcp_stream, handshake_bytes.as_slice()).await?;
-            println!("Awaiting response");
-            match receive_handshake(&mut tcp_stream, metadata.info_hash.to_owned()).await {
-                Ok(_) => Ok(tcp_stream),
-                Err(e) => Err(std::io::Error::new(
+    encryption: EncryptionPolicy,
+) -> Result<PeerStream, std::io::Error> {
+    let handshake = Handshake::new(metadata.info_hash.to_owned(), metadata.peer_id.to_owned());
+    connect_with_handshake(peer, &handshake, encryption, None)
+        .await
+        .map(|(stream, _)| stream)
+}
+
+// Connects to the peer and exchanges `handshake`, returning the stream and the peer's handshake.
+// With a uTP socket, TCP and uTP are tried at once and the first to connect is used. Unless
+// encryption is disabled the connection is encrypted first, with `Enabled` falling back to a new
+// plaintext connection when the peer doesn't speak MSE.
+pub async fn connect_with_handshake(
+    peer: &peers::Peer,
+    handshake: &Handshake,
+    encryption: EncryptionPolicy,
+    utp: Option<&UtpSocket>,
+) -> Result<(PeerStream, RemoteHandshake), std::io::Error> {
+    let transport = connect(peer, utp).await?;
+    let mut stream = match encryption {
+        EncryptionPolicy::Disabled => PeerStream::plaintext(transport),
+        _ => match mse::initiate(transport, &handshake.info_hash, encryption).await {
+            Ok(stream) => stream,
+            Err(e) if encryption == EncryptionPolicy::Required => {
+                return Err(std::io::Error::new(
                     std::io::ErrorKind::ConnectionRefused,
                     e,
-                )),
+                ))
             }
-        }
-        // Handles case when TCP stream creation fails
-        Err(_) => Err(std::io::Error::new(
+            Err(e) => {
+                println!("Falling back to plaintext for {}: {}", peer.address, e);
+                PeerStream::plaintext(connect(peer, utp).await?)
+            }
+        },
+    };
+
+    stream.write_all(&handshake.to_bytes()).await?;
+    println!("Awaiting response");
+    match receive_handshake(&mut stream, handshake.info_hash.to_owned()).await {
+        Ok(remote) => Ok((stream, remote)),
+        Err(e) => Err(std::io::Error::new(
             std::io::ErrorKind::ConnectionRefused,
-            "Couldn't connect to the peer",
+            e,
         )),
     }
 }
 
+async fn connect(
+    peer: &peers::Peer,
+    utp: Option<&UtpSocket>,
+) -> Result<BoxedTransport, std::io::Error> {
+    connect_transport(peer.address, utp).await.map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::ConnectionRefused,
+            "Couldn't connect to the peer",
+        )
+    })
+}
+
 // Received handshake from the peer
 async fn receive_handshake(
-    stream: &mut TcpStream,
+    stream: &mut PeerStream,
     our_info_hash: Vec<u8>,
-) -> Result<(), MessageError> {
-    // Reads different portions of the handshake message
-    let pstrlen = read_n(stream, 1).await?;
-    read_n(stream, pstrlen[0] as u32).await?; // ignore pstr
-    read_n(stream, 8).await?; // ignore reserved
-    let info_hash = read_n(stream, 20).await?;
-    let _peer_id = read_n(stream, 20).await?;
+) -> Result<RemoteHandshake, MessageError> {
+    let remote = read_handshake(stream).await?;
 
     // Case where received info hash is not same as ours
-    if info_hash != our_info_hash {
+    if remote.info_hash != our_info_hash {
         Err(MessageError::HandshakeError(
             "Invalid info hash".to_string(),
         ))
     } else {
-        Ok(())
+        Ok(remote)
     }
 }
+
+// Reads a handshake without checking the info hash, incoming connections tell us which torrent
+// they want through it
+pub async fn read_handshake(stream: &mut PeerStream) -> Result<RemoteHandshake, MessageError> {
+    // Reads different portions of the handshake message
+    let pstrlen = read_n(stream, 1).await?;
+    read_n(stream, pstrlen[0] as u32).await?; // ignore pstr
+    let reserved = read_n(stream, 8).await?;
+    let info_hash = read_n(stream, 20).await?;
+    let peer_id = read_n(stream, 20).await?;
+
+    Ok(RemoteHandshake {
+        reserved: reserved
+            .try_into()
+            .map_err(|_| MessageError::HandshakeError("Invalid reserved bytes".to_string()))?,
+        info_hash,
+        peer_id,
+    })
+}
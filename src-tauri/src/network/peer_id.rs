@@ -0,0 +1,175 @@
+use rand::{distributions::Alphanumeric, Rng};
+use std::fmt;
+
+pub const PEER_ID_LENGTH: usize = 20;
+
+// Builds an Azureus-style peer id: the client prefix (`-PR0001-`) followed by random characters.
+// Generated once per session and used for every handshake and announce.
+pub fn generate_peer_id(prefix: &str) -> String {
+    let prefix: String = prefix.chars().take(PEER_ID_LENGTH).collect();
+    let random: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(PEER_ID_LENGTH - prefix.len())
+        .map(char::from)
+        .collect();
+    prefix + &random
+}
+
+// The client software a remote peer identifies as.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientInfo {
+    pub name: String,
+    pub version: Option<String>,
+}
+
+impl fmt::Display for ClientInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.version {
+            Some(version) => write!(f, "{} {}", self.name, version),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+// Azureus-style two letter client codes, `-XXVVVV-`
+const AZUREUS_CLIENTS: [(&str, &str); 22] = [
+    ("PR", "Pirate"),
+    ("qB", "qBittorrent"),
+    ("TR", "Transmission"),
+    ("UT", "µTorrent"),
+    ("UM", "µTorrent Mac"),
+    ("UW", "µTorrent Web"),
+    ("BT", "BitTorrent"),
+    ("lt", "libTorrent (Rakshasa)"),
+    ("LT", "libtorrent (Rasterbar)"),
+    ("DE", "Deluge"),
+    ("AZ", "Vuze"),
+    ("BI", "BiglyBT"),
+    ("KT", "KTorrent"),
+    ("FD", "Free Download Manager"),
+    ("BC", "BitComet"),
+    ("XL", "Xunlei"),
+    ("SD", "Thunder"),
+    ("TL", "Tribler"),
+    ("WW", "WebTorrent"),
+    ("WD", "WebTorrent Desktop"),
+    ("FW", "FrostWire"),
+    ("AG", "Ares"),
+];
+
+// Shadow-style single letter client codes, `XVVV-----`
+const SHADOW_CLIENTS: [(char, &str); 6] = [
+    ('A', "ABC"),
+    ('M', "Mainline"),
+    ('O', "Osprey Permaseed"),
+    ('Q', "BTQueue"),
+    ('S', "Shadow's client"),
+    ('T', "BitTornado"),
+];
+
+// Works out which client sent `peer_id`, None for styles we don't recognise
+pub fn identify_client(peer_id: &[u8]) -> Option<ClientInfo> {
+    if peer_id.len() != PEER_ID_LENGTH {
+        return None;
+    }
+
+    if peer_id[0] == b'-' && peer_id[7] == b'-' {
+        let code = std::str::from_utf8(&peer_id[1..3]).ok()?;
+        let version = std::str::from_utf8(&peer_id[3..7]).ok()?;
+        let name = AZUREUS_CLIENTS
+            .iter()
+            .find(|(known_code, _)| *known_code == code)
+            .map(|(_, name)| name.to_string())
+            .unwrap_or_else(|| format!("Unknown ({})", code));
+        let version = if code == "TR" {
+            transmission_version(version)
+        } else {
+            azureus_version(version)
+        };
+        return Some(ClientInfo { name, version });
+    }
+
+    // Mainline writes its version as `M7-10-3-`
+    if peer_id[0] == b'M'
+        && peer_id[1].is_ascii_digit()
+        && peer_id[7] == b'-'
+        && peer_id[1..8]
+            .iter()
+            .all(|&character| character.is_ascii_digit() || character == b'-')
+    {
+        let version: Vec<&str> = std::str::from_utf8(&peer_id[1..8])
+            .ok()?
+            .split('-')
+            .filter(|part| !part.is_empty())
+            .collect();
+        return Some(ClientInfo {
+            name: "Mainline".to_string(),
+            version: Some(version.join(".")),
+        });
+    }
+
+    let (_, name) = SHADOW_CLIENTS
+        .iter()
+        .find(|(code, _)| *code as u8 == peer_id[0])?;
+    Some(ClientInfo {
+        name: name.to_string(),
+        version: Some(shadow_version(peer_id)?),
+    })
+}
+
+// Up to five version characters padded with `-`, then `---`. Random ids that merely start with
+// a known letter don't follow that pattern.
+fn shadow_version(peer_id: &[u8]) -> Option<String> {
+    if &peer_id[6..9] != b"---" {
+        return None;
+    }
+    let (version, padding) = peer_id[1..6].split_at(
+        peer_id[1..6]
+            .iter()
+            .position(|&character| character == b'-')
+            .unwrap_or(5),
+    );
+    if version.is_empty() || padding.iter().any(|&character| character != b'-') {
+        return None;
+    }
+    let digits: Vec<String> = version
+        .iter()
+        .map(|&character| version_digit(character).map(|digit| digit.to_string()))
+        .collect::<Option<Vec<String>>>()?;
+    Some(digits.join("."))
+}
+
+// `4500` is 4.5.0, trailing zero components past major.minor are dropped
+fn azureus_version(version: &str) -> Option<String> {
+    let mut digits: Vec<u32> = version
+        .bytes()
+        .map(version_digit)
+        .collect::<Option<Vec<u32>>>()?;
+    while digits.len() > 2 && digits.last() == Some(&0) {
+        digits.pop();
+    }
+    Some(
+        digits
+            .iter()
+            .map(|digit| digit.to_string())
+            .collect::<Vec<String>>()
+            .join("."),
+    )
+}
+
+// Transmission uses `-TR2940-` for 2.94, a major digit and two minor digits
+fn transmission_version(version: &str) -> Option<String> {
+    let major = version.get(0..1)?.parse::<u32>().ok()?;
+    let minor = version.get(1..3)?.parse::<u32>().ok()?;
+    Some(format!("{}.{:02}", major, minor))
+}
+
+// Version characters are 0-9, then A-Z and a-z for larger numbers
+fn version_digit(character: u8) -> Option<u32> {
+    match character {
+        b'0'..=b'9' => Some((character - b'0') as u32),
+        b'A'..=b'Z' => Some((character - b'A') as u32 + 10),
+        b'a'..=b'z' => Some((character - b'a') as u32 + 36),
+        _ => None,
+    }
+}
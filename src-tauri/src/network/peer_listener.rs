@@ -0,0 +1,227 @@
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, UdpSocket},
+    sync::RwLock,
+};
+
+use crate::{
+    config::EncryptionPolicy,
+    message_handling::message_error::MessageError,
+    torrent_management::{peers::Peer, torrent_manager::TorrentManager},
+};
+
+use super::{
+    mse,
+    peer_handshake::{read_handshake, Handshake},
+    peer_stream::PeerStream,
+    transport::BoxedTransport,
+    utp::socket::UtpSocket,
+};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const LISTEN_BACKLOG: i32 = 128;
+
+// Accepts incoming peer connections on `port` and hands them to the torrent they ask for.
+// With IPv6 enabled a single dual-stack socket takes both IPv4 and IPv6 peers, falling back to
+// IPv4 only where the host has no IPv6.
+pub async fn start_peer_listener(
+    torrent_manager: Arc<RwLock<TorrentManager>>,
+    port: u16,
+    ipv6_enabled: bool,
+) -> Result<SocketAddr, String> {
+    let listener = if ipv6_enabled {
+        bind_dual_stack(port).or_else(|_| bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))))
+    } else {
+        bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)))
+    }
+    .map_err(|e| format!("Failed to bind peer listener on port {}: {}", port, e))?;
+    let local_addr = listener
+        .local_addr()
+        .map_err(|e| format!("Failed to start peer listener: {}", e))?;
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, address)) => {
+                    spawn_incoming(Arc::clone(&torrent_manager), Box::new(stream), address)
+                }
+                Err(e) => println!("Failed to accept peer connection: {}", e),
+            }
+        }
+    });
+
+    Ok(local_addr)
+}
+
+// Accepts uTP peers on the UDP side of `port`, the same way as TCP ones
+pub async fn start_utp_listener(
+    torrent_manager: Arc<RwLock<TorrentManager>>,
+    port: u16,
+    ipv6_enabled: bool,
+) -> Result<UtpSocket, String> {
+    let socket = if ipv6_enabled {
+        bind_udp_dual_stack(port)
+            .or_else(|_| bind_udp(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))))
+    } else {
+        bind_udp(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)))
+    }
+    .map_err(|e| format!("Failed to bind uTP socket on port {}: {}", port, e))?;
+    let utp = UtpSocket::from_udp(socket);
+
+    let listener = utp.clone();
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, address)) => {
+                    spawn_incoming(Arc::clone(&torrent_manager), Box::new(stream), address)
+                }
+                Err(e) => {
+                    println!("uTP listener stopped: {}", e);
+                    return;
+                }
+            }
+        }
+    });
+
+    Ok(utp)
+}
+
+fn spawn_incoming(
+    torrent_manager: Arc<RwLock<TorrentManager>>,
+    transport: BoxedTransport,
+    address: SocketAddr,
+) {
+    tokio::spawn(async move {
+        let incoming = handle_incoming(torrent_manager, transport, address);
+        match tokio::time::timeout(HANDSHAKE_TIMEOUT, incoming).await {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => println!("Rejected peer {}: {}", address, e),
+            Err(_) => println!("Peer {} did not finish the handshake", address),
+        }
+    });
+}
+
+fn bind_udp_dual_stack(port: u16) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_only_v6(false)?;
+    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)).into())?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+fn bind_udp(address: SocketAddr) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::DGRAM,
+        Some(Protocol::UDP),
+    )?;
+    socket.bind(&address.into())?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+fn bind_dual_stack(port: u16) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_only_v6(false)?;
+    socket.set_reuse_address(true)?;
+    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)).into())?;
+    listen(socket)
+}
+
+fn bind(address: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    socket.set_reuse_address(true)?;
+    socket.bind(&address.into())?;
+    listen(socket)
+}
+
+fn listen(socket: Socket) -> std::io::Result<TcpListener> {
+    socket.listen(LISTEN_BACKLOG)?;
+    socket.set_nonblocking(true)?;
+    TcpListener::from_std(socket.into())
+}
+
+async fn handle_incoming(
+    torrent_manager: Arc<RwLock<TorrentManager>>,
+    mut stream: BoxedTransport,
+    address: SocketAddr,
+) -> Result<(), MessageError> {
+    let encryption = torrent_manager
+        .read()
+        .await
+        .config()
+        .read()
+        .await
+        .encryption;
+
+    // Enough of the start to tell a plaintext handshake from an MSE public key
+    let mut first_bytes = vec![0u8; 20];
+    stream.read_exact(&mut first_bytes).await?;
+    let (mut stream, encrypted_for) = if mse::is_plaintext_handshake(&first_bytes) {
+        if encryption == EncryptionPolicy::Required {
+            return Err(MessageError::HandshakeError(
+                "Plaintext connections are not allowed".to_string(),
+            ));
+        }
+        (
+            PeerStream::plaintext(stream).with_buffered(first_bytes),
+            None,
+        )
+    } else {
+        if encryption == EncryptionPolicy::Disabled {
+            return Err(MessageError::HandshakeError(
+                "Encrypted connections are not allowed".to_string(),
+            ));
+        }
+        let info_hashes = torrent_manager.read().await.info_hashes();
+        let (stream, info_hash) =
+            mse::accept(stream, first_bytes, &info_hashes, encryption).await?;
+        (stream, Some(info_hash))
+    };
+
+    let remote = read_handshake(&mut stream).await?;
+    if encrypted_for.is_some_and(|info_hash| info_hash.as_slice() != remote.info_hash) {
+        return Err(MessageError::HandshakeError(
+            "Info hash differs from the encryption handshake".to_string(),
+        ));
+    }
+
+    let torrent = torrent_manager
+        .read()
+        .await
+        .get_torrent(&hex::encode(&remote.info_hash))
+        .ok_or(MessageError::HandshakeError(
+            "Unknown info hash".to_string(),
+        ))?;
+    let torrent = torrent.read().await.clone();
+
+    if !torrent.is_active() {
+        return Err(MessageError::HandshakeError(
+            "Torrent is not running".to_string(),
+        ));
+    }
+    if torrent.is_at_peer_limit().await {
+        return Err(MessageError::HandshakeError("Too many peers".to_string()));
+    }
+
+    let peer_id = torrent.metadata.read().await.peer_id.clone();
+    let handshake = Handshake::new(remote.info_hash.clone(), peer_id)
+        .with_extension_protocol()
+        .with_fast_extension();
+    stream.write_all(&handshake.to_bytes()).await?;
+
+    // Either swarm of a hybrid torrent finds it, the peer stays in the one it asked for
+    let peer = Peer::new(address).in_swarm(&remote.info_hash);
+    torrent.register_connection(peer, stream, &remote).await;
+    Ok(())
+}
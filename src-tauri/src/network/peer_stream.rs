@@ -0,0 +1,116 @@
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::{
+    mse::Rc4,
+    transport::{BoxedTransport, TransportKind},
+};
+
+// A connection to a peer once the (optional) MSE handshake is done. Everything after that point,
+// starting with the BitTorrent handshake, goes through here and is encrypted when RC4 was
+// negotiated.
+pub struct PeerStream {
+    inner: BoxedTransport,
+    cipher: Option<StreamCipher>,
+    // Bytes that arrived during the MSE handshake and belong to the payload stream
+    buffered: Vec<u8>,
+}
+
+struct StreamCipher {
+    encrypt: Rc4,
+    decrypt: Rc4,
+}
+
+impl PeerStream {
+    pub fn plaintext(inner: BoxedTransport) -> Self {
+        PeerStream {
+            inner,
+            cipher: None,
+            buffered: Vec::new(),
+        }
+    }
+
+    pub fn encrypted(inner: BoxedTransport, encrypt: Rc4, decrypt: Rc4) -> Self {
+        PeerStream {
+            inner,
+            cipher: Some(StreamCipher { encrypt, decrypt }),
+            buffered: Vec::new(),
+        }
+    }
+
+    // `bytes` are returned by the next reads before anything from the socket
+    pub fn with_buffered(mut self, bytes: Vec<u8>) -> Self {
+        self.buffered = bytes;
+        self
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.peer_addr()
+    }
+
+    pub fn transport(&self) -> TransportKind {
+        self.inner.kind()
+    }
+}
+
+impl AsyncRead for PeerStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if !this.buffered.is_empty() {
+            let count = this.buffered.len().min(buf.remaining());
+            buf.put_slice(&this.buffered[..count]);
+            this.buffered.drain(..count);
+            return Poll::Ready(Ok(()));
+        }
+
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        if let Some(cipher) = &mut this.cipher {
+            cipher.decrypt.apply(&mut buf.filled_mut()[filled..]);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for PeerStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let Some(cipher) = &mut this.cipher else {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        };
+
+        // Encrypt with a copy of the keystream and only advance the real one by what the socket
+        // accepted, so a partial or pending write can be retried with the same bytes
+        let mut encrypted = buf.to_vec();
+        cipher.encrypt.clone().apply(&mut encrypted);
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, &encrypted))?;
+        cipher.encrypt.skip(written);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,40 @@
+use serde_bencode::value::Value;
+
+use crate::{
+    message_handling::message_error::MessageError,
+    torrent_management::peers::{unmarshal_peers, unmarshal_peers6, Peer},
+};
+
+// Peer exchange message (ut_pex). IPv4 peers travel in `added`/`dropped`, IPv6 peers in
+// `added6`/`dropped6`, all in the compact format.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PexMessage {
+    pub added: Vec<Peer>,
+    pub dropped: Vec<Peer>,
+}
+
+pub fn parse_pex(payload: &[u8]) -> Result<PexMessage, MessageError> {
+    let dict = match serde_bencode::from_bytes(payload) {
+        Ok(Value::Dict(dict)) => dict,
+        Ok(_) => return Err(MessageError::InvalidResponse),
+        Err(e) => return Err(MessageError::ConversionError(e.to_string())),
+    };
+
+    let peers = |ipv4_key: &[u8], ipv6_key: &[u8]| -> Result<Vec<Peer>, MessageError> {
+        let mut peers = match dict.get(ipv4_key) {
+            Some(Value::Bytes(bytes)) => {
+                unmarshal_peers(bytes).map_err(MessageError::ConversionError)?
+            }
+            _ => Vec::new(),
+        };
+        if let Some(Value::Bytes(bytes)) = dict.get(ipv6_key) {
+            peers.extend(unmarshal_peers6(bytes).map_err(MessageError::ConversionError)?);
+        }
+        Ok(peers)
+    };
+
+    Ok(PexMessage {
+        added: peers(b"added", b"added6")?,
+        dropped: peers(b"dropped", b"dropped6")?,
+    })
+}
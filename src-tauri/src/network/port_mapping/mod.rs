@@ -0,0 +1,316 @@
+pub mod natpmp;
+pub mod upnp;
+
+use serde::Serialize;
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{sync::RwLock, task::AbortHandle};
+
+use crate::events::{event_bus::EventBus, torrent_event::TorrentEvent};
+
+use self::{
+    natpmp::{PmpGateway, PmpVersion, NATPMP_PORT},
+    upnp::{UpnpGateway, SSDP_ADDRESS},
+};
+
+use super::local_address::local_ipv4_address;
+
+// Lease we ask for, mappings are renewed halfway through
+const LEASE_SECS: u32 = 7200;
+// Permanent mappings are renewed too, in case the router forgot them
+const PERMANENT_REFRESH: Duration = Duration::from_secs(30 * 60);
+// Gateways granting very short leases don't get hammered with renewals
+const MIN_RENEW_INTERVAL: Duration = Duration::from_secs(60);
+const RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const SSDP_WAIT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+impl Transport {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Transport::Tcp => "TCP",
+            Transport::Udp => "UDP",
+        }
+    }
+}
+
+// What a gateway granted. A lifetime of 0 is a permanent mapping.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MappedPort {
+    pub external_port: u16,
+    pub lifetime: u32,
+    pub external_ip: Option<IpAddr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum MappingMethod {
+    #[serde(rename = "UPnP")]
+    Upnp,
+    #[serde(rename = "NAT-PMP")]
+    NatPmp,
+    #[serde(rename = "PCP")]
+    Pcp,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PortMapping {
+    pub transport: Transport,
+    pub internal_port: u16,
+    pub external_port: u16,
+}
+
+// Whether peers on the internet can reach our listen port, as shown in the UI
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PortMappingStatus {
+    // None until a gateway mapped the port
+    pub method: Option<MappingMethod>,
+    pub external_ip: Option<IpAddr>,
+    pub mappings: Vec<PortMapping>,
+    // Why the last attempt failed
+    pub error: Option<String>,
+}
+
+// Where gateways are looked for. By default that's the router for NAT-PMP and PCP and the
+// multicast group for UPnP, tests point both at a fake gateway.
+#[derive(Debug, Clone)]
+pub struct GatewaySearch {
+    pub pmp_gateway: Option<SocketAddr>,
+    pub ssdp_address: SocketAddr,
+}
+
+impl Default for GatewaySearch {
+    fn default() -> GatewaySearch {
+        GatewaySearch {
+            pmp_gateway: default_gateway().map(|gateway| SocketAddr::from((gateway, NATPMP_PORT))),
+            ssdp_address: SSDP_ADDRESS,
+        }
+    }
+}
+
+enum Gateway {
+    Upnp(UpnpGateway),
+    Pmp(PmpGateway),
+}
+
+impl Gateway {
+    fn method(&self) -> MappingMethod {
+        match self {
+            Gateway::Upnp(_) => MappingMethod::Upnp,
+            Gateway::Pmp(gateway) if gateway.version() == PmpVersion::Pcp => MappingMethod::Pcp,
+            Gateway::Pmp(_) => MappingMethod::NatPmp,
+        }
+    }
+
+    async fn add_mapping(
+        &self,
+        transport: Transport,
+        internal_port: u16,
+        external_port: u16,
+    ) -> Result<MappedPort, String> {
+        match self {
+            Gateway::Upnp(gateway) => {
+                gateway
+                    .add_mapping(transport, internal_port, external_port, LEASE_SECS)
+                    .await
+            }
+            Gateway::Pmp(gateway) => {
+                gateway
+                    .add_mapping(transport, internal_port, external_port, LEASE_SECS)
+                    .await
+            }
+        }
+    }
+
+    // UPnP names mappings by their external port, NAT-PMP and PCP by the internal one
+    async fn remove_mapping(&self, mapping: &PortMapping) -> Result<(), String> {
+        match self {
+            Gateway::Upnp(gateway) => {
+                gateway
+                    .remove_mapping(mapping.transport, mapping.external_port)
+                    .await
+            }
+            Gateway::Pmp(gateway) => {
+                gateway
+                    .remove_mapping(mapping.transport, mapping.internal_port)
+                    .await
+            }
+        }
+    }
+}
+
+// Keeps TCP and UDP mappings of the listen port on the router, so peers behind the same kind of
+// NAT as us can still connect in
+pub struct PortMapper {
+    status: Arc<RwLock<PortMappingStatus>>,
+    gateway: Arc<RwLock<Option<Gateway>>>,
+    task: AbortHandle,
+}
+
+impl PortMapper {
+    // Maps `port` in the background and renews the mappings until `shutdown`
+    pub fn start(port: u16, search: GatewaySearch, events: EventBus) -> PortMapper {
+        let status = Arc::new(RwLock::new(PortMappingStatus::default()));
+        let gateway = Arc::new(RwLock::new(None));
+        let task = tokio::spawn(keep_mapped(
+            port,
+            search,
+            Arc::clone(&status),
+            Arc::clone(&gateway),
+            events,
+        ))
+        .abort_handle();
+        PortMapper {
+            status,
+            gateway,
+            task,
+        }
+    }
+
+    pub async fn status(&self) -> PortMappingStatus {
+        self.status.read().await.clone()
+    }
+
+    // Stops renewing and removes our mappings from the gateway
+    pub async fn shutdown(&self) {
+        self.task.abort();
+        let Some(gateway) = self.gateway.write().await.take() else {
+            return;
+        };
+        let mut status = self.status.write().await;
+        for mapping in &status.mappings {
+            if let Err(e) = gateway.remove_mapping(mapping).await {
+                println!(
+                    "Failed to remove the {} mapping of port {}: {}",
+                    mapping.transport.as_str(),
+                    mapping.external_port,
+                    e
+                );
+            }
+        }
+        *status = PortMappingStatus::default();
+    }
+}
+
+async fn keep_mapped(
+    port: u16,
+    search: GatewaySearch,
+    status: Arc<RwLock<PortMappingStatus>>,
+    gateway: Arc<RwLock<Option<Gateway>>>,
+    events: EventBus,
+) {
+    loop {
+        let previous = status.read().await.clone();
+        let (current, renew_in) = match map_port(port, &search, &gateway, &previous).await {
+            Ok((current, lifetime)) => {
+                let renew_in = match lifetime {
+                    0 => PERMANENT_REFRESH,
+                    lifetime => Duration::from_secs(lifetime as u64 / 2).max(MIN_RENEW_INTERVAL),
+                };
+                (current, renew_in)
+            }
+            Err(e) => {
+                println!("Port mapping failed: {}", e);
+                // Look for a gateway again next time, the router may have changed
+                *gateway.write().await = None;
+                let failed = PortMappingStatus {
+                    error: Some(e),
+                    ..PortMappingStatus::default()
+                };
+                (failed, RETRY_INTERVAL)
+            }
+        };
+
+        if current != previous {
+            if let (Some(method), Some(mapping)) = (current.method, current.mappings.first()) {
+                println!(
+                    "Mapped port {} to {} with {:?}",
+                    port, mapping.external_port, method
+                );
+            }
+            *status.write().await = current.clone();
+            events.emit(TorrentEvent::PortMappingChanged { status: current });
+        }
+        tokio::time::sleep(renew_in).await;
+    }
+}
+
+// Maps or renews the TCP and UDP mappings, returning them with the shortest lifetime granted
+async fn map_port(
+    port: u16,
+    search: &GatewaySearch,
+    gateway: &RwLock<Option<Gateway>>,
+    previous: &PortMappingStatus,
+) -> Result<(PortMappingStatus, u32), String> {
+    if gateway.read().await.is_none() {
+        let found = find_gateway(search).await?;
+        *gateway.write().await = Some(found);
+    }
+    let gateway = gateway.read().await;
+    let gateway = gateway.as_ref().ok_or("Gateway went away")?;
+
+    let mut status = PortMappingStatus {
+        method: Some(gateway.method()),
+        ..PortMappingStatus::default()
+    };
+    let mut lifetime = 0;
+    for transport in [Transport::Tcp, Transport::Udp] {
+        // Renewals ask for the external port we already have
+        let external_port = previous
+            .mappings
+            .iter()
+            .find(|mapping| mapping.transport == transport)
+            .map_or(port, |mapping| mapping.external_port);
+        let mapped = gateway.add_mapping(transport, port, external_port).await?;
+        if mapped.lifetime > 0 && (lifetime == 0 || mapped.lifetime < lifetime) {
+            lifetime = mapped.lifetime;
+        }
+        status.external_ip = status.external_ip.or(mapped.external_ip);
+        status.mappings.push(PortMapping {
+            transport,
+            internal_port: port,
+            external_port: mapped.external_port,
+        });
+    }
+    Ok((status, lifetime))
+}
+
+// NAT-PMP and PCP answer quickly when the router has them, so they are tried before UPnP
+async fn find_gateway(search: &GatewaySearch) -> Result<Gateway, String> {
+    if let Some(address) = search.pmp_gateway {
+        match PmpGateway::probe(address).await {
+            Ok(gateway) => return Ok(Gateway::Pmp(gateway)),
+            Err(e) => println!("No NAT-PMP or PCP gateway at {}: {}", address, e),
+        }
+    }
+    upnp::discover(search.ssdp_address, SSDP_WAIT)
+        .await
+        .map(Gateway::Upnp)
+        .map_err(|e| format!("No gateway to map ports with: {}", e))
+}
+
+// The router, from the routing table on Linux and guessed as the .1 of our subnet elsewhere
+pub fn default_gateway() -> Option<Ipv4Addr> {
+    if let Ok(routes) = std::fs::read_to_string("/proc/net/route") {
+        // Destination and gateway are hex in host byte order, the default route goes to 0
+        return routes.lines().skip(1).find_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                [_, "00000000", gateway, ..] => u32::from_str_radix(gateway, 16)
+                    .ok()
+                    .map(|gateway| Ipv4Addr::from(gateway.to_ne_bytes())),
+                _ => None,
+            }
+        });
+    }
+    let [a, b, c, _] = local_ipv4_address()?.octets();
+    Some(Ipv4Addr::new(a, b, c, 1))
+}
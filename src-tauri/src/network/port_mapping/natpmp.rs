@@ -0,0 +1,264 @@
+use rand::Rng;
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::{net::UdpSocket, time::timeout};
+
+use super::{MappedPort, Transport};
+
+pub const NATPMP_PORT: u16 = 5351;
+
+const NATPMP_VERSION: u8 = 0;
+const PCP_VERSION: u8 = 2;
+const RESPONSE_BIT: u8 = 0x80;
+// NAT-PMP opcodes (RFC 6886)
+const OP_EXTERNAL_ADDRESS: u8 = 0;
+const OP_MAP_UDP: u8 = 1;
+const OP_MAP_TCP: u8 = 2;
+// PCP opcodes (RFC 6887)
+const PCP_OP_ANNOUNCE: u8 = 0;
+const PCP_OP_MAP: u8 = 1;
+const PCP_HEADER_SIZE: usize = 24;
+const PCP_MAP_SIZE: usize = 36;
+
+// Requests are retransmitted after 250 ms, doubling the wait each time. Four tries keep an
+// unresponsive gateway from holding up discovery for long.
+const FIRST_RETRY: Duration = Duration::from_millis(250);
+const ATTEMPTS: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PmpVersion {
+    NatPmp,
+    Pcp,
+}
+
+// A gateway speaking NAT-PMP or its successor PCP, both on UDP port 5351 of the router
+#[derive(Debug, Clone)]
+pub struct PmpGateway {
+    address: SocketAddr,
+    version: PmpVersion,
+    // PCP tells our mappings apart by this nonce, so it is reused to refresh and delete them
+    nonce: [u8; 12],
+}
+
+impl PmpGateway {
+    // Asks in PCP first. Gateways that only know NAT-PMP answer with an unsupported version, or
+    // not at all.
+    pub async fn probe(address: SocketAddr) -> Result<PmpGateway, String> {
+        let mut gateway = PmpGateway {
+            address,
+            version: PmpVersion::Pcp,
+            nonce: rand::thread_rng().gen(),
+        };
+
+        let socket = connect(address).await?;
+        let announce = pcp_request(PCP_OP_ANNOUNCE, 0, client_ip(&socket)?, &[]);
+        match exchange(&socket, &announce).await {
+            Ok(reply) if reply.first() == Some(&PCP_VERSION) => {
+                check_pcp_reply(&reply, PCP_OP_ANNOUNCE)?;
+                Ok(gateway)
+            }
+            _ => {
+                gateway.version = PmpVersion::NatPmp;
+                gateway.natpmp_external_ip().await?;
+                Ok(gateway)
+            }
+        }
+    }
+
+    pub fn version(&self) -> PmpVersion {
+        self.version
+    }
+
+    // Maps `external_port` on the gateway to `internal_port` here for `lifetime` seconds. The
+    // gateway may pick another external port or a shorter lifetime.
+    pub async fn add_mapping(
+        &self,
+        transport: Transport,
+        internal_port: u16,
+        external_port: u16,
+        lifetime: u32,
+    ) -> Result<MappedPort, String> {
+        match self.version {
+            PmpVersion::Pcp => {
+                self.pcp_map(transport, internal_port, external_port, lifetime)
+                    .await
+            }
+            PmpVersion::NatPmp => {
+                let mut mapped = self
+                    .natpmp_map(transport, internal_port, external_port, lifetime)
+                    .await?;
+                mapped.external_ip = self.natpmp_external_ip().await.ok();
+                Ok(mapped)
+            }
+        }
+    }
+
+    // A mapping with a lifetime of 0 is a deletion in both protocols
+    pub async fn remove_mapping(
+        &self,
+        transport: Transport,
+        internal_port: u16,
+    ) -> Result<(), String> {
+        match self.version {
+            PmpVersion::Pcp => self.pcp_map(transport, internal_port, 0, 0).await,
+            PmpVersion::NatPmp => self.natpmp_map(transport, internal_port, 0, 0).await,
+        }
+        .map(|_| ())
+    }
+
+    async fn natpmp_external_ip(&self) -> Result<IpAddr, String> {
+        let socket = connect(self.address).await?;
+        let reply = exchange(&socket, &[NATPMP_VERSION, OP_EXTERNAL_ADDRESS]).await?;
+        check_natpmp_reply(&reply, OP_EXTERNAL_ADDRESS, 12)?;
+        let octets: [u8; 4] = reply[8..12].try_into().expect("Slice of 4 bytes");
+        Ok(IpAddr::V4(Ipv4Addr::from(octets)))
+    }
+
+    async fn natpmp_map(
+        &self,
+        transport: Transport,
+        internal_port: u16,
+        external_port: u16,
+        lifetime: u32,
+    ) -> Result<MappedPort, String> {
+        let opcode = match transport {
+            Transport::Udp => OP_MAP_UDP,
+            Transport::Tcp => OP_MAP_TCP,
+        };
+        let mut request = vec![NATPMP_VERSION, opcode, 0, 0];
+        request.extend_from_slice(&internal_port.to_be_bytes());
+        request.extend_from_slice(&external_port.to_be_bytes());
+        request.extend_from_slice(&lifetime.to_be_bytes());
+
+        let socket = connect(self.address).await?;
+        let reply = exchange(&socket, &request).await?;
+        check_natpmp_reply(&reply, opcode, 16)?;
+        if u16::from_be_bytes([reply[8], reply[9]]) != internal_port {
+            return Err("NAT-PMP reply is for another port".to_string());
+        }
+        Ok(MappedPort {
+            external_port: u16::from_be_bytes([reply[10], reply[11]]),
+            lifetime: u32::from_be_bytes(reply[12..16].try_into().expect("Slice of 4 bytes")),
+            external_ip: None,
+        })
+    }
+
+    async fn pcp_map(
+        &self,
+        transport: Transport,
+        internal_port: u16,
+        external_port: u16,
+        lifetime: u32,
+    ) -> Result<MappedPort, String> {
+        let protocol = match transport {
+            Transport::Tcp => 6,
+            Transport::Udp => 17,
+        };
+        let mut map = Vec::with_capacity(PCP_MAP_SIZE);
+        map.extend_from_slice(&self.nonce);
+        map.extend_from_slice(&[protocol, 0, 0, 0]);
+        map.extend_from_slice(&internal_port.to_be_bytes());
+        map.extend_from_slice(&external_port.to_be_bytes());
+        // No preference for the external address
+        map.extend_from_slice(&Ipv4Addr::UNSPECIFIED.to_ipv6_mapped().octets());
+
+        let socket = connect(self.address).await?;
+        let request = pcp_request(PCP_OP_MAP, lifetime, client_ip(&socket)?, &map);
+        let reply = exchange(&socket, &request).await?;
+        check_pcp_reply(&reply, PCP_OP_MAP)?;
+
+        let map = reply
+            .get(PCP_HEADER_SIZE..PCP_HEADER_SIZE + PCP_MAP_SIZE)
+            .ok_or("PCP reply is too short")?;
+        if map[..12] != self.nonce || map[12] != protocol {
+            return Err("PCP reply is for another mapping".to_string());
+        }
+        let external_ip: [u8; 16] = map[20..36].try_into().expect("Slice of 16 bytes");
+        let external_ip = Ipv6Addr::from(external_ip);
+        Ok(MappedPort {
+            external_port: u16::from_be_bytes([map[18], map[19]]),
+            lifetime: u32::from_be_bytes(reply[4..8].try_into().expect("Slice of 4 bytes")),
+            external_ip: Some(
+                external_ip
+                    .to_ipv4_mapped()
+                    .map_or(IpAddr::V6(external_ip), IpAddr::V4),
+            ),
+        })
+    }
+}
+
+fn pcp_request(opcode: u8, lifetime: u32, client_ip: IpAddr, data: &[u8]) -> Vec<u8> {
+    let client_ip = match client_ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+    let mut request = Vec::with_capacity(PCP_HEADER_SIZE + data.len());
+    request.extend_from_slice(&[PCP_VERSION, opcode, 0, 0]);
+    request.extend_from_slice(&lifetime.to_be_bytes());
+    request.extend_from_slice(&client_ip.octets());
+    request.extend_from_slice(data);
+    request
+}
+
+fn check_natpmp_reply(reply: &[u8], opcode: u8, size: usize) -> Result<(), String> {
+    if reply.len() < size || reply[0] != NATPMP_VERSION || reply[1] != RESPONSE_BIT | opcode {
+        return Err("Invalid NAT-PMP reply".to_string());
+    }
+    match u16::from_be_bytes([reply[2], reply[3]]) {
+        0 => Ok(()),
+        code => Err(format!("NAT-PMP gateway refused with result code {}", code)),
+    }
+}
+
+fn check_pcp_reply(reply: &[u8], opcode: u8) -> Result<(), String> {
+    if reply.len() < PCP_HEADER_SIZE || reply[0] != PCP_VERSION || reply[1] != RESPONSE_BIT | opcode
+    {
+        return Err("Invalid PCP reply".to_string());
+    }
+    match reply[3] {
+        0 => Ok(()),
+        code => Err(format!("PCP gateway refused with result code {}", code)),
+    }
+}
+
+async fn connect(address: SocketAddr) -> Result<UdpSocket, String> {
+    let local: SocketAddr = match address {
+        SocketAddr::V4(_) => "0.0.0.0:0".parse().expect("Valid address"),
+        SocketAddr::V6(_) => "[::]:0".parse().expect("Valid address"),
+    };
+    let socket = UdpSocket::bind(local)
+        .await
+        .map_err(|e| format!("Failed to bind the socket: {}", e))?;
+    socket
+        .connect(address)
+        .await
+        .map_err(|e| format!("Failed to reach gateway {}: {}", address, e))?;
+    Ok(socket)
+}
+
+// The address the gateway sees us as, PCP wants it in every request
+fn client_ip(socket: &UdpSocket) -> Result<IpAddr, String> {
+    socket
+        .local_addr()
+        .map(|address| address.ip())
+        .map_err(|e| e.to_string())
+}
+
+async fn exchange(socket: &UdpSocket, request: &[u8]) -> Result<Vec<u8>, String> {
+    let mut buffer = vec![0u8; 1100];
+    let mut wait = FIRST_RETRY;
+    for _ in 0..ATTEMPTS {
+        socket
+            .send(request)
+            .await
+            .map_err(|e| format!("Failed to send to the gateway: {}", e))?;
+        match timeout(wait, socket.recv(&mut buffer)).await {
+            Ok(Ok(size)) => return Ok(buffer[..size].to_vec()),
+            Ok(Err(e)) => return Err(format!("Failed to hear from the gateway: {}", e)),
+            Err(_) => wait *= 2,
+        }
+    }
+    Err("Gateway did not answer".to_string())
+}
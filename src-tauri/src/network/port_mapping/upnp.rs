@@ -0,0 +1,334 @@
+use hyper::{client::HttpConnector, header, Body, Client, Method, Request, StatusCode};
+use std::{
+    collections::HashSet,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    time::Duration,
+};
+use tokio::{
+    net::UdpSocket,
+    time::{timeout, Instant},
+};
+use url::Url;
+
+use super::{MappedPort, Transport};
+
+pub const SSDP_ADDRESS: SocketAddr =
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(239, 255, 255, 250), 1900));
+
+const SEARCH_TARGETS: [&str; 2] = [
+    "urn:schemas-upnp-org:device:InternetGatewayDevice:1",
+    "urn:schemas-upnp-org:device:InternetGatewayDevice:2",
+];
+// The services that can map ports, in order of preference
+const WAN_SERVICES: [&str; 3] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:2",
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAPPING_DESCRIPTION: &str = "Pirate";
+// Returned by gateways that only accept mappings without a lease duration
+const ONLY_PERMANENT_LEASES: u32 = 725;
+
+// The WAN connection service of an Internet Gateway Device, controlled with SOAP over HTTP
+#[derive(Debug, Clone)]
+pub struct UpnpGateway {
+    control_url: Url,
+    service_type: String,
+    // Our address on the gateway's network, where it forwards the mapped ports to
+    local_ip: IpAddr,
+    client: Client<HttpConnector>,
+}
+
+struct SoapError {
+    // The UPnP error code of a SOAP fault
+    code: Option<u32>,
+    message: String,
+}
+
+// Searches for gateways with SSDP on `search_address`, normally the multicast group, and takes
+// the first one whose description has a WAN connection service
+pub async fn discover(search_address: SocketAddr, wait: Duration) -> Result<UpnpGateway, String> {
+    let socket = UdpSocket::bind("0.0.0.0:0")
+        .await
+        .map_err(|e| format!("Failed to bind the socket: {}", e))?;
+    for target in SEARCH_TARGETS {
+        let search = format!(
+            "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: {}\r\n\r\n",
+            SSDP_ADDRESS, target
+        );
+        socket
+            .send_to(search.as_bytes(), search_address)
+            .await
+            .map_err(|e| format!("Failed to send the SSDP search: {}", e))?;
+    }
+
+    let deadline = Instant::now() + wait;
+    let mut seen = HashSet::new();
+    let mut last_error = "No UPnP gateway answered".to_string();
+    let mut buffer = vec![0u8; 2048];
+    while let Ok(received) = timeout(
+        deadline.saturating_duration_since(Instant::now()),
+        socket.recv_from(&mut buffer),
+    )
+    .await
+    {
+        let Ok((size, _)) = received else {
+            continue;
+        };
+        let Some(location) = header_value(&String::from_utf8_lossy(&buffer[..size]), "location")
+        else {
+            continue;
+        };
+        if !seen.insert(location.clone()) {
+            continue;
+        }
+        match UpnpGateway::from_location(&location).await {
+            Ok(gateway) => return Ok(gateway),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+impl UpnpGateway {
+    // Reads the device description at `location` to find the control url
+    pub async fn from_location(location: &str) -> Result<UpnpGateway, String> {
+        let location =
+            Url::parse(location).map_err(|e| format!("Invalid gateway location: {}", e))?;
+        let client = Client::new();
+        let request = Request::get(location.as_str())
+            .body(Body::empty())
+            .map_err(|e| e.to_string())?;
+        let (status, description) = send(&client, request).await?;
+        if status != StatusCode::OK {
+            return Err(format!("{} answered with {}", location, status));
+        }
+
+        // Relative control urls are resolved against URLBase, or the description's own url
+        let base = tag_text(&description, "URLBase")
+            .and_then(|base| Url::parse(base.trim()).ok())
+            .unwrap_or_else(|| location.clone());
+        let services = blocks(&description, "service");
+        let (service_type, control_url) = WAN_SERVICES
+            .iter()
+            .find_map(|wanted| {
+                services.iter().find_map(|service| {
+                    let service_type = tag_text(service, "serviceType")?.trim();
+                    let control_url = tag_text(service, "controlURL")?.trim();
+                    (service_type == *wanted).then(|| (service_type.to_string(), control_url))
+                })
+            })
+            .ok_or_else(|| format!("{} has no WAN connection service", location))?;
+        let control_url = base
+            .join(control_url)
+            .map_err(|e| format!("Invalid control url: {}", e))?;
+
+        Ok(UpnpGateway {
+            local_ip: local_ip_towards(&control_url)?,
+            control_url,
+            service_type,
+            client,
+        })
+    }
+
+    // Gateways that only take permanent mappings get one, the returned lifetime is then 0
+    pub async fn add_mapping(
+        &self,
+        transport: Transport,
+        internal_port: u16,
+        external_port: u16,
+        lifetime: u32,
+    ) -> Result<MappedPort, String> {
+        let lifetime = match self
+            .add_port_mapping(transport, internal_port, external_port, lifetime)
+            .await
+        {
+            Ok(()) => lifetime,
+            Err(SoapError {
+                code: Some(ONLY_PERMANENT_LEASES),
+                ..
+            }) => {
+                self.add_port_mapping(transport, internal_port, external_port, 0)
+                    .await
+                    .map_err(|e| e.message)?;
+                0
+            }
+            Err(e) => return Err(e.message),
+        };
+        Ok(MappedPort {
+            external_port,
+            lifetime,
+            external_ip: self.external_ip().await.ok(),
+        })
+    }
+
+    pub async fn remove_mapping(
+        &self,
+        transport: Transport,
+        external_port: u16,
+    ) -> Result<(), String> {
+        self.soap(
+            "DeletePortMapping",
+            &[
+                ("NewRemoteHost", String::new()),
+                ("NewExternalPort", external_port.to_string()),
+                ("NewProtocol", transport.as_str().to_string()),
+            ],
+        )
+        .await
+        .map(|_| ())
+        .map_err(|e| e.message)
+    }
+
+    pub async fn external_ip(&self) -> Result<IpAddr, String> {
+        let response = self
+            .soap("GetExternalIPAddress", &[])
+            .await
+            .map_err(|e| e.message)?;
+        tag_text(&response, "NewExternalIPAddress")
+            .and_then(|ip| ip.trim().parse().ok())
+            .ok_or("Gateway did not report its external address".to_string())
+    }
+
+    async fn add_port_mapping(
+        &self,
+        transport: Transport,
+        internal_port: u16,
+        external_port: u16,
+        lifetime: u32,
+    ) -> Result<(), SoapError> {
+        self.soap(
+            "AddPortMapping",
+            &[
+                ("NewRemoteHost", String::new()),
+                ("NewExternalPort", external_port.to_string()),
+                ("NewProtocol", transport.as_str().to_string()),
+                ("NewInternalPort", internal_port.to_string()),
+                ("NewInternalClient", self.local_ip.to_string()),
+                ("NewEnabled", "1".to_string()),
+                ("NewPortMappingDescription", MAPPING_DESCRIPTION.to_string()),
+                ("NewLeaseDuration", lifetime.to_string()),
+            ],
+        )
+        .await
+        .map(|_| ())
+    }
+
+    // Calls `action` on the service, arguments must be given in the order the action declares them
+    async fn soap(&self, action: &str, arguments: &[(&str, String)]) -> Result<String, SoapError> {
+        let arguments: String = arguments
+            .iter()
+            .map(|(name, value)| format!("<{0}>{1}</{0}>", name, value))
+            .collect();
+        let envelope = format!(
+            "<?xml version=\"1.0\"?>\r\n\
+             <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+             s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+             <s:Body><u:{0} xmlns:u=\"{1}\">{2}</u:{0}></s:Body></s:Envelope>",
+            action, self.service_type, arguments
+        );
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(self.control_url.as_str())
+            .header(header::CONTENT_TYPE, "text/xml; charset=\"utf-8\"")
+            .header(
+                "SOAPAction",
+                format!("\"{}#{}\"", self.service_type, action),
+            )
+            .body(Body::from(envelope))
+            .map_err(|e| SoapError {
+                code: None,
+                message: e.to_string(),
+            })?;
+
+        let (status, body) = send(&self.client, request)
+            .await
+            .map_err(|message| SoapError {
+                code: None,
+                message,
+            })?;
+        if status == StatusCode::OK {
+            return Ok(body);
+        }
+        let code = tag_text(&body, "errorCode").and_then(|code| code.trim().parse().ok());
+        let description = tag_text(&body, "errorDescription").unwrap_or("");
+        Err(SoapError {
+            code,
+            message: match code {
+                Some(code) => format!("{} failed with UPnP error {} {}", action, code, description),
+                None => format!("{} failed with {}", action, status),
+            },
+        })
+    }
+}
+
+async fn send(
+    client: &Client<HttpConnector>,
+    request: Request<Body>,
+) -> Result<(StatusCode, String), String> {
+    timeout(REQUEST_TIMEOUT, async {
+        let response = client
+            .request(request)
+            .await
+            .map_err(|e| format!("Request to the gateway failed: {}", e))?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| format!("Reading from the gateway failed: {}", e))?;
+        Ok((status, String::from_utf8_lossy(&body).to_string()))
+    })
+    .await
+    .map_err(|_| "Gateway did not answer".to_string())?
+}
+
+// Connecting a UDP socket sends nothing but tells which of our addresses reaches the gateway
+fn local_ip_towards(url: &Url) -> Result<IpAddr, String> {
+    let address = url
+        .socket_addrs(|| Some(80))
+        .ok()
+        .and_then(|addresses| addresses.into_iter().next())
+        .ok_or_else(|| format!("Can't resolve {}", url))?;
+    let socket = std::net::UdpSocket::bind(match address {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    })
+    .and_then(|socket| socket.connect(address).map(|_| socket))
+    .map_err(|e| format!("Can't reach the gateway: {}", e))?;
+    socket
+        .local_addr()
+        .map(|local| local.ip())
+        .map_err(|e| e.to_string())
+}
+
+// The value of an HTTP style header of an SSDP response, names are case-insensitive
+fn header_value(response: &str, name: &str) -> Option<String> {
+    response.lines().skip(1).find_map(|line| {
+        let (header, value) = line.split_once(':')?;
+        header
+            .trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().to_string())
+    })
+}
+
+// The text of the first `<tag>` element. Namespace prefixes aren't handled, gateways don't use
+// them for the elements we read.
+fn tag_text<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    blocks(xml, tag).into_iter().next()
+}
+
+fn blocks<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+        found.push(&rest[..end]);
+        rest = &rest[end + close.len()..];
+    }
+    found
+}
@@ -0,0 +1,74 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+use crate::config::{Config, SharedConfig};
+
+// Token bucket for one direction of traffic. The limit is read from the configuration on every
+// transfer, so changing it applies right away. 0 means unlimited.
+#[derive(Clone)]
+pub struct RateLimiter {
+    config: SharedConfig,
+    limit: fn(&Config) -> u64,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(config: SharedConfig, limit: fn(&Config) -> u64) -> RateLimiter {
+        RateLimiter {
+            config,
+            limit,
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: 0.0,
+                refilled: Instant::now(),
+            })),
+        }
+    }
+
+    // Takes `bytes` out of the bucket and waits until the limit allows them. The bucket may go
+    // into debt, so a block larger than a second's worth still gets through, just later.
+    pub async fn acquire(&self, bytes: u64) {
+        let rate = (self.limit)(&*self.config.read().await);
+        if rate == 0 {
+            return;
+        }
+        let wait = {
+            let mut bucket = self.bucket.lock().expect("Rate limiter lock poisoned");
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.refilled).as_secs_f64() * rate as f64;
+            bucket.refilled = now;
+            // At most a second's worth, an idle session doesn't save up for a burst
+            bucket.tokens = (bucket.tokens + refill).min(rate as f64) - bytes as f64;
+            match bucket.tokens < 0.0 {
+                true => Duration::from_secs_f64(-bucket.tokens / rate as f64),
+                false => Duration::ZERO,
+            }
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+// The configured download and upload limits, shared by every torrent of the session
+#[derive(Clone)]
+pub struct RateLimits {
+    pub download: RateLimiter,
+    pub upload: RateLimiter,
+}
+
+impl RateLimits {
+    pub fn new(config: SharedConfig) -> RateLimits {
+        RateLimits {
+            download: RateLimiter::new(Arc::clone(&config), |config| config.max_download_rate),
+            upload: RateLimiter::new(config, |config| config.max_upload_rate),
+        }
+    }
+}
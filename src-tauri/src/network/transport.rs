@@ -0,0 +1,70 @@
+use futures::future::{select_ok, BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use std::{io, net::SocketAddr};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+
+use super::utp::{socket::UtpSocket, stream::UtpStream};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    Tcp,
+    Utp,
+}
+
+// What a peer session runs over, TCP or uTP. Everything above the transport (encryption, the
+// BitTorrent handshake and the messages) only sees this trait.
+pub trait PeerTransport: AsyncRead + AsyncWrite + Send + Unpin {
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+    fn kind(&self) -> TransportKind;
+}
+
+pub type BoxedTransport = Box<dyn PeerTransport>;
+
+impl PeerTransport for TcpStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+
+    fn kind(&self) -> TransportKind {
+        TransportKind::Tcp
+    }
+}
+
+impl PeerTransport for UtpStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(UtpStream::peer_addr(self))
+    }
+
+    fn kind(&self) -> TransportKind {
+        TransportKind::Utp
+    }
+}
+
+// Connects over TCP and, when we have a uTP socket, over uTP at the same time. The first
+// transport to connect wins and the other attempt is dropped.
+pub async fn connect_transport(
+    address: SocketAddr,
+    utp: Option<&UtpSocket>,
+) -> io::Result<BoxedTransport> {
+    let mut attempts: Vec<BoxFuture<io::Result<BoxedTransport>>> = vec![async move {
+        let stream = TcpStream::connect(address).await?;
+        Ok(Box::new(stream) as BoxedTransport)
+    }
+    .boxed()];
+    if let Some(utp) = utp {
+        let utp = utp.clone();
+        attempts.push(
+            async move {
+                let stream = utp.connect(address).await?;
+                Ok(Box::new(stream) as BoxedTransport)
+            }
+            .boxed(),
+        );
+    }
+
+    select_ok(attempts).await.map(|(transport, _)| transport)
+}
@@ -0,0 +1,111 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use super::packet::MAX_PAYLOAD_SIZE;
+
+// LEDBAT (BEP 29): the window grows while the queuing delay we add is below the target and
+// shrinks as it goes over, so uTP backs off before other traffic on the link notices
+const TARGET_DELAY_MICROS: f64 = 100_000.0;
+const MAX_WINDOW_INCREASE_PER_RTT: f64 = 3000.0;
+pub const MIN_WINDOW: usize = 2 * MAX_PAYLOAD_SIZE;
+const MAX_WINDOW: usize = 1 << 20;
+const INITIAL_WINDOW: usize = 4 * MAX_PAYLOAD_SIZE;
+// The base delay is the lowest sample of the last few minutes, so that it follows route changes
+const BASE_DELAY_INTERVAL: Duration = Duration::from_secs(60);
+const BASE_DELAY_HISTORY: usize = 2;
+
+pub struct Ledbat {
+    window: usize,
+    // Lowest one-way delay sample of each interval, the current one last
+    base_delays: VecDeque<u32>,
+    interval_started: Instant,
+    last_decrease: Option<Instant>,
+}
+
+impl Default for Ledbat {
+    fn default() -> Ledbat {
+        Ledbat {
+            window: INITIAL_WINDOW,
+            base_delays: VecDeque::new(),
+            interval_started: Instant::now(),
+            last_decrease: None,
+        }
+    }
+}
+
+impl Ledbat {
+    // Bytes we may have in flight
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    // `delay_sample` is the one-way delay the peer measured for our packets. Clocks aren't
+    // synchronized, so only its distance from the lowest sample seen means anything.
+    pub fn on_ack(&mut self, bytes_acked: usize, delay_sample: u32, now: Instant) {
+        if bytes_acked == 0 {
+            return;
+        }
+        let queuing_delay = match delay_sample {
+            0 => 0.0,
+            sample => {
+                self.record_delay(sample, now);
+                sample.wrapping_sub(self.base_delay()) as i32 as f64
+            }
+        };
+
+        let off_target = ((TARGET_DELAY_MICROS - queuing_delay) / TARGET_DELAY_MICROS).max(-1.0);
+        let window_factor = bytes_acked.min(self.window) as f64 / self.window as f64;
+        let gain = MAX_WINDOW_INCREASE_PER_RTT * off_target * window_factor;
+        self.window =
+            (self.window as f64 + gain).clamp(MIN_WINDOW as f64, MAX_WINDOW as f64) as usize;
+    }
+
+    // Packet loss halves the window, once per round trip at most
+    pub fn on_loss(&mut self, now: Instant, rtt: Duration) {
+        if self
+            .last_decrease
+            .is_some_and(|last_decrease| now.duration_since(last_decrease) < rtt)
+        {
+            return;
+        }
+        self.window = (self.window / 2).max(MIN_WINDOW);
+        self.last_decrease = Some(now);
+    }
+
+    pub fn on_timeout(&mut self, now: Instant) {
+        self.window = MIN_WINDOW;
+        self.last_decrease = Some(now);
+    }
+
+    fn record_delay(&mut self, sample: u32, now: Instant) {
+        if self.base_delays.is_empty()
+            || now.duration_since(self.interval_started) > BASE_DELAY_INTERVAL
+        {
+            self.base_delays.push_back(sample);
+            self.interval_started = now;
+            if self.base_delays.len() > BASE_DELAY_HISTORY {
+                self.base_delays.pop_front();
+            }
+        } else if let Some(lowest) = self.base_delays.back_mut() {
+            if (sample.wrapping_sub(*lowest) as i32) < 0 {
+                *lowest = sample;
+            }
+        }
+    }
+
+    fn base_delay(&self) -> u32 {
+        self.base_delays
+            .iter()
+            .copied()
+            .reduce(|lowest, delay| {
+                if (delay.wrapping_sub(lowest) as i32) < 0 {
+                    delay
+                } else {
+                    lowest
+                }
+            })
+            .unwrap_or(0)
+    }
+}
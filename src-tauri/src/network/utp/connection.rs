@@ -0,0 +1,624 @@
+use rand::Rng;
+use socket2::SockRef;
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    net::SocketAddr,
+    sync::{Arc, OnceLock},
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+use tokio::{io::ReadBuf, net::UdpSocket};
+
+use super::{
+    congestion::Ledbat,
+    packet::{seq_before, Packet, PacketType, MAX_PAYLOAD_SIZE},
+};
+
+const RECEIVE_BUFFER_SIZE: usize = 1 << 20;
+const SEND_BUFFER_SIZE: usize = 1 << 20;
+// Packets that arrived ahead of a gap, kept until the gap is filled
+const MAX_OUT_OF_ORDER: usize = 1024;
+const INITIAL_TIMEOUT: Duration = Duration::from_secs(1);
+const MIN_TIMEOUT: Duration = Duration::from_millis(500);
+const MAX_TIMEOUT: Duration = Duration::from_secs(16);
+// Consecutive timeouts before the connection is given up
+const MAX_SYN_RETRANSMISSIONS: u32 = 4;
+const MAX_RETRANSMISSIONS: u32 = 8;
+// Also the number of packets acked past a gap before it is taken as lost
+const DUPLICATE_ACK_THRESHOLD: u32 = 3;
+// Longest selective ack bitmask we send, in bytes
+const MAX_SELECTIVE_ACK_SIZE: usize = 128;
+// Gaps resent per selective ack
+const MAX_SELECTIVE_RETRANSMISSIONS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    SynSent,
+    Connected,
+    Closed,
+}
+
+struct InFlight {
+    packet: Packet,
+    sent_at: Instant,
+    transmissions: u32,
+    // Selectively acked, waiting for the gap before it to be filled
+    acked: bool,
+}
+
+// The state of one uTP connection. Packets are fed in by the socket's receive loop, time by its
+// ticker and bytes by the stream, everything is answered synchronously with non-blocking sends since
+// uTP recovers from a dropped datagram anyway.
+pub struct Connection {
+    socket: Arc<UdpSocket>,
+    remote: SocketAddr,
+    state: State,
+    // We send with `send_id` and receive packets carrying `recv_id`
+    send_id: u16,
+    recv_id: u16,
+    // Next sequence number we send, and the last one received in order
+    seq_nr: u16,
+    ack_nr: u16,
+    last_ack_received: u16,
+    duplicate_acks: u32,
+    // Until everything sent before a loss is acked, each partial ack resends the next packet
+    recovery_end: Option<u16>,
+
+    in_flight: VecDeque<InFlight>,
+    bytes_in_flight: usize,
+    send_buffer: VecDeque<u8>,
+    congestion: Ledbat,
+    peer_window: usize,
+    rtt: Option<Duration>,
+    rtt_variance: Duration,
+    timeout: Duration,
+    timeout_at: Option<Instant>,
+    retransmissions: u32,
+
+    received: VecDeque<u8>,
+    out_of_order: HashMap<u16, Packet>,
+    // Delay of the last packet we received, echoed back for the peer's congestion control
+    reply_micros: u32,
+
+    fin_requested: bool,
+    fin_sent: bool,
+    read_closed: bool,
+    dropped: bool,
+    error: Option<io::ErrorKind>,
+
+    connect_waker: Option<Waker>,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+    close_waker: Option<Waker>,
+}
+
+impl Connection {
+    fn new(
+        socket: Arc<UdpSocket>,
+        remote: SocketAddr,
+        state: State,
+        send_id: u16,
+        recv_id: u16,
+    ) -> Connection {
+        Connection {
+            socket,
+            remote,
+            state,
+            send_id,
+            recv_id,
+            seq_nr: 1,
+            ack_nr: 0,
+            last_ack_received: 0,
+            duplicate_acks: 0,
+            recovery_end: None,
+            in_flight: VecDeque::new(),
+            bytes_in_flight: 0,
+            send_buffer: VecDeque::new(),
+            congestion: Ledbat::default(),
+            peer_window: RECEIVE_BUFFER_SIZE,
+            rtt: None,
+            rtt_variance: Duration::ZERO,
+            timeout: INITIAL_TIMEOUT,
+            timeout_at: None,
+            retransmissions: 0,
+            received: VecDeque::new(),
+            out_of_order: HashMap::new(),
+            reply_micros: 0,
+            fin_requested: false,
+            fin_sent: false,
+            read_closed: false,
+            dropped: false,
+            error: None,
+            connect_waker: None,
+            read_waker: None,
+            write_waker: None,
+            close_waker: None,
+        }
+    }
+
+    // Starts a connection to `remote` by sending a SYN
+    pub fn outgoing(socket: Arc<UdpSocket>, remote: SocketAddr, recv_id: u16) -> Connection {
+        let mut connection = Connection::new(
+            socket,
+            remote,
+            State::SynSent,
+            recv_id.wrapping_add(1),
+            recv_id,
+        );
+        connection.send_new(PacketType::Syn, Vec::new(), Instant::now());
+        connection
+    }
+
+    // Accepts the connection a peer asked for with `syn`
+    pub fn incoming(socket: Arc<UdpSocket>, remote: SocketAddr, syn: &Packet) -> Connection {
+        let mut connection = Connection::new(
+            socket,
+            remote,
+            State::Connected,
+            syn.connection_id,
+            syn.connection_id.wrapping_add(1),
+        );
+        connection.seq_nr = rand::thread_rng().gen();
+        connection.ack_nr = syn.seq_nr;
+        connection.reply_micros = now_micros().wrapping_sub(syn.timestamp);
+        connection.send_state();
+        connection
+    }
+
+    pub fn recv_id(&self) -> u16 {
+        self.recv_id
+    }
+
+    pub fn remote(&self) -> SocketAddr {
+        self.remote
+    }
+
+    // Whether the socket can forget this connection
+    pub fn is_finished(&self) -> bool {
+        self.error.is_some()
+            || (self.dropped
+                && (self.state == State::SynSent || (self.fin_sent && self.in_flight.is_empty())))
+    }
+
+    pub fn on_packet(&mut self, packet: Packet, now: Instant) {
+        if self.state == State::Closed {
+            return;
+        }
+        self.reply_micros = now_micros().wrapping_sub(packet.timestamp);
+
+        match packet.packet_type {
+            PacketType::Reset => {
+                self.fail(io::ErrorKind::ConnectionReset);
+                return;
+            }
+            // The peer didn't get our answer to its SYN
+            PacketType::Syn => {
+                self.send_state();
+                return;
+            }
+            _ => (),
+        }
+
+        if self.state == State::SynSent {
+            // Our SYN is the only packet in flight, anything acking it completes the connection
+            if packet.ack_nr != self.seq_nr.wrapping_sub(1) {
+                return;
+            }
+            self.state = State::Connected;
+            self.ack_nr = packet.seq_nr.wrapping_sub(1);
+            wake(&mut self.connect_waker);
+        }
+
+        self.process_ack(&packet, now);
+        if matches!(packet.packet_type, PacketType::Data | PacketType::Fin) {
+            self.receive(packet);
+            self.send_state();
+        }
+        self.flush(now);
+    }
+
+    // Retransmits when the oldest packet has gone unacknowledged for too long
+    pub fn on_tick(&mut self, now: Instant) {
+        let Some(timeout_at) = self.timeout_at else {
+            return;
+        };
+        if now < timeout_at || self.state == State::Closed {
+            return;
+        }
+
+        self.retransmissions += 1;
+        let limit = match self.state {
+            State::SynSent => MAX_SYN_RETRANSMISSIONS,
+            _ => MAX_RETRANSMISSIONS,
+        };
+        if self.retransmissions > limit {
+            self.fail(io::ErrorKind::TimedOut);
+            return;
+        }
+
+        self.congestion.on_timeout(now);
+        // SYNs are retried at a steady pace so a lost one doesn't hold up connection racing
+        if self.state != State::SynSent {
+            self.timeout = (self.timeout * 2).min(MAX_TIMEOUT);
+        }
+        self.recovery_end = Some(self.seq_nr);
+        self.retransmit_oldest(now);
+        self.timeout_at = Some(now + self.timeout);
+    }
+
+    pub fn poll_connected(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(kind) = self.error {
+            return Poll::Ready(Err(connection_error(kind)));
+        }
+        if self.state == State::SynSent {
+            self.connect_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    pub fn poll_read(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if !self.received.is_empty() {
+            let window_was_closing = self.received.len() + MAX_PAYLOAD_SIZE > RECEIVE_BUFFER_SIZE;
+            let count = self.received.len().min(buf.remaining());
+            let (front, back) = self.received.as_slices();
+            let from_front = count.min(front.len());
+            buf.put_slice(&front[..from_front]);
+            buf.put_slice(&back[..count - from_front]);
+            self.received.drain(..count);
+            // Tell a sender that stopped on our full buffer that there is room again
+            if window_was_closing {
+                self.send_state();
+            }
+            return Poll::Ready(Ok(()));
+        }
+        if self.read_closed {
+            return Poll::Ready(Ok(()));
+        }
+        if let Some(kind) = self.error {
+            return Poll::Ready(Err(connection_error(kind)));
+        }
+        self.read_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    pub fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        if let Some(kind) = self.error {
+            return Poll::Ready(Err(connection_error(kind)));
+        }
+        if self.fin_requested {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        let space = SEND_BUFFER_SIZE - self.send_buffer.len();
+        if space == 0 {
+            self.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let count = space.min(buf.len());
+        self.send_buffer.extend(&buf[..count]);
+        self.flush(Instant::now());
+        Poll::Ready(Ok(count))
+    }
+
+    // Sends a FIN once everything written so far is out, and waits for it to be acknowledged
+    pub fn poll_shutdown(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.fin_requested = true;
+        self.flush(Instant::now());
+        if let Some(kind) = self.error {
+            return Poll::Ready(Err(connection_error(kind)));
+        }
+        if self.fin_sent && self.in_flight.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+        self.close_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    // The stream is gone, finish sending what was written and close
+    pub fn close(&mut self) {
+        self.dropped = true;
+        if self.state == State::SynSent {
+            self.state = State::Closed;
+            return;
+        }
+        self.fin_requested = true;
+        self.flush(Instant::now());
+    }
+
+    fn process_ack(&mut self, packet: &Packet, now: Instant) {
+        self.peer_window = packet.window_size as usize;
+        let ack = packet.ack_nr;
+
+        let mut bytes_acked = 0;
+        let mut newest_acked = None;
+        while let Some(oldest) = self.in_flight.front() {
+            if seq_before(ack, oldest.packet.seq_nr) {
+                break;
+            }
+            let oldest = self.in_flight.pop_front().expect("Checked above");
+            if !oldest.acked {
+                bytes_acked += oldest.packet.payload.len();
+                self.bytes_in_flight -= oldest.packet.payload.len();
+            }
+            newest_acked = Some(oldest);
+        }
+        let acked_any = newest_acked.is_some();
+        if let Some(mask) = &packet.selective_ack {
+            bytes_acked += self.process_selective_ack(ack, mask, now);
+        }
+
+        // Retransmitted packets give ambiguous round trip times, and so do packets that waited
+        // behind a lost one
+        if let Some(newest) = newest_acked {
+            if newest.transmissions == 1 && self.recovery_end.is_none() {
+                self.update_rtt(now.duration_since(newest.sent_at));
+            }
+        }
+
+        self.congestion
+            .on_ack(bytes_acked, packet.timestamp_difference, now);
+        if acked_any {
+            self.retransmissions = 0;
+            self.duplicate_acks = 0;
+            self.timeout_at = if self.in_flight.is_empty() {
+                None
+            } else {
+                Some(now + self.timeout)
+            };
+            if let Some(recovery_end) = self.recovery_end {
+                if seq_before(ack.wrapping_add(1), recovery_end) && !self.in_flight.is_empty() {
+                    self.retransmit_oldest(now);
+                } else {
+                    self.recovery_end = None;
+                }
+            }
+            if self.fin_sent && self.in_flight.is_empty() {
+                wake(&mut self.close_waker);
+            }
+        } else if packet.packet_type == PacketType::State
+            && ack == self.last_ack_received
+            && !self.in_flight.is_empty()
+        {
+            self.duplicate_acks += 1;
+            if self.duplicate_acks == DUPLICATE_ACK_THRESHOLD {
+                let rtt = self.rtt.unwrap_or(INITIAL_TIMEOUT);
+                self.congestion.on_loss(now, rtt);
+                self.recovery_end = Some(self.seq_nr);
+                self.retransmit_oldest(now);
+            }
+        }
+        self.last_ack_received = ack;
+    }
+
+    // Marks the packets the peer has beyond the gap and resends the ones it is missing, returns
+    // the newly acked bytes
+    fn process_selective_ack(&mut self, ack: u16, mask: &[u8], now: Instant) -> usize {
+        let mut bytes_acked = 0;
+        for entry in self.in_flight.iter_mut() {
+            let bit = entry.packet.seq_nr.wrapping_sub(ack).wrapping_sub(2) as usize;
+            let is_set = mask
+                .get(bit / 8)
+                .is_some_and(|byte| byte & (1 << (bit % 8)) != 0);
+            if is_set && !entry.acked {
+                entry.acked = true;
+                bytes_acked += entry.packet.payload.len();
+                self.bytes_in_flight -= entry.packet.payload.len();
+            }
+        }
+
+        // A packet with enough acked packets after it is lost, unless we resent it recently
+        let rtt = self.rtt.unwrap_or(INITIAL_TIMEOUT);
+        let mut acked_after = 0;
+        let mut lost = Vec::new();
+        for (index, entry) in self.in_flight.iter().enumerate().rev() {
+            if entry.acked {
+                acked_after += 1;
+            } else if acked_after >= DUPLICATE_ACK_THRESHOLD
+                && now.duration_since(entry.sent_at) >= rtt
+            {
+                lost.push(index);
+            }
+        }
+        if !lost.is_empty() {
+            self.congestion.on_loss(now, rtt);
+            for index in lost.into_iter().rev().take(MAX_SELECTIVE_RETRANSMISSIONS) {
+                self.retransmit(index, now);
+            }
+        }
+        bytes_acked
+    }
+
+    fn receive(&mut self, packet: Packet) {
+        let expected = self.ack_nr.wrapping_add(1);
+        if packet.seq_nr == expected {
+            self.deliver(packet);
+            while let Some(next) = self.out_of_order.remove(&self.ack_nr.wrapping_add(1)) {
+                self.deliver(next);
+            }
+        } else if seq_before(expected, packet.seq_nr) && self.out_of_order.len() < MAX_OUT_OF_ORDER
+        {
+            self.out_of_order.insert(packet.seq_nr, packet);
+        }
+    }
+
+    fn deliver(&mut self, packet: Packet) {
+        self.ack_nr = packet.seq_nr;
+        if packet.packet_type == PacketType::Fin {
+            self.read_closed = true;
+            self.out_of_order.clear();
+        } else {
+            self.received.extend(packet.payload);
+        }
+        wake(&mut self.read_waker);
+    }
+
+    // Packetizes buffered bytes as far as the congestion and receive windows allow
+    fn flush(&mut self, now: Instant) {
+        if self.state != State::Connected {
+            return;
+        }
+        let window = self.congestion.window().min(self.peer_window);
+        let mut drained = false;
+        while !self.send_buffer.is_empty() {
+            let size = self.send_buffer.len().min(MAX_PAYLOAD_SIZE);
+            // One packet may always be in flight, it doubles as a probe of a closed window
+            if self.bytes_in_flight > 0 && self.bytes_in_flight + size > window {
+                break;
+            }
+            let payload: Vec<u8> = self.send_buffer.drain(..size).collect();
+            self.bytes_in_flight += payload.len();
+            self.send_new(PacketType::Data, payload, now);
+            drained = true;
+        }
+        if drained {
+            wake(&mut self.write_waker);
+        }
+
+        if self.fin_requested && !self.fin_sent && self.send_buffer.is_empty() {
+            self.send_new(PacketType::Fin, Vec::new(), now);
+            self.fin_sent = true;
+        }
+    }
+
+    // Sends a packet that takes up a sequence number and has to be acknowledged
+    fn send_new(&mut self, packet_type: PacketType, payload: Vec<u8>, now: Instant) {
+        // A SYN names the id we want to receive on, everything else the id the peer receives on
+        let connection_id = match packet_type {
+            PacketType::Syn => self.recv_id,
+            _ => self.send_id,
+        };
+        let mut packet = Packet::new(packet_type, connection_id);
+        packet.seq_nr = self.seq_nr;
+        packet.payload = payload;
+        self.seq_nr = self.seq_nr.wrapping_add(1);
+
+        self.transmit(&mut packet);
+        self.in_flight.push_back(InFlight {
+            packet,
+            sent_at: now,
+            transmissions: 1,
+            acked: false,
+        });
+        if self.timeout_at.is_none() {
+            self.timeout_at = Some(now + self.timeout);
+        }
+    }
+
+    fn retransmit_oldest(&mut self, now: Instant) {
+        if let Some(index) = self.in_flight.iter().position(|entry| !entry.acked) {
+            self.retransmit(index, now);
+        }
+    }
+
+    fn retransmit(&mut self, index: usize, now: Instant) {
+        let mut packet = self.in_flight[index].packet.clone();
+        self.transmit(&mut packet);
+        let entry = &mut self.in_flight[index];
+        entry.packet = packet;
+        entry.transmissions += 1;
+        entry.sent_at = now;
+    }
+
+    // Acknowledges what we have received so far, without using a sequence number
+    fn send_state(&mut self) {
+        let mut packet = Packet::new(PacketType::State, self.send_id);
+        packet.seq_nr = self.seq_nr;
+        self.transmit(&mut packet);
+    }
+
+    fn transmit(&self, packet: &mut Packet) {
+        packet.timestamp = now_micros();
+        packet.timestamp_difference = self.reply_micros;
+        packet.window_size = RECEIVE_BUFFER_SIZE.saturating_sub(self.received.len()) as u32;
+        packet.ack_nr = self.ack_nr;
+        packet.selective_ack = self.selective_ack();
+        send_datagram(&self.socket, &packet.encode(), self.remote);
+    }
+
+    // Which packets past the gap we already have, None when nothing is missing
+    fn selective_ack(&self) -> Option<Vec<u8>> {
+        if self.out_of_order.is_empty() {
+            return None;
+        }
+        let first = self.ack_nr.wrapping_add(2);
+        let mut mask = vec![0u8; 4];
+        for seq_nr in self.out_of_order.keys() {
+            let bit = seq_nr.wrapping_sub(first) as usize;
+            if bit >= MAX_SELECTIVE_ACK_SIZE * 8 {
+                continue;
+            }
+            // The mask grows in steps of four bytes
+            let size = (bit / 32 + 1) * 4;
+            if mask.len() < size {
+                mask.resize(size, 0);
+            }
+            mask[bit / 8] |= 1 << (bit % 8);
+        }
+        Some(mask)
+    }
+
+    fn update_rtt(&mut self, sample: Duration) {
+        match self.rtt {
+            None => {
+                self.rtt = Some(sample);
+                self.rtt_variance = sample / 2;
+            }
+            Some(rtt) => {
+                let difference = rtt.abs_diff(sample);
+                self.rtt_variance = (self.rtt_variance * 3 + difference) / 4;
+                self.rtt = Some((rtt * 7 + sample) / 8);
+            }
+        }
+        let rtt = self.rtt.unwrap_or(sample);
+        self.timeout = (rtt + self.rtt_variance * 4).clamp(MIN_TIMEOUT, MAX_TIMEOUT);
+    }
+
+    fn fail(&mut self, kind: io::ErrorKind) {
+        self.error = Some(kind);
+        self.state = State::Closed;
+        self.in_flight.clear();
+        self.bytes_in_flight = 0;
+        self.timeout_at = None;
+        wake(&mut self.connect_waker);
+        wake(&mut self.read_waker);
+        wake(&mut self.write_waker);
+        wake(&mut self.close_waker);
+    }
+}
+
+// Tells the peer we don't know the connection a packet was meant for
+pub fn send_reset(socket: &UdpSocket, remote: SocketAddr, packet: &Packet) {
+    let mut reset = Packet::new(PacketType::Reset, packet.connection_id);
+    reset.timestamp = now_micros();
+    reset.seq_nr = rand::thread_rng().gen();
+    reset.ack_nr = packet.seq_nr;
+    send_datagram(socket, &reset.encode(), remote);
+}
+
+// Sends straight away rather than through tokio, whose readiness tracking refuses the first send
+// on a new socket. A full socket buffer is no different from a lost datagram.
+fn send_datagram(socket: &UdpSocket, bytes: &[u8], remote: SocketAddr) {
+    let _ = SockRef::from(socket).send_to(bytes, &remote.into());
+}
+
+// Microseconds since an arbitrary point, wrapping like the header field
+fn now_micros() -> u32 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_micros() as u32
+}
+
+fn connection_error(kind: io::ErrorKind) -> io::Error {
+    io::Error::new(kind, format!("uTP connection failed: {:?}", kind))
+}
+
+fn wake(waker: &mut Option<Waker>) {
+    if let Some(waker) = waker.take() {
+        waker.wake();
+    }
+}
@@ -0,0 +1,6 @@
+// uTP (BEP 29), BitTorrent over UDP with LEDBAT congestion control
+pub mod congestion;
+pub mod connection;
+pub mod packet;
+pub mod socket;
+pub mod stream;
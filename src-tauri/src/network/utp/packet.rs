@@ -0,0 +1,137 @@
+// uTP packets (BEP 29): a 20 byte header, optional extensions and the payload
+pub const HEADER_SIZE: usize = 20;
+// Keeps packets under common MTUs once the IP and UDP headers are added
+pub const MAX_PAYLOAD_SIZE: usize = 1400 - HEADER_SIZE;
+const VERSION: u8 = 1;
+const SELECTIVE_ACK_EXTENSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketType {
+    Data = 0,
+    Fin = 1,
+    State = 2,
+    Reset = 3,
+    Syn = 4,
+}
+
+impl PacketType {
+    fn from_u8(value: u8) -> Option<PacketType> {
+        match value {
+            0 => Some(PacketType::Data),
+            1 => Some(PacketType::Fin),
+            2 => Some(PacketType::State),
+            3 => Some(PacketType::Reset),
+            4 => Some(PacketType::Syn),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    pub packet_type: PacketType,
+    pub connection_id: u16,
+    // Microseconds, from the sender's clock
+    pub timestamp: u32,
+    // How long the sender's last received packet was in flight, the one-way delay sample
+    pub timestamp_difference: u32,
+    // Bytes the sender is still willing to receive
+    pub window_size: u32,
+    pub seq_nr: u16,
+    pub ack_nr: u16,
+    // Bit i set means packet ack_nr + 2 + i has arrived, sent while there is a gap
+    pub selective_ack: Option<Vec<u8>>,
+    pub payload: Vec<u8>,
+}
+
+impl Packet {
+    pub fn new(packet_type: PacketType, connection_id: u16) -> Packet {
+        Packet {
+            packet_type,
+            connection_id,
+            timestamp: 0,
+            timestamp_difference: 0,
+            window_size: 0,
+            seq_nr: 0,
+            ack_nr: 0,
+            selective_ack: None,
+            payload: Vec::new(),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.payload.len());
+        bytes.push((self.packet_type as u8) << 4 | VERSION);
+        bytes.push(match self.selective_ack {
+            Some(_) => SELECTIVE_ACK_EXTENSION,
+            None => 0,
+        });
+        bytes.extend_from_slice(&self.connection_id.to_be_bytes());
+        bytes.extend_from_slice(&self.timestamp.to_be_bytes());
+        bytes.extend_from_slice(&self.timestamp_difference.to_be_bytes());
+        bytes.extend_from_slice(&self.window_size.to_be_bytes());
+        bytes.extend_from_slice(&self.seq_nr.to_be_bytes());
+        bytes.extend_from_slice(&self.ack_nr.to_be_bytes());
+        if let Some(mask) = &self.selective_ack {
+            bytes.push(0); // no further extensions
+            bytes.push(mask.len() as u8);
+            bytes.extend_from_slice(mask);
+        }
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+
+    // None for anything that isn't a well formed version 1 packet. Extensions other than
+    // selective acks are skipped.
+    pub fn decode(bytes: &[u8]) -> Option<Packet> {
+        if bytes.len() < HEADER_SIZE || bytes[0] & 0x0f != VERSION {
+            return None;
+        }
+        let packet_type = PacketType::from_u8(bytes[0] >> 4)?;
+        let u16_at = |index: usize| u16::from_be_bytes([bytes[index], bytes[index + 1]]);
+        let u32_at = |index: usize| {
+            u32::from_be_bytes([
+                bytes[index],
+                bytes[index + 1],
+                bytes[index + 2],
+                bytes[index + 3],
+            ])
+        };
+
+        let mut extension = bytes[1];
+        let mut offset = HEADER_SIZE;
+        let mut selective_ack = None;
+        while extension != 0 {
+            if offset + 2 > bytes.len() {
+                return None;
+            }
+            let length = bytes[offset + 1] as usize;
+            let end = offset + 2 + length;
+            if end > bytes.len() {
+                return None;
+            }
+            if extension == SELECTIVE_ACK_EXTENSION {
+                selective_ack = Some(bytes[offset + 2..end].to_vec());
+            }
+            extension = bytes[offset];
+            offset = end;
+        }
+
+        Some(Packet {
+            packet_type,
+            connection_id: u16_at(2),
+            timestamp: u32_at(4),
+            timestamp_difference: u32_at(8),
+            window_size: u32_at(12),
+            seq_nr: u16_at(16),
+            ack_nr: u16_at(18),
+            selective_ack,
+            payload: bytes[offset..].to_vec(),
+        })
+    }
+}
+
+// Sequence numbers wrap, `a` is before `b` when it is less than half the number space behind
+pub fn seq_before(a: u16, b: u16) -> bool {
+    (b.wrapping_sub(a) as i16) > 0
+}
@@ -0,0 +1,219 @@
+use rand::Rng;
+use std::{
+    collections::HashMap,
+    future::poll_fn,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+use tokio::{
+    net::UdpSocket,
+    sync::mpsc,
+    task::{AbortHandle, JoinHandle},
+    time::MissedTickBehavior,
+};
+
+use super::{
+    connection::{send_reset, Connection},
+    packet::{Packet, PacketType},
+    stream::UtpStream,
+};
+
+const TICK_INTERVAL: Duration = Duration::from_millis(50);
+// Connections waiting for `accept`, SYNs beyond this are reset
+const ACCEPT_BACKLOG: usize = 32;
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+type Connections = HashMap<(SocketAddr, u16), Arc<Mutex<Connection>>>;
+
+// A UDP socket carrying any number of uTP connections, in both directions. Incoming packets are
+// routed by sender address and connection id.
+#[derive(Clone)]
+pub struct UtpSocket {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    udp: Arc<UdpSocket>,
+    connections: Mutex<Connections>,
+    incoming: tokio::sync::Mutex<mpsc::Receiver<UtpStream>>,
+    tasks: Vec<AbortHandle>,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+impl UtpSocket {
+    pub async fn bind(address: SocketAddr) -> io::Result<UtpSocket> {
+        Ok(UtpSocket::from_udp(UdpSocket::bind(address).await?))
+    }
+
+    pub fn from_udp(udp: UdpSocket) -> UtpSocket {
+        let udp = Arc::new(udp);
+        let (incoming_tx, incoming_rx) = mpsc::channel(ACCEPT_BACKLOG);
+
+        let inner = Arc::new_cyclic(|weak: &Weak<Inner>| {
+            let receive: JoinHandle<()> =
+                tokio::spawn(receive_loop(Arc::clone(&udp), weak.clone(), incoming_tx));
+            let tick: JoinHandle<()> = tokio::spawn(tick_loop(weak.clone()));
+            Inner {
+                udp,
+                connections: Mutex::new(HashMap::new()),
+                incoming: tokio::sync::Mutex::new(incoming_rx),
+                tasks: vec![receive.abort_handle(), tick.abort_handle()],
+            }
+        });
+        UtpSocket { inner }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.udp.local_addr()
+    }
+
+    pub async fn connect(&self, remote: SocketAddr) -> io::Result<UtpStream> {
+        let connection = {
+            let mut connections = self.inner.connections.lock().expect("Lock poisoned");
+            let recv_id = loop {
+                let recv_id: u16 = rand::thread_rng().gen();
+                if !connections.contains_key(&(remote, recv_id)) {
+                    break recv_id;
+                }
+            };
+            let connection = Arc::new(Mutex::new(Connection::outgoing(
+                Arc::clone(&self.inner.udp),
+                remote,
+                recv_id,
+            )));
+            connections.insert((remote, recv_id), Arc::clone(&connection));
+            connection
+        };
+
+        // Dropping the stream, e.g. when a race is lost, abandons the connection
+        let stream = UtpStream::new(connection, remote, self.clone());
+        poll_fn(|cx| stream.connection().poll_connected(cx)).await?;
+        Ok(stream)
+    }
+
+    pub async fn accept(&self) -> io::Result<(UtpStream, SocketAddr)> {
+        let stream = self
+            .inner
+            .incoming
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "uTP socket closed"))?;
+        let remote = stream.peer_addr();
+        Ok((stream, remote))
+    }
+}
+
+async fn receive_loop(udp: Arc<UdpSocket>, inner: Weak<Inner>, incoming: mpsc::Sender<UtpStream>) {
+    let mut buffer = vec![0u8; MAX_DATAGRAM_SIZE];
+    loop {
+        let (size, from) = match udp.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            // ICMP errors for earlier sends show up here on some platforms
+            Err(_) => continue,
+        };
+        let Some(packet) = Packet::decode(&buffer[..size]) else {
+            continue;
+        };
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+        dispatch(&inner, packet, from, &incoming);
+    }
+}
+
+fn dispatch(
+    inner: &Arc<Inner>,
+    packet: Packet,
+    from: SocketAddr,
+    incoming: &mpsc::Sender<UtpStream>,
+) {
+    // A SYN names the id the peer receives on, we receive on the next one
+    let recv_id = match packet.packet_type {
+        PacketType::Syn => packet.connection_id.wrapping_add(1),
+        _ => packet.connection_id,
+    };
+    let existing = inner
+        .connections
+        .lock()
+        .expect("Lock poisoned")
+        .get(&(from, recv_id))
+        .cloned();
+
+    match existing {
+        Some(connection) => connection
+            .lock()
+            .expect("Lock poisoned")
+            .on_packet(packet, Instant::now()),
+        None if packet.packet_type == PacketType::Syn => {
+            let Ok(permit) = incoming.try_reserve() else {
+                send_reset(&inner.udp, from, &packet);
+                return;
+            };
+            let connection = Arc::new(Mutex::new(Connection::incoming(
+                Arc::clone(&inner.udp),
+                from,
+                &packet,
+            )));
+            inner
+                .connections
+                .lock()
+                .expect("Lock poisoned")
+                .insert((from, recv_id), Arc::clone(&connection));
+            permit.send(UtpStream::new(
+                connection,
+                from,
+                UtpSocket {
+                    inner: Arc::clone(inner),
+                },
+            ));
+        }
+        None if packet.packet_type != PacketType::Reset => send_reset(&inner.udp, from, &packet),
+        None => (),
+    }
+}
+
+// Drives retransmission timers and forgets connections that are done
+async fn tick_loop(inner: Weak<Inner>) {
+    let mut interval = tokio::time::interval(TICK_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+        let connections: Vec<_> = inner
+            .connections
+            .lock()
+            .expect("Lock poisoned")
+            .values()
+            .cloned()
+            .collect();
+
+        let now = Instant::now();
+        let mut finished = Vec::new();
+        for connection in connections {
+            let mut connection = connection.lock().expect("Lock poisoned");
+            connection.on_tick(now);
+            if connection.is_finished() {
+                finished.push((connection.remote(), connection.recv_id()));
+            }
+        }
+        if !finished.is_empty() {
+            let mut connections = inner.connections.lock().expect("Lock poisoned");
+            for key in finished {
+                connections.remove(&key);
+            }
+        }
+    }
+}
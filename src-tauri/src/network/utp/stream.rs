@@ -0,0 +1,76 @@
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::{connection::Connection, socket::UtpSocket};
+
+// One uTP connection, used like a TCP stream
+pub struct UtpStream {
+    connection: Arc<Mutex<Connection>>,
+    remote: SocketAddr,
+    // Keeps the socket's receive loop running for as long as the stream is in use
+    _socket: UtpSocket,
+}
+
+impl UtpStream {
+    pub(super) fn new(
+        connection: Arc<Mutex<Connection>>,
+        remote: SocketAddr,
+        socket: UtpSocket,
+    ) -> UtpStream {
+        UtpStream {
+            connection,
+            remote,
+            _socket: socket,
+        }
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.remote
+    }
+
+    pub(super) fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.connection.lock().expect("Lock poisoned")
+    }
+}
+
+impl AsyncRead for UtpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.connection().poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for UtpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.connection().poll_write(cx, buf)
+    }
+
+    // Written bytes are handed to the connection straight away, it sends them as the window
+    // allows
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.connection().poll_shutdown(cx)
+    }
+}
+
+impl Drop for UtpStream {
+    fn drop(&mut self) {
+        self.connection().close();
+    }
+}
@@ -0,0 +1,266 @@
+use hyper::{body::HttpBody, client::HttpConnector, header, Body, Client, Request, StatusCode};
+use percent_encoding::percent_decode_str;
+use std::time::Duration;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    time::timeout,
+};
+use url::Url;
+
+use crate::{parsing::parser::torrent_metadata::TorrentMetadata, storage::file_layout::FileLayout};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_REDIRECTS: usize = 5;
+
+// A server with a copy of the torrent's files (BEP 19). Pieces are fetched as byte ranges of
+// those files, over HTTP or FTP. There is no TLS support, HTTPS web seeds are skipped.
+#[derive(Clone)]
+pub struct WebSeed {
+    url: Url,
+    client: Client<HttpConnector>,
+}
+
+impl WebSeed {
+    pub fn new(url: &str) -> Result<WebSeed, String> {
+        let url = Url::parse(url).map_err(|e| format!("Invalid web seed {}: {}", url, e))?;
+        match url.scheme() {
+            "http" | "ftp" => Ok(WebSeed {
+                url,
+                client: Client::new(),
+            }),
+            "https" => Err(format!("HTTPS web seeds are not supported: {}", url)),
+            scheme => Err(format!("Unsupported web seed scheme: {}", scheme)),
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        self.url.as_str()
+    }
+
+    // Downloads a whole piece, with one request per file the piece overlaps
+    pub async fn fetch_piece(
+        &self,
+        metadata: &TorrentMetadata,
+        piece_index: u32,
+    ) -> Result<Vec<u8>, String> {
+        let layout = FileLayout::from_metadata(metadata);
+        let offset = piece_index as u64 * layout.piece_length;
+        let length = layout.piece_size(piece_index as usize);
+
+        let mut piece = Vec::with_capacity(length as usize);
+        for span in layout.file_spans(offset, length) {
+            // Pad files are zeros that servers don't have (BEP 47)
+            if layout.files[span.file_index].padding {
+                piece.resize(piece.len() + span.length as usize, 0);
+                continue;
+            }
+            let url = self.file_url(metadata, span.file_index);
+            let data = match url.scheme() {
+                "ftp" => fetch_ftp_range(&url, span.file_offset, span.length).await?,
+                _ => {
+                    self.fetch_http_range(&url, span.file_offset, span.length)
+                        .await?
+                }
+            };
+            if data.len() as u64 != span.length {
+                return Err(format!(
+                    "Expected {} bytes from {}, got {}",
+                    span.length,
+                    url,
+                    data.len()
+                ));
+            }
+            piece.extend_from_slice(&data);
+        }
+        Ok(piece)
+    }
+
+    // Multi-file torrents live under their name below the url. For single-file torrents a url
+    // ending in a slash is a directory holding the file, anything else is the file itself.
+    fn file_url(&self, metadata: &TorrentMetadata, file_index: usize) -> Url {
+        let mut parts = vec![metadata.info.name.clone()];
+        match &metadata.info.files {
+            Some(files) => parts.extend(files[file_index].path.iter().cloned()),
+            None if self.url.path().ends_with('/') => (),
+            None => return self.url.clone(),
+        }
+
+        let mut url = self.url.clone();
+        if let Ok(mut segments) = url.path_segments_mut() {
+            segments.pop_if_empty().extend(parts);
+        }
+        url
+    }
+
+    async fn fetch_http_range(
+        &self,
+        url: &Url,
+        start: u64,
+        length: u64,
+    ) -> Result<Vec<u8>, String> {
+        let mut url = url.clone();
+        for _ in 0..=MAX_REDIRECTS {
+            let request = Request::get(url.as_str())
+                .header(
+                    header::RANGE,
+                    format!("bytes={}-{}", start, start + length - 1),
+                )
+                .body(Body::empty())
+                .map_err(|e| e.to_string())?;
+            let response = timeout(REQUEST_TIMEOUT, self.client.request(request))
+                .await
+                .map_err(|_| format!("Request to {} timed out", url))?
+                .map_err(|e| format!("Request to {} failed: {}", url, e))?;
+
+            let status = response.status();
+            if status.is_redirection() {
+                let location = response
+                    .headers()
+                    .get(header::LOCATION)
+                    .and_then(|location| location.to_str().ok())
+                    .ok_or_else(|| format!("Redirect from {} without a location", url))?;
+                url = url
+                    .join(location)
+                    .map_err(|e| format!("Invalid redirect from {}: {}", url, e))?;
+                continue;
+            }
+
+            let skip = match status {
+                StatusCode::PARTIAL_CONTENT => 0,
+                // The server ignored the range and sends the whole file
+                StatusCode::OK => start,
+                status => return Err(format!("{} answered with {}", url, status)),
+            };
+            return timeout(
+                REQUEST_TIMEOUT,
+                read_range(response.into_body(), skip, length),
+            )
+            .await
+            .map_err(|_| format!("Reading from {} timed out", url))?
+            .map_err(|e| format!("Reading from {} failed: {}", url, e));
+        }
+        Err(format!("Too many redirects from {}", url))
+    }
+}
+
+// Keeps `length` bytes of the body after skipping `skip`, the rest is never downloaded. A body
+// that ends early comes back short.
+async fn read_range(mut body: Body, skip: u64, length: u64) -> Result<Vec<u8>, hyper::Error> {
+    let mut data = Vec::with_capacity(length as usize);
+    let mut position = 0;
+    while (data.len() as u64) < length {
+        let Some(chunk) = body.data().await else {
+            break;
+        };
+        let chunk = chunk?;
+        let chunk_start = position;
+        position += chunk.len() as u64;
+        if position <= skip {
+            continue;
+        }
+        let from = skip.saturating_sub(chunk_start) as usize;
+        let wanted = (length - data.len() as u64) as usize;
+        data.extend_from_slice(&chunk[from..chunk.len().min(from + wanted)]);
+    }
+    Ok(data)
+}
+
+// Passive mode FTP: log in, seek to `start` with REST and read `length` bytes of the file
+async fn fetch_ftp_range(url: &Url, start: u64, length: u64) -> Result<Vec<u8>, String> {
+    timeout(REQUEST_TIMEOUT, async {
+        let host = url.host_str().ok_or("FTP url without a host")?;
+        let port = url.port_or_known_default().unwrap_or(21);
+        let control = TcpStream::connect((host, port))
+            .await
+            .map_err(|e| format!("Couldn't connect to {}: {}", host, e))?;
+        let server_ip = control.peer_addr().map_err(|e| e.to_string())?.ip();
+        let mut control = BufReader::new(control);
+        read_reply(&mut control, &[220]).await?;
+
+        let user = match url.username() {
+            "" => "anonymous".to_string(),
+            user => decode(user),
+        };
+        let (code, _) = ftp_command(&mut control, &format!("USER {}", user), &[230, 331]).await?;
+        if code == 331 {
+            let password = url.password().map(decode).unwrap_or_default();
+            ftp_command(&mut control, &format!("PASS {}", password), &[230]).await?;
+        }
+        ftp_command(&mut control, "TYPE I", &[200]).await?;
+
+        let (_, passive) = ftp_command(&mut control, "PASV", &[227]).await?;
+        // The address in the reply is often a private one behind NAT, the port is what matters
+        let mut data = TcpStream::connect((server_ip, parse_pasv_port(&passive)?))
+            .await
+            .map_err(|e| format!("Couldn't open the FTP data connection: {}", e))?;
+
+        ftp_command(&mut control, &format!("REST {}", start), &[350]).await?;
+        let retrieve = format!("RETR {}", decode(url.path()));
+        ftp_command(&mut control, &retrieve, &[125, 150]).await?;
+
+        let mut buffer = vec![0u8; length as usize];
+        data.read_exact(&mut buffer)
+            .await
+            .map_err(|e| format!("FTP transfer failed: {}", e))?;
+        Ok(buffer)
+    })
+    .await
+    .map_err(|_| format!("Request to {} timed out", url))?
+}
+
+async fn ftp_command(
+    control: &mut BufReader<TcpStream>,
+    command: &str,
+    expected: &[u16],
+) -> Result<(u16, String), String> {
+    control
+        .get_mut()
+        .write_all(format!("{}\r\n", command).as_bytes())
+        .await
+        .map_err(|e| format!("FTP command failed: {}", e))?;
+    read_reply(control, expected).await
+}
+
+// Reads a reply, which spans several lines when the code is followed by a dash
+async fn read_reply(
+    control: &mut BufReader<TcpStream>,
+    expected: &[u16],
+) -> Result<(u16, String), String> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = control
+            .read_line(&mut line)
+            .await
+            .map_err(|e| format!("FTP reply failed: {}", e))?;
+        if read == 0 {
+            return Err("FTP server closed the connection".to_string());
+        }
+        let code = line.get(..3).and_then(|code| code.parse::<u16>().ok());
+        if let (Some(code), Some(b' ')) = (code, line.as_bytes().get(3)) {
+            let text = line[4..].trim_end().to_string();
+            return match expected.contains(&code) {
+                true => Ok((code, text)),
+                false => Err(format!("FTP server answered {} {}", code, text)),
+            };
+        }
+    }
+}
+
+// "Entering Passive Mode (h1,h2,h3,h4,p1,p2)"
+fn parse_pasv_port(reply: &str) -> Result<u16, String> {
+    let numbers: Vec<u16> = reply
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|part| !part.is_empty())
+        .filter_map(|part| part.parse().ok())
+        .collect();
+    match numbers[..] {
+        [.., high, low] if numbers.len() >= 6 && high < 256 && low < 256 => Ok(high * 256 + low),
+        _ => Err(format!("Invalid passive mode reply: {}", reply)),
+    }
+}
+
+fn decode(text: &str) -> String {
+    percent_decode_str(text).decode_utf8_lossy().to_string()
+}
@@ -0,0 +1,28 @@
+// Returns the length in bytes of the bencoded value at the start of `data`, or None if it is
+// malformed or truncated. Needed where a bencoded value is followed by raw bytes, as in BEP 9.
+pub fn bencode_value_len(data: &[u8]) -> Option<usize> {
+    match data.first()? {
+        b'i' => data
+            .iter()
+            .position(|&byte| byte == b'e')
+            .map(|end| end + 1),
+        b'l' | b'd' => {
+            let mut position = 1;
+            while *data.get(position)? != b'e' {
+                position += bencode_value_len(&data[position..])?;
+            }
+            Some(position + 1)
+        }
+        b'0'..=b'9' => {
+            let colon = data.iter().position(|&byte| byte == b':')?;
+            let length: usize = std::str::from_utf8(&data[..colon]).ok()?.parse().ok()?;
+            let end = colon + 1 + length;
+            if end <= data.len() {
+                Some(end)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,97 @@
+use url::Url;
+
+use super::parser::parse_error::ParseError;
+
+// The parts of a `magnet:?xt=urn:btih:...` link needed to find peers and fetch the metadata.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MagnetLink {
+    pub info_hash: [u8; 20],
+    pub display_name: Option<String>,
+    pub trackers: Vec<String>,
+}
+
+impl MagnetLink {
+    pub fn info_hash_hex(&self) -> String {
+        hex::encode(self.info_hash)
+    }
+}
+
+pub fn parse_magnet_link(uri: &str) -> Result<MagnetLink, ParseError> {
+    let url = Url::parse(uri.trim())
+        .map_err(|e| ParseError::ParseError(format!("Invalid magnet link: {}", e)))?;
+    if url.scheme() != "magnet" {
+        return Err(ParseError::ParseError(
+            "Magnet links must start with 'magnet:'".into(),
+        ));
+    }
+
+    let mut info_hash = None;
+    let mut display_name = None;
+    let mut trackers = Vec::new();
+
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "xt" => {
+                if let Some(encoded) = value.strip_prefix("urn:btih:") {
+                    info_hash = Some(decode_info_hash(encoded)?);
+                }
+            }
+            "dn" => display_name = Some(value.to_string()),
+            "tr" => trackers.push(value.to_string()),
+            _ => (),
+        }
+    }
+
+    Ok(MagnetLink {
+        info_hash: info_hash.ok_or(ParseError::ParseError(
+            "Magnet link has no BitTorrent info hash".into(),
+        ))?,
+        display_name,
+        trackers,
+    })
+}
+
+// Info hashes come either hex encoded (40 characters) or base32 encoded (32 characters)
+fn decode_info_hash(encoded: &str) -> Result<[u8; 20], ParseError> {
+    let bytes = match encoded.len() {
+        40 => hex::decode(encoded).map_err(|e| ParseError::ParseError(e.to_string()))?,
+        32 => decode_base32(encoded)?,
+        _ => {
+            return Err(ParseError::ParseError(format!(
+                "Invalid info hash length: {}",
+                encoded.len()
+            )))
+        }
+    };
+
+    bytes
+        .try_into()
+        .map_err(|_| ParseError::ParseError("Info hash must be 20 bytes".into()))
+}
+
+fn decode_base32(encoded: &str) -> Result<Vec<u8>, ParseError> {
+    let mut bytes = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer: u64 = 0;
+    let mut bits = 0;
+
+    for character in encoded.chars() {
+        let value = match character.to_ascii_uppercase() {
+            letter @ 'A'..='Z' => letter as u64 - 'A' as u64,
+            digit @ '2'..='7' => digit as u64 - '2' as u64 + 26,
+            other => {
+                return Err(ParseError::ParseError(format!(
+                    "Invalid base32 character: {}",
+                    other
+                )))
+            }
+        };
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+
+    Ok(bytes)
+}
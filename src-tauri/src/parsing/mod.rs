@@ -1 +1,3 @@
+pub mod bencode;
+pub mod magnet;
 pub mod parser;
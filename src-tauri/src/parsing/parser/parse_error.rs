@@ -1,4 +1,6 @@
-use serde_bencode::de;
+use serde_bencode::{de, value::Value};
+
+use crate::hash::{compute_info_hash, compute_info_hash_v2, truncate_info_hash};
 
 use super::torrent_metadata::TorrentMetadata;
 
@@ -43,7 +45,25 @@ pub fn parse_bencoded_torrent(bencoded_metadata: Vec<u8>) -> Result<TorrentMetad
     let bencode: Result<TorrentMetadata, _> = de::from_bytes(&bencoded_metadata);
 
     match bencode {
-        Ok(torrent_metadata) => Ok(torrent_metadata),
+        Ok(mut torrent_metadata) => {
+            let info = info_from_bencode(&bencoded_metadata)?;
+            torrent_metadata.info_hash =
+                compute_info_hash(&info).map_err(|e| ParseError::ParseError(e.to_string()))?;
+            if torrent_metadata.info.has_v2() {
+                torrent_metadata.info_hash_v2 = compute_info_hash_v2(&info)
+                    .map_err(|e| ParseError::ParseError(e.to_string()))?;
+                if !torrent_metadata.info.has_v1() {
+                    torrent_metadata.info_hash =
+                        truncate_info_hash(&torrent_metadata.info_hash_v2).to_vec();
+                }
+            }
+            torrent_metadata.info.add_v1_layout();
+            torrent_metadata
+                .info
+                .check_paths()
+                .map_err(ParseError::ParseError)?;
+            Ok(torrent_metadata)
+        }
         _ => {
             return Err(ParseError::ParseError(
                 "Top level bencode should be a dict".into(),
@@ -51,3 +71,11 @@ pub fn parse_bencoded_torrent(bencoded_metadata: Vec<u8>) -> Result<TorrentMetad
         }
     }
 }
+
+fn info_from_bencode(bencoded_metadata: &[u8]) -> Result<Value, ParseError> {
+    match de::from_bytes::<Value>(bencoded_metadata)? {
+        Value::Dict(mut dict) => dict.remove(&b"info".to_vec()),
+        _ => None,
+    }
+    .ok_or(ParseError::ParseError("Missing 'info' dictionary".into()))
+}
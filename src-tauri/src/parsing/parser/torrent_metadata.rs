@@ -1,19 +1,248 @@
-use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_bencode::value::Value;
+use serde_bytes::ByteBuf;
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{config::V2_HASH_SIZE, hash::truncate_info_hash};
 
 #[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub struct TorrentMetadata {
     pub info: TorrentMetadataInfo,
+    // Not part of the torrent file, filled in from the hash of the info dictionary while parsing
+    // For v2-only torrents this is the truncated v2 hash, which is what goes on the wire
+    #[serde(skip)]
     pub info_hash: Vec<u8>,
+    // SHA-256 of the info dictionary for v2 and hybrid torrents (BEP 52), empty otherwise
+    #[serde(skip)]
+    pub info_hash_v2: Vec<u8>,
+    #[serde(default)]
     pub announce: String,
+    // Web seeds (BEP 19), servers with a copy of the files
+    #[serde(default, rename = "url-list", deserialize_with = "one_or_many")]
+    pub url_list: Vec<String>,
+    // v2 piece hashes of every file larger than a piece, keyed by the file's pieces root
+    #[serde(default, rename = "piece layers")]
+    pub piece_layers: HashMap<ByteBuf, ByteBuf>,
+    // Where the downloaded data lives, decided when the torrent is added
+    #[serde(skip)]
     pub file_path: PathBuf,
+    // Where the data goes once the download is complete, None when it stays at `file_path`
+    #[serde(skip)]
+    pub completed_path: Option<PathBuf>,
+    // Files are named with a `.part` suffix until the download is complete
+    #[serde(skip)]
+    pub part_suffix: bool,
+    // Chosen when the torrent is added, picks the completed directory from the configured rules
+    #[serde(skip)]
+    pub category: Option<String>,
+    // Our session peer id, set when the torrent is added. Never part of the torrent file.
+    #[serde(skip)]
     pub peer_id: String,
+    // Sent with every announce so the tracker can tell it's still us if our IP changes
+    #[serde(skip)]
+    pub tracker_key: String,
+    // The `tracker id` the tracker gave each swarm, echoed back on later announces
+    #[serde(skip)]
+    pub tracker_ids: HashMap<Vec<u8>, String>,
 }
 
 #[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub struct TorrentMetadataInfo {
+    // Concatenated 20 byte SHA-1 hashes of every piece, absent from v2-only torrents
+    #[serde(default, with = "serde_bytes")]
     pub pieces: Vec<u8>,
+    #[serde(rename = "piece length")]
     pub piece_length: i64,
+    // Only present in single-file torrents
+    #[serde(default)]
     pub length: i64,
     pub name: String,
+    // Only present in multi-file torrents
+    #[serde(default)]
+    pub files: Option<Vec<TorrentMetadataFile>>,
+    // 2 for v2 and hybrid torrents
+    #[serde(default, rename = "meta version")]
+    pub meta_version: Option<i64>,
+    // v2 file list: nested directories, with each file under an empty key
+    #[serde(default, rename = "file tree")]
+    pub file_tree: Option<Value>,
+    // 1 for private torrents (BEP 27)
+    #[serde(default)]
+    pub private: Option<i64>,
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct TorrentMetadataFile {
+    pub length: i64,
+    pub path: Vec<String>,
+    // "p" marks the pad files (BEP 47) hybrid torrents use to align files to pieces
+    #[serde(default)]
+    pub attr: Option<String>,
+}
+
+// A file of the v2 file tree. Empty files have no pieces root.
+#[derive(PartialEq, Debug, Clone)]
+pub struct V2File {
+    pub path: Vec<String>,
+    pub length: u64,
+    pub pieces_root: Option<[u8; 32]>,
+}
+
+impl TorrentMetadata {
+    // The 20 byte info hashes peers and trackers know the torrent by. Hybrid torrents are in
+    // the v1 swarm and the v2 one.
+    pub fn swarm_info_hashes(&self) -> Vec<Vec<u8>> {
+        let mut info_hashes = vec![self.info_hash.clone()];
+        if self.info_hash_v2.len() == V2_HASH_SIZE {
+            let truncated = truncate_info_hash(&self.info_hash_v2).to_vec();
+            if truncated != self.info_hash {
+                info_hashes.push(truncated);
+            }
+        }
+        info_hashes
+    }
+}
+
+impl TorrentMetadataInfo {
+    // Private torrents only get peers from their own trackers, so no DHT, PEX or local peer
+    // discovery for them
+    pub fn is_private(&self) -> bool {
+        self.private == Some(1)
+    }
+
+    pub fn has_v1(&self) -> bool {
+        !self.pieces.is_empty()
+    }
+
+    pub fn has_v2(&self) -> bool {
+        self.meta_version == Some(2) && self.file_tree.is_some()
+    }
+
+    // The files of the file tree in torrent order, which is the order of their pieces
+    pub fn v2_files(&self) -> Vec<V2File> {
+        let mut files = Vec::new();
+        if let Some(tree) = &self.file_tree {
+            collect_v2_files(tree, &mut Vec::new(), &mut files);
+        }
+        files
+    }
+
+    // Gives v2-only torrents the v1 file list a hybrid torrent would have, pad files included, so
+    // the rest of the code sees the same piece-aligned layout either way
+    pub fn add_v1_layout(&mut self) {
+        if self.has_v1() || !self.has_v2() || self.files.is_some() || self.length != 0 {
+            return;
+        }
+        let v2_files = self.v2_files();
+        if let [file] = &v2_files[..] {
+            if file.path == [self.name.clone()] {
+                self.length = file.length as i64;
+                return;
+            }
+        }
+
+        let piece_length = self.piece_length.max(1) as u64;
+        let mut files = Vec::new();
+        for (index, file) in v2_files.iter().enumerate() {
+            files.push(TorrentMetadataFile {
+                length: file.length as i64,
+                path: file.path.clone(),
+                attr: None,
+            });
+            let padding = (piece_length - file.length % piece_length) % piece_length;
+            if padding > 0 && index + 1 < v2_files.len() {
+                files.push(TorrentMetadataFile {
+                    length: padding as i64,
+                    path: vec![".pad".to_string(), padding.to_string()],
+                    attr: Some("p".to_string()),
+                });
+            }
+        }
+        self.files = Some(files);
+    }
+
+    // Every name the files are saved under must stay inside the torrent's directory, a crafted
+    // torrent could otherwise write anywhere with `..` or an absolute path
+    pub fn check_paths(&self) -> Result<(), String> {
+        let paths = self
+            .files
+            .iter()
+            .flatten()
+            .map(|file| file.path.clone())
+            .chain(self.v2_files().into_iter().map(|file| file.path));
+        for path in std::iter::once(vec![self.name.clone()]).chain(paths) {
+            if path.is_empty() || !path.iter().all(|part| is_plain_name(part)) {
+                return Err(format!("Unsafe file path in torrent: {:?}", path));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TorrentMetadataFile {
+    pub fn is_padding(&self) -> bool {
+        self.attr.as_ref().is_some_and(|attr| attr.contains('p'))
+    }
+}
+
+// A single file or directory name, not empty, `.`, `..`, a root or anything with a separator
+fn is_plain_name(part: &str) -> bool {
+    let mut components = Path::new(part).components();
+    matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(name)), None) if name == part
+    )
+}
+
+// Bencoded dictionaries are sorted by key, so walking them in key order gives the torrent order
+fn collect_v2_files(node: &Value, path: &mut Vec<String>, files: &mut Vec<V2File>) {
+    let Value::Dict(entries) = node else {
+        return;
+    };
+    let mut keys: Vec<&Vec<u8>> = entries.keys().collect();
+    keys.sort();
+
+    for key in keys {
+        match (&entries[key], key.is_empty()) {
+            (Value::Dict(file), true) => {
+                let length = match file.get(&b"length".to_vec()) {
+                    Some(Value::Int(length)) => (*length).max(0) as u64,
+                    _ => 0,
+                };
+                let pieces_root = match file.get(&b"pieces root".to_vec()) {
+                    Some(Value::Bytes(root)) => root.as_slice().try_into().ok(),
+                    _ => None,
+                };
+                files.push(V2File {
+                    path: path.clone(),
+                    length,
+                    pieces_root,
+                });
+            }
+            (child, false) => {
+                path.push(String::from_utf8_lossy(key).to_string());
+                collect_v2_files(child, path, files);
+                path.pop();
+            }
+            _ => (),
+        }
+    }
+}
+
+// `url-list` is a single string when the torrent has only one web seed
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(url) => vec![url],
+        OneOrMany::Many(urls) => urls,
+    })
 }
@@ -0,0 +1,108 @@
+use hyper::{client::HttpConnector, header, Body, Client, Method, Request, StatusCode};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::fmt;
+
+use super::{
+    protocol::{RpcError, RpcRequest, RpcResponse},
+    server::RPC_PATH,
+};
+
+#[derive(Debug)]
+pub enum RpcClientError {
+    // The daemon could not be reached at all
+    Connection(String),
+    Unauthorized,
+    // The daemon answered with a JSON-RPC error
+    Rpc(RpcError),
+    InvalidResponse(String),
+}
+
+impl fmt::Display for RpcClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RpcClientError::Connection(e) => write!(f, "Failed to reach daemon: {}", e),
+            RpcClientError::Unauthorized => write!(f, "Daemon rejected the RPC token"),
+            RpcClientError::Rpc(e) => write!(f, "{}", e.message),
+            RpcClientError::InvalidResponse(e) => write!(f, "Invalid response from daemon: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RpcClientError {}
+
+// Talks to a running `pirated` over its JSON-RPC API.
+pub struct RpcClient {
+    client: Client<HttpConnector>,
+    url: String,
+    token: String,
+}
+
+impl RpcClient {
+    // `address` is either `host:port` or a full `http://host:port` url
+    pub fn new(address: &str, token: String) -> Self {
+        let base = if address.starts_with("http://") {
+            address.trim_end_matches('/').to_string()
+        } else {
+            format!("http://{}", address)
+        };
+        let url = if base.ends_with(RPC_PATH) {
+            base
+        } else {
+            format!("{}{}", base, RPC_PATH)
+        };
+
+        RpcClient {
+            client: Client::new(),
+            url,
+            token,
+        }
+    }
+
+    pub async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Result<T, RpcClientError> {
+        let request = RpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.to_string(),
+            params,
+            id: json!(1),
+        };
+        let body = serde_json::to_vec(&request)
+            .map_err(|e| RpcClientError::InvalidResponse(e.to_string()))?;
+
+        let http_request = Request::builder()
+            .method(Method::POST)
+            .uri(&self.url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::AUTHORIZATION, format!("Bearer {}", self.token))
+            .body(Body::from(body))
+            .map_err(|e| RpcClientError::Connection(e.to_string()))?;
+
+        let response = self
+            .client
+            .request(http_request)
+            .await
+            .map_err(|e| RpcClientError::Connection(e.to_string()))?;
+
+        match response.status() {
+            StatusCode::OK => (),
+            StatusCode::UNAUTHORIZED => return Err(RpcClientError::Unauthorized),
+            status => return Err(RpcClientError::InvalidResponse(status.to_string())),
+        }
+
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| RpcClientError::Connection(e.to_string()))?;
+        let response: RpcResponse = serde_json::from_slice(&body)
+            .map_err(|e| RpcClientError::InvalidResponse(e.to_string()))?;
+
+        if let Some(error) = response.error {
+            return Err(RpcClientError::Rpc(error));
+        }
+        serde_json::from_value(response.result.unwrap_or(Value::Null))
+            .map_err(|e| RpcClientError::InvalidResponse(e.to_string()))
+    }
+}
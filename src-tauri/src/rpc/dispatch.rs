@@ -0,0 +1,173 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    app_state::AppState,
+    commands::{self, add_torrent::AddTorrentOptions},
+    storage::backend::MoveConflict,
+    torrent_management::queue::QueueMove,
+};
+
+use super::protocol::{RpcError, COMMAND_ERROR, INVALID_PARAMS, METHOD_NOT_FOUND};
+
+// Parameter names follow the Rust argument names, the camelCase names the frontend
+// passes to `invoke` are accepted as well.
+#[derive(Deserialize)]
+struct AddTorrentParams {
+    #[serde(alias = "torrentFile")]
+    torrent_file: String,
+    #[serde(flatten)]
+    options: AddTorrentOptions,
+}
+
+#[derive(Deserialize)]
+struct TorrentParams {
+    #[serde(alias = "torrentHash")]
+    torrent_hash: String,
+}
+
+#[derive(Deserialize)]
+struct StreamUrlParams {
+    #[serde(alias = "torrentHash")]
+    torrent_hash: String,
+    #[serde(alias = "fileIndex")]
+    file_index: usize,
+}
+
+#[derive(Deserialize)]
+struct MoveStorageParams {
+    #[serde(alias = "torrentHash")]
+    torrent_hash: String,
+    destination: String,
+    conflict: Option<MoveConflict>,
+}
+
+#[derive(Deserialize)]
+struct QueuePositionParams {
+    #[serde(alias = "torrentHash")]
+    torrent_hash: String,
+    movement: QueueMove,
+}
+
+#[derive(Deserialize)]
+struct AutoManagedParams {
+    #[serde(alias = "torrentHash")]
+    torrent_hash: String,
+    #[serde(alias = "autoManaged")]
+    auto_managed: bool,
+}
+
+#[derive(Deserialize)]
+struct SetConfigParams {
+    changes: Value,
+}
+
+#[derive(Deserialize)]
+struct RemoveTorrentParams {
+    #[serde(alias = "torrentHash")]
+    torrent_hash: String,
+    #[serde(default, alias = "deleteData")]
+    delete_data: bool,
+}
+
+// Runs the command named `method`, mirroring the Tauri command of the same name.
+pub async fn dispatch(state: &AppState, method: &str, params: Value) -> Result<Value, RpcError> {
+    match method {
+        "add_torrent" => {
+            let params: AddTorrentParams = parse_params(params)?;
+            to_rpc_result(
+                commands::add_torrent::handle(state, params.torrent_file, params.options).await,
+            )
+        }
+        "start_torrent" => {
+            let params: TorrentParams = parse_params(params)?;
+            to_rpc_result(commands::start_torrent::handle(state, params.torrent_hash).await)
+        }
+        "pause_torrent" => {
+            let params: TorrentParams = parse_params(params)?;
+            to_rpc_result(commands::pause_torrent::handle(state, params.torrent_hash).await)
+        }
+        "resume_torrent" => {
+            let params: TorrentParams = parse_params(params)?;
+            to_rpc_result(commands::resume_torrent::handle(state, params.torrent_hash).await)
+        }
+        "stop_torrent" => {
+            let params: TorrentParams = parse_params(params)?;
+            to_rpc_result(commands::stop_torrent::handle(state, params.torrent_hash).await)
+        }
+        "remove_torrent" => {
+            let params: RemoveTorrentParams = parse_params(params)?;
+            to_rpc_result(
+                commands::remove_torrent::handle(state, params.torrent_hash, params.delete_data)
+                    .await,
+            )
+        }
+        "list_torrents" => to_rpc_result(commands::list_torrents::handle(state).await),
+        "get_torrent_details" => {
+            let params: TorrentParams = parse_params(params)?;
+            to_rpc_result(commands::get_torrent_details::handle(state, params.torrent_hash).await)
+        }
+        "get_stream_url" => {
+            let params: StreamUrlParams = parse_params(params)?;
+            to_rpc_result(
+                commands::get_stream_url::handle(state, params.torrent_hash, params.file_index)
+                    .await,
+            )
+        }
+        "get_config" => to_rpc_result(commands::get_config::handle(state).await),
+        "get_port_mapping" => to_rpc_result(commands::get_port_mapping::handle(state).await),
+        "get_disk_stats" => to_rpc_result(commands::get_disk_stats::handle(state).await),
+        "move_storage" => {
+            let params: MoveStorageParams = parse_params(params)?;
+            to_rpc_result(
+                commands::move_storage::handle(
+                    state,
+                    params.torrent_hash,
+                    params.destination,
+                    params.conflict,
+                )
+                .await,
+            )
+        }
+        "move_queue_position" => {
+            let params: QueuePositionParams = parse_params(params)?;
+            to_rpc_result(
+                commands::move_queue_position::handle(state, params.torrent_hash, params.movement)
+                    .await,
+            )
+        }
+        "set_auto_managed" => {
+            let params: AutoManagedParams = parse_params(params)?;
+            to_rpc_result(
+                commands::set_auto_managed::handle(state, params.torrent_hash, params.auto_managed)
+                    .await,
+            )
+        }
+        "set_config" => {
+            let params: SetConfigParams = parse_params(params)?;
+            to_rpc_result(commands::set_config::handle(state, params.changes).await)
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method: {}", method),
+        )),
+    }
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    // Commands without arguments may be called with the params omitted
+    let params = match params {
+        Value::Null => Value::Object(Default::default()),
+        params => params,
+    };
+    serde_json::from_value(params)
+        .map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid params: {}", e)))
+}
+
+fn to_rpc_result<T: Serialize>(result: Result<T, String>) -> Result<Value, RpcError> {
+    match result {
+        Ok(value) => serde_json::to_value(value)
+            .map_err(|e| RpcError::new(COMMAND_ERROR, format!("Failed to encode result: {}", e))),
+        Err(message) => Err(RpcError::new(COMMAND_ERROR, message)),
+    }
+}
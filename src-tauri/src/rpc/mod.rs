@@ -0,0 +1,4 @@
+pub mod client;
+pub mod dispatch;
+pub mod protocol;
+pub mod server;
@@ -0,0 +1,155 @@
+use hyper::{
+    header,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde_json::Value;
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+use crate::app_state::AppState;
+
+use super::{
+    dispatch::dispatch,
+    protocol::{RpcError, RpcRequest, RpcResponse, INVALID_REQUEST, PARSE_ERROR},
+};
+
+pub const RPC_PATH: &str = "/rpc";
+
+// Binds the JSON-RPC control API and serves it in the background, returning the bound address.
+// Every request must carry `Authorization: Bearer <token>`.
+pub async fn start_rpc_server(
+    state: Arc<AppState>,
+    address: &str,
+    token: String,
+) -> Result<SocketAddr, String> {
+    let listener = std::net::TcpListener::bind(address)
+        .map_err(|e| format!("Failed to bind RPC server: {}", e))?;
+    listener
+        .set_nonblocking(true)
+        .map_err(|e| format!("Failed to configure RPC server: {}", e))?;
+
+    let token = Arc::new(token);
+    let make_service = make_service_fn(move |_| {
+        let state = Arc::clone(&state);
+        let token = Arc::clone(&token);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle_request(Arc::clone(&state), Arc::clone(&token), request)
+            }))
+        }
+    });
+
+    let server = Server::from_tcp(listener)
+        .map_err(|e| format!("Failed to start RPC server: {}", e))?
+        .serve(make_service);
+    let local_addr = server.local_addr();
+
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            println!("RPC server error: {}", e);
+        }
+    });
+
+    Ok(local_addr)
+}
+
+async fn handle_request(
+    state: Arc<AppState>,
+    token: Arc<String>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    if request.uri().path() != RPC_PATH {
+        return Ok(status_response(StatusCode::NOT_FOUND));
+    }
+    if request.method() != Method::POST {
+        return Ok(status_response(StatusCode::METHOD_NOT_ALLOWED));
+    }
+    if !is_authorized(&request, &token) {
+        return Ok(Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(header::WWW_AUTHENTICATE, "Bearer")
+            .body(Body::empty())
+            .unwrap_or_default());
+    }
+
+    let body = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(body) => body,
+        Err(_) => return Ok(status_response(StatusCode::BAD_REQUEST)),
+    };
+
+    let response = match serde_json::from_slice::<Value>(&body) {
+        Ok(Value::Array(requests)) if !requests.is_empty() => {
+            let mut responses = Vec::with_capacity(requests.len());
+            for request in requests {
+                responses.push(handle_call(&state, request).await);
+            }
+            serde_json::to_vec(&responses)
+        }
+        Ok(request) => serde_json::to_vec(&handle_call(&state, request).await),
+        Err(e) => serde_json::to_vec(&RpcResponse::from_result(
+            Value::Null,
+            Err(RpcError::new(PARSE_ERROR, format!("Parse error: {}", e))),
+        )),
+    };
+
+    Ok(match response {
+        Ok(body) => Response::builder()
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap_or_default(),
+        Err(_) => status_response(StatusCode::INTERNAL_SERVER_ERROR),
+    })
+}
+
+async fn handle_call(state: &AppState, request: Value) -> RpcResponse {
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let request: RpcRequest = match serde_json::from_value(request) {
+        Ok(request) => request,
+        Err(e) => {
+            return RpcResponse::from_result(
+                id,
+                Err(RpcError::new(
+                    INVALID_REQUEST,
+                    format!("Invalid request: {}", e),
+                )),
+            )
+        }
+    };
+
+    if request.jsonrpc != "2.0" {
+        return RpcResponse::from_result(
+            request.id,
+            Err(RpcError::new(
+                INVALID_REQUEST,
+                "Only JSON-RPC 2.0 is supported",
+            )),
+        );
+    }
+
+    let result = dispatch(state, &request.method, request.params).await;
+    RpcResponse::from_result(request.id, result)
+}
+
+fn is_authorized(request: &Request<Body>, token: &str) -> bool {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|provided| constant_time_eq(provided.trim().as_bytes(), token.as_bytes()))
+        .unwrap_or(false)
+}
+
+// Compares without short-circuiting so the token can't be guessed byte by byte from response times
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
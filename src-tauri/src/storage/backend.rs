@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+use std::{io, path::Path, sync::Arc};
+
+use crate::config::AllocationMode;
+
+use super::file_layout::{FileLayout, FileSpan};
+
+pub type SharedStorage = Arc<dyn Storage>;
+
+// What moving the storage does about files that already exist at the destination
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MoveConflict {
+    // Nothing is moved
+    #[default]
+    Fail,
+    // Our files overwrite them
+    Replace,
+    // Their files are used from now on, ours stay behind at the old location
+    KeepExisting,
+}
+
+// Where a torrent's data lives. Blocks are addressed by piece like on the wire, the backend
+// maps them onto the files of its layout. Pad files read as zeros and are never stored.
+pub trait Storage: Send + Sync {
+    fn layout(&self) -> FileLayout;
+
+    // Fails with NotFound or UnexpectedEof when the block isn't stored yet
+    fn read_block(&self, piece_index: u32, begin: u32, length: u32) -> io::Result<Vec<u8>>;
+
+    fn write_block(&self, piece_index: u32, begin: u32, data: &[u8]) -> io::Result<()>;
+
+    // Creates the files the way `mode` asks before anything is written. Fails with StorageFull
+    // when what is still missing doesn't fit on the disk.
+    fn allocate(&self, mode: AllocationMode) -> io::Result<()>;
+
+    // Makes the blocks written so far durable
+    fn flush(&self) -> io::Result<()>;
+
+    // Moves the stored files to their paths in `target`, the same torrent's files somewhere
+    // else, which becomes the layout. `progress` is told the bytes moved so far and the bytes to
+    // move in total.
+    fn move_storage(
+        &self,
+        target: &FileLayout,
+        conflict: MoveConflict,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> io::Result<()>;
+
+    // Removes every stored file. Files that are already gone are not an error.
+    fn delete(&self) -> io::Result<()>;
+
+    // Whether every file is stored with its full length, which is when data found there is
+    // worth verifying
+    fn check_file_sizes(&self) -> io::Result<bool>;
+}
+
+// Storage calls block on the disk, async code runs them on the blocking thread pool
+pub async fn run_blocking<T, F>(storage: &SharedStorage, call: F) -> io::Result<T>
+where
+    F: FnOnce(&dyn Storage) -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let storage = Arc::clone(storage);
+    tokio::task::spawn_blocking(move || call(&*storage))
+        .await
+        .map_err(io::Error::other)?
+}
+
+// Moving onto a file that is already there with MoveConflict::Fail
+pub fn destination_exists(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("{} already exists", path.display()),
+    )
+}
+
+// The file regions holding a block, which must lie within its piece
+pub fn block_spans(
+    layout: &FileLayout,
+    piece_index: u32,
+    begin: u32,
+    length: u32,
+) -> io::Result<Vec<FileSpan>> {
+    let piece_size = layout.piece_size(piece_index as usize);
+    if begin as u64 + length as u64 > piece_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Block at {} of {} bytes is outside piece {}",
+                begin, length, piece_index
+            ),
+        ));
+    }
+    let offset = piece_index as u64 * layout.piece_length + begin as u64;
+    Ok(layout.file_spans(offset, length as u64))
+}
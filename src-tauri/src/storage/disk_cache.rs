@@ -0,0 +1,205 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use super::backend::SharedStorage;
+
+// A piece of one torrent, torrents are told apart by the id of their disk handle
+pub type PieceKey = (u64, u32);
+
+// Blocks of a piece that isn't complete yet, waiting to be written with the rest of it
+pub struct PendingPiece {
+    pub storage: SharedStorage,
+    pub piece_index: u32,
+    pub piece_size: u32,
+    // By offset in the piece
+    pub blocks: BTreeMap<u32, Vec<u8>>,
+    bytes: usize,
+    last_write: u64,
+}
+
+impl PendingPiece {
+    // The whole piece once its blocks cover every byte of it
+    fn assemble(&self) -> Option<Vec<u8>> {
+        let mut covered = 0;
+        for (&begin, block) in &self.blocks {
+            if begin > covered {
+                return None;
+            }
+            covered = covered.max(begin + block.len() as u32);
+        }
+        if covered < self.piece_size {
+            return None;
+        }
+        let mut piece = vec![0; self.piece_size as usize];
+        for (&begin, block) in &self.blocks {
+            let begin = begin as usize;
+            piece[begin..begin + block.len()].copy_from_slice(block);
+        }
+        Some(piece)
+    }
+}
+
+// Keeps incoming blocks in memory until their piece is complete, so the disk sees one write per
+// piece instead of one per 16 KiB block
+pub struct WriteCache {
+    pieces: HashMap<PieceKey, PendingPiece>,
+    bytes: usize,
+    capacity: usize,
+    writes: u64,
+}
+
+impl WriteCache {
+    pub fn new(capacity: usize) -> WriteCache {
+        WriteCache {
+            pieces: HashMap::new(),
+            bytes: 0,
+            capacity,
+            writes: 0,
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    // Adds a block, returning the whole piece when this block completed it
+    pub fn insert(
+        &mut self,
+        key: PieceKey,
+        storage: &SharedStorage,
+        piece_size: u32,
+        begin: u32,
+        block: Vec<u8>,
+    ) -> Option<Vec<u8>> {
+        self.writes += 1;
+        let pending = self.pieces.entry(key).or_insert_with(|| PendingPiece {
+            storage: Arc::clone(storage),
+            piece_index: key.1,
+            piece_size,
+            blocks: BTreeMap::new(),
+            bytes: 0,
+            last_write: 0,
+        });
+        pending.last_write = self.writes;
+        pending.bytes += block.len();
+        self.bytes += block.len();
+        if let Some(replaced) = pending.blocks.insert(begin, block) {
+            pending.bytes -= replaced.len();
+            self.bytes -= replaced.len();
+        }
+
+        let piece = pending.assemble()?;
+        self.remove(&key);
+        Some(piece)
+    }
+
+    // The least recently written pieces to write out as they are, until the cache fits again
+    pub fn evict(&mut self) -> Vec<PendingPiece> {
+        let mut evicted = Vec::new();
+        while self.bytes > self.capacity {
+            let Some(key) = self
+                .pieces
+                .iter()
+                .min_by_key(|(_, pending)| pending.last_write)
+                .map(|(key, _)| *key)
+            else {
+                break;
+            };
+            evicted.extend(self.remove(&key));
+        }
+        evicted
+    }
+
+    // Takes every pending piece of a torrent out of the cache
+    pub fn take_all(&mut self, id: u64) -> Vec<PendingPiece> {
+        let keys: Vec<PieceKey> = self
+            .pieces
+            .keys()
+            .filter(|(piece_id, _)| *piece_id == id)
+            .copied()
+            .collect();
+        keys.iter().filter_map(|key| self.remove(key)).collect()
+    }
+
+    fn remove(&mut self, key: &PieceKey) -> Option<PendingPiece> {
+        let pending = self.pieces.remove(key)?;
+        self.bytes -= pending.bytes;
+        Some(pending)
+    }
+}
+
+// Whole pieces recently read for uploading, least recently used go first
+pub struct ReadCache {
+    pieces: HashMap<PieceKey, (Arc<Vec<u8>>, u64)>,
+    bytes: usize,
+    capacity: usize,
+    uses: u64,
+}
+
+impl ReadCache {
+    pub fn new(capacity: usize) -> ReadCache {
+        ReadCache {
+            pieces: HashMap::new(),
+            bytes: 0,
+            capacity,
+            uses: 0,
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn contains(&self, key: &PieceKey) -> bool {
+        self.pieces.contains_key(key)
+    }
+
+    pub fn get(&mut self, key: &PieceKey) -> Option<Arc<Vec<u8>>> {
+        self.uses += 1;
+        let (piece, last_use) = self.pieces.get_mut(key)?;
+        *last_use = self.uses;
+        Some(Arc::clone(piece))
+    }
+
+    pub fn insert(&mut self, key: PieceKey, piece: Arc<Vec<u8>>) {
+        // Pieces bigger than the whole cache aren't kept
+        if piece.len() > self.capacity {
+            return;
+        }
+        self.remove(&key);
+        self.uses += 1;
+        self.bytes += piece.len();
+        self.pieces.insert(key, (piece, self.uses));
+        while self.bytes > self.capacity {
+            let Some(oldest) = self
+                .pieces
+                .iter()
+                .min_by_key(|(_, (_, last_use))| *last_use)
+                .map(|(key, _)| *key)
+            else {
+                break;
+            };
+            self.remove(&oldest);
+        }
+    }
+
+    pub fn remove(&mut self, key: &PieceKey) {
+        if let Some((piece, _)) = self.pieces.remove(key) {
+            self.bytes -= piece.len();
+        }
+    }
+
+    pub fn remove_all(&mut self, id: u64) {
+        let keys: Vec<PieceKey> = self
+            .pieces
+            .keys()
+            .filter(|(piece_id, _)| *piece_id == id)
+            .copied()
+            .collect();
+        for key in keys {
+            self.remove(&key);
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use serde::Serialize;
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    oneshot,
+};
+
+use super::{
+    backend::{SharedStorage, Storage},
+    disk_cache::{PendingPiece, ReadCache, WriteCache},
+};
+
+// Pieces after the one a peer asked for that are read into the cache, peers usually go on
+// with the next ones
+pub const READ_AHEAD_PIECES: usize = 4;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// Cache and queue statistics, as shown in the UI
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DiskStats {
+    pub queued_jobs: usize,
+    pub queue_capacity: usize,
+    // Times a peer had to wait because the queue was full
+    pub queue_full_waits: u64,
+    pub write_cache_bytes: usize,
+    pub read_cache_bytes: usize,
+    pub cache_capacity: usize,
+    pub read_hits: u64,
+    pub read_misses: u64,
+    pub blocks_received: u64,
+    // Whole pieces written at once, and partial ones the cache had to give up on
+    pub pieces_written: u64,
+    pub partial_pieces_written: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    queue_full_waits: AtomicU64,
+    read_hits: AtomicU64,
+    read_misses: AtomicU64,
+    blocks_received: AtomicU64,
+    pieces_written: AtomicU64,
+    partial_pieces_written: AtomicU64,
+}
+
+// The disk I/O subsystem shared by every torrent. Jobs go through a bounded queue to a pool of
+// worker threads, so slow disks hold up the peers feeding them instead of piling up memory.
+#[derive(Clone)]
+pub struct DiskIo {
+    jobs: mpsc::Sender<Job>,
+    queue_capacity: usize,
+    cache_capacity: usize,
+    write_cache: Arc<Mutex<WriteCache>>,
+    read_cache: Arc<Mutex<ReadCache>>,
+    counters: Arc<Counters>,
+    next_id: Arc<AtomicU64>,
+}
+
+// A torrent's access to the disk through the shared subsystem
+#[derive(Clone)]
+pub struct DiskHandle {
+    id: u64,
+    storage: SharedStorage,
+    piece_length: u64,
+    total_length: u64,
+    disk: DiskIo,
+}
+
+impl DiskIo {
+    // Half of `cache_capacity` bytes holds blocks waiting to be written, the other half pieces
+    // read for uploading
+    pub fn new(threads: usize, queue_capacity: usize, cache_capacity: usize) -> DiskIo {
+        let (jobs, receiver) = mpsc::channel::<Job>(queue_capacity.max(1));
+        let receiver = Arc::new(Mutex::new(receiver));
+        for index in 0..threads.max(1) {
+            let receiver = Arc::clone(&receiver);
+            let spawned = thread::Builder::new()
+                .name(format!("disk-io-{}", index))
+                .spawn(move || loop {
+                    // Only waiting for a job holds the lock, running it doesn't
+                    let job = receiver
+                        .lock()
+                        .expect("Disk queue lock poisoned")
+                        .blocking_recv();
+                    match job {
+                        Some(job) => job(),
+                        None => break,
+                    }
+                });
+            if let Err(e) = spawned {
+                println!("Failed to start disk I/O thread: {}", e);
+            }
+        }
+
+        DiskIo {
+            jobs,
+            queue_capacity: queue_capacity.max(1),
+            cache_capacity,
+            write_cache: Arc::new(Mutex::new(WriteCache::new(cache_capacity / 2))),
+            read_cache: Arc::new(Mutex::new(ReadCache::new(cache_capacity / 2))),
+            counters: Arc::new(Counters::default()),
+            next_id: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn open(&self, storage: SharedStorage) -> DiskHandle {
+        let layout = storage.layout();
+        DiskHandle {
+            id: self.next_id.fetch_add(1, Ordering::SeqCst),
+            storage,
+            piece_length: layout.piece_length,
+            total_length: layout.total_length,
+            disk: self.clone(),
+        }
+    }
+
+    pub fn stats(&self) -> DiskStats {
+        let count = |counter: &AtomicU64| counter.load(Ordering::SeqCst);
+        DiskStats {
+            queued_jobs: self.queue_capacity - self.jobs.capacity(),
+            queue_capacity: self.queue_capacity,
+            queue_full_waits: count(&self.counters.queue_full_waits),
+            write_cache_bytes: self
+                .write_cache
+                .lock()
+                .expect("Cache lock poisoned")
+                .bytes(),
+            read_cache_bytes: self.read_cache.lock().expect("Cache lock poisoned").bytes(),
+            cache_capacity: self.cache_capacity,
+            read_hits: count(&self.counters.read_hits),
+            read_misses: count(&self.counters.read_misses),
+            blocks_received: count(&self.counters.blocks_received),
+            pieces_written: count(&self.counters.pieces_written),
+            partial_pieces_written: count(&self.counters.partial_pieces_written),
+        }
+    }
+
+    // Runs `job` on a worker, waiting for room in the queue first
+    async fn run<T, F>(&self, job: F) -> io::Result<T>
+    where
+        F: FnOnce() -> io::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (reply, result) = oneshot::channel();
+        let job: Job = Box::new(move || {
+            let _ = reply.send(job());
+        });
+        match self.jobs.try_send(job) {
+            Ok(()) => (),
+            Err(TrySendError::Full(job)) => {
+                self.counters
+                    .queue_full_waits
+                    .fetch_add(1, Ordering::SeqCst);
+                self.jobs
+                    .send(job)
+                    .await
+                    .map_err(|_| io::Error::other("Disk I/O stopped"))?;
+            }
+            Err(TrySendError::Closed(_)) => return Err(io::Error::other("Disk I/O stopped")),
+        }
+        result
+            .await
+            .map_err(|_| io::Error::other("Disk I/O job was dropped"))?
+    }
+
+    // Writes the blocks of a piece the cache gave up on as they are
+    async fn write_pending(&self, pending: PendingPiece) -> io::Result<()> {
+        self.counters
+            .partial_pieces_written
+            .fetch_add(1, Ordering::SeqCst);
+        self.run(move || {
+            pending.blocks.iter().try_for_each(|(&begin, block)| {
+                pending
+                    .storage
+                    .write_block(pending.piece_index, begin, block)
+            })
+        })
+        .await
+    }
+}
+
+impl DiskHandle {
+    pub fn storage(&self) -> &SharedStorage {
+        &self.storage
+    }
+
+    fn piece_size(&self, piece_index: u32) -> u32 {
+        let start = piece_index as u64 * self.piece_length;
+        self.total_length
+            .saturating_sub(start)
+            .min(self.piece_length) as u32
+    }
+
+    // Blocks are cached until their piece is complete, then the piece is written in one go.
+    // Returns once the data is either cached or on disk.
+    pub async fn write_block(&self, piece_index: u32, begin: u32, data: Vec<u8>) -> io::Result<()> {
+        let disk = &self.disk;
+        disk.counters.blocks_received.fetch_add(1, Ordering::SeqCst);
+        // Whatever was read of this piece before is stale now
+        disk.read_cache
+            .lock()
+            .expect("Cache lock poisoned")
+            .remove(&(self.id, piece_index));
+
+        let (piece, evicted) = {
+            let mut write_cache = disk.write_cache.lock().expect("Cache lock poisoned");
+            let piece = write_cache.insert(
+                (self.id, piece_index),
+                &self.storage,
+                self.piece_size(piece_index),
+                begin,
+                data,
+            );
+            (piece, write_cache.evict())
+        };
+
+        for pending in evicted {
+            disk.write_pending(pending).await?;
+        }
+        if let Some(piece) = piece {
+            disk.counters.pieces_written.fetch_add(1, Ordering::SeqCst);
+            let storage = Arc::clone(&self.storage);
+            disk.run(move || storage.write_block(piece_index, 0, &piece))
+                .await?;
+        }
+        Ok(())
+    }
+
+    // Only complete pieces are read. The whole piece is read and cached, peers usually ask for
+    // all of its blocks.
+    pub async fn read_block(
+        &self,
+        piece_index: u32,
+        begin: u32,
+        length: u32,
+    ) -> io::Result<Vec<u8>> {
+        let disk = &self.disk;
+        let key = (self.id, piece_index);
+        let cached = disk
+            .read_cache
+            .lock()
+            .expect("Cache lock poisoned")
+            .get(&key);
+        let piece = match cached {
+            Some(piece) => {
+                disk.counters.read_hits.fetch_add(1, Ordering::SeqCst);
+                piece
+            }
+            None => {
+                disk.counters.read_misses.fetch_add(1, Ordering::SeqCst);
+                let storage = Arc::clone(&self.storage);
+                let piece_size = self.piece_size(piece_index);
+                let piece = Arc::new(
+                    disk.run(move || storage.read_block(piece_index, 0, piece_size))
+                        .await?,
+                );
+                disk.read_cache
+                    .lock()
+                    .expect("Cache lock poisoned")
+                    .insert(key, Arc::clone(&piece));
+                piece
+            }
+        };
+
+        let (begin, end) = (begin as usize, begin as usize + length as usize);
+        piece.get(begin..end).map(<[u8]>::to_vec).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Block at {} is outside piece {}", begin, piece_index),
+            )
+        })
+    }
+
+    // Reads complete pieces into the cache in the background. Skipped while the queue is full,
+    // the disk has better things to do then.
+    pub fn read_ahead(&self, pieces: Vec<u32>) {
+        for piece_index in pieces.into_iter().take(READ_AHEAD_PIECES) {
+            let key = (self.id, piece_index);
+            if self
+                .disk
+                .read_cache
+                .lock()
+                .expect("Cache lock poisoned")
+                .contains(&key)
+            {
+                continue;
+            }
+            let storage = Arc::clone(&self.storage);
+            let read_cache = Arc::clone(&self.disk.read_cache);
+            let piece_size = self.piece_size(piece_index);
+            let job: Job = Box::new(move || {
+                if let Ok(piece) = storage.read_block(piece_index, 0, piece_size) {
+                    read_cache
+                        .lock()
+                        .expect("Cache lock poisoned")
+                        .insert(key, Arc::new(piece));
+                }
+            });
+            if self.disk.jobs.try_send(job).is_err() {
+                return;
+            }
+        }
+    }
+
+    // Writes out every cached block, complete pieces or not, and makes it durable
+    pub async fn flush(&self) -> io::Result<()> {
+        let pending = self
+            .disk
+            .write_cache
+            .lock()
+            .expect("Cache lock poisoned")
+            .take_all(self.id);
+        for pending in pending {
+            self.disk.write_pending(pending).await?;
+        }
+        let storage = Arc::clone(&self.storage);
+        self.disk.run(move || storage.flush()).await
+    }
+
+    // Forgets everything cached for the torrent without writing it, before its data is deleted
+    pub fn discard(&self) {
+        self.disk
+            .write_cache
+            .lock()
+            .expect("Cache lock poisoned")
+            .take_all(self.id);
+        self.disk
+            .read_cache
+            .lock()
+            .expect("Cache lock poisoned")
+            .remove_all(self.id);
+    }
+
+    // Runs any other storage call, like deleting or moving the files, on the disk workers
+    pub async fn run<T, F>(&self, call: F) -> io::Result<T>
+    where
+        F: FnOnce(&dyn Storage) -> io::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let storage = Arc::clone(&self.storage);
+        self.disk.run(move || call(&*storage)).await
+    }
+}
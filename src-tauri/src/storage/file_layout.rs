@@ -0,0 +1,154 @@
+use bitvec::prelude::{BitSlice, Lsb0};
+use std::{ffi::OsString, ops::Range, path::PathBuf};
+
+use crate::parsing::parser::torrent_metadata::TorrentMetadata;
+
+// A single file of a torrent and where its bytes live in the torrent's contiguous byte space.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileEntry {
+    pub path: PathBuf,
+    pub length: u64,
+    pub offset: u64,
+    // Pad files only align the next file to a piece, they are all zeros and never on disk
+    pub padding: bool,
+}
+
+// The part of one file covered by a byte range of the torrent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileSpan {
+    pub file_index: usize,
+    pub file_offset: u64,
+    pub length: u64,
+}
+
+// Maps the torrent's byte space (and therefore its pieces) onto the files on disk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileLayout {
+    // The file itself for single-file torrents, the containing directory otherwise
+    pub root: PathBuf,
+    pub is_multi_file: bool,
+    pub files: Vec<FileEntry>,
+    pub piece_length: u64,
+    pub total_length: u64,
+}
+
+impl FileLayout {
+    // Single-file torrents live at `file_path`, multi-file torrents live in a directory at `file_path`.
+    // Files get their `.part` suffix while the metadata asks for one.
+    pub fn from_metadata(metadata: &TorrentMetadata) -> FileLayout {
+        let on_disk = |path: PathBuf, padding: bool| match metadata.part_suffix && !padding {
+            true => part_path(path),
+            false => path,
+        };
+        let info = &metadata.info;
+        let mut files = Vec::new();
+        let mut offset = 0;
+
+        match &info.files {
+            Some(entries) => {
+                for entry in entries {
+                    let path = entry
+                        .path
+                        .iter()
+                        .fold(metadata.file_path.clone(), |path, part| path.join(part));
+                    let length = entry.length.max(0) as u64;
+                    files.push(FileEntry {
+                        path: on_disk(path, entry.is_padding()),
+                        length,
+                        offset,
+                        padding: entry.is_padding(),
+                    });
+                    offset += length;
+                }
+            }
+            None => {
+                let length = info.length.max(0) as u64;
+                files.push(FileEntry {
+                    path: on_disk(metadata.file_path.clone(), false),
+                    length,
+                    offset,
+                    padding: false,
+                });
+                offset += length;
+            }
+        }
+
+        FileLayout {
+            root: metadata.file_path.clone(),
+            is_multi_file: info.files.is_some(),
+            files,
+            piece_length: info.piece_length.max(1) as u64,
+            total_length: offset,
+        }
+    }
+
+    pub fn file(&self, file_index: usize) -> Option<&FileEntry> {
+        self.files.get(file_index)
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.total_length.div_ceil(self.piece_length) as usize
+    }
+
+    // The last piece is usually shorter than the others.
+    pub fn piece_size(&self, piece_index: usize) -> u64 {
+        let start = piece_index as u64 * self.piece_length;
+        self.total_length
+            .saturating_sub(start)
+            .min(self.piece_length)
+    }
+
+    // Splits `length` bytes starting at the torrent-wide `offset` into the file regions holding them.
+    pub fn file_spans(&self, offset: u64, length: u64) -> Vec<FileSpan> {
+        let end = (offset + length).min(self.total_length);
+        self.files
+            .iter()
+            .enumerate()
+            .filter(|(_, file)| file.offset < end && file.offset + file.length > offset)
+            .map(|(file_index, file)| {
+                let start = offset.max(file.offset);
+                let stop = end.min(file.offset + file.length);
+                FileSpan {
+                    file_index,
+                    file_offset: start - file.offset,
+                    length: stop - start,
+                }
+            })
+            .collect()
+    }
+
+    // Returns the indices of the pieces covering `length` bytes starting at the torrent-wide `offset`.
+    pub fn pieces_for_range(&self, offset: u64, length: u64) -> Range<usize> {
+        if length == 0 {
+            return 0..0;
+        }
+        let first = offset / self.piece_length;
+        let last = (offset + length - 1) / self.piece_length;
+        first as usize..last as usize + 1
+    }
+
+    // Counts how many of the `length` bytes starting at `offset` belong to completed pieces.
+    pub fn completed_bytes(
+        &self,
+        offset: u64,
+        length: u64,
+        pieces_status: &BitSlice<u8, Lsb0>,
+    ) -> u64 {
+        let end = offset + length;
+        self.pieces_for_range(offset, length)
+            .filter(|&index| pieces_status.get(index).map(|bit| *bit).unwrap_or(false))
+            .map(|index| {
+                let piece_start = index as u64 * self.piece_length;
+                let piece_end = piece_start + self.piece_length;
+                piece_end.min(end) - piece_start.max(offset)
+            })
+            .sum()
+    }
+}
+
+// `movie.mkv` is downloaded as `movie.mkv.part`
+fn part_path(path: PathBuf) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(".part");
+    PathBuf::from(path)
+}
@@ -0,0 +1,337 @@
+use fs2::FileExt;
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::{Mutex, RwLock},
+};
+
+use crate::config::AllocationMode;
+
+use super::{
+    backend::{block_spans, destination_exists, MoveConflict, Storage},
+    file_layout::FileLayout,
+};
+
+// Torrents with many files would otherwise run out of file descriptors
+const MAX_OPEN_FILES: usize = 64;
+// Copies across filesystems go in chunks of this size, with progress after each
+const COPY_CHUNK_SIZE: usize = 1024 * 1024;
+
+struct OpenFile {
+    file: File,
+    writable: bool,
+}
+
+// Stores the torrent's files on the filesystem, at the paths of its layout
+pub struct FsStorage {
+    layout: RwLock<FileLayout>,
+    // Handles by file index, kept open between blocks
+    open_files: Mutex<HashMap<usize, OpenFile>>,
+}
+
+impl FsStorage {
+    pub fn new(layout: FileLayout) -> FsStorage {
+        FsStorage {
+            layout: RwLock::new(layout),
+            open_files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Runs `access` on the file, opening it first. Files are created for writing, along with
+    // their directories.
+    fn with_file<T, F>(
+        &self,
+        layout: &FileLayout,
+        file_index: usize,
+        write: bool,
+        access: F,
+    ) -> io::Result<T>
+    where
+        F: FnOnce(&mut File) -> io::Result<T>,
+    {
+        let mut open_files = self.open_files.lock().expect("Open files lock poisoned");
+        let reusable = open_files
+            .get(&file_index)
+            .is_some_and(|open| open.writable || !write);
+        if !reusable {
+            let path = &layout.files[file_index].path;
+            let file = match write {
+                true => create_file(path)?,
+                false => File::open(path)?,
+            };
+            if open_files.len() >= MAX_OPEN_FILES {
+                open_files.clear();
+            }
+            open_files.insert(
+                file_index,
+                OpenFile {
+                    file,
+                    writable: write,
+                },
+            );
+        }
+        let open = open_files
+            .get_mut(&file_index)
+            .expect("File was just opened");
+        access(&mut open.file)
+    }
+
+    fn close_files(&self) {
+        self.open_files
+            .lock()
+            .expect("Open files lock poisoned")
+            .clear();
+    }
+}
+
+impl Storage for FsStorage {
+    fn layout(&self) -> FileLayout {
+        self.layout.read().expect("Layout lock poisoned").clone()
+    }
+
+    fn read_block(&self, piece_index: u32, begin: u32, length: u32) -> io::Result<Vec<u8>> {
+        let layout = self.layout.read().expect("Layout lock poisoned");
+        let mut block = Vec::with_capacity(length as usize);
+        for span in block_spans(&layout, piece_index, begin, length)? {
+            let start = block.len();
+            block.resize(start + span.length as usize, 0);
+            if layout.files[span.file_index].padding {
+                continue;
+            }
+            self.with_file(&layout, span.file_index, false, |file| {
+                file.seek(SeekFrom::Start(span.file_offset))?;
+                file.read_exact(&mut block[start..])
+            })?;
+        }
+        Ok(block)
+    }
+
+    fn write_block(&self, piece_index: u32, begin: u32, data: &[u8]) -> io::Result<()> {
+        let layout = self.layout.read().expect("Layout lock poisoned");
+        let mut written = 0;
+        for span in block_spans(&layout, piece_index, begin, data.len() as u32)? {
+            let part = &data[written..written + span.length as usize];
+            written += part.len();
+            if layout.files[span.file_index].padding {
+                continue;
+            }
+            self.with_file(&layout, span.file_index, true, |file| {
+                file.seek(SeekFrom::Start(span.file_offset))?;
+                file.write_all(part)
+            })?;
+        }
+        Ok(())
+    }
+
+    // Compact files are left to grow as pieces arrive, except empty ones nothing would create
+    fn allocate(&self, mode: AllocationMode) -> io::Result<()> {
+        let layout = self.layout.read().expect("Layout lock poisoned");
+        let needed = missing_bytes(&layout)?;
+        let available = fs2::available_space(existing_ancestor(&layout.root))?;
+        if needed > available {
+            return Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                format!(
+                    "Not enough disk space in {}: {} bytes are needed but only {} are free",
+                    layout.root.display(),
+                    needed,
+                    available
+                ),
+            ));
+        }
+
+        for file in layout.files.iter().filter(|file| !file.padding) {
+            if mode == AllocationMode::Compact && file.length > 0 {
+                continue;
+            }
+            let handle = create_file(&file.path)?;
+            match mode {
+                AllocationMode::Full => handle.allocate(file.length)?,
+                _ if handle.metadata()?.len() < file.length => handle.set_len(file.length)?,
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let open_files = self.open_files.lock().expect("Open files lock poisoned");
+        for open in open_files.values().filter(|open| open.writable) {
+            open.file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    // Renames the files, copying them when the new root is on another filesystem. Files that
+    // were never written are skipped. When a file can't be moved, the ones already moved are
+    // put back so the torrent stays whole at its old location.
+    fn move_storage(
+        &self,
+        target: &FileLayout,
+        conflict: MoveConflict,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> io::Result<()> {
+        let mut layout = self.layout.write().expect("Layout lock poisoned");
+        self.close_files();
+        let moved = target.clone();
+        let mut moves: Vec<(&Path, &Path)> = layout
+            .files
+            .iter()
+            .zip(&moved.files)
+            .filter(|(from, to)| !from.padding && from.path != to.path && from.path.exists())
+            .map(|(from, to)| (from.path.as_path(), to.path.as_path()))
+            .collect();
+        if let Some((_, to)) = moves.iter().find(|(_, to)| to.exists()) {
+            match conflict {
+                MoveConflict::Fail => return Err(destination_exists(to)),
+                MoveConflict::Replace => (),
+                MoveConflict::KeepExisting => moves.retain(|(_, to)| !to.exists()),
+            }
+        }
+
+        let mut total = 0;
+        for (from, _) in &moves {
+            total += fs::metadata(from)?.len();
+        }
+        let mut done = 0;
+        for (index, (from, to)) in moves.iter().enumerate() {
+            let moved_file = move_file(from, to, &mut |copied| progress(done + copied, total));
+            match moved_file {
+                Ok(length) => done += length,
+                Err(e) => {
+                    for (from, to) in moves[..index].iter().rev() {
+                        if let Err(e) = move_file(to, from, &mut |_| ()) {
+                            println!("Failed to move {} back: {}", to.display(), e);
+                        }
+                    }
+                    remove_empty_directories(&moved);
+                    return Err(e);
+                }
+            }
+            progress(done, total);
+        }
+        remove_empty_directories(&layout);
+        *layout = moved;
+        Ok(())
+    }
+
+    fn delete(&self) -> io::Result<()> {
+        let layout = self.layout.read().expect("Layout lock poisoned");
+        self.close_files();
+        for file in layout.files.iter().filter(|file| !file.padding) {
+            match fs::remove_file(&file.path) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+                Err(e) => return Err(e),
+            }
+        }
+        remove_empty_directories(&layout);
+        Ok(())
+    }
+
+    fn check_file_sizes(&self) -> io::Result<bool> {
+        let layout = self.layout.read().expect("Layout lock poisoned");
+        for file in layout.files.iter().filter(|file| !file.padding) {
+            let size = match fs::metadata(&file.path) {
+                Ok(metadata) => metadata.len(),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+                Err(e) => return Err(e),
+            };
+            if size != file.length {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+// Opens a file for writing, creating it and its directories when needed
+fn create_file(path: &Path) -> io::Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
+
+// Renames a file or copies it over with progress, returning its length
+fn move_file(from: &Path, to: &Path, progress: &mut dyn FnMut(u64)) -> io::Result<u64> {
+    let length = fs::metadata(from)?.len();
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(from, to).is_ok() {
+        return Ok(length);
+    }
+    if let Err(e) = copy_file(from, to, progress) {
+        let _ = fs::remove_file(to);
+        return Err(e);
+    }
+    fs::remove_file(from)?;
+    Ok(length)
+}
+
+fn copy_file(from: &Path, to: &Path, progress: &mut dyn FnMut(u64)) -> io::Result<()> {
+    let mut source = File::open(from)?;
+    let mut destination = File::create(to)?;
+    let mut buffer = vec![0; COPY_CHUNK_SIZE];
+    let mut copied = 0;
+    loop {
+        let read = source.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        destination.write_all(&buffer[..read])?;
+        copied += read as u64;
+        progress(copied);
+    }
+    // The copy has to be on disk before the original goes away
+    destination.sync_all()
+}
+
+// Disk space the files still need. Space already taken counts, holes of sparse files don't.
+fn missing_bytes(layout: &FileLayout) -> io::Result<u64> {
+    let mut missing = 0;
+    for file in layout.files.iter().filter(|file| !file.padding) {
+        let allocated = match File::open(&file.path) {
+            Ok(handle) => handle.allocated_size()?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        missing += file.length.saturating_sub(allocated);
+    }
+    Ok(missing)
+}
+
+// Free space is asked of the closest directory that already exists
+fn existing_ancestor(path: &Path) -> &Path {
+    path.ancestors()
+        .find(|directory| directory.exists())
+        .unwrap_or_else(|| Path::new("."))
+}
+
+// Multi-file torrents live in their own directory tree, prunes whatever is now empty in it
+fn remove_empty_directories(layout: &FileLayout) {
+    if !layout.is_multi_file {
+        return;
+    }
+    let mut directories: Vec<&Path> = layout
+        .files
+        .iter()
+        .flat_map(|file| file.path.ancestors().skip(1))
+        .filter(|directory| directory.starts_with(&layout.root))
+        .collect();
+    directories.sort_by_key(|directory| std::cmp::Reverse(directory.components().count()));
+    directories.dedup();
+
+    for directory in directories {
+        // Fails for directories that still contain other files, which is what we want
+        let _ = fs::remove_dir(directory);
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    sync::{Mutex, RwLock},
+};
+
+use crate::config::AllocationMode;
+
+use super::{
+    backend::{block_spans, destination_exists, MoveConflict, Storage},
+    file_layout::FileLayout,
+};
+
+// Keeps the files in memory by path, for tests that shouldn't touch the disk
+pub struct MemoryStorage {
+    layout: RwLock<FileLayout>,
+    files: Mutex<HashMap<PathBuf, Vec<u8>>>,
+}
+
+impl MemoryStorage {
+    pub fn new(layout: FileLayout) -> MemoryStorage {
+        MemoryStorage {
+            layout: RwLock::new(layout),
+            files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // The contents of a stored file, None if nothing was written to it
+    pub fn file(&self, path: &Path) -> Option<Vec<u8>> {
+        self.files
+            .lock()
+            .expect("Files lock poisoned")
+            .get(path)
+            .cloned()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn layout(&self) -> FileLayout {
+        self.layout.read().expect("Layout lock poisoned").clone()
+    }
+
+    fn read_block(&self, piece_index: u32, begin: u32, length: u32) -> io::Result<Vec<u8>> {
+        let layout = self.layout.read().expect("Layout lock poisoned");
+        let files = self.files.lock().expect("Files lock poisoned");
+        let mut block = Vec::with_capacity(length as usize);
+        for span in block_spans(&layout, piece_index, begin, length)? {
+            let file = &layout.files[span.file_index];
+            if file.padding {
+                block.resize(block.len() + span.length as usize, 0);
+                continue;
+            }
+            let contents = files
+                .get(&file.path)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "File not stored"))?;
+            let start = span.file_offset as usize;
+            let stored = contents
+                .get(start..start + span.length as usize)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "Block not stored"))?;
+            block.extend_from_slice(stored);
+        }
+        Ok(block)
+    }
+
+    fn write_block(&self, piece_index: u32, begin: u32, data: &[u8]) -> io::Result<()> {
+        let layout = self.layout.read().expect("Layout lock poisoned");
+        let mut files = self.files.lock().expect("Files lock poisoned");
+        let mut written = 0;
+        for span in block_spans(&layout, piece_index, begin, data.len() as u32)? {
+            let part = &data[written..written + span.length as usize];
+            written += part.len();
+            let file = &layout.files[span.file_index];
+            if file.padding {
+                continue;
+            }
+            let contents = files.entry(file.path.clone()).or_default();
+            let start = span.file_offset as usize;
+            if contents.len() < start + part.len() {
+                contents.resize(start + part.len(), 0);
+            }
+            contents[start..start + part.len()].copy_from_slice(part);
+        }
+        Ok(())
+    }
+
+    // Memory never runs out here, files are just given their full length
+    fn allocate(&self, mode: AllocationMode) -> io::Result<()> {
+        if mode == AllocationMode::Compact {
+            return Ok(());
+        }
+        let layout = self.layout.read().expect("Layout lock poisoned");
+        let mut files = self.files.lock().expect("Files lock poisoned");
+        for file in layout.files.iter().filter(|file| !file.padding) {
+            let contents = files.entry(file.path.clone()).or_default();
+            if (contents.len() as u64) < file.length {
+                contents.resize(file.length as usize, 0);
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn move_storage(
+        &self,
+        target: &FileLayout,
+        conflict: MoveConflict,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> io::Result<()> {
+        let mut layout = self.layout.write().expect("Layout lock poisoned");
+        let mut files = self.files.lock().expect("Files lock poisoned");
+        let moved = target.clone();
+        let mut moves: Vec<(&PathBuf, &PathBuf)> = layout
+            .files
+            .iter()
+            .zip(&moved.files)
+            .filter(|(from, to)| from.path != to.path && files.contains_key(&from.path))
+            .map(|(from, to)| (&from.path, &to.path))
+            .collect();
+        if let Some((_, to)) = moves.iter().find(|(_, to)| files.contains_key(*to)) {
+            match conflict {
+                MoveConflict::Fail => return Err(destination_exists(to)),
+                MoveConflict::Replace => (),
+                MoveConflict::KeepExisting => moves.retain(|(_, to)| !files.contains_key(*to)),
+            }
+        }
+
+        let total = moves
+            .iter()
+            .map(|(from, _)| files[*from].len() as u64)
+            .sum();
+        let mut done = 0;
+        for (from, to) in moves {
+            let contents = files.remove(from).expect("Moved file is stored");
+            done += contents.len() as u64;
+            files.insert(to.clone(), contents);
+            progress(done, total);
+        }
+        *layout = moved;
+        Ok(())
+    }
+
+    fn delete(&self) -> io::Result<()> {
+        let layout = self.layout.read().expect("Layout lock poisoned");
+        let mut files = self.files.lock().expect("Files lock poisoned");
+        for file in &layout.files {
+            files.remove(&file.path);
+        }
+        Ok(())
+    }
+
+    fn check_file_sizes(&self) -> io::Result<bool> {
+        let layout = self.layout.read().expect("Layout lock poisoned");
+        let files = self.files.lock().expect("Files lock poisoned");
+        Ok(layout
+            .files
+            .iter()
+            .filter(|file| !file.padding)
+            .all(|file| files.get(&file.path).map_or(0, Vec::len) as u64 == file.length))
+    }
+}
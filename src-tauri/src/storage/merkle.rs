@@ -0,0 +1,42 @@
+use sha2::{Digest, Sha256};
+
+// v2 torrents (BEP 52) hash every file as a merkle tree over 16 KiB blocks
+pub const BLOCK_SIZE: usize = 16 * 1024;
+
+pub type Hash = [u8; 32];
+
+// SHA-256 of each block of `data`, the last one may be short
+pub fn block_hashes(data: &[u8]) -> Vec<Hash> {
+    data.chunks(BLOCK_SIZE)
+        .map(|block| Sha256::digest(block).into())
+        .collect()
+}
+
+// Root of a tree `width` leaves wide, a power of two. Leaves past the end of `hashes` are
+// `padding`.
+pub fn merkle_root(hashes: &[Hash], width: usize, padding: Hash) -> Hash {
+    let mut layer = hashes.to_vec();
+    layer.resize(width.max(1), padding);
+    while layer.len() > 1 {
+        layer = layer
+            .chunks(2)
+            .map(|pair| {
+                let mut hasher = Sha256::new();
+                hasher.update(pair[0]);
+                hasher.update(pair[1]);
+                hasher.finalize().into()
+            })
+            .collect();
+    }
+    layer[0]
+}
+
+// Root of a subtree whose `leaves` are all past the end of the file
+pub fn padding_hash(leaves: usize) -> Hash {
+    merkle_root(&[], leaves, [0u8; 32])
+}
+
+// Root of the tree over a piece, or over a whole file no bigger than a piece
+pub fn data_root(data: &[u8], width: usize) -> Hash {
+    merkle_root(&block_hashes(data), width, [0u8; 32])
+}
@@ -0,0 +1,9 @@
+pub mod backend;
+pub mod disk_cache;
+pub mod disk_io;
+pub mod file_layout;
+pub mod fs_storage;
+pub mod memory_storage;
+pub mod merkle;
+pub mod piece_hashes;
+pub mod verify;
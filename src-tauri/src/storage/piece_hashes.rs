@@ -0,0 +1,145 @@
+use serde_bytes::Bytes;
+use sha1::{Digest, Sha1};
+
+use crate::{
+    config::{HASH_SIZE, V2_HASH_SIZE},
+    parsing::parser::torrent_metadata::TorrentMetadata,
+};
+
+use super::merkle::{data_root, merkle_root, padding_hash, Hash, BLOCK_SIZE};
+
+// What a downloaded piece is checked against. v1 torrents have a SHA-1 hash per piece, v2
+// torrents a merkle root per piece, and hybrid torrents both, which must then both match.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PieceHashes {
+    pub v1: Vec<[u8; 20]>,
+    pub v2: Vec<V2PieceHash>,
+}
+
+// A v2 piece is the tree over its blocks, `width` leaves wide. The last piece of a file is
+// usually short and, in the torrent-wide layout, followed by padding that isn't hashed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct V2PieceHash {
+    pub root: Hash,
+    pub data_length: usize,
+    pub width: usize,
+}
+
+impl PieceHashes {
+    pub fn from_metadata(metadata: &TorrentMetadata) -> Result<PieceHashes, String> {
+        let v1: Vec<[u8; 20]> = metadata
+            .info
+            .pieces
+            .chunks_exact(HASH_SIZE)
+            .map(|chunk| chunk.try_into().expect("Chunks are exactly one hash long"))
+            .collect();
+        let v2 = match metadata.info.has_v2() {
+            true => match v2_piece_hashes(metadata) {
+                Ok(v2) => v2,
+                // Hybrid torrents can still be checked against their SHA-1 hashes
+                Err(e) if metadata.info.has_v1() => {
+                    println!("Checking pieces with SHA-1 only: {}", e);
+                    Vec::new()
+                }
+                Err(e) => return Err(e),
+            },
+            false => Vec::new(),
+        };
+        if !v1.is_empty() && !v2.is_empty() && v1.len() != v2.len() {
+            return Err("v1 and v2 piece counts differ".to_string());
+        }
+        Ok(PieceHashes { v1, v2 })
+    }
+
+    pub fn len(&self) -> usize {
+        self.v1.len().max(self.v2.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn verify(&self, piece_index: usize, piece: &[u8]) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        if !self.v1.is_empty()
+            && self
+                .v1
+                .get(piece_index)
+                .is_none_or(|expected| Sha1::digest(piece).as_slice() != expected)
+        {
+            return false;
+        }
+        if !self.v2.is_empty() {
+            let Some(expected) = self.v2.get(piece_index) else {
+                return false;
+            };
+            let Some(data) = piece.get(..expected.data_length) else {
+                return false;
+            };
+            if data_root(data, expected.width) != expected.root {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// Every file starts on a new piece. Files bigger than a piece have their piece hashes in
+// `piece layers`, checked against the file's pieces root here. Smaller files are one piece whose
+// tree is the file's own.
+fn v2_piece_hashes(metadata: &TorrentMetadata) -> Result<Vec<V2PieceHash>, String> {
+    let piece_length = metadata.info.piece_length as usize;
+    if piece_length < BLOCK_SIZE || !piece_length.is_power_of_two() {
+        return Err(format!("Invalid v2 piece length {}", piece_length));
+    }
+    let piece_width = piece_length / BLOCK_SIZE;
+
+    let mut hashes = Vec::new();
+    for file in metadata.info.v2_files() {
+        let Some(pieces_root) = file.pieces_root else {
+            continue;
+        };
+        let length = file.length as usize;
+        if length <= piece_length {
+            hashes.push(V2PieceHash {
+                root: pieces_root,
+                data_length: length,
+                width: length.div_ceil(BLOCK_SIZE).next_power_of_two(),
+            });
+            continue;
+        }
+
+        let layer = metadata
+            .piece_layers
+            .get(Bytes::new(&pieces_root))
+            .ok_or_else(|| format!("Missing piece layer for {}", file.path.join("/")))?;
+        let piece_count = length.div_ceil(piece_length);
+        let layer: Vec<Hash> = layer
+            .chunks_exact(V2_HASH_SIZE)
+            .map(|hash| hash.try_into().expect("Chunks are exactly one hash long"))
+            .collect();
+        if layer.len() != piece_count
+            || merkle_root(
+                &layer,
+                piece_count.next_power_of_two(),
+                padding_hash(piece_width),
+            ) != pieces_root
+        {
+            return Err(format!(
+                "Piece layer of {} doesn't match its root",
+                file.path.join("/")
+            ));
+        }
+
+        for (index, root) in layer.into_iter().enumerate() {
+            hashes.push(V2PieceHash {
+                root,
+                data_length: (length - index * piece_length).min(piece_length),
+                width: piece_width,
+            });
+        }
+    }
+    Ok(hashes)
+}
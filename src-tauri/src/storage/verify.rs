@@ -0,0 +1,55 @@
+use bitvec::prelude::*;
+use tokio::io;
+
+use super::{
+    backend::{run_blocking, SharedStorage},
+    piece_hashes::PieceHashes,
+};
+
+// Hashes every piece found in the storage and compares it with the expected piece hashes.
+// Missing or short files just leave the affected pieces unverified. `progress` is called after
+// each piece with the number of pieces checked so far.
+pub async fn verify_pieces<F>(
+    storage: &SharedStorage,
+    piece_hashes: &PieceHashes,
+    mut progress: F,
+) -> io::Result<BitVec<u8, Lsb0>>
+where
+    F: FnMut(usize),
+{
+    let layout = storage.layout();
+    let mut verified = bitvec![u8, Lsb0; 0; piece_hashes.len()];
+
+    for piece_index in 0..piece_hashes.len() {
+        let piece_size = layout.piece_size(piece_index) as u32;
+        if let Some(piece) = read_piece(storage, piece_index as u32, piece_size).await? {
+            if piece_hashes.verify(piece_index, &piece) {
+                verified.set(piece_index, true);
+            }
+        }
+        progress(piece_index + 1);
+    }
+
+    Ok(verified)
+}
+
+// Reads a whole piece, `None` if any part of it is not stored
+async fn read_piece(
+    storage: &SharedStorage,
+    piece_index: u32,
+    piece_size: u32,
+) -> io::Result<Option<Vec<u8>>> {
+    match run_blocking(storage, move |storage| {
+        storage.read_block(piece_index, 0, piece_size)
+    })
+    .await
+    {
+        Ok(piece) => Ok(Some(piece)),
+        Err(e)
+            if e.kind() == io::ErrorKind::NotFound || e.kind() == io::ErrorKind::UnexpectedEof =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,73 @@
+// A resolved, inclusive byte range within a file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RangeError {
+    // The header could not be parsed, the request should be served as if it had no Range header
+    Malformed,
+    // The range lies outside of the file, answered with 416
+    Unsatisfiable,
+}
+
+// Parses a single-range `Range` header value (`bytes=0-499`, `bytes=500-`, `bytes=-500`)
+// against a file of `file_length` bytes.
+pub fn parse_range_header(header: &str, file_length: u64) -> Result<ByteRange, RangeError> {
+    let spec = header
+        .trim()
+        .strip_prefix("bytes=")
+        .ok_or(RangeError::Malformed)?;
+
+    // Multiple ranges would require a multipart response, so only the first one is honoured
+    let spec = spec.split(',').next().ok_or(RangeError::Malformed)?.trim();
+    let (start, end) = spec.split_once('-').ok_or(RangeError::Malformed)?;
+
+    if file_length == 0 {
+        return Err(RangeError::Unsatisfiable);
+    }
+    let last_byte = file_length - 1;
+
+    let range = match (start.trim(), end.trim()) {
+        ("", "") => return Err(RangeError::Malformed),
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().map_err(|_| RangeError::Malformed)?;
+            if suffix == 0 {
+                return Err(RangeError::Unsatisfiable);
+            }
+            ByteRange {
+                start: file_length.saturating_sub(suffix),
+                end: last_byte,
+            }
+        }
+        (start, "") => ByteRange {
+            start: start.parse().map_err(|_| RangeError::Malformed)?,
+            end: last_byte,
+        },
+        (start, end) => {
+            let start: u64 = start.parse().map_err(|_| RangeError::Malformed)?;
+            let end: u64 = end.parse().map_err(|_| RangeError::Malformed)?;
+            if end < start {
+                return Err(RangeError::Malformed);
+            }
+            ByteRange {
+                start,
+                end: end.min(last_byte),
+            }
+        }
+    };
+
+    if range.start > last_byte {
+        return Err(RangeError::Unsatisfiable);
+    }
+
+    Ok(range)
+}
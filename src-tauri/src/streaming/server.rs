@@ -0,0 +1,251 @@
+use futures::stream::{self, Stream};
+use hyper::{
+    header,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+use tokio::sync::RwLock;
+
+use crate::{
+    storage::{
+        disk_io::DiskHandle,
+        file_layout::{FileEntry, FileLayout},
+    },
+    torrent_management::{piece_waiter::PieceWaiter, torrent_manager::TorrentManager},
+};
+
+use super::range::{parse_range_header, ByteRange, RangeError};
+
+// Upper bound for a single body chunk, chunks never cross a piece boundary either
+const STREAM_CHUNK_SIZE: u64 = 256 * 1024;
+
+// Binds the streaming server and serves it in the background, returning the bound address.
+pub async fn start_stream_server(
+    torrent_manager: Arc<RwLock<TorrentManager>>,
+    address: &str,
+) -> Result<SocketAddr, String> {
+    let listener = std::net::TcpListener::bind(address)
+        .map_err(|e| format!("Failed to bind streaming server: {}", e))?;
+    listener
+        .set_nonblocking(true)
+        .map_err(|e| format!("Failed to configure streaming server: {}", e))?;
+
+    let make_service = make_service_fn(move |_| {
+        let torrent_manager = Arc::clone(&torrent_manager);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle_request(Arc::clone(&torrent_manager), request)
+            }))
+        }
+    });
+
+    let server = Server::from_tcp(listener)
+        .map_err(|e| format!("Failed to start streaming server: {}", e))?
+        .serve(make_service);
+    let local_addr = server.local_addr();
+
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            println!("Streaming server error: {}", e);
+        }
+    });
+
+    Ok(local_addr)
+}
+
+pub fn stream_url(server_addr: &SocketAddr, torrent_hash: &str, file_index: usize) -> String {
+    format!(
+        "http://{}/torrents/{}/{}",
+        server_addr, torrent_hash, file_index
+    )
+}
+
+async fn handle_request(
+    torrent_manager: Arc<RwLock<TorrentManager>>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return Ok(empty_response(StatusCode::METHOD_NOT_ALLOWED));
+    }
+
+    let segments: Vec<&str> = request.uri().path().trim_matches('/').split('/').collect();
+    let (torrent_hash, file_index) = match segments.as_slice() {
+        ["torrents", torrent_hash, file_index] => match file_index.parse::<usize>() {
+            Ok(file_index) => (torrent_hash.to_lowercase(), file_index),
+            Err(_) => return Ok(empty_response(StatusCode::BAD_REQUEST)),
+        },
+        _ => return Ok(empty_response(StatusCode::NOT_FOUND)),
+    };
+
+    let torrent = match torrent_manager.read().await.get_torrent(&torrent_hash) {
+        Some(torrent) => torrent,
+        None => return Ok(empty_response(StatusCode::NOT_FOUND)),
+    };
+
+    let (disk, piece_waiter) = {
+        let torrent_guard = torrent.read().await;
+        (torrent_guard.disk(), torrent_guard.piece_waiter())
+    };
+    let layout = disk.storage().layout();
+
+    let file = match layout.file(file_index) {
+        Some(file) => file.clone(),
+        None => return Ok(empty_response(StatusCode::NOT_FOUND)),
+    };
+
+    let requested_range = request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| parse_range_header(value, file.length));
+
+    let (status, range) = match requested_range {
+        Some(Ok(range)) => (StatusCode::PARTIAL_CONTENT, Some(range)),
+        Some(Err(RangeError::Unsatisfiable)) => {
+            return Ok(Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", file.length))
+                .body(Body::empty())
+                .unwrap_or_default());
+        }
+        Some(Err(RangeError::Malformed)) | None => (StatusCode::OK, None),
+    };
+
+    let content_length = range.map(|range| range.len()).unwrap_or(file.length);
+    let mut response = Response::builder()
+        .status(status)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CONTENT_TYPE, content_type(&file))
+        .header(header::CONTENT_LENGTH, content_length);
+
+    if let Some(range) = range {
+        response = response.header(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", range.start, range.end, file.length),
+        );
+    }
+
+    let body = match (request.method() == Method::HEAD, range, file.length) {
+        (true, _, _) | (false, None, 0) => Body::empty(),
+        (false, Some(range), _) => {
+            Body::wrap_stream(file_stream(file, layout, disk, piece_waiter, range))
+        }
+        (false, None, length) => Body::wrap_stream(file_stream(
+            file,
+            layout,
+            disk,
+            piece_waiter,
+            ByteRange {
+                start: 0,
+                end: length - 1,
+            },
+        )),
+    };
+
+    Ok(response.body(body).unwrap_or_default())
+}
+
+struct StreamState {
+    file: FileEntry,
+    layout: FileLayout,
+    disk: DiskHandle,
+    piece_waiter: PieceWaiter,
+    position: u64,
+    end: u64,
+}
+
+// Streams `range` of `file`, waiting for each piece to be downloaded and verified before reading it.
+fn file_stream(
+    file: FileEntry,
+    layout: FileLayout,
+    disk: DiskHandle,
+    piece_waiter: PieceWaiter,
+    range: ByteRange,
+) -> impl Stream<Item = std::io::Result<Vec<u8>>> {
+    let state = StreamState {
+        file,
+        layout,
+        disk,
+        piece_waiter,
+        position: range.start,
+        end: range.end,
+    };
+
+    stream::unfold(state, |mut state| async move {
+        if state.position > state.end {
+            return None;
+        }
+
+        let torrent_offset = state.file.offset + state.position;
+        let piece_end =
+            (torrent_offset / state.layout.piece_length + 1) * state.layout.piece_length;
+        let chunk_length = (state.end + 1 - state.position)
+            .min(piece_end - torrent_offset)
+            .min(STREAM_CHUNK_SIZE);
+
+        let pieces = state.layout.pieces_for_range(torrent_offset, chunk_length);
+        state.piece_waiter.wait_for_pieces(pieces).await;
+
+        match read_chunk(&state, torrent_offset, chunk_length).await {
+            Ok(chunk) => {
+                state.position += chunk_length;
+                Some((Ok(chunk), state))
+            }
+            Err(e) => {
+                // End the stream after reporting the error
+                state.position = state.end + 1;
+                Some((Err(e), state))
+            }
+        }
+    })
+}
+
+// Read like blocks for peers, through the storage and the disk caches. Chunks stay within a
+// piece, so each is one block.
+async fn read_chunk(
+    state: &StreamState,
+    torrent_offset: u64,
+    chunk_length: u64,
+) -> std::io::Result<Vec<u8>> {
+    let piece_length = state.layout.piece_length;
+    state
+        .disk
+        .read_block(
+            (torrent_offset / piece_length) as u32,
+            (torrent_offset % piece_length) as u32,
+            chunk_length as u32,
+        )
+        .await
+}
+
+fn content_type(file: &FileEntry) -> &'static str {
+    let extension = file
+        .path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+
+    match extension.as_deref() {
+        Some("mp4") | Some("m4v") => "video/mp4",
+        Some("mkv") => "video/x-matroska",
+        Some("webm") => "video/webm",
+        Some("avi") => "video/x-msvideo",
+        Some("mov") => "video/quicktime",
+        Some("mp3") => "audio/mpeg",
+        Some("flac") => "audio/flac",
+        Some("ogg") => "audio/ogg",
+        Some("wav") => "audio/wav",
+        Some("m4a") => "audio/mp4",
+        Some("srt") => "application/x-subrip",
+        Some("vtt") => "text/vtt",
+        Some("txt") => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+fn empty_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
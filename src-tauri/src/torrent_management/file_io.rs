@@ -1,48 +1,12 @@
-use bincode;
 use bitvec::macros::internal::funty::Integral;
 use tokio::io::AsyncWriteExt;
 use tokio::{
-    io,
     net::TcpStream,
     time::{sleep, Duration},
 };
 
-use crate::parsing::parser::torrent_metadata::TorrentMetadata;
-
 use super::message::Message;
 
-pub async fn save_piece_to_disk(
-    piece_data: &[u8],
-    path: &str,
-    metadata: &TorrentMetadata,
-) -> io::Result<()> {
-    // Serialize the metadata
-    let encoded_metadata: Vec<u8> = bincode::serialize(metadata).unwrap();
-
-    // Open the metadata file in write mode (overwrite if it exists)
-    let mut meta_file = tokio::fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .open(format!("{}.meta", path))
-        .await?;
-
-    // Write the metadata to the metadata file
-    meta_file.write_all(&encoded_metadata).await?;
-
-    // Open the data file in append mode (create it if it doesn't exist)
-    let data_file = tokio::fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .append(true)
-        .open(format!("{}.data", path))
-        .await;
-
-    // Write the received piece data to the end of the data file
-    data_file?.write_all(piece_data).await?;
-
-    Ok(())
-}
-
 pub async fn upload_piece_to_peer(
     peer_address: String,
     piece_index: u32,
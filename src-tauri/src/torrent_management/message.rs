@@ -1,11 +1,9 @@
 use crate::message_handling::message_error::MessageError;
-use serde::Serialize;
-use serde_bencode::to_bytes;
 // Struct that represents the payload of a message.
 // Contains an ID, the actual message, and any payload that comes with the message.
 
 // Enum representing all potential message types in the defined protocol.
-#[derive(Debug, PartialEq, Serialize)]
+#[derive(Debug, PartialEq)]
 pub enum Message {
     Choke,
     Unchoke,
@@ -13,18 +11,69 @@ pub enum Message {
     NotInterested,
     Have,
     Bitfield(Vec<u8>),
-    Request(u32),
+    // Piece index, offset within the piece and length of the requested block
+    Request(u32, u32, u32),
     Piece(usize, usize, Vec<u8>),
     Cancel,
     KeepAlive,
+    // Fast extension (BEP 6)
+    SuggestPiece(u32),
+    HaveAll,
+    HaveNone,
+    RejectRequest(u32, u32, u32),
+    AllowedFast(u32),
+    // Extension protocol message (BEP 10): extended message id and payload
+    Extended(u8, Vec<u8>),
 }
 
 impl Message {
+    // The message as it goes on the wire: length prefix, id and payload
     pub fn encode(&self) -> Vec<u8> {
-        to_bytes(self).expect("Failed to encode message")
+        let (id, payload) = match self {
+            Message::KeepAlive => return 0u32.to_be_bytes().to_vec(),
+            Message::Choke => (CHOKE_ID, Vec::new()),
+            Message::Unchoke => (UNCHOKE_ID, Vec::new()),
+            Message::Interested => (INTERESTED_ID, Vec::new()),
+            Message::NotInterested => (NOT_INTERESTED_ID, Vec::new()),
+            Message::Have => (4, Vec::new()),
+            Message::Bitfield(bitfield) => (BITFIELD_ID, bitfield.clone()),
+            Message::Request(index, begin, length) => (6, block_payload(*index, *begin, *length)),
+            Message::Piece(index, begin, data) => {
+                let mut payload = Vec::with_capacity(8 + data.len());
+                payload.extend_from_slice(&(*index as u32).to_be_bytes());
+                payload.extend_from_slice(&(*begin as u32).to_be_bytes());
+                payload.extend_from_slice(data);
+                (7, payload)
+            }
+            Message::Cancel => (8, Vec::new()),
+            Message::SuggestPiece(index) => (13, index.to_be_bytes().to_vec()),
+            Message::HaveAll => (14, Vec::new()),
+            Message::HaveNone => (15, Vec::new()),
+            Message::RejectRequest(index, begin, length) => {
+                (16, block_payload(*index, *begin, *length))
+            }
+            Message::AllowedFast(index) => (17, index.to_be_bytes().to_vec()),
+            Message::Extended(extended_id, payload) => {
+                let mut extended = vec![*extended_id];
+                extended.extend_from_slice(payload);
+                (20, extended)
+            }
+        };
+        let mut message = Vec::with_capacity(5 + payload.len());
+        message.extend_from_slice(&(1 + payload.len() as u32).to_be_bytes());
+        message.push(id);
+        message.extend_from_slice(&payload);
+        message
     }
 }
 
+// Ids of the messages we send without going through `Message`
+pub const CHOKE_ID: u8 = 0;
+pub const UNCHOKE_ID: u8 = 1;
+pub const INTERESTED_ID: u8 = 2;
+pub const NOT_INTERESTED_ID: u8 = 3;
+pub const BITFIELD_ID: u8 = 5;
+
 // Function to identify the type of message according to its id.
 // Returns a Message enum instance on success or a MessageError otherwise.
 pub fn identify_message(message_id: u8, message_body: &[u8]) -> Result<Message, MessageError> {
@@ -37,46 +86,56 @@ pub fn identify_message(message_id: u8, message_body: &[u8]) -> Result<Message,
         4 => Ok(Message::Have),
         5 => Ok(Message::Bitfield(message_body.to_vec())),
         6 => {
-            if let Some(index) = message_body.get(0) {
-                Ok(Message::Request(*index as u32))
-            } else {
-                Err(MessageError::UnknownMessage)
-            }
+            let (index, begin, length) = parse_block(message_body)?;
+            Ok(Message::Request(index, begin, length))
         }
         7 => {
             if message_body.len() < 8 {
                 return Err(MessageError::UnknownMessage);
             }
-
-            let index = if message_body.len() >= 4 {
-                Some(usize::from_le_bytes(
-                    message_body[0..4]
-                        .try_into()
-                        .expect("Expected 4 bytes for index"),
-                ))
-            } else {
-                return Err(MessageError::UnknownMessage);
-            };
-
-            let begin = if message_body.len() >= 8 {
-                Some(usize::from_le_bytes(
-                    message_body[4..8]
-                        .try_into()
-                        .expect("Expected 4 bytes for begin"),
-                ))
-            } else {
-                return Err(MessageError::UnknownMessage);
-            };
-
-            let data = message_body[8..].to_vec();
-
-            if let (Some(index), Some(begin)) = (index, begin) {
-                Ok(Message::Piece(index, begin, data))
-            } else {
-                Err(MessageError::UnknownMessage)
-            }
+            let index = parse_u32(&message_body[0..4])? as usize;
+            let begin = parse_u32(&message_body[4..8])? as usize;
+            Ok(Message::Piece(index, begin, message_body[8..].to_vec()))
         }
         8 => Ok(Message::Cancel),
+        13 => Ok(Message::SuggestPiece(parse_u32(message_body)?)),
+        14 => Ok(Message::HaveAll),
+        15 => Ok(Message::HaveNone),
+        16 => {
+            let (index, begin, length) = parse_block(message_body)?;
+            Ok(Message::RejectRequest(index, begin, length))
+        }
+        17 => Ok(Message::AllowedFast(parse_u32(message_body)?)),
+        20 => match message_body.split_first() {
+            Some((extended_id, payload)) => Ok(Message::Extended(*extended_id, payload.to_vec())),
+            None => Err(MessageError::UnknownMessage),
+        },
         _ => Err(MessageError::UnknownMessage),
     }
 }
+
+fn parse_u32(bytes: &[u8]) -> Result<u32, MessageError> {
+    bytes
+        .get(0..4)
+        .map(|bytes| u32::from_be_bytes(bytes.try_into().expect("Slice of 4 bytes")))
+        .ok_or(MessageError::UnknownMessage)
+}
+
+fn block_payload(index: u32, begin: u32, length: u32) -> Vec<u8> {
+    [index, begin, length]
+        .iter()
+        .flat_map(|value| value.to_be_bytes())
+        .collect()
+}
+
+// Index, begin and length of a request or rejected request
+fn parse_block(bytes: &[u8]) -> Result<(u32, u32, u32), MessageError> {
+    if bytes.len() < 12 {
+        return Err(MessageError::UnknownMessage);
+    }
+    Ok((
+        parse_u32(&bytes[0..4])?,
+        parse_u32(&bytes[4..8])?,
+        parse_u32(&bytes[8..12])?,
+    ))
+}
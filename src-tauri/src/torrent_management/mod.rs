@@ -1,6 +1,10 @@
 pub mod file_io;
 pub mod message;
 pub mod peers;
+pub mod piece_waiter;
+pub mod queue;
 pub mod torrent;
+pub mod torrent_creator;
 pub mod torrent_manager;
+pub mod torrent_snapshot;
 pub mod torrent_status;
@@ -1,22 +1,97 @@
 use crate::{
-    config,
+    config::{Config, PEER6_SIZE, PEER_SIZE},
     parsing::parser::torrent_metadata::TorrentMetadata,
-    tracker::{self, build_tracker_query},
+    tracker::{self, build_tracker_query, AnnounceEvent, TransferStats},
 };
 use serde_bencode::value::Value;
-use std::convert::TryInto;
-use std::net::Ipv4Addr;
+use std::{
+    collections::HashSet,
+    net::{IpAddr, SocketAddr},
+};
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct Peer {
-    pub ip: Ipv4Addr,
-    pub port: u16,
+    // IPv4 or IPv6, IPv4-mapped IPv6 addresses are stored as plain IPv4
+    pub address: SocketAddr,
+    // The swarm the peer was found in, hybrid torrents are in two. None means the torrent's own
+    // info hash, which is the v1 one for hybrids.
+    pub info_hash: Option<[u8; 20]>,
+}
+
+// A peer is its address, whichever swarm it was found in
+impl PartialEq for Peer {
+    fn eq(&self, other: &Peer) -> bool {
+        self.address == other.address
+    }
+}
+
+impl Peer {
+    pub fn new(address: SocketAddr) -> Peer {
+        let address = match address {
+            SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+                Some(ip) => SocketAddr::new(IpAddr::V4(ip), v6.port()),
+                None => address,
+            },
+            address => address,
+        };
+        Peer {
+            address,
+            info_hash: None,
+        }
+    }
+
+    // Found in the swarm of `info_hash`, the handshake has to name the same one
+    pub fn in_swarm(mut self, info_hash: &[u8]) -> Peer {
+        self.info_hash = info_hash.try_into().ok();
+        self
+    }
 }
 
-pub async fn get_peers(metadata: &TorrentMetadata) -> Result<Vec<Peer>, String> {
-    let query = build_tracker_query(metadata).await?;
+// Hybrid torrents are announced in both of their swarms, the peers are merged. Tracker ids
+// handed out in the responses are kept in `metadata` for the next announce.
+pub async fn get_peers(
+    metadata: &mut TorrentMetadata,
+    event: Option<AnnounceEvent>,
+    transfer: &TransferStats,
+    configuration: &Config,
+) -> Result<Vec<Peer>, String> {
+    let mut peers = Vec::new();
+    let mut last_error = None;
+    for info_hash in metadata.swarm_info_hashes() {
+        let swarm = TorrentMetadata {
+            info_hash: info_hash.clone(),
+            ..metadata.clone()
+        };
+        match announce_swarm(&swarm, event, transfer, configuration).await {
+            Ok((swarm_peers, tracker_id)) => {
+                for peer in swarm_peers {
+                    if !peers.contains(&peer) {
+                        peers.push(peer.in_swarm(&info_hash));
+                    }
+                }
+                if let Some(tracker_id) = tracker_id {
+                    metadata.tracker_ids.insert(info_hash, tracker_id);
+                }
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    match (peers.is_empty(), last_error) {
+        (true, Some(e)) => Err(e),
+        _ => Ok(peers),
+    }
+}
 
-    let response_bytes = match tracker::execute_tracker_query(query).await {
+// The swarm's peers and the tracker id, if the tracker sent one
+async fn announce_swarm(
+    metadata: &TorrentMetadata,
+    event: Option<AnnounceEvent>,
+    transfer: &TransferStats,
+    configuration: &Config,
+) -> Result<(Vec<Peer>, Option<String>), String> {
+    let query = build_tracker_query(metadata, event, transfer, configuration).await?;
+
+    let response_bytes = match tracker::execute_tracker_query(query, configuration).await {
         Ok(data) => data,
         Err(e) => return Err(e),
     };
@@ -32,40 +107,111 @@ pub async fn get_peers(metadata: &TorrentMetadata) -> Result<Vec<Peer>, String>
         return Err("Response should be a dict!".to_string());
     };
 
-    let peer_list = match response_dict.get(&b"peers".to_vec()) {
-        Some(Value::Bytes(s)) => s.clone(),
-        _ => return Err("Expected peers to be a ByteString".to_string()),
+    if let Some(Value::Bytes(reason)) = response_dict.get(&b"failure reason".to_vec()) {
+        return Err(format!(
+            "Tracker refused the announce: {}",
+            String::from_utf8_lossy(reason)
+        ));
+    }
+
+    // Compact IPv4 peers come in `peers`, compact IPv6 peers (BEP 7) in `peers6`. Trackers that
+    // don't do compact responses send `peers` as a list of dictionaries instead.
+    let mut peers = match response_dict.get(&b"peers".to_vec()) {
+        Some(Value::Bytes(s)) => unmarshal_peers(s)?,
+        Some(Value::List(entries)) => unmarshal_peer_dicts(entries).await,
+        None => Vec::new(),
+        _ => return Err("Expected peers to be a ByteString or a list".to_string()),
     };
+    match response_dict.get(&b"peers6".to_vec()) {
+        Some(Value::Bytes(s)) => peers.extend(unmarshal_peers6(s)?),
+        Some(Value::List(entries)) => peers.extend(unmarshal_peer_dicts(entries).await),
+        None => (),
+        _ => return Err("Expected peers6 to be a ByteString or a list".to_string()),
+    }
 
-    unmarshal_peers(&peer_list)
+    if !configuration.ipv6_enabled {
+        peers.retain(|peer| peer.address.is_ipv4());
+    }
+    dedup_peers(&mut peers);
+
+    let tracker_id = match response_dict.get(&b"tracker id".to_vec()) {
+        Some(Value::Bytes(tracker_id)) => Some(String::from_utf8_lossy(tracker_id).to_string()),
+        _ => None,
+    };
+
+    Ok((peers, tracker_id))
 }
 
-pub fn unmarshal_peers(peers: &Vec<u8>) -> Result<Vec<Peer>, String> {
-    let configuration = config::Config::new();
-    let peer_size = configuration.peer_size;
-    let mut unmarshalled_peers: Vec<Peer> = Vec::new();
+// Drops every peer whose address came up before, keeping the order
+pub fn dedup_peers(peers: &mut Vec<Peer>) {
+    let mut seen = HashSet::new();
+    peers.retain(|peer| seen.insert(peer.address));
+}
 
-    if peers.len() as u16 % peer_size != 0 {
-        return Err("Received malformed peers".to_string());
+// Decodes the non-compact form, a list of `{ "ip", "port", "peer id" }` dictionaries. `ip` may
+// be an IPv4 or IPv6 literal or a hostname, which is resolved. Entries that can't be used are
+// skipped rather than failing the whole announce. The peer id is checked during the handshake
+// instead, so it is not kept here.
+pub async fn unmarshal_peer_dicts(entries: &[Value]) -> Vec<Peer> {
+    let mut peers = Vec::new();
+
+    for entry in entries {
+        let Value::Dict(dict) = entry else {
+            continue;
+        };
+        let (Some(Value::Bytes(ip)), Some(Value::Int(port))) =
+            (dict.get(&b"ip".to_vec()), dict.get(&b"port".to_vec()))
+        else {
+            println!("Skipping peer entry without ip or port");
+            continue;
+        };
+        let (Ok(host), Ok(port)) = (std::str::from_utf8(ip), u16::try_from(*port)) else {
+            println!("Skipping malformed peer entry");
+            continue;
+        };
+
+        match host.parse::<IpAddr>() {
+            Ok(ip) => peers.push(Peer::new(SocketAddr::new(ip, port))),
+            Err(_) => match tokio::net::lookup_host((host, port)).await {
+                Ok(mut addresses) => {
+                    if let Some(address) = addresses.next() {
+                        peers.push(Peer::new(address));
+                    }
+                }
+                Err(e) => println!("Failed to resolve peer {}: {}", host, e),
+            },
+        }
     }
-    let peer_chunks: Vec<&[u8]> = peers.chunks(peer_size as usize).collect();
 
-    for chunk in peer_chunks {
-        // Split the chunk into IP and port parts
-        let (ip_part, port_part) = chunk.split_at(4);
+    peers
+}
 
-        // Convert port bytes into u16
-        let port = match port_part.try_into() {
-            Ok(array) => u16::from_be_bytes(array),
-            Err(_) => return Err(format!("Invalid port value in chunk: {:?}", chunk)),
-        };
+// Decodes compact IPv4 peers, 4 address bytes followed by 2 port bytes each
+pub fn unmarshal_peers(peers: &[u8]) -> Result<Vec<Peer>, String> {
+    unmarshal_compact(peers, PEER_SIZE as usize)
+}
+
+// Decodes compact IPv6 peers, 16 address bytes followed by 2 port bytes each
+pub fn unmarshal_peers6(peers: &[u8]) -> Result<Vec<Peer>, String> {
+    unmarshal_compact(peers, PEER6_SIZE)
+}
 
-        // Create a new Peer object and push it to the vector
-        unmarshalled_peers.push(Peer {
-            ip: Ipv4Addr::new(ip_part[0], ip_part[1], ip_part[2], ip_part[3]),
-            port,
-        });
+fn unmarshal_compact(peers: &[u8], peer_size: usize) -> Result<Vec<Peer>, String> {
+    if peers.len() % peer_size != 0 {
+        return Err("Received malformed peers".to_string());
     }
 
-    Ok(unmarshalled_peers)
+    peers
+        .chunks(peer_size)
+        .map(|chunk| {
+            // Split the chunk into IP and port parts
+            let (ip_part, port_part) = chunk.split_at(peer_size - 2);
+            let port = u16::from_be_bytes([port_part[0], port_part[1]]);
+            let ip = match ip_part.len() {
+                4 => IpAddr::from(<[u8; 4]>::try_from(ip_part).map_err(|e| e.to_string())?),
+                _ => IpAddr::from(<[u8; 16]>::try_from(ip_part).map_err(|e| e.to_string())?),
+            };
+            Ok(Peer::new(SocketAddr::new(ip, port)))
+        })
+        .collect()
 }
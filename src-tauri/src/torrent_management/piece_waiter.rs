@@ -0,0 +1,45 @@
+use bitvec::prelude::{BitVec, Lsb0};
+use std::{ops::Range, sync::Arc};
+use tokio::sync::{Notify, RwLock};
+
+// Lets readers wait for pieces to be downloaded and verified without holding the torrent lock.
+#[derive(Clone)]
+pub struct PieceWaiter {
+    pieces_status: Arc<RwLock<BitVec<u8, Lsb0>>>,
+    piece_notify: Arc<Notify>,
+}
+
+impl PieceWaiter {
+    pub fn new(pieces_status: Arc<RwLock<BitVec<u8, Lsb0>>>, piece_notify: Arc<Notify>) -> Self {
+        PieceWaiter {
+            pieces_status,
+            piece_notify,
+        }
+    }
+
+    pub async fn has_pieces(&self, pieces: Range<usize>) -> bool {
+        let pieces_status = self.pieces_status.read().await;
+        pieces.into_iter().all(|index| {
+            pieces_status
+                .get(index)
+                .map(|status| *status)
+                .unwrap_or(false)
+        })
+    }
+
+    // Resolves once every piece in `pieces` is marked as downloaded.
+    pub async fn wait_for_pieces(&self, pieces: Range<usize>) {
+        loop {
+            // Register interest before checking, so a piece completing in between is not missed
+            let notified = self.piece_notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if self.has_pieces(pieces.clone()).await {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use serde::Deserialize;
+
+use crate::config::Config;
+
+// How often the queue hands out slots, besides right after torrents are started or moved
+pub const QUEUE_INTERVAL_MS: u64 = 2000;
+
+// An auto-managed torrent that wants to run, in queue order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueEntry {
+    pub active: bool,
+    pub seeding: bool,
+    // Running for a while without getting anywhere, it doesn't take a slot
+    pub slow: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueAction {
+    Start,
+    // Give up the slot and wait for another one
+    Queue,
+    Keep,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueMove {
+    Up,
+    Down,
+    Top,
+    Bottom,
+}
+
+// 0 means no limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueLimits {
+    pub downloads: usize,
+    pub seeds: usize,
+    pub total: usize,
+}
+
+impl QueueLimits {
+    pub fn from_config(configuration: &Config) -> QueueLimits {
+        QueueLimits {
+            downloads: configuration.max_active_downloads,
+            seeds: configuration.max_active_seeds,
+            total: configuration.max_active_total,
+        }
+    }
+}
+
+// Slots go to torrents in queue order. Running torrents past the limits are queued again, so a
+// torrent moved up takes over the slot of one further down.
+pub fn plan_queue(entries: &[QueueEntry], limits: &QueueLimits) -> Vec<QueueAction> {
+    let below = |count: usize, limit: usize| limit == 0 || count < limit;
+    let mut downloads = 0;
+    let mut seeds = 0;
+    entries
+        .iter()
+        .map(|entry| {
+            if entry.active && entry.slow {
+                return QueueAction::Keep;
+            }
+            let has_slot = below(downloads + seeds, limits.total)
+                && match entry.seeding {
+                    true => below(seeds, limits.seeds),
+                    false => below(downloads, limits.downloads),
+                };
+            match (has_slot, entry.active) {
+                (true, active) => {
+                    match entry.seeding {
+                        true => seeds += 1,
+                        false => downloads += 1,
+                    }
+                    match active {
+                        true => QueueAction::Keep,
+                        false => QueueAction::Start,
+                    }
+                }
+                (false, true) => QueueAction::Queue,
+                (false, false) => QueueAction::Keep,
+            }
+        })
+        .collect()
+}
+
+// Where a torrent at `position` in a queue of `length` ends up
+pub fn move_position(position: usize, length: usize, movement: QueueMove) -> usize {
+    let last = length.saturating_sub(1);
+    match movement {
+        QueueMove::Up => position.saturating_sub(1),
+        QueueMove::Down => (position + 1).min(last),
+        QueueMove::Top => 0,
+        QueueMove::Bottom => last,
+    }
+}
@@ -1,56 +1,150 @@
 use crate::{
-    message_handling::message_error::MessageError,
-    parsing::parser::torrent_metadata::TorrentMetadata, peers::Peer,
+    config::{AllocationMode, Config, SharedConfig},
+    events::{event_bus::EventBus, torrent_event::TorrentEvent},
+    network::{
+        extension::{extended_handshake, send_extended, EXTENDED_HANDSHAKE_ID},
+        fast::{
+            allowed_fast_set, send_allowed_fast, send_availability, send_reject_request,
+            FastPeerState, ALLOWED_FAST_SET_SIZE,
+        },
+        peer_connection::{read_messages, send_message, PeerWriter},
+        peer_handshake::{connect_with_handshake, Handshake, RemoteHandshake},
+        peer_id::ClientInfo,
+        peer_stream::PeerStream,
+        rate_limit::RateLimits,
+        utp::socket::UtpSocket,
+        web_seed::WebSeed,
+    },
+    parsing::parser::torrent_metadata::TorrentMetadata,
+    storage::{
+        backend::MoveConflict,
+        disk_io::{DiskHandle, READ_AHEAD_PIECES},
+        file_layout::FileLayout,
+        piece_hashes::PieceHashes,
+        verify::verify_pieces,
+    },
+    torrent_management::peers::{get_peers, Peer},
+    tracker::{announce_event, AnnounceEvent, TransferStats},
 };
 use bitvec::prelude::BitVec;
 use bitvec::prelude::Lsb0;
-use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use sha1::{Digest, Sha1};
-use std::{collections::HashMap, net::Ipv4Addr, sync::Arc};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::{
-    io::{self, AsyncReadExt, AsyncWriteExt, Result},
-    net::TcpStream,
-    sync::{Mutex, RwLock},
+    io::{self, AsyncWriteExt, ReadHalf, Result},
+    sync::{oneshot, Mutex, Notify, RwLock},
+    task::{AbortHandle, JoinHandle},
 };
 
 use super::{
-    file_io::save_piece_to_disk,
     message::{self, Message},
+    piece_waiter::PieceWaiter,
+    torrent_snapshot::{progress, FileSnapshot, PeerSnapshot, TorrentDetails, TorrentSummary},
     torrent_status::TorrentStatus,
 };
 
+// How long a peer has to send a piece we asked for
+const PIECE_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// How long the download waits before looking again when no one has a piece we miss
+const PIECE_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+type PendingRequests = HashMap<(SocketAddr, u32), oneshot::Sender<io::Result<Vec<u8>>>>;
+
 pub struct Torrent {
     info_hash: [u8; 20],
-    current_downloaded: AtomicU64,
-    current_uploaded: AtomicU64,
+    current_downloaded: Arc<AtomicU64>,
+    current_uploaded: Arc<AtomicU64>,
+    // Bytes per second, refreshed by the rate sampler while the torrent is active
+    download_rate: Arc<AtomicU64>,
+    upload_rate: Arc<AtomicU64>,
     total_size: u64,
-    pub peers: Arc<Vec<Peer>>,
+    pub peers: Arc<RwLock<Vec<Peer>>>,
     pub metadata: Arc<RwLock<TorrentMetadata>>,
     status: Arc<RwLock<TorrentStatus>>,
-    peer_connections: Arc<RwLock<HashMap<Ipv4Addr, Arc<Mutex<TcpStream>>>>>,
+    peer_connections: Arc<RwLock<HashMap<SocketAddr, Arc<Mutex<PeerWriter>>>>>,
+    // The task reading each connection's messages
+    peer_readers: Arc<std::sync::Mutex<HashMap<SocketAddr, AbortHandle>>>,
+    // Pieces requested from peers, handed over by the peer's read loop
+    pending_requests: Arc<std::sync::Mutex<PendingRequests>>,
+    // What each connected peer identified as in its handshake
+    peer_clients: Arc<RwLock<HashMap<SocketAddr, ClientInfo>>>,
+    // Connected peers that negotiated the Fast extension
+    fast_peers: Arc<RwLock<HashMap<SocketAddr, FastPeerState>>>,
+    // Peers start out choked, these are the ones we currently let download from us
+    unchoked_peers: Arc<RwLock<HashSet<SocketAddr>>>,
+    // Peers that want to download from us, unchoked while there are free upload slots
+    interested_peers: Arc<RwLock<HashSet<SocketAddr>>>,
+    // Peers that choke us, they only send the pieces of their allowed-fast set
+    choking_peers: Arc<RwLock<HashSet<SocketAddr>>>,
+    // Pieces peers suggested we download first
+    suggested_pieces: Arc<RwLock<HashSet<u32>>>,
+    // Web seeds of the running session that still work, they count as having every piece
+    active_web_seeds: Arc<AtomicUsize>,
     pub pieces_status: Arc<RwLock<BitVec<u8, Lsb0>>>,
     piece_frequency: Arc<RwLock<HashMap<u32, Arc<AtomicU64>>>>,
-    piece_hashes: Arc<Vec<[u8; 20]>>,
+    piece_hashes: Arc<PieceHashes>,
     is_downloading: AtomicBool,
-    path: String,
+    disk: DiskHandle,
+    // How the files are created when the torrent starts
+    allocation: AllocationMode,
+    piece_notify: Arc<Notify>,
+    events: EventBus,
+    config: SharedConfig,
+    // Shared with the peer listener, None when uTP is disabled
+    utp_socket: Option<UtpSocket>,
+    // The session-wide limits once the torrent is added, its own until then
+    rate_limits: RateLimits,
+    // Abort handles of the background tasks belonging to the running session
+    session_tasks: Arc<std::sync::Mutex<Vec<AbortHandle>>>,
+    session_started: Arc<std::sync::Mutex<Option<Instant>>>,
+    // Started and queued by the torrent manager's queue rather than by hand
+    auto_managed: Arc<AtomicBool>,
+    queue_position: Arc<AtomicUsize>,
 }
 
 impl Clone for Torrent {
     fn clone(&self) -> Self {
         Torrent {
             info_hash: self.info_hash,
-            current_downloaded: AtomicU64::new(self.current_downloaded.load(Ordering::SeqCst)),
-            current_uploaded: AtomicU64::new(self.current_uploaded.load(Ordering::SeqCst)),
+            current_downloaded: Arc::clone(&self.current_downloaded),
+            current_uploaded: Arc::clone(&self.current_uploaded),
+            download_rate: Arc::clone(&self.download_rate),
+            upload_rate: Arc::clone(&self.upload_rate),
             total_size: self.total_size,
             peers: Arc::clone(&self.peers),
             metadata: Arc::clone(&self.metadata),
             status: Arc::clone(&self.status),
             peer_connections: Arc::clone(&self.peer_connections),
+            peer_readers: Arc::clone(&self.peer_readers),
+            pending_requests: Arc::clone(&self.pending_requests),
+            peer_clients: Arc::clone(&self.peer_clients),
+            fast_peers: Arc::clone(&self.fast_peers),
+            unchoked_peers: Arc::clone(&self.unchoked_peers),
+            interested_peers: Arc::clone(&self.interested_peers),
+            choking_peers: Arc::clone(&self.choking_peers),
+            suggested_pieces: Arc::clone(&self.suggested_pieces),
+            active_web_seeds: Arc::clone(&self.active_web_seeds),
             pieces_status: Arc::clone(&self.pieces_status),
             piece_frequency: Arc::clone(&self.piece_frequency),
             piece_hashes: Arc::clone(&self.piece_hashes),
             is_downloading: AtomicBool::new(self.is_downloading.load(Ordering::SeqCst)),
-            path: self.path.clone(),
+            disk: self.disk.clone(),
+            allocation: self.allocation,
+            piece_notify: Arc::clone(&self.piece_notify),
+            events: self.events.clone(),
+            config: Arc::clone(&self.config),
+            utp_socket: self.utp_socket.clone(),
+            rate_limits: self.rate_limits.clone(),
+            session_tasks: Arc::clone(&self.session_tasks),
+            session_started: Arc::clone(&self.session_started),
+            auto_managed: Arc::clone(&self.auto_managed),
+            queue_position: Arc::clone(&self.queue_position),
         }
     }
 }
@@ -59,62 +153,736 @@ impl Torrent {
     pub fn new(
         info_hash: [u8; 20],
         total_size: u64,
-        peers: Arc<Vec<Peer>>,
+        peers: Arc<RwLock<Vec<Peer>>>,
         metadata: Arc<RwLock<TorrentMetadata>>,
         pieces_status: Arc<RwLock<BitVec<u8, Lsb0>>>,
         piece_frequency: Arc<RwLock<HashMap<u32, Arc<AtomicU64>>>>,
-        piece_hashes: Arc<Vec<[u8; 20]>>,
+        piece_hashes: Arc<PieceHashes>,
         is_downloading: AtomicBool,
-        path: String,
+        disk: DiskHandle,
+        allocation: AllocationMode,
+        events: EventBus,
+        config: SharedConfig,
+        utp_socket: Option<UtpSocket>,
     ) -> Self {
         Torrent {
             info_hash,
-            current_downloaded: AtomicU64::new(0),
-            current_uploaded: AtomicU64::new(0),
+            current_downloaded: Arc::new(AtomicU64::new(0)),
+            current_uploaded: Arc::new(AtomicU64::new(0)),
+            download_rate: Arc::new(AtomicU64::new(0)),
+            upload_rate: Arc::new(AtomicU64::new(0)),
             total_size,
             peers,
             metadata,
             status: Arc::new(RwLock::new(TorrentStatus::Connecting)),
             peer_connections: Arc::new(RwLock::new(HashMap::new())),
+            peer_readers: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            pending_requests: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            peer_clients: Arc::new(RwLock::new(HashMap::new())),
+            fast_peers: Arc::new(RwLock::new(HashMap::new())),
+            unchoked_peers: Arc::new(RwLock::new(HashSet::new())),
+            interested_peers: Arc::new(RwLock::new(HashSet::new())),
+            choking_peers: Arc::new(RwLock::new(HashSet::new())),
+            suggested_pieces: Arc::new(RwLock::new(HashSet::new())),
+            active_web_seeds: Arc::new(AtomicUsize::new(0)),
             pieces_status,
             piece_frequency,
             piece_hashes,
             is_downloading,
-            path,
-        }
-    }
-
-    pub async fn start(&mut self) -> Result<()> {
-        self.status = Arc::new(RwLock::new(TorrentStatus::Connecting));
-        let peer_connections = Arc::clone(&self.peer_connections);
-        for peer in &*self.peers {
-            let peer_clone = peer.clone();
-            let peer_connections_clone = Arc::clone(&peer_connections);
-            tokio::spawn(async move {
-                match TcpStream::connect((peer_clone.ip, peer_clone.port)).await {
-                    Ok(stream) => {
-                        let mut peer_connections = peer_connections_clone.write().await;
-                        peer_connections.insert(peer_clone.ip, Arc::new(Mutex::new(stream)));
-                        // Send the handshake message
-                        // In the handshake response, you would also receive piece availability info
-                        // Update piece_frequency map here
-                    }
-                    Err(e) => println!("Failed to connect to peer: {:?}", e),
+            disk,
+            allocation,
+            piece_notify: Arc::new(Notify::new()),
+            events,
+            rate_limits: RateLimits::new(Arc::clone(&config)),
+            config,
+            utp_socket,
+            session_tasks: Arc::new(std::sync::Mutex::new(Vec::new())),
+            session_started: Arc::new(std::sync::Mutex::new(None)),
+            auto_managed: Arc::new(AtomicBool::new(false)),
+            queue_position: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    // Shares the limits with the other torrents of the session
+    pub fn with_rate_limits(mut self, rate_limits: RateLimits) -> Self {
+        self.rate_limits = rate_limits;
+        self
+    }
+
+    pub fn rate_limits(&self) -> &RateLimits {
+        &self.rate_limits
+    }
+
+    pub fn info_hash_hex(&self) -> String {
+        hex::encode(self.info_hash)
+    }
+
+    pub async fn summary(&self) -> TorrentSummary {
+        let metadata = self.metadata.read().await;
+        let layout = FileLayout::from_metadata(&metadata);
+        let pieces_status = self.pieces_status.read().await;
+        let completed_size = layout.completed_bytes(0, layout.total_length, &pieces_status);
+
+        let downloaded = self.current_downloaded.load(Ordering::SeqCst);
+        let uploaded = self.current_uploaded.load(Ordering::SeqCst);
+        let download_rate = self.download_rate.load(Ordering::SeqCst);
+        let remaining = self.total_size.saturating_sub(completed_size);
+
+        TorrentSummary {
+            name: metadata.info.name.clone(),
+            info_hash: self.info_hash_hex(),
+            total_size: self.total_size,
+            completed_size,
+            progress: progress(completed_size, self.total_size),
+            downloaded,
+            uploaded,
+            download_rate,
+            upload_rate: self.upload_rate.load(Ordering::SeqCst),
+            eta_seconds: match (remaining, download_rate) {
+                (0, _) => Some(0),
+                (_, 0) => None,
+                (remaining, rate) => Some(remaining / rate),
+            },
+            status: *self.status.read().await,
+            ratio: match downloaded {
+                0 => 0.0,
+                downloaded => uploaded as f64 / downloaded as f64,
+            },
+            connected_peers: self.peer_connections.read().await.len(),
+            queue_position: self.queue_position(),
+            auto_managed: self.is_auto_managed(),
+        }
+    }
+
+    pub async fn details(&self) -> TorrentDetails {
+        let summary = self.summary().await;
+        let metadata = self.metadata.read().await;
+        let layout = FileLayout::from_metadata(&metadata);
+        let pieces_status = self.pieces_status.read().await;
+        let peer_connections = self.peer_connections.read().await;
+        let peer_clients = self.peer_clients.read().await;
+
+        let files = layout
+            .files
+            .iter()
+            .enumerate()
+            .filter(|(_, file)| !file.padding)
+            .map(|(index, file)| {
+                let completed_size =
+                    layout.completed_bytes(file.offset, file.length, &pieces_status);
+                FileSnapshot {
+                    index,
+                    path: file.path.to_string_lossy().to_string(),
+                    size: file.length,
+                    completed_size,
+                    progress: progress(completed_size, file.length),
                 }
+            })
+            .collect();
+
+        let peers = self
+            .peers
+            .read()
+            .await
+            .iter()
+            .map(|peer| PeerSnapshot {
+                address: peer.address.to_string(),
+                connected: peer_connections.contains_key(&peer.address),
+                client: peer_clients
+                    .get(&peer.address)
+                    .map(|client| client.to_string()),
+            })
+            .collect();
+
+        TorrentDetails {
+            summary,
+            info_hash_v2: match metadata.info_hash_v2.is_empty() {
+                true => None,
+                false => Some(hex::encode(&metadata.info_hash_v2)),
+            },
+            piece_length: layout.piece_length,
+            allocation_mode: self.allocation,
+            category: metadata.category.clone(),
+            piece_count: pieces_status.len(),
+            pieces: hex::encode(pieces_status.as_raw_slice()),
+            peers,
+            trackers: vec![metadata.announce.clone()],
+            files,
+        }
+    }
+
+    async fn set_status(&self, status: TorrentStatus) {
+        let mut current_status = self.status.write().await;
+        if *current_status != status {
+            *current_status = status;
+            self.events.emit(TorrentEvent::StatusChanged {
+                info_hash: self.info_hash_hex(),
+                status,
             });
         }
+    }
+
+    // Starts a session: connects to peers and spawns the download, announce and rate sampling
+    // tasks in the background. The tasks are tracked so `pause` and `stop` can cancel them.
+    pub async fn start(&self) -> Result<()> {
+        if self.is_active() {
+            return Ok(());
+        }
+        if self.check_status().await == TorrentStatus::Moving {
+            return Err(io::Error::other("The torrent's files are being moved"));
+        }
+
+        // Before any peer can send data, a full disk fails the torrent instead of the download
+        let allocation = self.allocation;
+        if let Err(e) = self
+            .disk
+            .run(move |storage| storage.allocate(allocation))
+            .await
+        {
+            self.set_status(TorrentStatus::Error).await;
+            return Err(io::Error::new(
+                e.kind(),
+                format!("Failed to allocate the torrent's files: {}", e),
+            ));
+        }
+
+        *self
+            .session_started
+            .lock()
+            .expect("Session start lock poisoned") = Some(Instant::now());
+        self.set_status(TorrentStatus::Connecting).await;
+        let max_peers = self.config.read().await.max_peers_per_torrent;
+        let peers: Vec<Peer> = self
+            .peers
+            .read()
+            .await
+            .iter()
+            .take(max_peers)
+            .cloned()
+            .collect();
+        for peer in peers {
+            self.connect_peer(peer).await;
+        }
+
+        self.spawn_rate_sampler();
+        self.spawn_announcer();
+
+        let torrent = self.clone();
+        self.track_session_task(tokio::spawn(async move {
+            if let Err(e) = torrent.download_and_seed().await {
+                torrent.emit_error(format!("Download failed: {}", e));
+            }
+        }));
+
+        Ok(())
+    }
+
+    // Connects in the background as part of the running session
+    async fn connect_peer(&self, peer: Peer) {
+        let encryption = self.config.read().await.encryption;
+        let peer_id = self.metadata.read().await.peer_id.clone();
+        let torrent = self.clone();
+        // Hybrid torrents are in two swarms, peers only know the torrent by their swarm's hash
+        let info_hash = peer.info_hash.unwrap_or(self.info_hash);
+        let handshake = Handshake::new(info_hash.to_vec(), peer_id)
+            .with_extension_protocol()
+            .with_fast_extension();
+        let utp_socket = self.utp_socket.clone();
+        self.track_session_task(tokio::spawn(async move {
+            let connection =
+                connect_with_handshake(&peer, &handshake, encryption, utp_socket.as_ref());
+            match connection.await {
+                Ok((stream, remote)) => {
+                    torrent.register_connection(peer, stream, &remote).await;
+                    // In the handshake response, you would also receive piece availability info
+                    // Update piece_frequency map here
+                }
+                Err(e) => println!("Failed to connect to peer: {:?}", e),
+            }
+        }));
+    }
+
+    // LAN peers go to the front of the peer list, so they are connected to and asked for pieces
+    // before anyone else. A running session connects to them right away.
+    pub async fn add_local_peer(&self, peer: Peer) {
+        {
+            let mut peers = self.peers.write().await;
+            peers.retain(|known| *known != peer);
+            peers.insert(0, peer.clone());
+        }
+        let connected = self
+            .peer_connections
+            .read()
+            .await
+            .contains_key(&peer.address);
+        if self.is_active() && !connected && !self.is_at_peer_limit().await {
+            self.connect_peer(peer).await;
+        }
+    }
+
+    // Takes over a connection whose handshake is done, outgoing or accepted by the peer listener
+    pub async fn register_connection(
+        &self,
+        peer: Peer,
+        mut stream: PeerStream,
+        remote: &RemoteHandshake,
+    ) {
+        if remote.supports_extension_protocol() {
+            let listen_port = self.config.read().await.listen_port;
+            let extended = extended_handshake(listen_port, self.is_private().await);
+            if let Err(e) = send_extended(&mut stream, EXTENDED_HANDSHAKE_ID, &extended, &[]).await
+            {
+                println!("Failed to send extended handshake: {}", e);
+            }
+        }
+
+        if remote.supports_fast_extension() {
+            let fast_state = self.start_fast_extension(&peer, &mut stream).await;
+            self.fast_peers
+                .write()
+                .await
+                .insert(peer.address, fast_state);
+        }
+
+        // Peers choke us until they say otherwise, and we want their pieces until we have them all
+        self.choking_peers.write().await.insert(peer.address);
+        if !self.is_complete().await {
+            if let Err(e) = send_message(&mut stream, message::INTERESTED_ID, &[]).await {
+                println!("Failed to send interested: {}", e);
+            }
+        }
+
+        let client = remote.client();
+        if let Some(client) = &client {
+            self.peer_clients
+                .write()
+                .await
+                .insert(peer.address, client.clone());
+        }
+        let (reader, writer) = tokio::io::split(stream);
+        self.peer_connections
+            .write()
+            .await
+            .insert(peer.address, Arc::new(Mutex::new(writer)));
+        self.add_peers(vec![peer.clone()]).await;
+        self.events.emit(TorrentEvent::PeerConnected {
+            info_hash: self.info_hash_hex(),
+            peer: peer.address.to_string(),
+            client: client.map(|client| client.to_string()),
+        });
+        self.spawn_peer_reader(peer, reader);
+    }
+
+    // Handles the peer's messages in the background, the peer is disconnected once they stop
+    fn spawn_peer_reader(&self, peer: Peer, mut reader: ReadHalf<PeerStream>) {
+        let address = peer.address;
+        let mut torrent = self.clone();
+        let task = tokio::spawn(async move {
+            if let Err(e) = read_messages(&mut reader, &peer, &mut torrent).await {
+                println!("Connection to {} ended: {}", peer.address, e);
+            }
+            // Already done, nothing left to abort
+            torrent
+                .peer_readers
+                .lock()
+                .expect("Peer reader list poisoned")
+                .remove(&peer.address);
+            torrent.disconnect_peer(&peer.address).await;
+        });
+        self.peer_readers
+            .lock()
+            .expect("Peer reader list poisoned")
+            .insert(address, task.abort_handle());
+        self.track_session_task(task);
+    }
+
+    // Announces our pieces and the allowed-fast set to a peer that negotiated the Fast extension
+    async fn start_fast_extension(&self, peer: &Peer, stream: &mut PeerStream) -> FastPeerState {
+        let pieces_status = self.pieces_status.read().await.clone();
+        if let Err(e) = send_availability(stream, &pieces_status).await {
+            println!("Failed to send piece availability: {}", e);
+        }
+
+        // Both ends derive the set from the info hash of the connection's swarm
+        let allowed_fast: HashSet<u32> = allowed_fast_set(
+            peer.address.ip(),
+            &peer.info_hash.unwrap_or(self.info_hash),
+            self.piece_hashes.len() as u32,
+            ALLOWED_FAST_SET_SIZE,
+        )
+        .into_iter()
+        .collect();
+        // Offering pieces we don't have yet would only get their requests rejected
+        for &piece_index in &allowed_fast {
+            if pieces_status
+                .get(piece_index as usize)
+                .is_some_and(|bit| *bit)
+            {
+                if let Err(e) = send_allowed_fast(stream, piece_index).await {
+                    println!("Failed to send allowed fast piece: {}", e);
+                    break;
+                }
+            }
+        }
+
+        FastPeerState {
+            allowed_fast,
+            ..Default::default()
+        }
+    }
 
-        self.download_and_seed().await?;
+    // A choked peer may still download the pieces of its allowed-fast set
+    pub async fn may_serve(&self, address: &SocketAddr, piece_index: u32) -> bool {
+        self.unchoked_peers.read().await.contains(address)
+            || self
+                .fast_peers
+                .read()
+                .await
+                .get(address)
+                .is_some_and(|state| state.allowed_fast.contains(&piece_index))
+    }
 
+    // Chokes or unchokes a connected peer, letting it know
+    pub async fn set_choking(&self, address: &SocketAddr, choking: bool) -> io::Result<()> {
+        let changed = match choking {
+            true => self.unchoked_peers.write().await.remove(address),
+            false => self.unchoked_peers.write().await.insert(*address),
+        };
+        if !changed {
+            return Ok(());
+        }
+        let message = match choking {
+            true => message::CHOKE_ID,
+            false => message::UNCHOKE_ID,
+        };
+        let connection = self.peer_connections.read().await.get(address).cloned();
+        if let Some(connection) = connection {
+            send_message(&mut *connection.lock().await, message, &[])
+                .await
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        }
         Ok(())
     }
 
-    pub async fn download_and_seed(&mut self) -> Result<()> {
-        *self.status.write().await = TorrentStatus::Downloading;
+    // Unchokes interested peers while there are free upload slots
+    async fn update_choking(&self) {
+        let upload_slots = self.config.read().await.upload_slots;
+        let waiting: Vec<SocketAddr> = {
+            let unchoked_peers = self.unchoked_peers.read().await;
+            self.interested_peers
+                .read()
+                .await
+                .iter()
+                .filter(|address| !unchoked_peers.contains(address))
+                .copied()
+                .collect()
+        };
+        for address in waiting {
+            if upload_slots != 0 && self.unchoked_peers.read().await.len() >= upload_slots {
+                break;
+            }
+            if let Err(e) = self.set_choking(&address, false).await {
+                println!("Failed to unchoke {}: {}", address, e);
+            }
+        }
+    }
+
+    // A peer that loses interest gives up its upload slot
+    pub async fn set_peer_interested(&self, address: &SocketAddr, interested: bool) {
+        if interested {
+            self.interested_peers.write().await.insert(*address);
+        } else {
+            self.interested_peers.write().await.remove(address);
+            if let Err(e) = self.set_choking(address, true).await {
+                println!("Failed to choke {}: {}", address, e);
+            }
+        }
+        self.update_choking().await;
+    }
+
+    // Without the Fast extension a choke drops every request we sent the peer
+    pub async fn set_peer_choking(&self, address: &SocketAddr, choking: bool) {
+        if !choking {
+            self.choking_peers.write().await.remove(address);
+            return;
+        }
+        self.choking_peers.write().await.insert(*address);
+        if !self.fast_peers.read().await.contains_key(address) {
+            self.pending_requests
+                .lock()
+                .expect("Pending request list poisoned")
+                .retain(|(peer, _), _| peer != address);
+        }
+    }
+
+    // Drops a request we won't serve. Peers with the Fast extension are told so they don't wait
+    // for the block, others only notice through the choke.
+    pub async fn reject_request(&self, address: &SocketAddr, index: u32, begin: u32, length: u32) {
+        if !self.fast_peers.read().await.contains_key(address) {
+            println!("Dropping request for piece {} from {}", index, address);
+            return;
+        }
+        let Some(connection) = self.peer_connections.read().await.get(address).cloned() else {
+            return;
+        };
+        let mut connection = connection.lock().await;
+        if let Err(e) = send_reject_request(&mut *connection, index, begin, length).await {
+            println!("Failed to reject request from {}: {}", address, e);
+        }
+    }
+
+    // A peer with every piece (HaveAll) counts towards the availability of all of them
+    pub async fn add_seed_availability(&self) {
+        let mut piece_frequency = self.piece_frequency.write().await;
+        for piece_index in 0..self.piece_hashes.len() as u32 {
+            piece_frequency
+                .entry(piece_index)
+                .or_insert_with(|| Arc::new(AtomicU64::new(0)))
+                .fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    // Pieces in a peer's allowed-fast set can be requested from it even while it chokes us
+    pub async fn add_peer_allowed_fast(&self, address: &SocketAddr, piece_index: u32) {
+        if let Some(state) = self.fast_peers.write().await.get_mut(address) {
+            state.peer_allowed_fast.insert(piece_index);
+        }
+    }
+
+    pub async fn suggest_piece(&self, piece_index: u32) {
+        if (piece_index as usize) < self.piece_hashes.len() {
+            self.suggested_pieces.write().await.insert(piece_index);
+        }
+    }
+
+    // Accepting more connections would exceed the configured peer limit
+    pub async fn is_private(&self) -> bool {
+        self.metadata.read().await.info.is_private()
+    }
+
+    pub async fn is_at_peer_limit(&self) -> bool {
+        let max_peers = self.config.read().await.max_peers_per_torrent;
+        self.peer_connections.read().await.len() >= max_peers
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self
+            .session_tasks
+            .lock()
+            .expect("Session task list poisoned")
+            .is_empty()
+    }
+
+    fn track_session_task(&self, task: JoinHandle<()>) {
+        self.session_tasks
+            .lock()
+            .expect("Session task list poisoned")
+            .push(task.abort_handle());
+    }
+
+    // Cancels every task of the current session and drops all peer connections
+    async fn end_session(&self) {
+        let session_tasks = std::mem::take(
+            &mut *self
+                .session_tasks
+                .lock()
+                .expect("Session task list poisoned"),
+        );
+        let was_active = !session_tasks.is_empty();
+        for task in session_tasks {
+            task.abort();
+        }
+        *self
+            .session_started
+            .lock()
+            .expect("Session start lock poisoned") = None;
+
+        self.download_rate.store(0, Ordering::SeqCst);
+        self.upload_rate.store(0, Ordering::SeqCst);
+        self.peer_clients.write().await.clear();
+        self.fast_peers.write().await.clear();
+        self.unchoked_peers.write().await.clear();
+        self.interested_peers.write().await.clear();
+        self.choking_peers.write().await.clear();
+        self.peer_readers
+            .lock()
+            .expect("Peer reader list poisoned")
+            .clear();
+        self.pending_requests
+            .lock()
+            .expect("Pending request list poisoned")
+            .clear();
+        self.suggested_pieces.write().await.clear();
+        self.active_web_seeds.store(0, Ordering::SeqCst);
+
+        let disconnected: Vec<SocketAddr> = self
+            .peer_connections
+            .write()
+            .await
+            .drain()
+            .map(|(address, _)| address)
+            .collect();
+        for address in disconnected {
+            self.events.emit(TorrentEvent::PeerDisconnected {
+                info_hash: self.info_hash_hex(),
+                peer: address.to_string(),
+            });
+        }
+
+        if was_active {
+            self.flush_storage().await;
+            self.spawn_event_announce(AnnounceEvent::Stopped).await;
+        }
+    }
+
+    // Totals of the session so far, and what is still missing of the torrent
+    pub async fn transfer_stats(&self) -> TransferStats {
+        let layout = FileLayout::from_metadata(&*self.metadata.read().await);
+        let completed_size =
+            layout.completed_bytes(0, layout.total_length, &self.pieces_status.read().await);
+        TransferStats {
+            uploaded: self.current_uploaded.load(Ordering::SeqCst),
+            downloaded: self.current_downloaded.load(Ordering::SeqCst),
+            left: layout.total_length.saturating_sub(completed_size),
+        }
+    }
+
+    // The tracker may be slow or unreachable, so the announce runs detached from the caller
+    async fn spawn_event_announce(&self, event: AnnounceEvent) {
+        let transfer = self.transfer_stats().await;
+        let metadata = self.metadata.read().await.clone();
+        let configuration = self.config.read().await.clone();
+        let info_hash = self.info_hash_hex();
+        let events = self.events.clone();
+        tokio::spawn(async move {
+            if let Err(e) = announce_event(&metadata, event, &transfer, &configuration).await {
+                events.emit(TorrentEvent::Error {
+                    info_hash: Some(info_hash),
+                    message: format!("Failed to announce to tracker: {}", e),
+                });
+            }
+        });
+    }
+
+    // Announces `started` when the session begins, then re-announces periodically. Newly learned
+    // peers are merged into the peer list and connected to while there is room.
+    fn spawn_announcer(&self) {
+        let torrent = self.clone();
+        let config = Arc::clone(&self.config);
+        let metadata = Arc::clone(&self.metadata);
+        let peers = Arc::clone(&self.peers);
+        let events = self.events.clone();
+        let info_hash = self.info_hash_hex();
+
+        self.track_session_task(tokio::spawn(async move {
+            let mut event = Some(AnnounceEvent::Started);
+            loop {
+                // Read on every round so interval changes apply without restarting the torrent
+                let configuration = config.read().await.clone();
+                if event.is_none() {
+                    tokio::time::sleep(Duration::from_secs(configuration.announce_interval_secs))
+                        .await;
+                }
+
+                let transfer = torrent.transfer_stats().await;
+                let mut announced = metadata.read().await.clone();
+                let result =
+                    get_peers(&mut announced, event.take(), &transfer, &configuration).await;
+                // Keep the tracker ids for the next round
+                metadata.write().await.tracker_ids = announced.tracker_ids.clone();
+                match result {
+                    Ok(announced_peers) => {
+                        events.emit(TorrentEvent::TrackerResponse {
+                            info_hash: info_hash.clone(),
+                            tracker: announced.announce.clone(),
+                            peers: announced_peers.len(),
+                        });
+                        let mut new_peers = Vec::new();
+                        {
+                            let mut peers = peers.write().await;
+                            for peer in announced_peers {
+                                if !peers.contains(&peer) {
+                                    peers.push(peer.clone());
+                                    new_peers.push(peer);
+                                }
+                            }
+                        }
+                        for peer in new_peers {
+                            if torrent.is_at_peer_limit().await {
+                                break;
+                            }
+                            torrent.connect_peer(peer).await;
+                        }
+                    }
+                    Err(message) => events.emit(TorrentEvent::Error {
+                        info_hash: Some(info_hash.clone()),
+                        message,
+                    }),
+                }
+            }
+        }));
+    }
 
-        while let Ok(piece_index) = self.select_rarest_piece().await {
+    // Samples the transfer counters once per second for as long as the session runs
+    fn spawn_rate_sampler(&self) {
+        let info_hash = self.info_hash_hex();
+        let current_downloaded = Arc::clone(&self.current_downloaded);
+        let current_uploaded = Arc::clone(&self.current_uploaded);
+        let download_rate = Arc::clone(&self.download_rate);
+        let upload_rate = Arc::clone(&self.upload_rate);
+        let events = self.events.clone();
+
+        self.track_session_task(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(1));
+            let mut last_downloaded = current_downloaded.load(Ordering::SeqCst);
+            let mut last_uploaded = current_uploaded.load(Ordering::SeqCst);
+
+            loop {
+                ticker.tick().await;
+
+                let downloaded = current_downloaded.load(Ordering::SeqCst);
+                let uploaded = current_uploaded.load(Ordering::SeqCst);
+                let sampled_download_rate = downloaded.saturating_sub(last_downloaded);
+                let sampled_upload_rate = uploaded.saturating_sub(last_uploaded);
+                last_downloaded = downloaded;
+                last_uploaded = uploaded;
+
+                download_rate.store(sampled_download_rate, Ordering::SeqCst);
+                upload_rate.store(sampled_upload_rate, Ordering::SeqCst);
+                events.emit(TorrentEvent::RateSample {
+                    info_hash: info_hash.clone(),
+                    download_rate: sampled_download_rate,
+                    upload_rate: sampled_upload_rate,
+                    downloaded,
+                    uploaded,
+                });
+            }
+        }));
+    }
+
+    fn emit_error(&self, message: String) {
+        println!("{}", message);
+        self.events.emit(TorrentEvent::Error {
+            info_hash: Some(self.info_hash_hex()),
+            message,
+        });
+    }
+
+    pub async fn download_and_seed(&self) -> Result<()> {
+        self.set_status(TorrentStatus::Downloading).await;
+        let mut web_seeds = self.web_seeds().await;
+        self.active_web_seeds
+            .store(web_seeds.len(), Ordering::SeqCst);
+
+        while !self.is_complete().await {
+            // Peers tell us what they have once connected, until then there may be nothing to ask
+            let Some(piece_index) = self.select_rarest_piece().await else {
+                tokio::time::sleep(PIECE_RETRY_INTERVAL).await;
+                continue;
+            };
             let mut bad_peers = Vec::new();
-            for peer in &*self.peers {
+            let peers = self.peers.read().await.clone();
+            for peer in &peers {
                 let piece_data = match self.download_piece_from_peer(peer, piece_index).await {
                     Ok(data) => data,
                     Err(e) => {
@@ -128,31 +896,7 @@ impl Torrent {
                     continue;
                 }
 
-                let metadata = &*self.metadata.read().await;
-                // Save the piece to disk
-                let path = &self.path;
-                match save_piece_to_disk(&piece_data, path, metadata).await {
-                    Ok(_) => (),
-                    Err(e) => {
-                        println!("Error while saving piece to disk: {}", e);
-                        continue;
-                    }
-                }
-
-                // Mark piece as downloaded
-                self.pieces_status
-                    .write()
-                    .await
-                    .set(piece_index as usize, true);
-
-                // Update downloaded size
-                self.current_downloaded
-                    .fetch_add(piece_data.len() as u64, Ordering::SeqCst);
-
-                // Check if the torrent has completed downloading
-                if self.current_downloaded.load(Ordering::SeqCst) == self.total_size {
-                    println!("Torrent completed!");
-                    *self.status.write().await = TorrentStatus::Completed;
+                if self.store_piece(piece_index, &piece_data).await {
                     break;
                 }
             }
@@ -160,72 +904,382 @@ impl Torrent {
             for bad_peer in bad_peers {
                 self.remove_peer(&bad_peer).await;
             }
+
+            if !self.pieces_status.read().await[piece_index as usize] {
+                self.download_piece_from_web_seeds(&mut web_seeds, piece_index)
+                    .await;
+            }
+            // Everyone who has it refused or failed, give them time before asking again
+            if !self.pieces_status.read().await[piece_index as usize] {
+                tokio::time::sleep(PIECE_RETRY_INTERVAL).await;
+            }
         }
 
-        // Start seeding after torrent download is completed
-        if *self.status.read().await == TorrentStatus::Completed {
+        // Connected peers are served by their read loops for as long as the session runs
+        if self.is_complete().await {
             println!("Start seeding torrent");
-            // TODO: Implement seeding logic
+            self.set_status(TorrentStatus::Seeding).await;
         }
 
         Ok(())
     }
 
+    // Saves a verified piece and marks it as downloaded, returning whether that worked
+    pub async fn store_piece(&self, piece_index: u32, piece_data: &[u8]) -> bool {
+        if let Err(e) = self.write_block(piece_index, 0, piece_data.to_vec()).await {
+            self.emit_error(format!("Error while saving piece to disk: {}", e));
+            return false;
+        }
+
+        // Mark piece as downloaded, the last one completes the torrent
+        let completed = {
+            let mut pieces_status = self.pieces_status.write().await;
+            let was_stored = pieces_status[piece_index as usize];
+            pieces_status.set(piece_index as usize, true);
+            !was_stored && pieces_status.all()
+        };
+        self.notify_piece_completed(piece_index);
+
+        // Update downloaded size
+        self.current_downloaded
+            .fetch_add(piece_data.len() as u64, Ordering::SeqCst);
+
+        if completed {
+            println!("Torrent completed!");
+            self.flush_storage().await;
+            self.move_completed_files().await;
+            self.set_status(TorrentStatus::Completed).await;
+            self.spawn_event_announce(AnnounceEvent::Completed).await;
+        }
+        true
+    }
+
+    async fn web_seeds(&self) -> Vec<WebSeed> {
+        if !self.config.read().await.web_seeds_enabled {
+            return Vec::new();
+        }
+        self.metadata
+            .read()
+            .await
+            .url_list
+            .iter()
+            .filter_map(|url| match WebSeed::new(url) {
+                Ok(web_seed) => Some(web_seed),
+                Err(e) => {
+                    println!("Skipping web seed: {}", e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    // Tries the web seeds in turn. One that fails or sends bad data is dropped for the rest of
+    // the session, so a dead mirror doesn't keep pieces looking available.
+    async fn download_piece_from_web_seeds(&self, web_seeds: &mut Vec<WebSeed>, piece_index: u32) {
+        let metadata = self.metadata.read().await.clone();
+        while let Some(web_seed) = web_seeds.first() {
+            let fetched = web_seed.fetch_piece(&metadata, piece_index).await;
+            if let Ok(piece_data) = &fetched {
+                self.rate_limits
+                    .download
+                    .acquire(piece_data.len() as u64)
+                    .await;
+            }
+            match fetched {
+                Ok(piece_data) if self.validate_piece(&piece_data, piece_index).await => {
+                    // A failed save is our disk's fault, not the web seed's
+                    self.store_piece(piece_index, &piece_data).await;
+                    return;
+                }
+                Ok(_) => self.emit_error(format!(
+                    "Web seed {} sent bad data for piece {}",
+                    web_seed.url(),
+                    piece_index
+                )),
+                Err(e) => self.emit_error(format!("Web seed {} failed: {}", web_seed.url(), e)),
+            }
+            web_seeds.remove(0);
+            self.active_web_seeds.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
     pub async fn validate_piece(&self, piece: &[u8], piece_index: u32) -> bool {
-        let piece_hash = Sha1::digest(piece);
-        let expected_hash = self.piece_hashes[piece_index as usize];
-        piece_hash.as_slice() == expected_hash
+        self.piece_hashes.verify(piece_index as usize, piece)
     }
 
-    async fn select_rarest_piece(&self) -> Result<u32> {
+    // The wanted piece the fewest peers have, None while nobody has any we miss
+    async fn select_rarest_piece(&self) -> Option<u32> {
         let piece_frequency = self.piece_frequency.read().await;
         let pieces_status = self.pieces_status.read().await;
-        let status = Arc::clone(&self.status);
+        let suggested_pieces = self.suggested_pieces.read().await;
+        // Web seeds have every piece
+        let web_seeds = self.active_web_seeds.load(Ordering::SeqCst) as u64;
+        let availability = |k: &u32| {
+            piece_frequency
+                .get(k)
+                .map_or(0, |v| v.load(Ordering::SeqCst))
+                + web_seeds
+        };
+        let wanted = |k: &u32| !pieces_status[*k as usize] && availability(k) > 0;
 
-        let rarest_piece_index = (*piece_frequency)
-            .iter()
-            .filter(|&(k, _v)| !pieces_status[*k as usize])
-            .min_by_key(|&(_k, v)| v.load(Ordering::SeqCst))
-            .map(|(k, _v)| *k);
-
-        match rarest_piece_index {
-            Some(index) => Ok(index),
-            None => match pieces_status.all() {
-                true => {
-                    *status.write().await = TorrentStatus::Completed;
-                    Err(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        "All pieces have been retrieved. Torrent is complete.",
-                    ))
-                }
+        // Pieces a peer suggested are likely in its cache, so they go first
+        let suggested_piece_index = suggested_pieces.iter().copied().find(wanted);
+        suggested_piece_index.or_else(|| {
+            (0..pieces_status.len() as u32)
+                .filter(wanted)
+                .min_by_key(availability)
+        })
+    }
+
+    pub async fn pause(&self) {
+        self.end_session().await;
+        self.set_status(TorrentStatus::Paused).await;
+    }
+
+    pub async fn stop(&self) -> Result<()> {
+        self.end_session().await;
+        self.set_status(TorrentStatus::Stopped).await;
+
+        Ok(())
+    }
+
+    // Waits for the queue to start it, unless it is running already
+    pub async fn enqueue(&self) {
+        if !self.is_active() {
+            self.set_status(TorrentStatus::Queued).await;
+        }
+    }
+
+    // Gives up the session until the queue has a slot again
+    pub async fn queue(&self) {
+        self.end_session().await;
+        self.set_status(TorrentStatus::Queued).await;
+    }
+
+    pub fn is_auto_managed(&self) -> bool {
+        self.auto_managed.load(Ordering::SeqCst)
+    }
+
+    pub fn set_auto_managed(&self, auto_managed: bool) {
+        self.auto_managed.store(auto_managed, Ordering::SeqCst);
+    }
+
+    pub fn queue_position(&self) -> usize {
+        self.queue_position.load(Ordering::SeqCst)
+    }
+
+    pub fn set_queue_position(&self, position: usize) {
+        self.queue_position.store(position, Ordering::SeqCst);
+    }
+
+    pub async fn is_complete(&self) -> bool {
+        self.pieces_status.read().await.all()
+    }
+
+    // Whether the session had time to get going and still moves less than the slow rate
+    pub fn is_slow(&self, seeding: bool, configuration: &Config) -> bool {
+        let grace = Duration::from_secs(configuration.slow_torrent_grace_secs);
+        let settled = self
+            .session_started
+            .lock()
+            .expect("Session start lock poisoned")
+            .is_some_and(|started| started.elapsed() >= grace);
+        settled
+            && match seeding {
+                true => self.upload_rate.load(Ordering::SeqCst) < configuration.slow_upload_rate,
                 false => {
-                    *status.write().await = TorrentStatus::Paused;
-                    Err(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        "No rare pieces available. Torrent is paused.",
-                    ))
+                    self.download_rate.load(Ordering::SeqCst) < configuration.slow_download_rate
                 }
-            },
+            }
+    }
+
+    pub async fn check_status(&self) -> TorrentStatus {
+        let status = self.status.read().await;
+        *status
+    }
+
+    // Deletes the downloaded files, blocks still waiting in the cache are dropped
+    pub async fn delete_data(&self) -> io::Result<()> {
+        self.disk.discard();
+        self.disk.run(|storage| storage.delete()).await
+    }
+
+    // Moves the files into `directory`, keeping their name. A running session is ended so nothing
+    // is read or written during the move, and started again afterwards wherever the files are.
+    pub async fn move_storage(&self, directory: &Path, conflict: MoveConflict) -> Result<()> {
+        // Checked and set under one lock, so only one of two concurrent moves gets through
+        let status = {
+            let mut current_status = self.status.write().await;
+            if *current_status == TorrentStatus::Moving {
+                return Err(io::Error::other(
+                    "The torrent's files are already being moved",
+                ));
+            }
+            std::mem::replace(&mut *current_status, TorrentStatus::Moving)
+        };
+        self.events.emit(TorrentEvent::StatusChanged {
+            info_hash: self.info_hash_hex(),
+            status: TorrentStatus::Moving,
+        });
+        let was_active = self.is_active();
+        self.end_session().await;
+
+        let moved = self.relocate(directory, conflict).await;
+        // Starting is refused while moving
+        self.set_status(status).await;
+        let restarted = match was_active {
+            true => self.start().await,
+            false => Ok(()),
+        };
+        match (moved, restarted) {
+            (moved, Ok(())) => moved,
+            (Ok(()), Err(e)) => Err(io::Error::new(
+                e.kind(),
+                format!("The files were moved, but restarting failed: {}", e),
+            )),
+            (Err(moved), Err(restarted)) => Err(io::Error::new(
+                moved.kind(),
+                format!("{}, and restarting failed: {}", moved, restarted),
+            )),
+        }
+    }
+
+    async fn relocate(&self, directory: &Path, conflict: MoveConflict) -> Result<()> {
+        let mut target = self.metadata.read().await.clone();
+        target.file_path = match target.file_path.file_name() {
+            Some(name) => directory.join(name),
+            None => return Err(io::Error::other("The torrent has no save path")),
+        };
+        self.move_files(target, conflict).await?;
+
+        // Files kept from the destination are whatever they are, the pieces are checked again
+        if conflict == MoveConflict::KeepExisting {
+            let verified = verify_pieces(self.disk.storage(), &self.piece_hashes, |_| ()).await?;
+            *self.pieces_status.write().await = verified;
         }
+        Ok(())
     }
 
-    pub async fn pause(&mut self) {
-        self.status = Arc::new(RwLock::new(TorrentStatus::Paused));
-        let mut peer_connections = self.peer_connections.write().await;
-        peer_connections.clear();
+    // A finished download loses its `.part` suffix and goes to its completed directory. Peers
+    // keep being served, reads wait for the move.
+    pub async fn move_completed_files(&self) {
+        let mut target = self.metadata.read().await.clone();
+        if !target.part_suffix && target.completed_path.is_none() {
+            return;
+        }
+        if let Some(completed_path) = target.completed_path.take() {
+            target.file_path = completed_path;
+        }
+        target.part_suffix = false;
+        if let Err(e) = self.move_files(target, MoveConflict::Fail).await {
+            self.emit_error(format!("Failed to move the completed download: {}", e));
+        }
     }
 
-    pub async fn stop(&mut self) -> Result<()> {
-        self.status = Arc::new(RwLock::new(TorrentStatus::Stopped));
-        let mut peer_connections = self.peer_connections.write().await;
-        peer_connections.clear();
+    // Moves the files to where `target` puts them and takes over its save path
+    async fn move_files(&self, target: TorrentMetadata, conflict: MoveConflict) -> Result<()> {
+        // Cached blocks go to the old location first, cached pieces may not match what is at
+        // the new one
+        self.disk.flush().await?;
+        self.disk.discard();
+
+        let events = self.events.clone();
+        let info_hash = self.info_hash_hex();
+        let layout = FileLayout::from_metadata(&target);
+        self.disk
+            .run(move |storage| {
+                storage.move_storage(&layout, conflict, &mut |moved, total| {
+                    events.emit(TorrentEvent::MoveProgress {
+                        info_hash: info_hash.clone(),
+                        moved,
+                        total,
+                    })
+                })
+            })
+            .await?;
 
+        let mut metadata = self.metadata.write().await;
+        metadata.file_path = target.file_path;
+        metadata.completed_path = target.completed_path;
+        metadata.part_suffix = target.part_suffix;
+        self.events.emit(TorrentEvent::StorageMoved {
+            info_hash: self.info_hash_hex(),
+            path: metadata.file_path.to_string_lossy().to_string(),
+        });
         Ok(())
     }
 
-    async fn check_status(&self) -> TorrentStatus {
-        let status = self.status.read().await;
-        *status
+    // Downloads and uploads both go through the disk cache, whatever the storage backend
+    pub async fn write_block(&self, piece_index: u32, begin: u32, data: Vec<u8>) -> io::Result<()> {
+        self.disk.write_block(piece_index, begin, data).await
+    }
+
+    pub async fn read_block(
+        &self,
+        piece_index: u32,
+        begin: u32,
+        length: u32,
+    ) -> io::Result<Vec<u8>> {
+        self.disk.read_block(piece_index, begin, length).await
+    }
+
+    async fn flush_storage(&self) {
+        if let Err(e) = self.disk.flush().await {
+            self.emit_error(format!("Failed to flush the torrent's data: {}", e));
+        }
+    }
+
+    // Peers uploading from us tend to go through pieces in order
+    async fn read_ahead_after(&self, piece_index: u32) {
+        let following: Vec<u32> = {
+            let pieces_status = self.pieces_status.read().await;
+            (piece_index + 1..pieces_status.len() as u32)
+                .take(READ_AHEAD_PIECES)
+                .filter(|&index| pieces_status[index as usize])
+                .collect()
+        };
+        self.disk.read_ahead(following);
+    }
+
+    // Sends a peer the block it asked for, from a piece we have
+    pub async fn serve_request(&self, address: &SocketAddr, index: u32, begin: u32, length: u32) {
+        if !self
+            .pieces_status
+            .read()
+            .await
+            .get(index as usize)
+            .is_some_and(|bit| *bit)
+        {
+            self.reject_request(address, index, begin, length).await;
+            return;
+        }
+        let block = match self.read_block(index, begin, length).await {
+            Ok(block) => {
+                self.read_ahead_after(index).await;
+                block
+            }
+            Err(e) => {
+                self.emit_error(format!(
+                    "Failed to read piece {} for {}: {}",
+                    index, address, e
+                ));
+                self.reject_request(address, index, begin, length).await;
+                return;
+            }
+        };
+        let Some(connection) = self.peer_connections.read().await.get(address).cloned() else {
+            return;
+        };
+        self.rate_limits.upload.acquire(length as u64).await;
+        let message = Message::Piece(index as usize, begin as usize, block);
+        let sent = connection.lock().await.write_all(&message.encode()).await;
+        match sent {
+            Ok(()) => {
+                self.current_uploaded
+                    .fetch_add(length as u64, Ordering::SeqCst);
+            }
+            Err(e) => println!("Failed to send piece {} to {}: {}", index, address, e),
+        }
     }
 
     pub async fn download_piece_from_peer(
@@ -233,54 +1287,176 @@ impl Torrent {
         peer: &Peer,
         piece_index: u32,
     ) -> io::Result<Vec<u8>> {
-        // Lock the entire HashMap
-        let read_guard = self.peer_connections.read().await;
-
-        // Get the TcpStream from the HashMap
-        let connection_arc_mutex = read_guard.get(&peer.ip).ok_or_else(|| {
-            std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "No connection to the specified peer",
-            )
-        })?;
-
-        // Lock the connection
-        let mut connection = connection_arc_mutex.lock().await;
-
-        // Create buffer
-        let mut buffer = vec![];
-
-        // Construct and send the request
-        let request_message = Message::Request(piece_index);
-        connection.write_all(&request_message.encode()).await?;
-
-        // Read the response
-        connection.read_to_end(&mut buffer).await?;
-
-        // Process the received message to extract the piece data
-        let response_msg = message::identify_message(buffer[0], &buffer[1..]).unwrap();
-
-        // Check if the received message is of the Piece type
-        match response_msg {
-            Message::Piece(index, begin, data) => {
-                if index == piece_index as usize {
-                    Ok(data)
-                } else {
-                    Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        MessageError::MismatchedIndex.to_string(),
-                    ))
-                }
-            }
-            _ => Err(io::Error::new(
-                io::ErrorKind::Other,
-                MessageError::InvalidResponse.to_string(),
+        let piece_size = FileLayout::from_metadata(&*self.metadata.read().await)
+            .piece_size(piece_index as usize) as u32;
+        let connection = self
+            .peer_connections
+            .read()
+            .await
+            .get(&peer.address)
+            .cloned()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "No connection to the specified peer",
+                )
+            })?;
+        let allowed_fast = self
+            .fast_peers
+            .read()
+            .await
+            .get(&peer.address)
+            .is_some_and(|state| state.peer_allowed_fast.contains(&piece_index));
+        if self.choking_peers.read().await.contains(&peer.address) && !allowed_fast {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "The peer is choking us",
+            ));
+        }
+
+        // The peer's read loop hands over the piece, or the rejection
+        let key = (peer.address, piece_index);
+        let (sender, receiver) = oneshot::channel();
+        self.pending_requests
+            .lock()
+            .expect("Pending request list poisoned")
+            .insert(key, sender);
+        let request = Message::Request(piece_index, 0, piece_size);
+        if let Err(e) = connection.lock().await.write_all(&request.encode()).await {
+            self.pending_requests
+                .lock()
+                .expect("Pending request list poisoned")
+                .remove(&key);
+            return Err(e);
+        }
+
+        match tokio::time::timeout(PIECE_REQUEST_TIMEOUT, receiver).await {
+            Ok(Ok(piece)) => piece,
+            Ok(Err(_)) => Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "The request was dropped",
             )),
+            Err(_) => {
+                self.pending_requests
+                    .lock()
+                    .expect("Pending request list poisoned")
+                    .remove(&key);
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "The peer did not send the piece",
+                ))
+            }
+        }
+    }
+
+    // Hands a piece to the download waiting for it, the data comes back when nobody asked for it
+    pub fn answer_request(
+        &self,
+        address: &SocketAddr,
+        piece_index: u32,
+        data: Vec<u8>,
+    ) -> Option<Vec<u8>> {
+        let waiting = self
+            .pending_requests
+            .lock()
+            .expect("Pending request list poisoned")
+            .remove(&(*address, piece_index));
+        match waiting {
+            Some(sender) => {
+                let _ = sender.send(Ok(data));
+                None
+            }
+            None => Some(data),
+        }
+    }
+
+    pub fn fail_request(&self, address: &SocketAddr, piece_index: u32, reason: &str) {
+        let waiting = self
+            .pending_requests
+            .lock()
+            .expect("Pending request list poisoned")
+            .remove(&(*address, piece_index));
+        if let Some(sender) = waiting {
+            let _ = sender.send(Err(io::Error::other(reason.to_string())));
+        }
+    }
+
+    pub fn config(&self) -> SharedConfig {
+        Arc::clone(&self.config)
+    }
+
+    // Merges newly learned peers into the peer list, returning how many were new
+    pub async fn add_peers(&self, new_peers: Vec<Peer>) -> usize {
+        let mut peers = self.peers.write().await;
+        let mut added = 0;
+        for peer in new_peers {
+            if !peers.contains(&peer) {
+                peers.push(peer);
+                added += 1;
+            }
         }
+        added
+    }
+
+    // Reads through here see what the disk cache holds, and the files wherever they are now
+    pub fn disk(&self) -> DiskHandle {
+        self.disk.clone()
+    }
+
+    pub fn piece_waiter(&self) -> PieceWaiter {
+        PieceWaiter::new(
+            Arc::clone(&self.pieces_status),
+            Arc::clone(&self.piece_notify),
+        )
     }
 
-    pub async fn remove_peer(&mut self, bad_peer: &Peer) {
-        let peers = &mut (*Arc::make_mut(&mut self.peers));
-        peers.retain(|peer| *peer != *bad_peer);
+    // Wakes up anyone waiting on piece availability, e.g. the streaming server
+    pub fn notify_piece_completed(&self, piece_index: u32) {
+        self.piece_notify.notify_waiters();
+        self.events.emit(TorrentEvent::PieceCompleted {
+            info_hash: self.info_hash_hex(),
+            piece_index,
+        });
+    }
+
+    pub async fn remove_peer(&self, bad_peer: &Peer) {
+        self.peers.write().await.retain(|peer| *peer != *bad_peer);
+        self.disconnect_peer(&bad_peer.address).await;
+    }
+
+    // Drops the connection and everything the session knew about the peer. Downloads waiting on
+    // it fail right away and its upload slot goes to someone else.
+    async fn disconnect_peer(&self, address: &SocketAddr) {
+        let reader = self
+            .peer_readers
+            .lock()
+            .expect("Peer reader list poisoned")
+            .remove(address);
+        if let Some(reader) = reader {
+            reader.abort();
+        }
+        self.pending_requests
+            .lock()
+            .expect("Pending request list poisoned")
+            .retain(|(peer, _), _| peer != address);
+        let was_connected = self
+            .peer_connections
+            .write()
+            .await
+            .remove(address)
+            .is_some();
+        self.peer_clients.write().await.remove(address);
+        self.fast_peers.write().await.remove(address);
+        self.unchoked_peers.write().await.remove(address);
+        self.interested_peers.write().await.remove(address);
+        self.choking_peers.write().await.remove(address);
+
+        if was_connected {
+            self.events.emit(TorrentEvent::PeerDisconnected {
+                info_hash: self.info_hash_hex(),
+                peer: address.to_string(),
+            });
+            self.update_choking().await;
+        }
     }
 }
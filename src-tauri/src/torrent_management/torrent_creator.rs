@@ -0,0 +1,181 @@
+use serde_bencode::value::Value;
+use sha1::{Digest, Sha1};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+use tokio::io::AsyncReadExt;
+
+const MIN_PIECE_LENGTH: u64 = 16 * 1024;
+const MAX_PIECE_LENGTH: u64 = 16 * 1024 * 1024;
+// Aim for roughly this many pieces when picking a piece length automatically
+const TARGET_PIECE_COUNT: u64 = 1500;
+
+// Builds a bencoded .torrent file for a file or a directory. `progress` is called with the
+// number of bytes hashed so far and the total.
+pub async fn create_torrent<F>(
+    path: &Path,
+    announce: Option<String>,
+    piece_length: Option<u64>,
+    mut progress: F,
+) -> Result<Vec<u8>, String>
+where
+    F: FnMut(u64, u64),
+{
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("Invalid path: {}", path.display()))?
+        .to_string();
+
+    let metadata = tokio::fs::metadata(path)
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+    // Relative path and length of every file, in the order they are laid out in the torrent
+    let files: Vec<(Vec<String>, u64)> = if metadata.is_dir() {
+        let mut files = Vec::new();
+        collect_files(path, &mut Vec::new(), &mut files)?;
+        files.sort();
+        files
+    } else {
+        vec![(Vec::new(), metadata.len())]
+    };
+
+    let total_length: u64 = files.iter().map(|(_, length)| length).sum();
+    if total_length == 0 {
+        return Err("Cannot create a torrent without any data".to_string());
+    }
+
+    let piece_length = match piece_length {
+        Some(length) if length.is_power_of_two() && length >= MIN_PIECE_LENGTH => length,
+        Some(length) => {
+            return Err(format!(
+                "Piece length must be a power of two of at least {} bytes, got {}",
+                MIN_PIECE_LENGTH, length
+            ))
+        }
+        None => auto_piece_length(total_length),
+    };
+
+    let mut pieces = Vec::new();
+    let mut piece = Vec::with_capacity(piece_length as usize);
+    let mut hashed = 0;
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    for (relative_path, _) in &files {
+        let file_path = relative_path
+            .iter()
+            .fold(path.to_path_buf(), |file_path, part| file_path.join(part));
+        let mut file = tokio::fs::File::open(&file_path)
+            .await
+            .map_err(|e| format!("Failed to open {}: {}", file_path.display(), e))?;
+
+        loop {
+            let read = file
+                .read(&mut buffer)
+                .await
+                .map_err(|e| format!("Failed to read {}: {}", file_path.display(), e))?;
+            if read == 0 {
+                break;
+            }
+
+            // Pieces run across file boundaries
+            let mut data = &buffer[..read];
+            while !data.is_empty() {
+                let take = data.len().min(piece_length as usize - piece.len());
+                piece.extend_from_slice(&data[..take]);
+                data = &data[take..];
+                if piece.len() == piece_length as usize {
+                    pieces.extend_from_slice(&Sha1::digest(&piece));
+                    piece.clear();
+                }
+            }
+
+            hashed += read as u64;
+            progress(hashed, total_length);
+        }
+    }
+
+    if !piece.is_empty() {
+        pieces.extend_from_slice(&Sha1::digest(&piece));
+    }
+
+    let mut info = HashMap::new();
+    info.insert(b"name".to_vec(), Value::Bytes(name.into_bytes()));
+    info.insert(b"piece length".to_vec(), Value::Int(piece_length as i64));
+    info.insert(b"pieces".to_vec(), Value::Bytes(pieces));
+    if metadata.is_dir() {
+        let entries = files
+            .into_iter()
+            .map(|(relative_path, length)| {
+                let mut entry = HashMap::new();
+                entry.insert(b"length".to_vec(), Value::Int(length as i64));
+                entry.insert(
+                    b"path".to_vec(),
+                    Value::List(
+                        relative_path
+                            .into_iter()
+                            .map(|part| Value::Bytes(part.into_bytes()))
+                            .collect(),
+                    ),
+                );
+                Value::Dict(entry)
+            })
+            .collect();
+        info.insert(b"files".to_vec(), Value::List(entries));
+    } else {
+        info.insert(b"length".to_vec(), Value::Int(total_length as i64));
+    }
+
+    let mut torrent = HashMap::new();
+    if let Some(announce) = announce {
+        torrent.insert(b"announce".to_vec(), Value::Bytes(announce.into_bytes()));
+    }
+    torrent.insert(
+        b"created by".to_vec(),
+        Value::Bytes(format!("pirate {}", env!("CARGO_PKG_VERSION")).into_bytes()),
+    );
+    torrent.insert(b"info".to_vec(), Value::Dict(info));
+
+    serde_bencode::to_bytes(&Value::Dict(torrent)).map_err(|e| e.to_string())
+}
+
+// Smallest power of two giving at most `TARGET_PIECE_COUNT` pieces, within sane bounds
+fn auto_piece_length(total_length: u64) -> u64 {
+    let mut piece_length = MIN_PIECE_LENGTH;
+    while piece_length < MAX_PIECE_LENGTH && total_length / piece_length > TARGET_PIECE_COUNT {
+        piece_length *= 2;
+    }
+    piece_length
+}
+
+fn collect_files(
+    directory: &Path,
+    prefix: &mut Vec<String>,
+    files: &mut Vec<(Vec<String>, u64)>,
+) -> Result<(), String> {
+    let entries = std::fs::read_dir(directory)
+        .map_err(|e| format!("Failed to read {}: {}", directory.display(), e))?;
+
+    for entry in entries {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let entry_path: PathBuf = entry.path();
+        let name = entry
+            .file_name()
+            .into_string()
+            .map_err(|_| format!("Non Unicode path: {}", entry_path.display()))?;
+        let file_type = entry.file_type().map_err(|e| e.to_string())?;
+
+        prefix.push(name);
+        if file_type.is_dir() {
+            collect_files(&entry_path, prefix, files)?;
+        } else if file_type.is_file() {
+            let length = entry.metadata().map_err(|e| e.to_string())?.len();
+            files.push((prefix.clone(), length));
+        }
+        prefix.pop();
+    }
+
+    Ok(())
+}
@@ -1,40 +1,142 @@
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
-use super::torrent::Torrent;
+use crate::{
+    config::SharedConfig,
+    events::{event_bus::EventBus, torrent_event::TorrentEvent},
+};
+
+use super::{
+    queue::{
+        move_position, plan_queue, QueueAction, QueueEntry, QueueLimits, QueueMove,
+        QUEUE_INTERVAL_MS,
+    },
+    torrent::Torrent,
+    torrent_snapshot::{TorrentDetails, TorrentSummary},
+    torrent_status::TorrentStatus,
+};
+
+// What the queue does with each auto-managed torrent, decided under the manager lock
+pub type QueuePlan = Vec<(String, Arc<RwLock<Torrent>>, QueueAction)>;
 
 pub struct TorrentManager {
     torrents: HashMap<String, Arc<RwLock<Torrent>>>,
+    // Other hashes a torrent is known by, the truncated and full v2 hashes of v2 torrents
+    aliases: HashMap<String, String>,
+    // Torrent hashes by queue position, the first one gets a slot first
+    queue: Vec<String>,
+    events: EventBus,
+    config: SharedConfig,
 }
 
 impl TorrentManager {
-    pub fn new() -> Self {
+    pub fn new(events: EventBus, config: SharedConfig) -> Self {
         Self {
             torrents: HashMap::new(),
+            aliases: HashMap::new(),
+            queue: Vec::new(),
+            events,
+            config,
         }
     }
-    pub fn add_torrent(&mut self, torrent_hash: String, torrent: Arc<RwLock<Torrent>>) {
-        self.torrents.insert(torrent_hash, torrent);
+
+    pub fn events(&self) -> EventBus {
+        self.events.clone()
     }
 
-    pub async fn start_torrent(&self, torrent_hash: &str) -> Result<(), String> {
-        if let Some(torrent) = self.torrents.get(torrent_hash) {
-            let torrent = torrent.clone();
-            let mut torrent_guard = torrent.write().await;
-            match torrent_guard.start().await {
-                Ok(_) => Ok(()),
-                Err(e) => Err(e.to_string()),
-            }
-        } else {
-            Err("Torrent not found".to_string())
+    pub fn config(&self) -> SharedConfig {
+        Arc::clone(&self.config)
+    }
+
+    // New torrents join the end of the queue. One known by the same hash is kept, replacing it
+    // would leave its session running unreachable.
+    pub async fn add_torrent(
+        &mut self,
+        torrent_hash: String,
+        torrent: Arc<RwLock<Torrent>>,
+    ) -> Result<(), String> {
+        if self.get_torrent(&torrent_hash).is_some() {
+            return Err(format!("Torrent {} was already added", torrent_hash));
         }
+        torrent.read().await.set_queue_position(self.queue.len());
+        self.queue.push(torrent_hash.clone());
+        self.torrents.insert(torrent_hash.clone(), torrent);
+        self.events.emit(TorrentEvent::TorrentAdded {
+            info_hash: torrent_hash,
+        });
+        Ok(())
+    }
+
+    pub fn add_alias(&mut self, alias: String, torrent_hash: &str) {
+        if alias != torrent_hash {
+            self.aliases.insert(alias, torrent_hash.to_string());
+        }
+    }
+
+    fn resolve<'a>(&'a self, torrent_hash: &'a str) -> &'a str {
+        self.aliases
+            .get(torrent_hash)
+            .map_or(torrent_hash, |torrent_hash| torrent_hash.as_str())
+    }
+
+    pub fn get_torrent(&self, torrent_hash: &str) -> Option<Arc<RwLock<Torrent>>> {
+        self.torrents.get(self.resolve(torrent_hash)).cloned()
+    }
+
+    pub fn torrents(&self) -> Vec<Arc<RwLock<Torrent>>> {
+        self.torrents.values().cloned().collect()
+    }
+
+    // Info hashes of every swarm we are in, encrypted peers name theirs only indirectly
+    pub fn info_hashes(&self) -> Vec<[u8; 20]> {
+        self.torrents
+            .keys()
+            .chain(self.aliases.keys())
+            .filter_map(|torrent_hash| hex::decode(torrent_hash).ok()?.try_into().ok())
+            .collect()
+    }
+
+    pub async fn list_torrents(&self) -> Vec<TorrentSummary> {
+        let mut summaries = Vec::with_capacity(self.torrents.len());
+        for torrent in self.torrents.values() {
+            summaries.push(torrent.read().await.summary().await);
+        }
+        summaries.sort_by(|a, b| a.name.cmp(&b.name));
+        summaries
+    }
+
+    pub async fn get_torrent_details(&self, torrent_hash: &str) -> Result<TorrentDetails, String> {
+        match self.torrents.get(self.resolve(torrent_hash)) {
+            Some(torrent) => Ok(torrent.read().await.details().await),
+            None => Err("Torrent not found".to_string()),
+        }
+    }
+
+    // Auto-managed torrents are queued and started once they get a slot, others start right away.
+    // Starting allocates files, so the plan is carried out by the caller without the manager.
+    pub async fn start_torrent(&self, torrent_hash: &str) -> Result<QueuePlan, String> {
+        let torrent = self
+            .get_torrent(torrent_hash)
+            .ok_or("Torrent not found".to_string())?;
+        let torrent_guard = torrent.read().await;
+        if !torrent_guard.is_auto_managed() {
+            drop(torrent_guard);
+            return Ok(vec![(
+                self.resolve(torrent_hash).to_string(),
+                torrent,
+                QueueAction::Start,
+            )]);
+        }
+        torrent_guard.enqueue().await;
+        drop(torrent_guard);
+        Ok(self.queue_plan().await)
     }
 
     pub async fn pause_torrent(&self, torrent_hash: &str) -> Result<(), String> {
-        if let Some(torrent) = self.torrents.get(torrent_hash) {
-            let torrent = torrent.clone();
-            let mut torrent_guard = torrent.write().await;
+        if let Some(torrent) = self.torrents.get(self.resolve(torrent_hash)) {
+            let torrent_guard = torrent.read().await;
             torrent_guard.pause().await;
             Ok(())
         } else {
@@ -42,13 +144,188 @@ impl TorrentManager {
         }
     }
 
+    pub async fn resume_torrent(&self, torrent_hash: &str) -> Result<QueuePlan, String> {
+        let torrent = self
+            .get_torrent(torrent_hash)
+            .ok_or("Torrent not found".to_string())?;
+        let status = torrent.read().await.check_status().await;
+        match status {
+            TorrentStatus::Paused | TorrentStatus::Stopped | TorrentStatus::Error => {
+                self.start_torrent(torrent_hash).await
+            }
+            _ => Err("Torrent is not paused or stopped".to_string()),
+        }
+    }
+
     pub async fn stop_torrent(&self, torrent_hash: &str) -> Result<(), String> {
-        if let Some(torrent) = self.torrents.get(torrent_hash) {
-            let torrent = torrent.clone();
-            let mut torrent_guard = torrent.write().await;
+        if let Some(torrent) = self.torrents.get(self.resolve(torrent_hash)) {
+            let torrent_guard = torrent.read().await;
             torrent_guard.stop().await.map_err(|e| e.to_string())
         } else {
             Err("Torrent not found".to_string())
         }
     }
+
+    pub async fn stop_all(&self) {
+        for (torrent_hash, torrent) in &self.torrents {
+            if let Err(e) = torrent.read().await.stop().await {
+                println!("Failed to stop torrent {}: {}", torrent_hash, e);
+            }
+        }
+    }
+
+    // Stops the torrent and forgets about it, optionally deleting everything it wrote to disk
+    pub async fn remove_torrent(
+        &mut self,
+        torrent_hash: &str,
+        delete_data: bool,
+    ) -> Result<(), String> {
+        let torrent_hash = self.resolve(torrent_hash).to_string();
+        let torrent = self
+            .torrents
+            .remove(&torrent_hash)
+            .ok_or("Torrent not found".to_string())?;
+        self.aliases.retain(|_, aliased| *aliased != torrent_hash);
+        self.queue.retain(|queued| *queued != torrent_hash);
+        self.renumber_queue().await;
+        let torrent_guard = torrent.read().await;
+        torrent_guard.stop().await.map_err(|e| e.to_string())?;
+
+        self.events.emit(TorrentEvent::TorrentRemoved {
+            info_hash: torrent_hash.to_string(),
+        });
+
+        if delete_data {
+            torrent_guard
+                .delete_data()
+                .await
+                .map_err(|e| format!("Torrent removed but failed to delete its data: {}", e))?;
+        }
+
+        Ok(())
+    }
+
+    // Hands the torrent back to the queue or takes it away, a queued torrent is then paused
+    pub async fn set_auto_managed(
+        &self,
+        torrent_hash: &str,
+        auto_managed: bool,
+    ) -> Result<QueuePlan, String> {
+        let torrent = self
+            .torrents
+            .get(self.resolve(torrent_hash))
+            .ok_or("Torrent not found".to_string())?;
+        let torrent_guard = torrent.read().await;
+        torrent_guard.set_auto_managed(auto_managed);
+        if !auto_managed && torrent_guard.check_status().await == TorrentStatus::Queued {
+            torrent_guard.pause().await;
+        }
+        drop(torrent_guard);
+        Ok(self.queue_plan().await)
+    }
+
+    pub async fn move_in_queue(
+        &mut self,
+        torrent_hash: &str,
+        movement: QueueMove,
+    ) -> Result<QueuePlan, String> {
+        let torrent_hash = self.resolve(torrent_hash).to_string();
+        let position = self
+            .queue
+            .iter()
+            .position(|queued| *queued == torrent_hash)
+            .ok_or("Torrent not found".to_string())?;
+        let queued = self.queue.remove(position);
+        self.queue.insert(
+            move_position(position, self.queue.len() + 1, movement),
+            queued,
+        );
+        self.renumber_queue().await;
+        // A torrent moved up may take over a slot
+        Ok(self.queue_plan().await)
+    }
+
+    async fn renumber_queue(&self) {
+        for (position, torrent_hash) in self.queue.iter().enumerate() {
+            if let Some(torrent) = self.torrents.get(torrent_hash) {
+                torrent.read().await.set_queue_position(position);
+            }
+        }
+    }
+
+    // Which auto-managed torrents to start and queue so the running ones fit the configured
+    // slots. Torrents that are paused, stopped or not auto-managed are left alone.
+    pub async fn queue_plan(&self) -> QueuePlan {
+        let configuration = self.config.read().await.clone();
+        let mut managed = Vec::new();
+        let mut entries = Vec::new();
+        for torrent_hash in &self.queue {
+            let Some(torrent) = self.torrents.get(torrent_hash) else {
+                continue;
+            };
+            let torrent_guard = torrent.read().await;
+            let active = torrent_guard.is_active();
+            let wants_to_run =
+                active || torrent_guard.check_status().await == TorrentStatus::Queued;
+            if !torrent_guard.is_auto_managed() || !wants_to_run {
+                continue;
+            }
+            let seeding = torrent_guard.is_complete().await;
+            entries.push(QueueEntry {
+                active,
+                seeding,
+                slow: configuration.dont_count_slow_torrents
+                    && torrent_guard.is_slow(seeding, &configuration),
+            });
+            managed.push((torrent_hash.clone(), Arc::clone(torrent)));
+        }
+
+        let actions = plan_queue(&entries, &QueueLimits::from_config(&configuration));
+        managed
+            .into_iter()
+            .zip(actions)
+            .map(|((torrent_hash, torrent), action)| (torrent_hash, torrent, action))
+            .collect()
+    }
+}
+
+// Carries out a queue plan, which doesn't need the manager. Every torrent that fails to start
+// gets an error event, the last failure is returned as well.
+pub async fn apply_queue_plan(plan: QueuePlan, events: &EventBus) -> Result<(), String> {
+    let mut result = Ok(());
+    for (torrent_hash, torrent, action) in plan {
+        let torrent_guard = torrent.read().await;
+        match action {
+            QueueAction::Start => {
+                if let Err(e) = torrent_guard.start().await {
+                    events.emit(TorrentEvent::Error {
+                        info_hash: Some(torrent_hash),
+                        message: e.to_string(),
+                    });
+                    result = Err(e.to_string());
+                }
+            }
+            QueueAction::Queue => torrent_guard.queue().await,
+            QueueAction::Keep => (),
+        }
+    }
+    result
+}
+
+// Keeps handing out slots as torrents finish, stall or speed up
+pub fn start_queue_manager(torrent_manager: Arc<RwLock<TorrentManager>>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(QUEUE_INTERVAL_MS));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            // Starting allocates files, which may take a while, the manager stays usable meanwhile
+            let (plan, events) = {
+                let torrent_manager = torrent_manager.read().await;
+                (torrent_manager.queue_plan().await, torrent_manager.events())
+            };
+            // Failures were reported as events, the next round tries again
+            let _ = apply_queue_plan(plan, &events).await;
+        }
+    });
 }
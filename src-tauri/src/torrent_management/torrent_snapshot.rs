@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+
+use crate::config::AllocationMode;
+
+use super::torrent_status::TorrentStatus;
+
+// Serialisable view of a torrent for list views.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TorrentSummary {
+    pub name: String,
+    pub info_hash: String,
+    pub total_size: u64,
+    pub completed_size: u64,
+    // Between 0.0 and 1.0
+    pub progress: f64,
+    pub downloaded: u64,
+    pub uploaded: u64,
+    pub download_rate: u64,
+    pub upload_rate: u64,
+    // None while nothing is being downloaded
+    pub eta_seconds: Option<u64>,
+    pub status: TorrentStatus,
+    pub ratio: f64,
+    pub connected_peers: usize,
+    pub queue_position: usize,
+    pub auto_managed: bool,
+}
+
+// Everything the detail view needs on top of the summary.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TorrentDetails {
+    #[serde(flatten)]
+    pub summary: TorrentSummary,
+    // SHA-256 info hash of v2 and hybrid torrents, `info_hash` is then the truncated form or v1
+    pub info_hash_v2: Option<String>,
+    pub piece_length: u64,
+    pub allocation_mode: AllocationMode,
+    pub category: Option<String>,
+    pub piece_count: usize,
+    // Hex encoded bitfield of completed pieces, least significant bit first within each byte
+    pub pieces: String,
+    pub peers: Vec<PeerSnapshot>,
+    pub trackers: Vec<String>,
+    pub files: Vec<FileSnapshot>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerSnapshot {
+    pub address: String,
+    pub connected: bool,
+    // e.g. "qBittorrent 4.5.2", None until the handshake identified the client
+    pub client: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileSnapshot {
+    pub index: usize,
+    pub path: String,
+    pub size: u64,
+    pub completed_size: u64,
+    pub progress: f64,
+}
+
+pub fn progress(completed: u64, total: u64) -> f64 {
+    if total == 0 {
+        1.0
+    } else {
+        completed as f64 / total as f64
+    }
+}
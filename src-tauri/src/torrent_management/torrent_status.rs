@@ -1,4 +1,7 @@
-#[derive(Clone, Copy, PartialEq)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum TorrentStatus {
     Initialized,
     Connecting,
@@ -7,4 +10,10 @@ pub enum TorrentStatus {
     Paused,
     Stopped,
     Completed,
+    // Its files are being moved to another directory
+    Moving,
+    // Auto-managed and waiting for the queue to give it a slot
+    Queued,
+    // Couldn't start, like when the disk is too full for its files
+    Error,
 }
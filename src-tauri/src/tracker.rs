@@ -1,13 +1,62 @@
 extern crate url;
 
-use crate::{config, parsing::parser::torrent_metadata::TorrentMetadata};
-use std::borrow::Cow;
+use crate::{
+    config::Config, network::local_address::local_ipv6_address,
+    parsing::parser::torrent_metadata::TorrentMetadata,
+};
+use rand::Rng;
+use std::{borrow::Cow, time::Duration};
 use tokio::net::UdpSocket;
 use url::form_urlencoded;
 
-pub async fn build_tracker_query(metadata: &TorrentMetadata) -> Result<String, String> {
-    let configuration = config::Config::new();
-    let bittorent_port = configuration.bittorent_port;
+// How long to wait for the tracker to acknowledge a `completed` or `stopped` announce
+const EVENT_ANNOUNCE_TIMEOUT: Duration = Duration::from_secs(5);
+// Don't let an unresponsive tracker hold up adding a torrent
+const TRACKER_RESPONSE_TIMEOUT: Duration = Duration::from_secs(15);
+const TRACKER_KEY_LENGTH: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnnounceEvent {
+    Started,
+    Completed,
+    Stopped,
+}
+
+impl AnnounceEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnnounceEvent::Started => "started",
+            AnnounceEvent::Completed => "completed",
+            AnnounceEvent::Stopped => "stopped",
+        }
+    }
+}
+
+// A random `key` for a torrent's announces. Private trackers use it to recognize us across
+// announces, so it must not change while the torrent is loaded.
+pub fn generate_tracker_key() -> String {
+    let mut rng = rand::thread_rng();
+    (0..TRACKER_KEY_LENGTH)
+        .map(|_| format!("{:x}", rng.gen_range(0..16)))
+        .collect()
+}
+
+// What an announce reports about the torrent's transfer
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TransferStats {
+    pub uploaded: u64,
+    pub downloaded: u64,
+    // Bytes of the pieces we don't have yet
+    pub left: u64,
+}
+
+pub async fn build_tracker_query(
+    metadata: &TorrentMetadata,
+    event: Option<AnnounceEvent>,
+    transfer: &TransferStats,
+    configuration: &Config,
+) -> Result<String, String> {
+    let listen_port = configuration.listen_port.to_string();
 
     let formatted_url = if metadata.announce.starts_with("s") {
         let mut url: String = metadata.announce.chars().skip(2).collect();
@@ -17,13 +66,41 @@ pub async fn build_tracker_query(metadata: &TorrentMetadata) -> Result<String, S
         metadata.announce.clone()
     };
 
-    let encoded_params = form_urlencoded::Serializer::new(String::new())
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+    serializer
         .append_pair("peer_id", &metadata.peer_id)
-        .append_pair("port", &bittorent_port)
-        .append_pair("uploaded", "0")
-        .append_pair("downloaded", "0")
-        .append_pair("compact", "0")
-        .append_pair("left", &metadata.info.length.to_string())
+        .append_pair("port", &listen_port)
+        .append_pair("uploaded", &transfer.uploaded.to_string())
+        .append_pair("downloaded", &transfer.downloaded.to_string())
+        .append_pair(
+            "compact",
+            if configuration.compact_peers {
+                "1"
+            } else {
+                "0"
+            },
+        )
+        .append_pair("left", &transfer.left.to_string());
+
+    if let Some(event) = event {
+        serializer.append_pair("event", event.as_str());
+    }
+
+    if !metadata.tracker_key.is_empty() {
+        serializer.append_pair("key", &metadata.tracker_key);
+    }
+    if let Some(tracker_id) = metadata.tracker_ids.get(&metadata.info_hash) {
+        serializer.append_pair("trackerid", tracker_id);
+    }
+
+    // Lets the tracker hand our IPv6 address to peers even when we announce over IPv4 (BEP 7)
+    if configuration.ipv6_enabled {
+        if let Some(address) = local_ipv6_address() {
+            serializer.append_pair("ipv6", &address.to_string());
+        }
+    }
+
+    let encoded_params = serializer
         .encoding_override(Some(&|input| {
             if input != "!" {
                 Cow::Borrowed(input.as_bytes())
@@ -39,10 +116,13 @@ pub async fn build_tracker_query(metadata: &TorrentMetadata) -> Result<String, S
     Ok(query)
 }
 
-pub async fn execute_tracker_query(query: String) -> Result<Vec<u8>, String> {
-    let configuration = config::Config::new();
-    let tcp_port = configuration.tcp_port;
-    let mut buffer = vec![0; 1024];
+pub async fn execute_tracker_query(
+    query: String,
+    configuration: &Config,
+) -> Result<Vec<u8>, String> {
+    let tcp_port = configuration.default_tracker_port;
+    // Dictionary peer lists are much larger than compact ones
+    let mut buffer = vec![0; 65536];
 
     let mut parsed_url =
         url::Url::parse(&query).map_err(|_| "Could not parse the URL".to_string())?;
@@ -62,7 +142,7 @@ pub async fn execute_tracker_query(query: String) -> Result<Vec<u8>, String> {
     let host = parsed_url
         .host_str()
         .ok_or("Failed to parse host".to_string())?;
-    let port = parsed_url.port().unwrap();
+    let port = parsed_url.port_or_known_default().unwrap_or(tcp_port);
 
     println!("Connecting to address: {}:{},", host, port);
 
@@ -87,14 +167,45 @@ pub async fn execute_tracker_query(query: String) -> Result<Vec<u8>, String> {
 
     println!("Sent params: {}", _bytes_sent);
 
-    match socket.recv(&mut buffer).await {
-        Ok(_) => (),
-        Err(e) => {
+    match tokio::time::timeout(TRACKER_RESPONSE_TIMEOUT, socket.recv(&mut buffer)).await {
+        Ok(Ok(received)) => buffer.truncate(received),
+        Ok(Err(e)) => {
             println!("Failed to receive the buffer: {}", e);
             return Err(e.to_string());
         }
+        Err(_) => return Err("Tracker did not respond".to_string()),
     };
 
     println!("wakka flokka");
     Ok(buffer)
 }
+
+// Announces a lifecycle event whose response we don't need, e.g. leaving the swarm on `stopped`.
+// Hybrid torrents announce it in both of their swarms.
+pub async fn announce_event(
+    metadata: &TorrentMetadata,
+    event: AnnounceEvent,
+    transfer: &TransferStats,
+    configuration: &Config,
+) -> Result<(), String> {
+    let mut result = Ok(());
+    for info_hash in metadata.swarm_info_hashes() {
+        let swarm = TorrentMetadata {
+            info_hash,
+            ..metadata.clone()
+        };
+        let query = build_tracker_query(&swarm, Some(event), transfer, configuration).await?;
+        let response = execute_tracker_query(query, configuration);
+
+        if let Err(e) = match tokio::time::timeout(EVENT_ANNOUNCE_TIMEOUT, response).await {
+            Ok(response) => response.map(|_| ()),
+            Err(_) => Err(format!(
+                "Tracker did not respond to {} announce",
+                event.as_str()
+            )),
+        } {
+            result = Err(e);
+        }
+    }
+    result
+}
@@ -0,0 +1 @@
+pub mod watch_folder;
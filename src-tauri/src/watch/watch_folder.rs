@@ -0,0 +1,232 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    app_state::AppState,
+    commands::{
+        add_torrent::{self, AddTorrentOptions},
+        remove_torrent, start_torrent,
+    },
+    events::torrent_event::TorrentEvent,
+    network::metadata_exchange::torrent_from_magnet,
+    parsing::magnet::parse_magnet_link,
+};
+
+pub const ADDED_DIRECTORY: &str = "added";
+pub const FAILED_DIRECTORY: &str = "failed";
+
+// Scans `directory` every `interval` and adds new .torrent and .magnet files through the same
+// path as the `add_torrent` command. Processed files end up in `added/`, files that could not be
+// added in `failed/` with a `.error` file next to them. Runs until the task is dropped.
+pub async fn watch_folder(
+    state: &AppState,
+    directory: PathBuf,
+    auto_start: bool,
+    interval: Duration,
+) {
+    for subdirectory in [ADDED_DIRECTORY, FAILED_DIRECTORY] {
+        if let Err(e) = tokio::fs::create_dir_all(directory.join(subdirectory)).await {
+            println!(
+                "Failed to prepare watch directory {}: {}",
+                directory.display(),
+                e
+            );
+            return;
+        }
+    }
+    println!("Watching {} for new torrents", directory.display());
+
+    // Size of each candidate at the previous scan, a file is only picked up once its size stopped
+    // changing so we don't read torrents that are still being copied in
+    let mut pending: HashMap<PathBuf, u64> = HashMap::new();
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        ticker.tick().await;
+
+        let candidates = match scan(&directory).await {
+            Ok(candidates) => candidates,
+            Err(e) => {
+                println!(
+                    "Failed to scan watch directory {}: {}",
+                    directory.display(),
+                    e
+                );
+                continue;
+            }
+        };
+
+        for path in settled_files(&mut pending, candidates) {
+            process_file(state, &directory, &path, auto_start).await;
+        }
+    }
+}
+
+// The candidates whose size is the same as at the previous scan. The others are remembered in
+// `pending` with their current size, for the next scan to compare against.
+pub fn settled_files(
+    pending: &mut HashMap<PathBuf, u64>,
+    candidates: Vec<(PathBuf, u64)>,
+) -> Vec<PathBuf> {
+    let mut settled = Vec::new();
+    let mut still_pending = HashMap::new();
+    for (path, size) in candidates {
+        if pending.get(&path) == Some(&size) {
+            settled.push(path);
+        } else {
+            still_pending.insert(path, size);
+        }
+    }
+    *pending = still_pending;
+    settled
+}
+
+async fn scan(directory: &Path) -> std::io::Result<Vec<(PathBuf, u64)>> {
+    let mut candidates = Vec::new();
+    let mut entries = tokio::fs::read_dir(directory).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let metadata = entry.metadata().await?;
+        if metadata.is_file() && metadata.len() > 0 && watched_extension(&path).is_some() {
+            candidates.push((path, metadata.len()));
+        }
+    }
+    Ok(candidates)
+}
+
+fn watched_extension(path: &Path) -> Option<&'static str> {
+    match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+        "torrent" => Some("torrent"),
+        "magnet" => Some("magnet"),
+        _ => None,
+    }
+}
+
+async fn process_file(state: &AppState, directory: &Path, path: &Path, auto_start: bool) {
+    // Moving the file out first means a crash can't make us add it twice
+    let added_path = match move_into(path, &directory.join(ADDED_DIRECTORY)).await {
+        Ok(added_path) => added_path,
+        Err(e) => {
+            println!("Failed to move {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    // Without a download directory the data goes next to where the file was dropped, the file
+    // itself is in `added/` by now
+    let download_directory = state.config.read().await.download_directory.clone();
+    let options = AddTorrentOptions {
+        download_directory: download_directory.or_else(|| Some(directory.to_path_buf())),
+        ..Default::default()
+    };
+    let result = match watched_extension(&added_path) {
+        Some("magnet") => add_magnet_file(state, &added_path, options, auto_start).await,
+        _ => add_and_start(state, &added_path, options, auto_start).await,
+    };
+
+    match result {
+        Ok(torrent_hash) => println!("Added {} from watch directory", torrent_hash),
+        Err(e) => {
+            println!("Failed to add {}: {}", path.display(), e);
+            state.events.emit(TorrentEvent::Error {
+                info_hash: None,
+                message: format!("Failed to add {}: {}", path.display(), e),
+            });
+            if let Err(move_error) = record_failure(directory, &added_path, &e).await {
+                println!(
+                    "Failed to move {} to failed: {}",
+                    added_path.display(),
+                    move_error
+                );
+            }
+        }
+    }
+}
+
+// A .magnet file holds the link as text. The fetched .torrent is written next to it in `added/`.
+async fn add_magnet_file(
+    state: &AppState,
+    path: &Path,
+    options: AddTorrentOptions,
+    auto_start: bool,
+) -> Result<String, String> {
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| format!("Failed to read magnet file: {}", e))?;
+    let link = contents
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .ok_or("Magnet file is empty")?;
+    let magnet = parse_magnet_link(link).map_err(|e| e.to_string())?;
+
+    let configuration = state.config.read().await.clone();
+    let torrent = torrent_from_magnet(
+        &magnet,
+        &state.peer_id,
+        &configuration,
+        state.utp_socket.as_ref(),
+    )
+    .await?;
+
+    let torrent_path = path.with_extension("torrent");
+    tokio::fs::write(&torrent_path, torrent)
+        .await
+        .map_err(|e| format!("Failed to write {}: {}", torrent_path.display(), e))?;
+    let result = add_and_start(state, &torrent_path, options, auto_start).await;
+    // Only the .magnet file is kept for failures
+    if result.is_err() {
+        if let Err(e) = tokio::fs::remove_file(&torrent_path).await {
+            println!("Failed to remove {}: {}", torrent_path.display(), e);
+        }
+    }
+    result
+}
+
+// Adds the torrent file and starts it if asked to. A torrent that fails to start is removed
+// again, so a file in `failed/` never leaves a torrent behind.
+async fn add_and_start(
+    state: &AppState,
+    torrent_file: &Path,
+    options: AddTorrentOptions,
+    auto_start: bool,
+) -> Result<String, String> {
+    let torrent_hash =
+        add_torrent::handle(state, torrent_file.to_string_lossy().to_string(), options).await?;
+    if auto_start {
+        if let Err(e) = start_torrent::handle(state, torrent_hash.clone()).await {
+            if let Err(remove_error) =
+                remove_torrent::handle(state, torrent_hash.clone(), false).await
+            {
+                println!("Failed to remove {}: {}", torrent_hash, remove_error);
+            }
+            return Err(e);
+        }
+    }
+    Ok(torrent_hash)
+}
+
+async fn record_failure(directory: &Path, added_path: &Path, error: &str) -> std::io::Result<()> {
+    let failed_path = move_into(added_path, &directory.join(FAILED_DIRECTORY)).await?;
+    let mut error_path = failed_path.clone().into_os_string();
+    error_path.push(".error");
+    tokio::fs::write(error_path, format!("{}\n", error)).await
+}
+
+// Moves `path` into `directory`, adding a timestamp to the name if it's already taken
+async fn move_into(path: &Path, directory: &Path) -> std::io::Result<PathBuf> {
+    let file_name = path.file_name().unwrap_or_default();
+    let mut destination = directory.join(file_name);
+    if tokio::fs::try_exists(&destination).await? {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        destination = directory.join(format!("{}-{}", timestamp, file_name.to_string_lossy()));
+    }
+    tokio::fs::rename(path, &destination).await?;
+    Ok(destination)
+}
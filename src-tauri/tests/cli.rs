@@ -0,0 +1,175 @@
+use clap::Parser;
+use pirate::{
+    app_state::AppState,
+    cli::{
+        is_cli_invocation, local, output, remote, Cli, Command, EXIT_DAEMON_UNAVAILABLE,
+        EXIT_FAILURE, EXIT_SUCCESS, EXIT_VERIFY_FAILED,
+    },
+    commands::list_torrents,
+    config::Config,
+    rpc::server::start_rpc_server,
+    torrent_management::torrent_status::TorrentStatus,
+};
+use std::{path::Path, sync::Arc};
+
+const TOKEN: &str = "secret";
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+async fn daemon(download_directory: &Path) -> (Arc<AppState>, String) {
+    let configuration = Config {
+        listen_port: 0,
+        streaming_enabled: false,
+        utp_enabled: false,
+        lsd_enabled: false,
+        port_mapping_enabled: false,
+        auto_managed: false,
+        download_directory: Some(download_directory.to_path_buf()),
+        ..Config::default()
+    };
+    let state = Arc::new(AppState::new(configuration, None).await.unwrap());
+    let address = start_rpc_server(Arc::clone(&state), "127.0.0.1:0", TOKEN.to_string())
+        .await
+        .unwrap();
+    (state, address.to_string())
+}
+
+fn torrent_file(directory: &Path) -> std::path::PathBuf {
+    let mut torrent =
+        b"d4:infod6:lengthi16e4:name8:file.bin12:piece lengthi16e6:pieces20:".to_vec();
+    torrent.extend([0; 20]);
+    torrent.extend(b"ee");
+    let path = directory.join("file.torrent");
+    std::fs::write(&path, torrent).unwrap();
+    path
+}
+
+#[test]
+fn subcommands_and_flags_select_the_cli() {
+    assert!(is_cli_invocation(&args(&["pirate", "list"])));
+    assert!(is_cli_invocation(&args(&["pirate", "magnet-to-torrent"])));
+    assert!(is_cli_invocation(&args(&["pirate", "--version"])));
+    // No arguments, or ones the GUI gets from the OS, open the window
+    assert!(!is_cli_invocation(&args(&["pirate"])));
+    assert!(!is_cli_invocation(&args(&["pirate", "movie.torrent"])));
+}
+
+#[test]
+fn arguments_are_parsed_into_commands() {
+    let cli = Cli::try_parse_from(["pirate", "start", "abcd"]).unwrap();
+    assert!(matches!(cli.command, Command::Start { torrent_hash } if torrent_hash == "abcd"));
+
+    let cli =
+        Cli::try_parse_from(["pirate", "--rpc", "host:1", "add", "a.torrent", "--start"]).unwrap();
+    assert_eq!(cli.rpc.as_deref(), Some("host:1"));
+    assert!(matches!(
+        cli.command,
+        Command::Add {
+            start: true,
+            foreground: false,
+            ..
+        }
+    ));
+
+    let cli = Cli::try_parse_from(["pirate", "remove", "abcd", "--delete-data"]).unwrap();
+    assert!(matches!(
+        cli.command,
+        Command::Remove {
+            delete_data: true,
+            ..
+        }
+    ));
+
+    assert!(Cli::try_parse_from(["pirate", "start"]).is_err());
+    assert!(Cli::try_parse_from(["pirate", "explode"]).is_err());
+}
+
+#[test]
+fn output_is_human_readable() {
+    assert_eq!(output::format_bytes(512), "512 B");
+    assert_eq!(output::format_bytes(1536), "1.5 KiB");
+    assert_eq!(output::format_bytes(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    assert_eq!(output::format_eta(Some(3725)), "01:02:05");
+    assert_eq!(output::format_eta(None), "--:--:--");
+    assert_eq!(
+        output::progress_bar(0.5),
+        format!("[{}{}]  50.0%", "#".repeat(15), "-".repeat(15))
+    );
+}
+
+#[tokio::test]
+async fn remote_commands_drive_the_daemon() {
+    let directory = tempfile::tempdir().unwrap();
+    let (state, address) = daemon(directory.path()).await;
+    let torrent_file = torrent_file(directory.path());
+
+    let code = remote::add(&address, TOKEN.to_string(), &torrent_file, false).await;
+    assert_eq!(code, EXIT_SUCCESS);
+    let torrent_hash = list_torrents::handle(&state).await.unwrap()[0]
+        .info_hash
+        .clone();
+
+    // `pirate start` works on a torrent that was only added
+    let code = remote::simple(&address, TOKEN.to_string(), "start_torrent", &torrent_hash).await;
+    assert_eq!(code, EXIT_SUCCESS);
+    let code = remote::simple(&address, TOKEN.to_string(), "pause_torrent", &torrent_hash).await;
+    assert_eq!(code, EXIT_SUCCESS);
+    assert_eq!(
+        list_torrents::handle(&state).await.unwrap()[0].status,
+        TorrentStatus::Paused
+    );
+
+    assert_eq!(
+        remote::info(&address, TOKEN.to_string(), &torrent_hash).await,
+        EXIT_SUCCESS
+    );
+    assert_eq!(
+        remote::list(&address, TOKEN.to_string()).await,
+        EXIT_SUCCESS
+    );
+    let code = remote::remove(&address, TOKEN.to_string(), &torrent_hash, false).await;
+    assert_eq!(code, EXIT_SUCCESS);
+    assert!(list_torrents::handle(&state).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn failures_have_their_own_exit_codes() {
+    let directory = tempfile::tempdir().unwrap();
+    let (_state, address) = daemon(directory.path()).await;
+
+    // The daemon refused the command
+    let code = remote::info(&address, TOKEN.to_string(), &"00".repeat(20)).await;
+    assert_eq!(code, EXIT_FAILURE);
+    // The daemon can't be used at all
+    assert_eq!(
+        remote::list(&address, "wrong".to_string()).await,
+        EXIT_DAEMON_UNAVAILABLE
+    );
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed_address = closed.local_addr().unwrap().to_string();
+    drop(closed);
+    assert_eq!(
+        remote::list(&closed_address, TOKEN.to_string()).await,
+        EXIT_DAEMON_UNAVAILABLE
+    );
+}
+
+#[tokio::test]
+async fn created_torrents_verify_their_data() {
+    let directory = tempfile::tempdir().unwrap();
+    let data = directory.path().join("data.bin");
+    std::fs::write(&data, vec![5; 100_000]).unwrap();
+    let torrent_file = directory.path().join("data.bin.torrent");
+
+    let code = local::create(&data, None, Some(16384), Some(torrent_file.clone())).await;
+    assert_eq!(code, EXIT_SUCCESS);
+    // The data is found next to the torrent file
+    assert_eq!(local::verify(&torrent_file, None).await, EXIT_SUCCESS);
+
+    std::fs::write(&data, vec![6; 100_000]).unwrap();
+    assert_eq!(local::verify(&torrent_file, None).await, EXIT_VERIFY_FAILED);
+    let missing = directory.path().join("missing.torrent");
+    assert_eq!(local::verify(&missing, None).await, EXIT_FAILURE);
+}
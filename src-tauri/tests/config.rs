@@ -0,0 +1,184 @@
+use pirate::{
+    app_state::AppState,
+    commands::{get_config, set_config},
+    config::{Config, EncryptionPolicy},
+};
+use serde_json::json;
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+// Environment variables are shared by the tests, loading waits while one test has them set
+static ENVIRONMENT: Mutex<()> = Mutex::new(());
+
+fn load(path: &Path) -> Result<Config, String> {
+    let _environment = ENVIRONMENT.lock().expect("Environment lock poisoned");
+    Config::load(Some(path))
+}
+
+async fn state(directory: &Path, config_path: PathBuf) -> AppState {
+    let configuration = Config {
+        listen_port: 0,
+        streaming_enabled: false,
+        utp_enabled: false,
+        lsd_enabled: false,
+        port_mapping_enabled: false,
+        download_directory: Some(directory.to_path_buf()),
+        ..Config::default()
+    };
+    let state = AppState::new(configuration, Some(config_path))
+        .await
+        .unwrap();
+    // The engine listens on a port the OS picked, the setting itself has to be valid
+    state.config.write().await.listen_port = 6881;
+    state
+}
+
+#[test]
+fn missing_files_leave_the_defaults() {
+    assert_eq!(Config::default().validate(), Ok(()));
+
+    let directory = tempfile::tempdir().unwrap();
+    let configuration = load(&directory.path().join("config.toml")).unwrap();
+    assert_eq!(configuration.listen_port, 6881);
+    assert_eq!(configuration.encryption, EncryptionPolicy::Enabled);
+    assert_eq!(configuration.download_directory, None);
+}
+
+#[test]
+fn file_settings_replace_the_defaults() {
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("config.toml");
+    std::fs::write(
+        &path,
+        "listen_port = 7000\n\
+        download_directory = \"/data\"\n\
+        encryption = \"required\"\n\
+        [categories]\n\
+        linux = \"/data/linux\"\n",
+    )
+    .unwrap();
+
+    let configuration = load(&path).unwrap();
+    assert_eq!(configuration.listen_port, 7000);
+    assert_eq!(
+        configuration.download_directory,
+        Some(PathBuf::from("/data"))
+    );
+    assert_eq!(configuration.encryption, EncryptionPolicy::Required);
+    assert_eq!(
+        configuration.categories.get("linux"),
+        Some(&PathBuf::from("/data/linux"))
+    );
+    // Everything else keeps its default
+    assert_eq!(configuration.max_peers_per_torrent, 50);
+
+    std::fs::write(&path, "listen_prot = 7000\n").unwrap();
+    assert_eq!(load(&path).unwrap_err(), "Unknown setting: listen_prot");
+    std::fs::write(&path, "listen_port = 0\n").unwrap();
+    assert_eq!(load(&path).unwrap_err(), "listen_port must not be 0");
+    std::fs::write(&path, "listen_port = \"many\"\n").unwrap();
+    assert!(load(&path)
+        .unwrap_err()
+        .starts_with("Invalid configuration"));
+    std::fs::write(&path, "listen_port = \n").unwrap();
+    assert!(load(&path).unwrap_err().starts_with("Invalid config file"));
+}
+
+#[test]
+fn environment_variables_override_the_file() {
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("config.toml");
+    std::fs::write(&path, "default_tracker_port = 8080\npart_suffix = false\n").unwrap();
+
+    let environment = ENVIRONMENT.lock().expect("Environment lock poisoned");
+    std::env::set_var("PIRATE_DEFAULT_TRACKER_PORT", "9090");
+    std::env::set_var("PIRATE_PART_SUFFIX", "true");
+    std::env::set_var("PIRATE_PEER_ID_PREFIX", "-XX0001-");
+    let configuration = Config::load(Some(&path));
+
+    std::env::set_var("PIRATE_PART_SUFFIX", "maybe");
+    let invalid = Config::load(Some(&path));
+    for variable in [
+        "PIRATE_DEFAULT_TRACKER_PORT",
+        "PIRATE_PART_SUFFIX",
+        "PIRATE_PEER_ID_PREFIX",
+    ] {
+        std::env::remove_var(variable);
+    }
+    drop(environment);
+
+    let configuration = configuration.unwrap();
+    assert_eq!(configuration.default_tracker_port, 9090);
+    assert!(configuration.part_suffix);
+    assert_eq!(configuration.peer_id_prefix, "-XX0001-");
+    assert_eq!(
+        invalid.unwrap_err(),
+        "PIRATE_PART_SUFFIX must be true or false"
+    );
+}
+
+#[tokio::test]
+async fn runtime_changes_are_applied_and_saved() {
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("settings").join("config.toml");
+    let state = state(directory.path(), path.clone()).await;
+
+    let changes = json!({ "max_download_rate": 1024, "completed_directory": "/done" });
+    let updated = set_config::handle(&state, changes).await.unwrap();
+    assert_eq!(updated.max_download_rate, 1024);
+    assert_eq!(updated.completed_directory, Some(PathBuf::from("/done")));
+    assert_eq!(get_config::handle(&state).await.unwrap(), updated);
+
+    // Only the changed settings end up in the file
+    let saved: toml::Value = toml::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(saved.as_table().unwrap().len(), 2);
+    let reloaded = load(&path).unwrap();
+    assert_eq!(reloaded.max_download_rate, 1024);
+    assert_eq!(reloaded.completed_directory, Some(PathBuf::from("/done")));
+
+    // Clearing a setting removes it from the file
+    set_config::handle(&state, json!({ "completed_directory": null }))
+        .await
+        .unwrap();
+    let reloaded = load(&path).unwrap();
+    assert_eq!(reloaded.completed_directory, None);
+    assert_eq!(reloaded.max_download_rate, 1024);
+}
+
+#[tokio::test]
+async fn invalid_changes_are_rejected() {
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("config.toml");
+    let state = state(directory.path(), path.clone()).await;
+    let before = get_config::handle(&state).await.unwrap();
+
+    let rejected = [
+        (
+            json!({ "disk_io_threads": 0 }),
+            "disk_io_threads must be at least 1",
+        ),
+        (
+            json!({ "rpc_address": "localhost" }),
+            "rpc_address is not a valid socket address: localhost",
+        ),
+        (
+            json!({ "upload_limit": 10 }),
+            "Unknown setting: upload_limit",
+        ),
+        (
+            json!(["max_upload_rate"]),
+            "Configuration must be a table of settings",
+        ),
+    ];
+    for (changes, error) in rejected {
+        assert_eq!(
+            set_config::handle(&state, changes).await.unwrap_err(),
+            error
+        );
+    }
+
+    assert_eq!(get_config::handle(&state).await.unwrap(), before);
+    assert!(!path.exists());
+}
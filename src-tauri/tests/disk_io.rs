@@ -0,0 +1,262 @@
+use pirate::{
+    config::AllocationMode,
+    storage::{
+        backend::{MoveConflict, SharedStorage, Storage},
+        disk_io::DiskIo,
+        file_layout::{FileEntry, FileLayout},
+        memory_storage::MemoryStorage,
+    },
+};
+use std::{
+    io::{self, ErrorKind},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+const PIECE_LENGTH: u64 = 32;
+const TOTAL_LENGTH: u64 = 100;
+const CACHE_SIZE: usize = 1024;
+const TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// One file of four pieces, the last one 4 bytes long
+fn layout() -> FileLayout {
+    let root = Path::new("/nowhere/file.bin");
+    FileLayout {
+        root: root.to_path_buf(),
+        is_multi_file: false,
+        files: vec![FileEntry {
+            path: root.to_path_buf(),
+            length: TOTAL_LENGTH,
+            offset: 0,
+            padding: false,
+        }],
+        piece_length: PIECE_LENGTH,
+        total_length: TOTAL_LENGTH,
+    }
+}
+
+fn piece(index: u32) -> Vec<u8> {
+    let start = index as u64 * PIECE_LENGTH;
+    (start..TOTAL_LENGTH.min(start + PIECE_LENGTH))
+        .map(|byte| byte as u8)
+        .collect()
+}
+
+fn memory_storage() -> SharedStorage {
+    Arc::new(MemoryStorage::new(layout()))
+}
+
+// Polls until `done`, for work the disk threads do in the background
+async fn eventually(mut done: impl FnMut() -> bool) {
+    tokio::time::timeout(TEST_TIMEOUT, async {
+        while !done() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("Disk I/O never caught up");
+}
+
+#[tokio::test]
+async fn coalesces_blocks_into_whole_pieces() {
+    let disk_io = DiskIo::new(2, 16, CACHE_SIZE);
+    let storage = memory_storage();
+    let disk = disk_io.open(Arc::clone(&storage));
+
+    let data = piece(1);
+    for begin in [16, 8, 24] {
+        disk.write_block(1, begin, data[begin as usize..begin as usize + 8].to_vec())
+            .await
+            .unwrap();
+    }
+    // Nothing reaches the storage until the piece is complete
+    assert_eq!(
+        storage.read_block(1, 0, 32).unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+    assert_eq!(disk_io.stats().write_cache_bytes, 24);
+
+    disk.write_block(1, 0, data[..8].to_vec()).await.unwrap();
+    assert_eq!(storage.read_block(1, 0, 32).unwrap(), data);
+
+    let stats = disk_io.stats();
+    assert_eq!(stats.blocks_received, 4);
+    assert_eq!(stats.pieces_written, 1);
+    assert_eq!(stats.partial_pieces_written, 0);
+    assert_eq!(stats.write_cache_bytes, 0);
+}
+
+#[tokio::test]
+async fn flush_writes_incomplete_pieces() {
+    let disk_io = DiskIo::new(1, 16, CACHE_SIZE);
+    let storage = memory_storage();
+    let disk = disk_io.open(Arc::clone(&storage));
+
+    disk.write_block(2, 0, piece(2)[..16].to_vec())
+        .await
+        .unwrap();
+    disk.flush().await.unwrap();
+
+    assert_eq!(storage.read_block(2, 0, 16).unwrap(), piece(2)[..16]);
+    assert_eq!(disk_io.stats().partial_pieces_written, 1);
+    assert_eq!(disk_io.stats().write_cache_bytes, 0);
+}
+
+#[tokio::test]
+async fn writes_out_the_oldest_pieces_when_the_cache_is_full() {
+    // 40 bytes for writing, less than two blocks of 24
+    let disk_io = DiskIo::new(1, 16, 80);
+    let storage = memory_storage();
+    let disk = disk_io.open(Arc::clone(&storage));
+
+    disk.write_block(0, 0, piece(0)[..24].to_vec())
+        .await
+        .unwrap();
+    disk.write_block(1, 0, piece(1)[..24].to_vec())
+        .await
+        .unwrap();
+
+    assert_eq!(storage.read_block(0, 0, 24).unwrap(), piece(0)[..24]);
+    assert!(storage.read_block(1, 0, 24).is_err());
+    assert_eq!(disk_io.stats().write_cache_bytes, 24);
+}
+
+#[tokio::test]
+async fn serves_reads_from_the_cache_and_reads_ahead() {
+    let disk_io = DiskIo::new(2, 16, CACHE_SIZE);
+    let storage = memory_storage();
+    for index in 0..4 {
+        storage.write_block(index, 0, &piece(index)).unwrap();
+    }
+    let disk = disk_io.open(Arc::clone(&storage));
+
+    assert_eq!(disk.read_block(0, 0, 16).await.unwrap(), piece(0)[..16]);
+    assert_eq!(disk.read_block(0, 16, 16).await.unwrap(), piece(0)[16..]);
+    assert_eq!(
+        (disk_io.stats().read_misses, disk_io.stats().read_hits),
+        (1, 1)
+    );
+
+    disk.read_ahead(vec![1, 2, 3]);
+    // Pieces 1 and 2 are full pieces, piece 3 is 4 bytes
+    eventually(|| disk_io.stats().read_cache_bytes == 32 * 3 + 4).await;
+    assert_eq!(disk.read_block(3, 0, 4).await.unwrap(), piece(3));
+    assert_eq!(
+        (disk_io.stats().read_misses, disk_io.stats().read_hits),
+        (1, 2)
+    );
+}
+
+#[tokio::test]
+async fn writes_replace_what_was_read() {
+    let disk_io = DiskIo::new(1, 16, CACHE_SIZE);
+    let storage = memory_storage();
+    storage.write_block(0, 0, &[0; 32]).unwrap();
+    let disk = disk_io.open(Arc::clone(&storage));
+
+    assert_eq!(disk.read_block(0, 0, 32).await.unwrap(), vec![0; 32]);
+    disk.write_block(0, 0, piece(0)).await.unwrap();
+    assert_eq!(disk.read_block(0, 0, 32).await.unwrap(), piece(0));
+}
+
+#[tokio::test]
+async fn discards_the_cache_of_one_torrent() {
+    let disk_io = DiskIo::new(1, 16, CACHE_SIZE);
+    let kept = disk_io.open(memory_storage());
+    let dropped = disk_io.open(memory_storage());
+
+    kept.write_block(0, 0, vec![1; 8]).await.unwrap();
+    dropped.write_block(0, 0, vec![2; 8]).await.unwrap();
+    dropped.discard();
+
+    assert_eq!(disk_io.stats().write_cache_bytes, 8);
+}
+
+// Holds every write until the test opens the gate
+struct GatedStorage {
+    storage: MemoryStorage,
+    open: Arc<AtomicBool>,
+    writers: Arc<AtomicUsize>,
+}
+
+impl Storage for GatedStorage {
+    fn layout(&self) -> FileLayout {
+        self.storage.layout()
+    }
+
+    fn read_block(&self, piece_index: u32, begin: u32, length: u32) -> io::Result<Vec<u8>> {
+        self.storage.read_block(piece_index, begin, length)
+    }
+
+    fn write_block(&self, piece_index: u32, begin: u32, data: &[u8]) -> io::Result<()> {
+        self.writers.fetch_add(1, Ordering::SeqCst);
+        while !self.open.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        self.storage.write_block(piece_index, begin, data)
+    }
+
+    fn allocate(&self, mode: AllocationMode) -> io::Result<()> {
+        self.storage.allocate(mode)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn move_storage(
+        &self,
+        target: &FileLayout,
+        conflict: MoveConflict,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> io::Result<()> {
+        self.storage.move_storage(target, conflict, progress)
+    }
+
+    fn delete(&self) -> io::Result<()> {
+        self.storage.delete()
+    }
+
+    fn check_file_sizes(&self) -> io::Result<bool> {
+        self.storage.check_file_sizes()
+    }
+}
+
+#[tokio::test]
+async fn writers_wait_while_the_queue_is_full() {
+    let disk_io = DiskIo::new(1, 1, CACHE_SIZE);
+    let open = Arc::new(AtomicBool::new(false));
+    let writers = Arc::new(AtomicUsize::new(0));
+    let storage: SharedStorage = Arc::new(GatedStorage {
+        storage: MemoryStorage::new(layout()),
+        open: Arc::clone(&open),
+        writers: Arc::clone(&writers),
+    });
+    let disk = disk_io.open(storage);
+
+    // The worker blocks on the first piece, the second waits in the queue and the third can't
+    // even be queued
+    let write = |index| {
+        let disk = disk.clone();
+        tokio::spawn(async move { disk.write_block(index, 0, piece(index)).await })
+    };
+    let mut writes = vec![write(0)];
+    eventually(|| writers.load(Ordering::SeqCst) == 1).await;
+    writes.push(write(1));
+    eventually(|| disk_io.stats().queued_jobs == 1).await;
+    writes.push(write(2));
+    eventually(|| disk_io.stats().queue_full_waits == 1).await;
+    assert!(writes.iter().all(|write| !write.is_finished()));
+
+    open.store(true, Ordering::SeqCst);
+    for write in writes {
+        write.await.unwrap().unwrap();
+    }
+    assert_eq!(disk_io.stats().pieces_written, 3);
+    assert_eq!(disk_io.stats().queued_jobs, 0);
+}
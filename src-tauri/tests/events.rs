@@ -0,0 +1,98 @@
+use pirate::{
+    events::{
+        event_bus::{forward_events, EventBus},
+        torrent_event::{coalesce_events, TorrentEvent},
+    },
+    torrent_management::torrent_status::TorrentStatus,
+};
+use std::time::Duration;
+
+fn rate(info_hash: &str, downloaded: u64) -> TorrentEvent {
+    TorrentEvent::RateSample {
+        info_hash: info_hash.to_string(),
+        download_rate: downloaded,
+        upload_rate: 0,
+        downloaded,
+        uploaded: 0,
+    }
+}
+
+fn status(info_hash: &str, status: TorrentStatus) -> TorrentEvent {
+    TorrentEvent::StatusChanged {
+        info_hash: info_hash.to_string(),
+        status,
+    }
+}
+
+fn piece(info_hash: &str, piece_index: u32) -> TorrentEvent {
+    TorrentEvent::PieceCompleted {
+        info_hash: info_hash.to_string(),
+        piece_index,
+    }
+}
+
+#[test]
+fn keeps_only_the_latest_progress_of_each_torrent() {
+    let events = vec![
+        rate("a", 1),
+        status("a", TorrentStatus::Downloading),
+        rate("b", 1),
+        rate("a", 2),
+        status("a", TorrentStatus::Completed),
+        rate("b", 2),
+    ];
+    assert_eq!(
+        coalesce_events(events),
+        vec![
+            rate("a", 2),
+            status("a", TorrentStatus::Completed),
+            rate("b", 2),
+        ]
+    );
+}
+
+#[test]
+fn keeps_every_other_event_in_order() {
+    let events = vec![
+        piece("a", 0),
+        rate("a", 1),
+        piece("a", 1),
+        piece("a", 0),
+        rate("a", 2),
+    ];
+    assert_eq!(
+        coalesce_events(events),
+        vec![piece("a", 0), piece("a", 1), piece("a", 0), rate("a", 2)]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn forwards_one_batch_per_interval() {
+    let bus = EventBus::new();
+    let receiver = bus.subscribe();
+    let (sender, mut batches) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(forward_events(
+        receiver,
+        Duration::from_millis(100),
+        move |batch| {
+            let _ = sender.send(batch);
+        },
+    ));
+    // The first tick fires right away, with nothing to send yet
+    tokio::task::yield_now().await;
+
+    for downloaded in 1..=10 {
+        bus.emit(rate("a", downloaded));
+    }
+    bus.emit(piece("a", 3));
+    assert_eq!(
+        batches.recv().await.unwrap(),
+        vec![rate("a", 10), piece("a", 3)]
+    );
+
+    // Remaining events are sent once the bus is gone
+    bus.emit(rate("a", 11));
+    drop(bus);
+    assert_eq!(batches.recv().await.unwrap(), vec![rate("a", 11)]);
+    assert!(batches.recv().await.is_none());
+}
@@ -0,0 +1,75 @@
+use pirate::network::lsd::{format_announce, parse_announce, LsdAnnounce, LsdSocket};
+use std::{net::Ipv4Addr, time::Duration};
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[test]
+fn parses_what_it_formats() {
+    let info_hashes = [[0xab; 20], [0x01; 20]];
+    let group = "239.192.152.143:6771".parse().unwrap();
+    let announce = format_announce(group, 6881, &info_hashes, "cookie1");
+
+    assert_eq!(
+        parse_announce(announce.as_bytes()),
+        Some(LsdAnnounce {
+            port: 6881,
+            info_hashes: info_hashes.to_vec(),
+            cookie: Some("cookie1".to_string()),
+        })
+    );
+}
+
+#[test]
+fn accepts_other_clients_header_spelling() {
+    let announce = "BT-SEARCH * HTTP/1.1\r\nHOST: 239.192.152.143:6771\r\nport: 51413\r\n\
+        infohash: 0123456789ABCDEF0123456789ABCDEF01234567\r\nInfohash: not-a-hash\r\n\r\n\r\n";
+
+    let parsed = parse_announce(announce.as_bytes()).unwrap();
+    assert_eq!(parsed.port, 51413);
+    assert_eq!(
+        parsed.info_hashes,
+        vec![<[u8; 20]>::try_from(
+            hex::decode("0123456789abcdef0123456789abcdef01234567").unwrap()
+        )
+        .unwrap()]
+    );
+    assert_eq!(parsed.cookie, None);
+}
+
+#[test]
+fn rejects_announces_without_port_or_info_hash() {
+    assert_eq!(
+        parse_announce(b"M-SEARCH * HTTP/1.1\r\nPort: 1\r\n\r\n"),
+        None
+    );
+    assert_eq!(
+        parse_announce(b"BT-SEARCH * HTTP/1.1\r\nPort: 6881\r\n\r\n\r\n"),
+        None
+    );
+    let no_port = format!(
+        "BT-SEARCH * HTTP/1.1\r\nInfohash: {}\r\n\r\n\r\n",
+        hex::encode([1u8; 20])
+    );
+    assert_eq!(parse_announce(no_port.as_bytes()), None);
+}
+
+#[tokio::test]
+async fn finds_peers_through_multicast_on_loopback() {
+    let announcer = LsdSocket::bind_v4(Ipv4Addr::LOCALHOST).unwrap();
+    let listener = LsdSocket::bind_v4(Ipv4Addr::LOCALHOST).unwrap();
+    let info_hash = [7u8; 20];
+
+    announcer.announce(6881, &[info_hash]).await.unwrap();
+
+    let (peer, announce) = tokio::time::timeout(TEST_TIMEOUT, listener.receive())
+        .await
+        .expect("No announce arrived")
+        .unwrap();
+    assert_eq!(peer.ip(), Ipv4Addr::LOCALHOST);
+    assert_eq!(peer.port(), 6881);
+    assert_eq!(announce.info_hashes, vec![info_hash]);
+
+    // Our own announces loop back too but are not peers
+    let own = tokio::time::timeout(Duration::from_millis(500), announcer.receive()).await;
+    assert!(own.is_err());
+}
@@ -0,0 +1,258 @@
+use pirate::{
+    parsing::parser::{parse_error::parse_bencoded_torrent, torrent_metadata::TorrentMetadata},
+    storage::{
+        merkle::{block_hashes, data_root, merkle_root, padding_hash, Hash, BLOCK_SIZE},
+        piece_hashes::PieceHashes,
+    },
+};
+use serde_bencode::value::Value;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+// Four blocks per piece
+const PIECE_LENGTH: usize = 4 * BLOCK_SIZE;
+// Two full pieces and a short one of two blocks, the second only partly used
+const BIG_LENGTH: usize = 2 * PIECE_LENGTH + BLOCK_SIZE + 3616;
+// Smaller than a piece, three blocks in a tree four leaves wide
+const SMALL_LENGTH: usize = 2 * BLOCK_SIZE + 7000;
+
+fn pair(left: Hash, right: Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+fn data(length: usize, seed: u8) -> Vec<u8> {
+    (0..length)
+        .map(|i| (i % 251) as u8 ^ seed)
+        .collect::<Vec<u8>>()
+}
+
+fn dict(entries: Vec<(&str, Value)>) -> Value {
+    Value::Dict(
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.as_bytes().to_vec(), value))
+            .collect::<HashMap<_, _>>(),
+    )
+}
+
+fn v2_file(length: usize, pieces_root: Hash) -> Value {
+    dict(vec![(
+        "",
+        dict(vec![
+            ("length", Value::Int(length as i64)),
+            ("pieces root", Value::Bytes(pieces_root.to_vec())),
+        ]),
+    )])
+}
+
+struct V2Torrent {
+    big: Vec<u8>,
+    small: Vec<u8>,
+    big_root: Hash,
+    small_root: Hash,
+    layer: Vec<Hash>,
+}
+
+impl V2Torrent {
+    fn new() -> V2Torrent {
+        let big = data(BIG_LENGTH, 1);
+        let small = data(SMALL_LENGTH, 2);
+        // Every piece of a big file is a full width tree, the blocks past the end hash to zeros
+        let layer: Vec<Hash> = big
+            .chunks(PIECE_LENGTH)
+            .map(|piece| data_root(piece, PIECE_LENGTH / BLOCK_SIZE))
+            .collect();
+        let big_root = merkle_root(&layer, 4, padding_hash(PIECE_LENGTH / BLOCK_SIZE));
+        let small_root = data_root(&small, 4);
+        V2Torrent {
+            big,
+            small,
+            big_root,
+            small_root,
+            layer,
+        }
+    }
+
+    // The pieces as the torrent-wide layout has them, the big file padded to a piece boundary
+    fn pieces(&self) -> Vec<Vec<u8>> {
+        let mut pieces: Vec<Vec<u8>> = self.big.chunks(PIECE_LENGTH).map(<[u8]>::to_vec).collect();
+        pieces[2].resize(PIECE_LENGTH, 0);
+        pieces.push(self.small.clone());
+        pieces
+    }
+
+    fn info(&self) -> Vec<(&'static str, Value)> {
+        vec![
+            (
+                "file tree",
+                dict(vec![
+                    ("big", v2_file(BIG_LENGTH, self.big_root)),
+                    ("small", v2_file(SMALL_LENGTH, self.small_root)),
+                ]),
+            ),
+            ("meta version", Value::Int(2)),
+            ("name", Value::Bytes(b"v2".to_vec())),
+            ("piece length", Value::Int(PIECE_LENGTH as i64)),
+        ]
+    }
+
+    // The same files with the v1 fields as well, a pad file aligns the small one to a piece
+    fn hybrid_info(&self) -> Vec<(&'static str, Value)> {
+        let file = |length: usize, path: &[&str], pad: bool| {
+            let mut entries = vec![
+                ("length", Value::Int(length as i64)),
+                (
+                    "path",
+                    Value::List(
+                        path.iter()
+                            .map(|part| Value::Bytes(part.as_bytes().to_vec()))
+                            .collect(),
+                    ),
+                ),
+            ];
+            if pad {
+                entries.push(("attr", Value::Bytes(b"p".to_vec())));
+            }
+            dict(entries)
+        };
+        let padding = 3 * PIECE_LENGTH - BIG_LENGTH;
+        let pieces: Vec<u8> = self
+            .pieces()
+            .iter()
+            .flat_map(|piece| Sha1::digest(piece).to_vec())
+            .collect();
+        let mut info = self.info();
+        info.push((
+            "files",
+            Value::List(vec![
+                file(BIG_LENGTH, &["big"], false),
+                file(padding, &[".pad", &padding.to_string()], true),
+                file(SMALL_LENGTH, &["small"], false),
+            ]),
+        ));
+        info.push(("pieces", Value::Bytes(pieces)));
+        info
+    }
+
+    fn metadata(&self, info: Vec<(&str, Value)>, layer: &[Hash]) -> TorrentMetadata {
+        let torrent = dict(vec![
+            ("info", dict(info)),
+            (
+                "piece layers",
+                Value::Dict(HashMap::from([(
+                    self.big_root.to_vec(),
+                    Value::Bytes(layer.concat()),
+                )])),
+            ),
+        ]);
+        parse_bencoded_torrent(serde_bencode::to_bytes(&torrent).unwrap()).unwrap()
+    }
+}
+
+#[test]
+fn merkle_roots_pad_to_their_width() {
+    let [a, b, c, padding] = [[1u8; 32], [2u8; 32], [3u8; 32], [9u8; 32]];
+    assert_eq!(
+        merkle_root(&[a, b, c], 4, padding),
+        pair(pair(a, b), pair(c, padding))
+    );
+    assert_eq!(merkle_root(&[a, b], 2, padding), pair(a, b));
+    // A tree one leaf wide is that leaf, an empty one is all padding
+    assert_eq!(merkle_root(&[a], 1, padding), a);
+    assert_eq!(merkle_root(&[], 0, padding), padding);
+    assert_eq!(
+        merkle_root(&[], 4, padding),
+        pair(pair(padding, padding), pair(padding, padding))
+    );
+}
+
+#[test]
+fn padding_subtrees_hash_zeros() {
+    assert_eq!(padding_hash(1), [0; 32]);
+    assert_eq!(padding_hash(2), pair([0; 32], [0; 32]));
+    assert_eq!(padding_hash(4), pair(padding_hash(2), padding_hash(2)));
+}
+
+#[test]
+fn data_roots_hash_blocks_and_pad_with_zeros() {
+    let data = data(BLOCK_SIZE + 100, 3);
+    let blocks = block_hashes(&data);
+    assert_eq!(blocks.len(), 2);
+    assert_eq!(blocks[1], <Hash>::from(Sha256::digest(&data[BLOCK_SIZE..])));
+    assert_eq!(data_root(&data, 2), pair(blocks[0], blocks[1]));
+    assert_eq!(
+        data_root(&data, 4),
+        pair(pair(blocks[0], blocks[1]), padding_hash(2))
+    );
+}
+
+#[test]
+fn v2_pieces_are_checked_against_their_layer() {
+    let torrent = V2Torrent::new();
+    let piece_hashes =
+        PieceHashes::from_metadata(&torrent.metadata(torrent.info(), &torrent.layer)).unwrap();
+    assert!(piece_hashes.v1.is_empty());
+    assert_eq!(piece_hashes.len(), 4);
+
+    let pieces = torrent.pieces();
+    for (index, piece) in pieces.iter().enumerate() {
+        assert!(piece_hashes.verify(index, piece), "piece {}", index);
+    }
+    // Only the file's data counts in its short last piece, not what follows it
+    let last_of_big = &torrent.big[2 * PIECE_LENGTH..];
+    assert!(piece_hashes.verify(2, last_of_big));
+    assert!(!piece_hashes.verify(2, &last_of_big[..last_of_big.len() - 1]));
+
+    let mut corrupted = pieces[1].clone();
+    corrupted[BLOCK_SIZE + 5] ^= 1;
+    assert!(!piece_hashes.verify(1, &corrupted));
+    assert!(!piece_hashes.verify(3, &pieces[0]));
+    assert!(!piece_hashes.verify(4, &pieces[0]));
+}
+
+#[test]
+fn piece_layers_must_match_their_pieces_root() {
+    let torrent = V2Torrent::new();
+    let mut wrong = torrent.layer.clone();
+    wrong[1][0] ^= 1;
+    assert!(PieceHashes::from_metadata(&torrent.metadata(torrent.info(), &wrong)).is_err());
+    // One hash short
+    assert!(PieceHashes::from_metadata(&torrent.metadata(torrent.info(), &wrong[..2])).is_err());
+    assert!(PieceHashes::from_metadata(&torrent.metadata(torrent.info(), &[])).is_err());
+}
+
+#[test]
+fn hybrid_pieces_must_match_both_hashes() {
+    let torrent = V2Torrent::new();
+    let piece_hashes =
+        PieceHashes::from_metadata(&torrent.metadata(torrent.hybrid_info(), &torrent.layer))
+            .unwrap();
+    assert_eq!(piece_hashes.v1.len(), 4);
+    assert_eq!(piece_hashes.v2.len(), 4);
+
+    let pieces = torrent.pieces();
+    for (index, piece) in pieces.iter().enumerate() {
+        assert!(piece_hashes.verify(index, piece), "piece {}", index);
+    }
+    // The v2 root only covers the file's data, the SHA-1 hash covers the pad file too
+    let mut padding_changed = pieces[2].clone();
+    *padding_changed.last_mut().unwrap() = 1;
+    assert!(!piece_hashes.verify(2, &padding_changed));
+    assert!(!piece_hashes.verify(2, &torrent.big[2 * PIECE_LENGTH..]));
+}
+
+#[test]
+fn hybrids_with_a_bad_layer_fall_back_to_sha1() {
+    let torrent = V2Torrent::new();
+    let mut wrong = torrent.layer.clone();
+    wrong[0][0] ^= 1;
+    let piece_hashes =
+        PieceHashes::from_metadata(&torrent.metadata(torrent.hybrid_info(), &wrong)).unwrap();
+    assert!(piece_hashes.v2.is_empty());
+    assert_eq!(piece_hashes.v1.len(), 4);
+    assert!(piece_hashes.verify(0, &torrent.pieces()[0]));
+}
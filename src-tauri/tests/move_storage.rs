@@ -0,0 +1,290 @@
+use bitvec::prelude::{BitVec, Lsb0};
+use pirate::{
+    config::{AllocationMode, Config},
+    events::{event_bus::EventBus, torrent_event::TorrentEvent},
+    parsing::parser::parse_error::parse_bencoded_torrent,
+    storage::{
+        backend::{MoveConflict, Storage},
+        disk_io::DiskIo,
+        file_layout::FileLayout,
+        fs_storage::FsStorage,
+        piece_hashes::PieceHashes,
+    },
+    torrent_management::{torrent::Torrent, torrent_status::TorrentStatus},
+};
+use sha1::{Digest, Sha1};
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    path::Path,
+    sync::{atomic::AtomicBool, Arc},
+};
+use tokio::sync::RwLock;
+
+const PIECE_LENGTH: usize = 8;
+
+// a.bin is bytes 0..10 and b/c.bin 10..32, a.bin is all of piece 0 and part of piece 1
+fn contents() -> Vec<u8> {
+    (1..=10).chain(101..=122).collect()
+}
+
+fn torrent_file() -> Vec<u8> {
+    let mut torrent = b"d4:infod5:filesl\
+        d6:lengthi10e4:pathl5:a.binee\
+        d6:lengthi22e4:pathl1:b5:c.binee\
+        e4:name3:dir12:piece lengthi8e6:pieces80:"
+        .to_vec();
+    for piece in contents().chunks(PIECE_LENGTH) {
+        torrent.extend(Sha1::digest(piece));
+    }
+    torrent.extend(b"ee");
+    torrent
+}
+
+// A complete torrent saved in `directory`
+fn torrent(directory: &Path, events: &EventBus) -> Torrent {
+    let mut metadata = parse_bencoded_torrent(torrent_file()).unwrap();
+    metadata.file_path = directory.join("dir");
+    let storage = FsStorage::new(FileLayout::from_metadata(&metadata));
+    for (index, piece) in contents().chunks(PIECE_LENGTH).enumerate() {
+        storage.write_block(index as u32, 0, piece).unwrap();
+    }
+    let info_hash: [u8; 20] = metadata.info_hash.as_slice().try_into().unwrap();
+    let piece_hashes = PieceHashes::from_metadata(&metadata).unwrap();
+    Torrent::new(
+        info_hash,
+        contents().len() as u64,
+        Arc::new(RwLock::new(Vec::new())),
+        Arc::new(RwLock::new(metadata)),
+        Arc::new(RwLock::new(BitVec::<u8, Lsb0>::repeat(true, 4))),
+        Arc::new(RwLock::new(HashMap::new())),
+        Arc::new(piece_hashes),
+        AtomicBool::new(false),
+        DiskIo::new(1, 16, 1 << 20).open(Arc::new(storage)),
+        AllocationMode::Sparse,
+        events.clone(),
+        Arc::new(RwLock::new(Config::default())),
+        None,
+    )
+}
+
+// Two directories to move between, with another a.bin already at the destination
+fn directories(destination_root: &Path) -> (tempfile::TempDir, tempfile::TempDir) {
+    let from = tempfile::tempdir().unwrap();
+    let to = tempfile::tempdir_in(destination_root).unwrap();
+    std::fs::create_dir_all(to.path().join("dir")).unwrap();
+    std::fs::write(to.path().join("dir").join("a.bin"), b"theirs").unwrap();
+    (from, to)
+}
+
+fn ours_a() -> Vec<u8> {
+    contents()[..10].to_vec()
+}
+
+fn ours_c() -> Vec<u8> {
+    contents()[10..].to_vec()
+}
+
+async fn pieces_status(torrent: &Torrent) -> Vec<bool> {
+    torrent
+        .pieces_status
+        .read()
+        .await
+        .iter()
+        .map(|bit| *bit)
+        .collect()
+}
+
+#[tokio::test]
+async fn fail_leaves_everything_in_place() {
+    let (from, to) = directories(&std::env::temp_dir());
+    let torrent = torrent(from.path(), &EventBus::new());
+    let status = torrent.check_status().await;
+
+    let error = torrent
+        .move_storage(to.path(), MoveConflict::Fail)
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    assert_eq!(
+        std::fs::read(from.path().join("dir/a.bin")).unwrap(),
+        ours_a()
+    );
+    assert_eq!(
+        std::fs::read(from.path().join("dir/b/c.bin")).unwrap(),
+        ours_c()
+    );
+    assert_eq!(
+        std::fs::read(to.path().join("dir/a.bin")).unwrap(),
+        b"theirs"
+    );
+    assert!(!to.path().join("dir/b").exists());
+    assert_eq!(
+        torrent.metadata.read().await.file_path,
+        from.path().join("dir")
+    );
+    assert_eq!(torrent.check_status().await, status);
+}
+
+#[tokio::test]
+async fn replace_overwrites_their_files() {
+    let (from, to) = directories(&std::env::temp_dir());
+    let events = EventBus::new();
+    let mut received = events.subscribe();
+    let torrent = torrent(from.path(), &events);
+    let status = torrent.check_status().await;
+
+    torrent
+        .move_storage(to.path(), MoveConflict::Replace)
+        .await
+        .unwrap();
+    assert_eq!(
+        std::fs::read(to.path().join("dir/a.bin")).unwrap(),
+        ours_a()
+    );
+    assert_eq!(
+        std::fs::read(to.path().join("dir/b/c.bin")).unwrap(),
+        ours_c()
+    );
+    assert!(!from.path().join("dir").exists());
+    assert_eq!(
+        torrent.metadata.read().await.file_path,
+        to.path().join("dir")
+    );
+    assert_eq!(
+        torrent.read_block(1, 0, 8).await.unwrap(),
+        contents()[8..16]
+    );
+    assert_eq!(pieces_status(&torrent).await, vec![true; 4]);
+
+    // Moving while it happens, back to what it was afterwards
+    let mut statuses = Vec::new();
+    while let Ok(event) = received.try_recv() {
+        if let TorrentEvent::StatusChanged { status, .. } = event {
+            statuses.push(status);
+        }
+    }
+    assert_eq!(statuses, vec![TorrentStatus::Moving, status]);
+}
+
+#[tokio::test]
+async fn keep_existing_uses_their_files_and_checks_them() {
+    let (from, to) = directories(&std::env::temp_dir());
+    let torrent = torrent(from.path(), &EventBus::new());
+
+    torrent
+        .move_storage(to.path(), MoveConflict::KeepExisting)
+        .await
+        .unwrap();
+    assert_eq!(
+        std::fs::read(to.path().join("dir/a.bin")).unwrap(),
+        b"theirs"
+    );
+    assert_eq!(
+        std::fs::read(to.path().join("dir/b/c.bin")).unwrap(),
+        ours_c()
+    );
+    // Ours stays behind at the old location
+    assert_eq!(
+        std::fs::read(from.path().join("dir/a.bin")).unwrap(),
+        ours_a()
+    );
+    // Their a.bin doesn't match the pieces it is part of
+    assert_eq!(
+        pieces_status(&torrent).await,
+        vec![false, false, true, true]
+    );
+}
+
+#[tokio::test]
+async fn copies_to_another_filesystem() {
+    // Renaming fails across filesystems, the files are copied and the originals deleted
+    let other_filesystem = Path::new("/dev/shm");
+    let device = |path: &Path| std::os::unix::fs::MetadataExt::dev(&path.metadata().unwrap());
+    if !other_filesystem.is_dir() || device(other_filesystem) == device(&std::env::temp_dir()) {
+        println!("No second filesystem to move to, skipping");
+        return;
+    }
+    let (from, to) = directories(other_filesystem);
+    let events = EventBus::new();
+    let mut received = events.subscribe();
+    let torrent = torrent(from.path(), &events);
+
+    torrent
+        .move_storage(to.path(), MoveConflict::Replace)
+        .await
+        .unwrap();
+    assert_eq!(
+        std::fs::read(to.path().join("dir/a.bin")).unwrap(),
+        ours_a()
+    );
+    assert_eq!(
+        std::fs::read(to.path().join("dir/b/c.bin")).unwrap(),
+        ours_c()
+    );
+    assert!(!from.path().join("dir").exists());
+    assert_eq!(torrent.read_block(3, 0, 8).await.unwrap(), contents()[24..]);
+
+    let mut moved = Vec::new();
+    while let Ok(event) = received.try_recv() {
+        if let TorrentEvent::MoveProgress {
+            moved: bytes,
+            total,
+            ..
+        } = event
+        {
+            moved.push((bytes, total));
+        }
+    }
+    assert_eq!(moved.last(), Some(&(32, 32)));
+}
+
+#[tokio::test]
+async fn only_one_of_two_moves_runs() {
+    let (from, to) = directories(&std::env::temp_dir());
+    let other = tempfile::tempdir().unwrap();
+    let torrent = torrent(from.path(), &EventBus::new());
+
+    let (first, second) = tokio::join!(
+        torrent.move_storage(to.path(), MoveConflict::Replace),
+        torrent.move_storage(other.path(), MoveConflict::Replace),
+    );
+    assert!(first.is_ok());
+    assert!(second
+        .unwrap_err()
+        .to_string()
+        .contains("already being moved"));
+    assert_eq!(
+        std::fs::read(to.path().join("dir/a.bin")).unwrap(),
+        ours_a()
+    );
+    assert!(!other.path().join("dir").exists());
+}
+
+#[tokio::test]
+async fn restarts_a_running_torrent_after_the_move() {
+    let (from, to) = directories(&std::env::temp_dir());
+    let torrent = torrent(from.path(), &EventBus::new());
+    torrent.start().await.unwrap();
+
+    torrent
+        .move_storage(to.path(), MoveConflict::Replace)
+        .await
+        .unwrap();
+    assert!(torrent.is_active());
+    assert_ne!(torrent.check_status().await, TorrentStatus::Moving);
+
+    // A failed move starts it again just the same
+    std::fs::create_dir_all(from.path().join("dir")).unwrap();
+    std::fs::write(from.path().join("dir/a.bin"), b"theirs").unwrap();
+    assert!(torrent
+        .move_storage(from.path(), MoveConflict::Fail)
+        .await
+        .is_err());
+    assert!(torrent.is_active());
+    assert_eq!(
+        torrent.metadata.read().await.file_path,
+        to.path().join("dir")
+    );
+    torrent.stop().await.unwrap();
+}
@@ -0,0 +1,219 @@
+use pirate::{
+    config::{EncryptionPolicy, DEFAULT_PSTR},
+    network::{
+        mse::{self, crypto_provide, is_plaintext_handshake, Rc4, CRYPTO_PLAINTEXT, CRYPTO_RC4},
+        peer_stream::PeerStream,
+        transport::{BoxedTransport, PeerTransport, TransportKind},
+    },
+};
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf};
+
+const INFO_HASH: [u8; 20] = [7; 20];
+const OTHER_INFO_HASH: [u8; 20] = [9; 20];
+
+// Both ends of a connection, in memory
+struct Duplex(DuplexStream);
+
+impl AsyncRead for Duplex {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Duplex {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_shutdown(cx)
+    }
+}
+
+impl PeerTransport for Duplex {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(SocketAddr::from(([127, 0, 0, 1], 6881)))
+    }
+
+    fn kind(&self) -> TransportKind {
+        TransportKind::Tcp
+    }
+}
+
+fn connection() -> (BoxedTransport, BoxedTransport) {
+    let (a, b) = tokio::io::duplex(64 * 1024);
+    (Box::new(Duplex(a)), Box::new(Duplex(b)))
+}
+
+// Runs both sides of the handshake, the accepting side reads the first 20 bytes up front like
+// the listener does
+async fn handshake(
+    outgoing: EncryptionPolicy,
+    incoming: EncryptionPolicy,
+) -> (
+    Result<PeerStream, String>,
+    Result<(PeerStream, [u8; 20]), String>,
+) {
+    let (initiator, mut acceptor) = connection();
+    let accepting = tokio::spawn(async move {
+        let mut first_bytes = vec![0u8; 20];
+        acceptor.read_exact(&mut first_bytes).await.unwrap();
+        assert!(!is_plaintext_handshake(&first_bytes));
+        mse::accept(
+            acceptor,
+            first_bytes,
+            &[OTHER_INFO_HASH, INFO_HASH],
+            incoming,
+        )
+        .await
+        .map_err(|e| e.to_string())
+    });
+    let initiated = mse::initiate(initiator, &INFO_HASH, outgoing)
+        .await
+        .map_err(|e| e.to_string());
+    (initiated, accepting.await.unwrap())
+}
+
+// Sends a message each way and checks it arrives intact
+async fn exchange(a: &mut PeerStream, b: &mut PeerStream) {
+    a.write_all(b"ping from the initiator").await.unwrap();
+    let mut received = [0u8; 23];
+    b.read_exact(&mut received).await.unwrap();
+    assert_eq!(&received, b"ping from the initiator");
+
+    b.write_all(b"pong").await.unwrap();
+    let mut received = [0u8; 4];
+    a.read_exact(&mut received).await.unwrap();
+    assert_eq!(&received, b"pong");
+}
+
+#[test]
+fn rc4_round_trips() {
+    let mut data = b"Attack at dawn".to_vec();
+    Rc4::new(b"Secret").apply(&mut data);
+    // Known RC4 test vector
+    assert_eq!(hex::encode(&data), "45a01f645fc35b383552544b9bf5");
+    Rc4::new(b"Secret").apply(&mut data);
+    assert_eq!(data, b"Attack at dawn");
+}
+
+#[tokio::test]
+async fn both_sides_agree_on_the_keys() {
+    // Every run pads with a different random length, the markers must be found after all of them
+    for _ in 0..20 {
+        let (initiated, accepted) =
+            handshake(EncryptionPolicy::Enabled, EncryptionPolicy::Enabled).await;
+        let mut initiator = initiated.unwrap();
+        let (mut acceptor, info_hash) = accepted.unwrap();
+
+        assert_eq!(info_hash, INFO_HASH);
+        assert!(initiator.is_encrypted() && acceptor.is_encrypted());
+        exchange(&mut initiator, &mut acceptor).await;
+    }
+}
+
+#[tokio::test]
+async fn negotiates_the_method_both_sides_allow() {
+    let cases = [
+        (
+            EncryptionPolicy::Required,
+            EncryptionPolicy::Enabled,
+            Some(true),
+        ),
+        (
+            EncryptionPolicy::Enabled,
+            EncryptionPolicy::Required,
+            Some(true),
+        ),
+        // Only plaintext is allowed on the accepting side, it is selected within MSE
+        (
+            EncryptionPolicy::Enabled,
+            EncryptionPolicy::Disabled,
+            Some(false),
+        ),
+        (EncryptionPolicy::Required, EncryptionPolicy::Disabled, None),
+    ];
+    for (outgoing, incoming, encrypted) in cases {
+        let (initiated, accepted) = handshake(outgoing, incoming).await;
+        match encrypted {
+            Some(encrypted) => {
+                let mut initiator = initiated.unwrap();
+                let (mut acceptor, _) = accepted.unwrap();
+                assert_eq!(initiator.is_encrypted(), encrypted);
+                assert_eq!(acceptor.is_encrypted(), encrypted);
+                exchange(&mut initiator, &mut acceptor).await;
+            }
+            None => assert!(accepted.is_err()),
+        }
+    }
+}
+
+#[test]
+fn provides_methods_by_policy() {
+    assert_eq!(crypto_provide(EncryptionPolicy::Disabled), CRYPTO_PLAINTEXT);
+    assert_eq!(
+        crypto_provide(EncryptionPolicy::Enabled),
+        CRYPTO_PLAINTEXT | CRYPTO_RC4
+    );
+    assert_eq!(crypto_provide(EncryptionPolicy::Required), CRYPTO_RC4);
+}
+
+#[tokio::test]
+async fn rejects_unknown_torrents() {
+    let (initiator, mut acceptor) = connection();
+    let accepting = tokio::spawn(async move {
+        let mut first_bytes = vec![0u8; 20];
+        acceptor.read_exact(&mut first_bytes).await.unwrap();
+        mse::accept(
+            acceptor,
+            first_bytes,
+            &[OTHER_INFO_HASH],
+            EncryptionPolicy::Enabled,
+        )
+        .await
+        .map(|_| ())
+    });
+    let initiated = mse::initiate(initiator, &INFO_HASH, EncryptionPolicy::Enabled).await;
+    assert!(accepting.await.unwrap().is_err());
+    assert!(initiated.is_err());
+}
+
+#[tokio::test]
+async fn plaintext_handshakes_are_recognised_and_replayed() {
+    let mut handshake = vec![DEFAULT_PSTR.len() as u8];
+    handshake.extend(DEFAULT_PSTR.as_bytes());
+    handshake.extend([0u8; 8]);
+    handshake.extend(INFO_HASH);
+    handshake.extend([1u8; 20]);
+
+    let (mut initiator, mut acceptor) = connection();
+    initiator.write_all(&handshake).await.unwrap();
+    let mut first_bytes = vec![0u8; 20];
+    acceptor.read_exact(&mut first_bytes).await.unwrap();
+    assert!(is_plaintext_handshake(&first_bytes));
+
+    // The bytes read to tell the two apart are read again from the stream
+    let mut stream = PeerStream::plaintext(acceptor).with_buffered(first_bytes);
+    let mut received = vec![0u8; handshake.len()];
+    stream.read_exact(&mut received).await.unwrap();
+    assert_eq!(received, handshake);
+    assert!(!stream.is_encrypted());
+}
@@ -0,0 +1,61 @@
+use pirate::network::peer_id::{generate_peer_id, identify_client, ClientInfo};
+
+fn client(name: &str, version: Option<&str>) -> Option<ClientInfo> {
+    Some(ClientInfo {
+        name: name.to_string(),
+        version: version.map(str::to_string),
+    })
+}
+
+#[test]
+fn identifies_clients_by_peer_id() {
+    let cases: [(&[u8], Option<ClientInfo>); 12] = [
+        // Azureus-style
+        (b"-qB4500-a1b2c3d4e5f6", client("qBittorrent", Some("4.5"))),
+        (
+            b"-LT2090-a1b2c3d4e5f6",
+            client("libtorrent (Rasterbar)", Some("2.0.9")),
+        ),
+        (
+            b"-TR2940-a1b2c3d4e5f6",
+            client("Transmission", Some("2.94")),
+        ),
+        (b"-ZZ1000-a1b2c3d4e5f6", client("Unknown (ZZ)", Some("1.0"))),
+        // Shadow-style
+        (
+            b"T03I-----a1b2c3d4e5f",
+            client("BitTornado", Some("0.3.18")),
+        ),
+        (
+            b"S58B-----a1b2c3d4e5f",
+            client("Shadow's client", Some("5.8.11")),
+        ),
+        (b"A2----------a1b2c3d4", client("ABC", Some("2"))),
+        // Mainline
+        (b"M7-10-3-a1b2c3d4e5f6", client("Mainline", Some("7.10.3"))),
+        // Random ids that happen to start with a known letter
+        (b"Tq8xR2mZ0pL4vN7kW1sY", None),
+        (b"S58B-x---a1b2c3d4e5f", None),
+        (b"M3abc-def1234567890a", None),
+        // Not 20 bytes
+        (b"-qB4500-", None),
+    ];
+    for (peer_id, expected) in cases {
+        assert_eq!(
+            identify_client(peer_id),
+            expected,
+            "{}",
+            String::from_utf8_lossy(peer_id)
+        );
+    }
+}
+
+#[test]
+fn generated_ids_identify_as_pirate() {
+    let peer_id = generate_peer_id("-PR0001-");
+    assert_eq!(peer_id.len(), 20);
+    assert_eq!(
+        identify_client(peer_id.as_bytes()),
+        client("Pirate", Some("0.0.0.1"))
+    );
+}
@@ -0,0 +1,501 @@
+use bitvec::prelude::{BitVec, Lsb0};
+use pirate::{
+    config::{AllocationMode, Config},
+    events::event_bus::EventBus,
+    network::{
+        extension::UT_PEX_ID,
+        fast::{allowed_fast_set, ALLOWED_FAST_SET_SIZE},
+        peer_connection::receive_message,
+        peer_handshake::RemoteHandshake,
+        peer_stream::PeerStream,
+    },
+    parsing::parser::parse_error::parse_bencoded_torrent,
+    storage::{
+        backend::Storage,
+        disk_io::{DiskIo, READ_AHEAD_PIECES},
+        file_layout::FileLayout,
+        memory_storage::MemoryStorage,
+        piece_hashes::PieceHashes,
+    },
+    torrent_management::{
+        message::Message, peers::Peer, torrent::Torrent, torrent_status::TorrentStatus,
+    },
+};
+use serde_bencode::value::Value;
+use sha1::{Digest, Sha1};
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+    sync::RwLock,
+};
+
+const PIECE_LENGTH: usize = 16;
+// More pieces than the allowed-fast set holds
+const PIECE_COUNT: usize = 16;
+const TEST_TIMEOUT: Duration = Duration::from_secs(10);
+const FAST_EXTENSION: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 0x04];
+
+fn piece(index: usize) -> Vec<u8> {
+    vec![index as u8; PIECE_LENGTH]
+}
+
+// Private torrents carry the flag in the info dictionary (BEP 27)
+fn torrent_file(private: bool) -> Vec<u8> {
+    let mut torrent = format!(
+        "d4:infod6:lengthi{}e4:name8:file.bin12:piece lengthi{}e6:pieces{}:",
+        PIECE_LENGTH * PIECE_COUNT,
+        PIECE_LENGTH,
+        PIECE_COUNT * 20
+    )
+    .into_bytes();
+    for index in 0..PIECE_COUNT {
+        torrent.extend(Sha1::digest(piece(index)));
+    }
+    if private {
+        torrent.extend(b"7:privatei1e");
+    }
+    torrent.extend(b"ee");
+    torrent
+}
+
+fn disk() -> DiskIo {
+    DiskIo::new(1, 16, 1 << 20)
+}
+
+fn torrent(complete: bool, upload_slots: usize) -> Torrent {
+    torrent_from(torrent_file(false), complete, upload_slots, &disk())
+}
+
+// A torrent with every piece or none of them, kept in memory
+fn torrent_from(
+    torrent_file: Vec<u8>,
+    complete: bool,
+    upload_slots: usize,
+    disk: &DiskIo,
+) -> Torrent {
+    let mut metadata = parse_bencoded_torrent(torrent_file).unwrap();
+    metadata.file_path = PathBuf::from("/nowhere/file.bin");
+    let storage = MemoryStorage::new(FileLayout::from_metadata(&metadata));
+    if complete {
+        for index in 0..PIECE_COUNT {
+            storage.write_block(index as u32, 0, &piece(index)).unwrap();
+        }
+    }
+    let info_hash: [u8; 20] = metadata.info_hash.as_slice().try_into().unwrap();
+    let piece_hashes = PieceHashes::from_metadata(&metadata).unwrap();
+    Torrent::new(
+        info_hash,
+        (PIECE_LENGTH * PIECE_COUNT) as u64,
+        Arc::new(RwLock::new(Vec::new())),
+        Arc::new(RwLock::new(metadata)),
+        Arc::new(RwLock::new(BitVec::<u8, Lsb0>::repeat(
+            complete,
+            PIECE_COUNT,
+        ))),
+        Arc::new(RwLock::new(HashMap::new())),
+        Arc::new(piece_hashes),
+        AtomicBool::new(false),
+        disk.open(Arc::new(storage)),
+        AllocationMode::Sparse,
+        EventBus::new(),
+        Arc::new(RwLock::new(Config {
+            upload_slots,
+            ..Config::default()
+        })),
+        None,
+    )
+}
+
+// Registers our end of a loopback connection with the torrent, the test plays the remote peer
+async fn connect(torrent: &Torrent) -> (TcpStream, Peer) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let remote = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (ours, address) = listener.accept().await.unwrap();
+    let handshake = RemoteHandshake {
+        reserved: FAST_EXTENSION,
+        info_hash: Vec::new(),
+        peer_id: b"-qB4500-a1b2c3d4e5f6".to_vec(),
+    };
+    let peer = Peer::new(address);
+    torrent
+        .register_connection(
+            peer.clone(),
+            PeerStream::plaintext(Box::new(ours)),
+            &handshake,
+        )
+        .await;
+    (remote, peer)
+}
+
+async fn send(remote: &mut TcpStream, message: Message) {
+    remote.write_all(&message.encode()).await.unwrap();
+}
+
+async fn receive(remote: &mut TcpStream) -> Message {
+    tokio::time::timeout(TEST_TIMEOUT, receive_message(remote))
+        .await
+        .expect("The torrent never answered")
+        .unwrap()
+}
+
+fn allowed_fast(torrent: &Torrent) -> Vec<u32> {
+    allowed_fast_set(
+        IpAddr::V4(Ipv4Addr::LOCALHOST),
+        &hex::decode(torrent.info_hash_hex())
+            .unwrap()
+            .try_into()
+            .unwrap(),
+        PIECE_COUNT as u32,
+        ALLOWED_FAST_SET_SIZE,
+    )
+}
+
+fn request(index: u32) -> Message {
+    Message::Request(index, 0, PIECE_LENGTH as u32)
+}
+
+fn served(index: u32) -> Message {
+    Message::Piece(index as usize, 0, piece(index as usize))
+}
+
+// Reads what a seed sends right after the handshake, HaveAll and the allowed-fast set
+async fn skip_fast_state(remote: &mut TcpStream, torrent: &Torrent) {
+    for _ in 0..1 + allowed_fast(torrent).len() {
+        receive(remote).await;
+    }
+}
+
+// Messages are handled in order, once a request is answered everything sent before it was too
+async fn handled(remote: &mut TcpStream, torrent: &Torrent) {
+    let free = allowed_fast(torrent)[0];
+    send(remote, request(free)).await;
+    assert_eq!(receive(remote).await, served(free));
+}
+
+fn pex(added: &[&str], added6: &[&str]) -> Message {
+    let compact = |peers: &[&str]| {
+        let mut bytes = Vec::new();
+        for peer in peers {
+            match peer.parse::<SocketAddr>().unwrap() {
+                SocketAddr::V4(address) => bytes.extend(address.ip().octets()),
+                SocketAddr::V6(address) => bytes.extend(address.ip().octets()),
+            }
+            bytes.extend(peer.parse::<SocketAddr>().unwrap().port().to_be_bytes());
+        }
+        Value::Bytes(bytes)
+    };
+    let mut dict = HashMap::new();
+    dict.insert(b"added".to_vec(), compact(added));
+    dict.insert(b"added6".to_vec(), compact(added6));
+    Message::Extended(
+        UT_PEX_ID,
+        serde_bencode::to_bytes(&Value::Dict(dict)).unwrap(),
+    )
+}
+
+async fn known_peers(torrent: &Torrent) -> Vec<String> {
+    torrent
+        .peers
+        .read()
+        .await
+        .iter()
+        .map(|peer| peer.address.to_string())
+        .collect()
+}
+
+// Retries while the read loop hasn't caught up with what the remote peer sent
+async fn download(torrent: Torrent, peer: Peer, index: u32) -> std::io::Result<Vec<u8>> {
+    tokio::time::timeout(TEST_TIMEOUT, async {
+        loop {
+            match torrent.download_piece_from_peer(&peer, index).await {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    tokio::time::sleep(Duration::from_millis(5)).await
+                }
+                result => return result,
+            }
+        }
+    })
+    .await
+    .expect("The download never started")
+}
+
+#[tokio::test]
+async fn choked_peers_only_get_their_allowed_fast_pieces() {
+    let torrent = torrent(true, 1);
+    let (mut remote, _) = connect(&torrent).await;
+
+    // A seed announces itself with HaveAll, then offers the allowed-fast set
+    assert_eq!(receive(&mut remote).await, Message::HaveAll);
+    let allowed = allowed_fast(&torrent);
+    for _ in 0..allowed.len() {
+        match receive(&mut remote).await {
+            Message::AllowedFast(index) => assert!(allowed.contains(&index)),
+            other => panic!("Expected AllowedFast, got {:?}", other),
+        }
+    }
+    let free = allowed[0];
+    let other = (0..PIECE_COUNT as u32)
+        .find(|index| !allowed.contains(index))
+        .unwrap();
+
+    send(&mut remote, request(other)).await;
+    assert_eq!(
+        receive(&mut remote).await,
+        Message::RejectRequest(other, 0, PIECE_LENGTH as u32)
+    );
+    send(&mut remote, request(free)).await;
+    assert_eq!(receive(&mut remote).await, served(free));
+
+    // Showing interest earns the free upload slot
+    send(&mut remote, Message::Interested).await;
+    assert_eq!(receive(&mut remote).await, Message::Unchoke);
+    send(&mut remote, request(other)).await;
+    assert_eq!(receive(&mut remote).await, served(other));
+}
+
+#[tokio::test]
+async fn upload_slots_go_to_the_next_interested_peer() {
+    let torrent = torrent(true, 1);
+    let (mut first, _) = connect(&torrent).await;
+    let (mut second, _) = connect(&torrent).await;
+    let fast_messages = 1 + allowed_fast(&torrent).len();
+    for _ in 0..fast_messages {
+        receive(&mut first).await;
+        receive(&mut second).await;
+    }
+
+    send(&mut first, Message::Interested).await;
+    assert_eq!(receive(&mut first).await, Message::Unchoke);
+    send(&mut second, Message::Interested).await;
+    // Still choked, there is only one slot
+    let other = (0..PIECE_COUNT as u32)
+        .find(|index| !allowed_fast(&torrent).contains(index))
+        .unwrap();
+    send(&mut second, request(other)).await;
+    assert_eq!(
+        receive(&mut second).await,
+        Message::RejectRequest(other, 0, PIECE_LENGTH as u32)
+    );
+
+    send(&mut first, Message::NotInterested).await;
+    assert_eq!(receive(&mut first).await, Message::Choke);
+    assert_eq!(receive(&mut second).await, Message::Unchoke);
+
+    // A peer that goes away frees its slot as well
+    drop(second);
+    send(&mut first, Message::Interested).await;
+    assert_eq!(receive(&mut first).await, Message::Unchoke);
+}
+
+#[tokio::test]
+async fn downloads_through_the_read_loop() {
+    let torrent = torrent(false, 1);
+    let (mut remote, peer) = connect(&torrent).await;
+
+    // We have nothing and want everything
+    assert_eq!(receive(&mut remote).await, Message::HaveNone);
+    assert_eq!(receive(&mut remote).await, Message::Interested);
+
+    // The peer chokes us, only its allowed-fast pieces can be asked for
+    send(&mut remote, Message::HaveAll).await;
+    send(&mut remote, Message::SuggestPiece(5)).await;
+    send(&mut remote, Message::AllowedFast(3)).await;
+    let downloading = tokio::spawn(download(torrent.clone(), peer.clone(), 3));
+    assert_eq!(receive(&mut remote).await, request(3));
+    send(&mut remote, served(3)).await;
+    assert_eq!(downloading.await.unwrap().unwrap(), piece(3));
+    assert_eq!(
+        torrent
+            .download_piece_from_peer(&peer, 4)
+            .await
+            .unwrap_err()
+            .kind(),
+        ErrorKind::WouldBlock
+    );
+
+    // Once unchoked anything can be asked for, and rejected
+    send(&mut remote, Message::Unchoke).await;
+    let downloading = tokio::spawn(download(torrent.clone(), peer.clone(), 4));
+    assert_eq!(receive(&mut remote).await, request(4));
+    send(
+        &mut remote,
+        Message::RejectRequest(4, 0, PIECE_LENGTH as u32),
+    )
+    .await;
+    assert!(downloading.await.unwrap().is_err());
+
+    // Requests still waiting fail when the peer goes away
+    let downloading = tokio::spawn(download(torrent.clone(), peer.clone(), 6));
+    assert_eq!(receive(&mut remote).await, request(6));
+    drop(remote);
+    assert!(downloading.await.unwrap().is_err());
+    assert_eq!(torrent.summary().await.connected_peers, 0);
+}
+
+#[tokio::test]
+async fn learns_peers_through_pex() {
+    let torrent = torrent(true, 1);
+    let (mut remote, peer) = connect(&torrent).await;
+    let connected = peer.address.to_string();
+    skip_fast_state(&mut remote, &torrent).await;
+
+    send(
+        &mut remote,
+        pex(&["10.0.0.1:6881", "10.0.0.2:6882"], &["[2001:db8::1]:6881"]),
+    )
+    .await;
+    handled(&mut remote, &torrent).await;
+    assert_eq!(
+        known_peers(&torrent).await,
+        vec![
+            &connected,
+            "10.0.0.1:6881",
+            "10.0.0.2:6882",
+            "[2001:db8::1]:6881"
+        ]
+    );
+
+    // Peers we know already aren't added twice, IPv6 ones only while IPv6 is enabled
+    torrent.config().write().await.ipv6_enabled = false;
+    send(
+        &mut remote,
+        pex(&["10.0.0.2:6882", "10.0.0.3:6881"], &["[2001:db8::2]:6881"]),
+    )
+    .await;
+    handled(&mut remote, &torrent).await;
+    assert_eq!(
+        known_peers(&torrent).await,
+        vec![
+            &connected,
+            "10.0.0.1:6881",
+            "10.0.0.2:6882",
+            "[2001:db8::1]:6881",
+            "10.0.0.3:6881"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn private_torrents_ignore_pex() {
+    let torrent = torrent_from(torrent_file(true), true, 1, &disk());
+    assert!(torrent.is_private().await);
+    let (mut remote, peer) = connect(&torrent).await;
+    skip_fast_state(&mut remote, &torrent).await;
+
+    send(
+        &mut remote,
+        pex(&["10.0.0.1:6881"], &["[2001:db8::1]:6881"]),
+    )
+    .await;
+    handled(&mut remote, &torrent).await;
+    // Only the peer we are connected to
+    assert_eq!(known_peers(&torrent).await, vec![peer.address.to_string()]);
+}
+
+#[tokio::test]
+async fn serves_from_the_read_cache_and_reads_ahead() {
+    let disk = disk();
+    let torrent = torrent_from(torrent_file(false), true, 1, &disk);
+    let (mut remote, _) = connect(&torrent).await;
+    skip_fast_state(&mut remote, &torrent).await;
+    send(&mut remote, Message::Interested).await;
+    assert_eq!(receive(&mut remote).await, Message::Unchoke);
+
+    send(&mut remote, request(0)).await;
+    assert_eq!(receive(&mut remote).await, served(0));
+    assert_eq!(disk.stats().read_misses, 1);
+
+    // The pieces after the requested one are read into the cache in the background
+    let cached = (1 + READ_AHEAD_PIECES) * PIECE_LENGTH;
+    tokio::time::timeout(TEST_TIMEOUT, async {
+        while disk.stats().read_cache_bytes < cached {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("The following pieces were never read ahead");
+
+    for index in 1..=READ_AHEAD_PIECES as u32 {
+        send(&mut remote, request(index)).await;
+        assert_eq!(receive(&mut remote).await, served(index));
+    }
+    let stats = disk.stats();
+    assert_eq!(stats.read_misses, 1);
+    assert_eq!(stats.read_hits, READ_AHEAD_PIECES as u64);
+    assert_eq!(
+        torrent.summary().await.uploaded,
+        ((1 + READ_AHEAD_PIECES) * PIECE_LENGTH) as u64
+    );
+}
+
+#[tokio::test]
+async fn complete_torrents_go_on_seeding() {
+    let torrent = torrent(true, 1);
+    torrent.download_and_seed().await.unwrap();
+    assert_eq!(torrent.summary().await.status, TorrentStatus::Seeding);
+}
+
+#[tokio::test]
+async fn downloads_wait_for_peers_to_say_what_they_have() {
+    let torrent = torrent(false, 1);
+    let downloading = tokio::spawn({
+        let torrent = torrent.clone();
+        async move { torrent.download_and_seed().await }
+    });
+
+    // No one has told us about any piece yet, that isn't a reason to give up
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!downloading.is_finished());
+    assert_eq!(torrent.check_status().await, TorrentStatus::Downloading);
+
+    let (mut remote, _) = connect(&torrent).await;
+    assert_eq!(receive(&mut remote).await, Message::HaveNone);
+    assert_eq!(receive(&mut remote).await, Message::Interested);
+    send(&mut remote, Message::HaveAll).await;
+    send(&mut remote, Message::Unchoke).await;
+    let serving = tokio::spawn(async move {
+        while let Ok(message) = receive_message(&mut remote).await {
+            if let Message::Request(index, _, _) = message {
+                send(&mut remote, served(index)).await;
+            }
+        }
+    });
+    tokio::time::timeout(TEST_TIMEOUT, downloading)
+        .await
+        .expect("The download never finished")
+        .unwrap()
+        .unwrap();
+    serving.abort();
+    assert!(torrent.is_complete().await);
+    assert_eq!(torrent.check_status().await, TorrentStatus::Seeding);
+}
+
+#[tokio::test]
+async fn uploads_keep_to_the_upload_limit() {
+    let torrent = torrent(true, 1);
+    let (mut remote, _) = connect(&torrent).await;
+    skip_fast_state(&mut remote, &torrent).await;
+    send(&mut remote, Message::Interested).await;
+    assert_eq!(receive(&mut remote).await, Message::Unchoke);
+
+    // Four pieces a second
+    torrent.config().write().await.max_upload_rate = 4 * PIECE_LENGTH as u64;
+    let started = std::time::Instant::now();
+    for index in 0..4 {
+        send(&mut remote, request(index)).await;
+    }
+    for index in 0..4 {
+        assert_eq!(receive(&mut remote).await, served(index));
+    }
+    assert!(started.elapsed() >= Duration::from_millis(900));
+}
@@ -0,0 +1,84 @@
+use pirate::torrent_management::peers::{dedup_peers, unmarshal_peer_dicts, Peer};
+use serde_bencode::value::Value;
+use std::{collections::HashMap, net::SocketAddr};
+
+fn entry(fields: Vec<(&str, Value)>) -> Value {
+    Value::Dict(
+        fields
+            .into_iter()
+            .map(|(key, value)| (key.as_bytes().to_vec(), value))
+            .collect::<HashMap<_, _>>(),
+    )
+}
+
+fn ip(ip: &str) -> Value {
+    Value::Bytes(ip.as_bytes().to_vec())
+}
+
+fn peer(address: &str) -> Peer {
+    Peer::new(address.parse().unwrap())
+}
+
+#[tokio::test]
+async fn decodes_literal_and_hostname_peers() {
+    let entries = vec![
+        entry(vec![
+            ("ip", ip("10.0.0.1")),
+            ("port", Value::Int(6881)),
+            ("peer id", Value::Bytes(vec![1; 20])),
+        ]),
+        entry(vec![("ip", ip("2001:db8::1")), ("port", Value::Int(6882))]),
+        entry(vec![("ip", ip("localhost")), ("port", Value::Int(6883))]),
+    ];
+    let peers = unmarshal_peer_dicts(&entries).await;
+
+    assert_eq!(peers.len(), 3);
+    assert_eq!(peers[0], peer("10.0.0.1:6881"));
+    assert_eq!(peers[1], peer("[2001:db8::1]:6882"));
+    assert!(peers[2].address.ip().is_loopback());
+    assert_eq!(peers[2].address.port(), 6883);
+}
+
+#[tokio::test]
+async fn skips_malformed_entries() {
+    let entries = vec![
+        Value::Int(1),
+        entry(vec![("ip", ip("10.0.0.1"))]),
+        entry(vec![("port", Value::Int(6881))]),
+        entry(vec![("ip", ip("10.0.0.2")), ("port", Value::Int(70000))]),
+        entry(vec![
+            ("ip", Value::Bytes(vec![0xff, 0xfe])),
+            ("port", Value::Int(6881)),
+        ]),
+        entry(vec![("ip", ip("10.0.0.3")), ("port", Value::Int(6881))]),
+    ];
+    assert_eq!(
+        unmarshal_peer_dicts(&entries).await,
+        vec![peer("10.0.0.3:6881")]
+    );
+}
+
+#[test]
+fn drops_duplicates_that_are_not_next_to_each_other() {
+    let mut peers: Vec<Peer> = [
+        "10.0.0.1:6881",
+        "10.0.0.2:6881",
+        "10.0.0.1:6881",
+        "10.0.0.1:6882",
+        "10.0.0.2:6881",
+    ]
+    .iter()
+    .map(|address| peer(address))
+    .collect();
+    dedup_peers(&mut peers);
+
+    let addresses: Vec<SocketAddr> = peers.iter().map(|peer| peer.address).collect();
+    assert_eq!(
+        addresses,
+        vec![
+            "10.0.0.1:6881".parse().unwrap(),
+            "10.0.0.2:6881".parse().unwrap(),
+            "10.0.0.1:6882".parse().unwrap(),
+        ]
+    );
+}
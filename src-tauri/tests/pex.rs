@@ -0,0 +1,89 @@
+use pirate::{
+    network::pex::{parse_pex, PexMessage},
+    torrent_management::peers::Peer,
+};
+use serde_bencode::value::Value;
+use std::{collections::HashMap, net::SocketAddr};
+
+fn peer(address: &str) -> Peer {
+    Peer::new(address.parse().unwrap())
+}
+
+// Encodes addresses in the compact format, 4 or 16 address bytes followed by the port
+fn compact(addresses: &[&str]) -> Value {
+    let mut bytes = Vec::new();
+    for address in addresses {
+        let address: SocketAddr = address.parse().unwrap();
+        match address {
+            SocketAddr::V4(address) => bytes.extend(address.ip().octets()),
+            SocketAddr::V6(address) => bytes.extend(address.ip().octets()),
+        }
+        bytes.extend(address.port().to_be_bytes());
+    }
+    Value::Bytes(bytes)
+}
+
+fn pex_payload(fields: Vec<(&str, Value)>) -> Vec<u8> {
+    let dict: HashMap<Vec<u8>, Value> = fields
+        .into_iter()
+        .map(|(key, value)| (key.as_bytes().to_vec(), value))
+        .collect();
+    serde_bencode::to_bytes(&Value::Dict(dict)).unwrap()
+}
+
+#[test]
+fn round_trips_ipv4_and_ipv6_peers() {
+    let payload = pex_payload(vec![
+        ("added", compact(&["10.0.0.1:6881", "192.168.1.20:51413"])),
+        // One flag byte per added peer, not needed to learn the peers
+        ("added.f", Value::Bytes(vec![0x01, 0x10])),
+        (
+            "added6",
+            compact(&["[2001:db8::1]:6881", "[::ffff:10.0.0.9]:6882"]),
+        ),
+        ("added6.f", Value::Bytes(vec![0x00, 0x00])),
+        ("dropped", compact(&["10.0.0.2:6881"])),
+        ("dropped6", compact(&["[2001:db8::2]:6883"])),
+    ]);
+
+    assert_eq!(
+        parse_pex(&payload).unwrap(),
+        PexMessage {
+            added: vec![
+                peer("10.0.0.1:6881"),
+                peer("192.168.1.20:51413"),
+                peer("[2001:db8::1]:6881"),
+                // IPv4-mapped addresses come out as plain IPv4
+                peer("10.0.0.9:6882"),
+            ],
+            dropped: vec![peer("10.0.0.2:6881"), peer("[2001:db8::2]:6883")],
+        }
+    );
+}
+
+#[test]
+fn missing_lists_are_empty() {
+    let payload = pex_payload(vec![("added6", compact(&["[::1]:6881"]))]);
+    assert_eq!(
+        parse_pex(&payload).unwrap(),
+        PexMessage {
+            added: vec![peer("[::1]:6881")],
+            dropped: Vec::new(),
+        }
+    );
+    assert_eq!(
+        parse_pex(&pex_payload(Vec::new())).unwrap(),
+        PexMessage::default()
+    );
+}
+
+#[test]
+fn rejects_malformed_messages() {
+    // Not a dictionary
+    assert!(parse_pex(b"li1ee").is_err());
+    // Not bencode at all
+    assert!(parse_pex(b"added").is_err());
+    // A compact IPv4 list must come in 6 byte entries, IPv6 in 18
+    assert!(parse_pex(&pex_payload(vec![("added", Value::Bytes(vec![1; 7]))])).is_err());
+    assert!(parse_pex(&pex_payload(vec![("added6", Value::Bytes(vec![1; 6]))])).is_err());
+}
@@ -0,0 +1,308 @@
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use pirate::{
+    events::event_bus::EventBus,
+    network::port_mapping::{
+        natpmp::{PmpGateway, PmpVersion},
+        GatewaySearch, MappingMethod, PortMapper, PortMappingStatus, Transport,
+    },
+};
+use std::{
+    convert::Infallible,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::net::UdpSocket;
+
+// Finding no gateway takes all the NAT-PMP, PCP and SSDP timeouts
+const TEST_TIMEOUT: Duration = Duration::from_secs(20);
+const EXTERNAL_IP: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 5);
+
+fn localhost() -> SocketAddr {
+    "127.0.0.1:0".parse().unwrap()
+}
+
+// Requests a fake gateway received, in order
+type Requests = Arc<Mutex<Vec<String>>>;
+
+// Answers NAT-PMP requests, and PCP ones with "unsupported version" unless `pcp` is set
+async fn fake_pmp_gateway(pcp: bool) -> (SocketAddr, Requests) {
+    let socket = UdpSocket::bind(localhost()).await.unwrap();
+    let address = socket.local_addr().unwrap();
+    let requests = Requests::default();
+    let log = Arc::clone(&requests);
+    tokio::spawn(async move {
+        let mut buffer = [0u8; 1100];
+        loop {
+            let (size, from) = socket.recv_from(&mut buffer).await.unwrap();
+            let request = &buffer[..size];
+            let reply = match (request[0], pcp) {
+                (2, true) => pcp_reply(request, &log),
+                (2, false) => vec![0, 0x80 | request[1], 0, 1, 0, 0, 0, 1],
+                _ => natpmp_reply(request, &log),
+            };
+            socket.send_to(&reply, from).await.unwrap();
+        }
+    });
+    (address, requests)
+}
+
+fn natpmp_reply(request: &[u8], log: &Requests) -> Vec<u8> {
+    let mut reply = vec![0, 0x80 | request[1], 0, 0, 0, 0, 0, 1];
+    if request[1] == 0 {
+        reply.extend_from_slice(&EXTERNAL_IP.octets());
+        return reply;
+    }
+    let lifetime = u32::from_be_bytes(request[8..12].try_into().unwrap());
+    log.lock().unwrap().push(format!(
+        "map {} {} lifetime {}",
+        if request[1] == 2 { "TCP" } else { "UDP" },
+        u16::from_be_bytes([request[4], request[5]]),
+        lifetime
+    ));
+    // Internal port, then the requested external port is granted
+    reply.extend_from_slice(&request[4..8]);
+    reply.extend_from_slice(&lifetime.to_be_bytes());
+    reply
+}
+
+fn pcp_reply(request: &[u8], log: &Requests) -> Vec<u8> {
+    let mut reply = vec![2, 0x80 | request[1], 0, 0];
+    reply.extend_from_slice(&request[4..8]);
+    reply.extend_from_slice(&[0u8; 16]);
+    if request[1] == 1 {
+        let map = &request[24..60];
+        log.lock().unwrap().push(format!(
+            "map protocol {} {} lifetime {}",
+            map[12],
+            u16::from_be_bytes([map[16], map[17]]),
+            u32::from_be_bytes(request[4..8].try_into().unwrap())
+        ));
+        reply.extend_from_slice(&map[..20]);
+        reply.extend_from_slice(&EXTERNAL_IP.to_ipv6_mapped().octets());
+    }
+    reply
+}
+
+// An Internet Gateway Device: an SSDP responder pointing at an HTTP server with the description
+// and the SOAP control url. Only permanent mappings are accepted, like on many real routers.
+async fn fake_upnp_gateway() -> (SocketAddr, Requests) {
+    let requests = Requests::default();
+    let log = Arc::clone(&requests);
+    let make_service = make_service_fn(move |_| {
+        let log = Arc::clone(&log);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let log = Arc::clone(&log);
+                async move { Ok::<_, Infallible>(upnp_response(request, &log).await) }
+            }))
+        }
+    });
+    let server = Server::bind(&localhost()).serve(make_service);
+    let http_address = server.local_addr();
+    tokio::spawn(server);
+
+    let ssdp = UdpSocket::bind(localhost()).await.unwrap();
+    let ssdp_address = ssdp.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buffer = [0u8; 2048];
+        loop {
+            let (_, from) = ssdp.recv_from(&mut buffer).await.unwrap();
+            let reply = format!(
+                "HTTP/1.1 200 OK\r\nST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\
+                 Location: http://{}/description.xml\r\n\r\n",
+                http_address
+            );
+            ssdp.send_to(reply.as_bytes(), from).await.unwrap();
+        }
+    });
+    (ssdp_address, requests)
+}
+
+async fn upnp_response(request: Request<Body>, log: &Requests) -> Response<Body> {
+    if request.uri().path() == "/description.xml" {
+        return Response::new(Body::from(
+            "<?xml version=\"1.0\"?><root><device><serviceList><service>\
+             <serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>\
+             <controlURL>/l3f</controlURL></service></serviceList><deviceList><device>\
+             <serviceList><service>\
+             <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>\
+             <controlURL>/control</controlURL></service></serviceList>\
+             </device></deviceList></device></root>",
+        ));
+    }
+
+    let action = request
+        .headers()
+        .get("SOAPAction")
+        .and_then(|action| action.to_str().ok())
+        .and_then(|action| action.trim_matches('"').split('#').nth(1))
+        .unwrap_or_default()
+        .to_string();
+    let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+    let body = String::from_utf8_lossy(&body).to_string();
+    let argument = |name: &str| {
+        let start = body.find(&format!("<{}>", name)).unwrap() + name.len() + 2;
+        let end = body.find(&format!("</{}>", name)).unwrap();
+        body[start..end].to_string()
+    };
+
+    match action.as_str() {
+        "GetExternalIPAddress" => Response::new(Body::from(format!(
+            "<s:Envelope><s:Body><u:GetExternalIPAddressResponse>\
+             <NewExternalIPAddress>{}</NewExternalIPAddress>\
+             </u:GetExternalIPAddressResponse></s:Body></s:Envelope>",
+            EXTERNAL_IP
+        ))),
+        "AddPortMapping" if argument("NewLeaseDuration") != "0" => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from(
+                "<s:Envelope><s:Body><s:Fault><detail><UPnPError><errorCode>725</errorCode>\
+                 <errorDescription>OnlyPermanentLeasesSupported</errorDescription>\
+                 </UPnPError></detail></s:Fault></s:Body></s:Envelope>",
+            ))
+            .unwrap(),
+        "AddPortMapping" | "DeletePortMapping" => {
+            log.lock().unwrap().push(format!(
+                "{} {} {}",
+                action,
+                argument("NewProtocol"),
+                argument("NewExternalPort")
+            ));
+            Response::new(Body::from("<s:Envelope><s:Body/></s:Envelope>"))
+        }
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap(),
+    }
+}
+
+async fn mapped_status(port_mapper: &PortMapper) -> PortMappingStatus {
+    tokio::time::timeout(TEST_TIMEOUT, async {
+        loop {
+            let status = port_mapper.status().await;
+            if status.method.is_some() || status.error.is_some() {
+                return status;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("Port was never mapped")
+}
+
+fn requests(requests: &Requests) -> Vec<String> {
+    requests.lock().unwrap().clone()
+}
+
+#[tokio::test]
+async fn maps_and_removes_ports_with_natpmp() {
+    let (gateway, log) = fake_pmp_gateway(false).await;
+    let unused_ssdp = localhost();
+    let port_mapper = PortMapper::start(
+        6881,
+        GatewaySearch {
+            pmp_gateway: Some(gateway),
+            ssdp_address: unused_ssdp,
+        },
+        EventBus::new(),
+    );
+
+    let status = mapped_status(&port_mapper).await;
+    assert_eq!(status.error, None);
+    assert_eq!(status.method, Some(MappingMethod::NatPmp));
+    assert_eq!(status.external_ip, Some(IpAddr::V4(EXTERNAL_IP)));
+    let mapped: Vec<(Transport, u16)> = status
+        .mappings
+        .iter()
+        .map(|mapping| (mapping.transport, mapping.external_port))
+        .collect();
+    assert_eq!(mapped, vec![(Transport::Tcp, 6881), (Transport::Udp, 6881)]);
+
+    port_mapper.shutdown().await;
+    assert_eq!(
+        requests(&log),
+        vec![
+            "map TCP 6881 lifetime 7200",
+            "map UDP 6881 lifetime 7200",
+            "map TCP 6881 lifetime 0",
+            "map UDP 6881 lifetime 0",
+        ]
+    );
+    assert_eq!(port_mapper.status().await, PortMappingStatus::default());
+}
+
+#[tokio::test]
+async fn maps_ports_with_pcp() {
+    let (address, log) = fake_pmp_gateway(true).await;
+    let gateway = PmpGateway::probe(address).await.unwrap();
+    assert_eq!(gateway.version(), PmpVersion::Pcp);
+
+    let mapped = gateway
+        .add_mapping(Transport::Udp, 6881, 6881, 3600)
+        .await
+        .unwrap();
+    assert_eq!(mapped.external_port, 6881);
+    assert_eq!(mapped.lifetime, 3600);
+    assert_eq!(mapped.external_ip, Some(IpAddr::V4(EXTERNAL_IP)));
+
+    gateway.remove_mapping(Transport::Udp, 6881).await.unwrap();
+    assert_eq!(
+        requests(&log),
+        vec![
+            "map protocol 17 6881 lifetime 3600",
+            "map protocol 17 6881 lifetime 0",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn maps_and_removes_ports_with_upnp() {
+    let (ssdp_address, log) = fake_upnp_gateway().await;
+    let port_mapper = PortMapper::start(
+        6881,
+        GatewaySearch {
+            pmp_gateway: None,
+            ssdp_address,
+        },
+        EventBus::new(),
+    );
+
+    let status = mapped_status(&port_mapper).await;
+    assert_eq!(status.error, None);
+    assert_eq!(status.method, Some(MappingMethod::Upnp));
+    assert_eq!(status.external_ip, Some(IpAddr::V4(EXTERNAL_IP)));
+    assert_eq!(status.mappings.len(), 2);
+
+    port_mapper.shutdown().await;
+    assert_eq!(
+        requests(&log),
+        vec![
+            "AddPortMapping TCP 6881",
+            "AddPortMapping UDP 6881",
+            "DeletePortMapping TCP 6881",
+            "DeletePortMapping UDP 6881",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn reports_when_no_gateway_answers() {
+    let silent = UdpSocket::bind(localhost()).await.unwrap();
+    let port_mapper = PortMapper::start(
+        6881,
+        GatewaySearch {
+            pmp_gateway: Some(silent.local_addr().unwrap()),
+            ssdp_address: silent.local_addr().unwrap(),
+        },
+        EventBus::new(),
+    );
+
+    let status = mapped_status(&port_mapper).await;
+    assert_eq!(status.method, None);
+    assert!(status.error.is_some());
+}
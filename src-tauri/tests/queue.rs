@@ -0,0 +1,104 @@
+use pirate::torrent_management::queue::{
+    move_position, plan_queue, QueueAction, QueueEntry, QueueLimits, QueueMove,
+};
+
+const LIMITS: QueueLimits = QueueLimits {
+    downloads: 2,
+    seeds: 1,
+    total: 3,
+};
+
+fn queued(seeding: bool) -> QueueEntry {
+    QueueEntry {
+        active: false,
+        seeding,
+        slow: false,
+    }
+}
+
+fn running(seeding: bool) -> QueueEntry {
+    QueueEntry {
+        active: true,
+        seeding,
+        slow: false,
+    }
+}
+
+#[test]
+fn starts_torrents_in_queue_order_until_the_slots_are_full() {
+    let entries = [queued(false), queued(false), queued(false), queued(true)];
+    assert_eq!(
+        plan_queue(&entries, &LIMITS),
+        vec![
+            QueueAction::Start,
+            QueueAction::Start,
+            QueueAction::Keep,
+            QueueAction::Start,
+        ]
+    );
+}
+
+#[test]
+fn torrents_further_up_take_over_running_slots() {
+    // The first one was just moved to the top
+    let entries = [queued(false), running(false), running(false)];
+    assert_eq!(
+        plan_queue(&entries, &LIMITS),
+        vec![QueueAction::Start, QueueAction::Keep, QueueAction::Queue]
+    );
+}
+
+#[test]
+fn the_total_limits_downloads_and_seeds_together() {
+    let limits = QueueLimits {
+        downloads: 0,
+        seeds: 0,
+        total: 2,
+    };
+    let entries = [running(true), queued(false), queued(false)];
+    assert_eq!(
+        plan_queue(&entries, &limits),
+        vec![QueueAction::Keep, QueueAction::Start, QueueAction::Keep]
+    );
+}
+
+#[test]
+fn slow_torrents_keep_running_without_a_slot() {
+    let stalled = QueueEntry {
+        slow: true,
+        ..running(false)
+    };
+    let entries = [stalled, stalled, queued(false), queued(false)];
+    assert_eq!(
+        plan_queue(&entries, &LIMITS),
+        vec![
+            QueueAction::Keep,
+            QueueAction::Keep,
+            QueueAction::Start,
+            QueueAction::Start,
+        ]
+    );
+}
+
+#[test]
+fn no_limits_start_everything() {
+    let limits = QueueLimits {
+        downloads: 0,
+        seeds: 0,
+        total: 0,
+    };
+    let entries = vec![queued(false); 100];
+    assert!(plan_queue(&entries, &limits)
+        .iter()
+        .all(|action| *action == QueueAction::Start));
+}
+
+#[test]
+fn moves_stay_within_the_queue() {
+    assert_eq!(move_position(2, 5, QueueMove::Up), 1);
+    assert_eq!(move_position(0, 5, QueueMove::Up), 0);
+    assert_eq!(move_position(2, 5, QueueMove::Down), 3);
+    assert_eq!(move_position(4, 5, QueueMove::Down), 4);
+    assert_eq!(move_position(3, 5, QueueMove::Top), 0);
+    assert_eq!(move_position(1, 5, QueueMove::Bottom), 4);
+}
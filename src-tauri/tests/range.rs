@@ -0,0 +1,93 @@
+use pirate::streaming::range::{parse_range_header, ByteRange, RangeError};
+
+const FILE_LENGTH: u64 = 1000;
+
+fn range(start: u64, end: u64) -> Result<ByteRange, RangeError> {
+    Ok(ByteRange { start, end })
+}
+
+#[test]
+fn parses_closed_ranges() {
+    assert_eq!(
+        parse_range_header("bytes=0-499", FILE_LENGTH),
+        range(0, 499)
+    );
+    assert_eq!(
+        parse_range_header(" bytes=10-10 ", FILE_LENGTH),
+        range(10, 10)
+    );
+    // The end is clamped to the file
+    assert_eq!(
+        parse_range_header("bytes=900-5000", FILE_LENGTH),
+        range(900, 999)
+    );
+}
+
+#[test]
+fn parses_open_ended_ranges() {
+    assert_eq!(
+        parse_range_header("bytes=500-", FILE_LENGTH),
+        range(500, 999)
+    );
+    assert_eq!(
+        parse_range_header("bytes=999-", FILE_LENGTH),
+        range(999, 999)
+    );
+}
+
+#[test]
+fn parses_suffix_ranges() {
+    assert_eq!(
+        parse_range_header("bytes=-200", FILE_LENGTH),
+        range(800, 999)
+    );
+    // A suffix longer than the file asks for all of it
+    assert_eq!(
+        parse_range_header("bytes=-5000", FILE_LENGTH),
+        range(0, 999)
+    );
+    assert_eq!(
+        parse_range_header("bytes=-0", FILE_LENGTH),
+        Err(RangeError::Unsatisfiable)
+    );
+}
+
+#[test]
+fn only_the_first_of_several_ranges_is_used() {
+    assert_eq!(
+        parse_range_header("bytes=0-9, 20-29", FILE_LENGTH),
+        range(0, 9)
+    );
+}
+
+#[test]
+fn rejects_malformed_ranges() {
+    for header in [
+        "0-499",
+        "items=0-499",
+        "bytes=",
+        "bytes=-",
+        "bytes=abc-",
+        "bytes=1-x",
+        "bytes=500-100",
+    ] {
+        assert_eq!(
+            parse_range_header(header, FILE_LENGTH),
+            Err(RangeError::Malformed),
+            "{}",
+            header
+        );
+    }
+}
+
+#[test]
+fn rejects_ranges_past_the_end_of_the_file() {
+    assert_eq!(
+        parse_range_header("bytes=1000-", FILE_LENGTH),
+        Err(RangeError::Unsatisfiable)
+    );
+    assert_eq!(
+        parse_range_header("bytes=0-10", 0),
+        Err(RangeError::Unsatisfiable)
+    );
+}
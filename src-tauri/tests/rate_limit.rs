@@ -0,0 +1,69 @@
+use pirate::{
+    config::Config,
+    network::rate_limit::{RateLimiter, RateLimits},
+};
+use std::{sync::Arc, time::Duration};
+use tokio::{sync::RwLock, time::Instant};
+
+fn limits(max_download_rate: u64) -> (Arc<RwLock<Config>>, RateLimits) {
+    let config = Arc::new(RwLock::new(Config {
+        max_download_rate,
+        ..Config::default()
+    }));
+    let limits = RateLimits::new(Arc::clone(&config));
+    (config, limits)
+}
+
+async fn timed(limiter: &RateLimiter, bytes: u64) -> Duration {
+    let started = Instant::now();
+    limiter.acquire(bytes).await;
+    started.elapsed()
+}
+
+#[tokio::test(start_paused = true)]
+async fn unlimited_never_waits() {
+    let (_, limits) = limits(0);
+    assert_eq!(timed(&limits.download, 1 << 30).await, Duration::ZERO);
+    assert_eq!(timed(&limits.upload, 1 << 30).await, Duration::ZERO);
+}
+
+#[tokio::test(start_paused = true)]
+async fn waits_for_the_bytes_to_fit_the_rate() {
+    let (_, limits) = limits(1000);
+    assert_eq!(timed(&limits.download, 1000).await, Duration::from_secs(1));
+    assert_eq!(
+        timed(&limits.download, 500).await,
+        Duration::from_millis(500)
+    );
+    // Uploads have their own limit
+    assert_eq!(timed(&limits.upload, 1 << 30).await, Duration::ZERO);
+
+    // An idle second refills the bucket, but not beyond a second's worth
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    assert_eq!(timed(&limits.download, 1000).await, Duration::ZERO);
+    assert_eq!(
+        timed(&limits.download, 250).await,
+        Duration::from_millis(250)
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn every_clone_shares_the_bucket() {
+    let (_, limits) = limits(1000);
+    let other = limits.clone();
+    let (first, second) = tokio::join!(timed(&limits.download, 1000), timed(&other.download, 1000));
+    // Together they moved two seconds' worth
+    assert_eq!(first.max(second), Duration::from_secs(2));
+}
+
+#[tokio::test(start_paused = true)]
+async fn limit_changes_apply_right_away() {
+    let (config, limits) = limits(1000);
+    config.write().await.max_download_rate = 4000;
+    assert_eq!(
+        timed(&limits.download, 2000).await,
+        Duration::from_millis(500)
+    );
+    config.write().await.max_download_rate = 0;
+    assert_eq!(timed(&limits.download, 1 << 30).await, Duration::ZERO);
+}
@@ -0,0 +1,115 @@
+use pirate::{
+    app_state::AppState,
+    config::Config,
+    rpc::{
+        client::{RpcClient, RpcClientError},
+        dispatch::dispatch,
+        protocol::{COMMAND_ERROR, INVALID_PARAMS, METHOD_NOT_FOUND},
+        server::start_rpc_server,
+    },
+};
+use serde_json::{json, Value};
+use std::{path::Path, sync::Arc};
+
+async fn state(download_directory: &Path) -> Arc<AppState> {
+    let configuration = Config {
+        listen_port: 0,
+        streaming_enabled: false,
+        utp_enabled: false,
+        lsd_enabled: false,
+        port_mapping_enabled: false,
+        download_directory: Some(download_directory.to_path_buf()),
+        ..Config::default()
+    };
+    Arc::new(AppState::new(configuration, None).await.unwrap())
+}
+
+fn torrent_file(directory: &Path) -> String {
+    let mut torrent =
+        b"d4:infod6:lengthi16e4:name9:movie.bin12:piece lengthi16e6:pieces20:".to_vec();
+    torrent.extend([0; 20]);
+    torrent.extend(b"ee");
+    let path = directory.join("movie.torrent");
+    std::fs::write(&path, torrent).unwrap();
+    path.to_string_lossy().to_string()
+}
+
+#[tokio::test]
+async fn calls_without_the_token_are_refused() {
+    let directory = tempfile::tempdir().unwrap();
+    let state = state(directory.path()).await;
+    let address = start_rpc_server(state, "127.0.0.1:0", "secret".to_string())
+        .await
+        .unwrap()
+        .to_string();
+
+    for token in ["", "wrong", "secret2", "Secret"] {
+        let result = RpcClient::new(&address, token.to_string())
+            .call::<Value>("list_torrents", Value::Null)
+            .await;
+        assert!(
+            matches!(result, Err(RpcClientError::Unauthorized)),
+            "{:?}",
+            token
+        );
+    }
+    let torrents: Value = RpcClient::new(&address, "secret".to_string())
+        .call("list_torrents", Value::Null)
+        .await
+        .unwrap();
+    assert_eq!(torrents, json!([]));
+}
+
+#[tokio::test]
+async fn methods_run_the_command_of_the_same_name() {
+    let directory = tempfile::tempdir().unwrap();
+    let state = state(directory.path()).await;
+
+    // The frontend's camelCase argument names work as well
+    let params = json!({ "torrentFile": torrent_file(directory.path()) });
+    let torrent_hash = dispatch(&state, "add_torrent", params).await.unwrap();
+    let torrent_hash = torrent_hash.as_str().unwrap().to_string();
+
+    let torrents = dispatch(&state, "list_torrents", Value::Null)
+        .await
+        .unwrap();
+    assert_eq!(torrents[0]["info_hash"], json!(torrent_hash));
+    assert_eq!(torrents[0]["name"], json!("movie.bin"));
+
+    let params = json!({ "torrent_hash": torrent_hash });
+    let details = dispatch(&state, "get_torrent_details", params)
+        .await
+        .unwrap();
+    assert_eq!(details["name"], json!("movie.bin"));
+    assert_eq!(details["piece_count"], json!(1));
+
+    let params = json!({ "torrent_hash": torrent_hash, "delete_data": false });
+    dispatch(&state, "remove_torrent", params).await.unwrap();
+    let torrents = dispatch(&state, "list_torrents", Value::Null)
+        .await
+        .unwrap();
+    assert_eq!(torrents, json!([]));
+}
+
+#[tokio::test]
+async fn failures_keep_their_json_rpc_codes() {
+    let directory = tempfile::tempdir().unwrap();
+    let state = state(directory.path()).await;
+
+    let error = dispatch(&state, "format_disk", Value::Null)
+        .await
+        .unwrap_err();
+    assert_eq!(error.code, METHOD_NOT_FOUND);
+
+    let error = dispatch(&state, "get_torrent_details", Value::Null)
+        .await
+        .unwrap_err();
+    assert_eq!(error.code, INVALID_PARAMS);
+
+    let params = json!({ "torrent_hash": "00".repeat(20) });
+    let error = dispatch(&state, "get_torrent_details", params)
+        .await
+        .unwrap_err();
+    assert_eq!(error.code, COMMAND_ERROR);
+    assert_eq!(error.message, "Torrent not found");
+}
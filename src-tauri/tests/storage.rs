@@ -0,0 +1,389 @@
+use fs2::FileExt;
+use pirate::{
+    config::AllocationMode,
+    storage::{
+        backend::{MoveConflict, SharedStorage, Storage},
+        file_layout::{FileEntry, FileLayout},
+        fs_storage::FsStorage,
+        memory_storage::MemoryStorage,
+        piece_hashes::PieceHashes,
+        verify::verify_pieces,
+    },
+};
+use sha1::{Digest, Sha1};
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+const PIECE_LENGTH: u64 = 8;
+
+// Two files with a pad file aligning the second one to a piece:
+// a.bin is bytes 0..10, the padding 10..16 and b/c.bin 16..36
+fn layout(root: &Path) -> FileLayout {
+    let file = |path: PathBuf, length, offset, padding| FileEntry {
+        path,
+        length,
+        offset,
+        padding,
+    };
+    FileLayout {
+        root: root.to_path_buf(),
+        is_multi_file: true,
+        files: vec![
+            file(root.join("a.bin"), 10, 0, false),
+            file(root.join(".pad").join("6"), 6, 10, true),
+            file(root.join("b").join("c.bin"), 20, 16, false),
+        ],
+        piece_length: PIECE_LENGTH,
+        total_length: 36,
+    }
+}
+
+// What a complete download holds, pad file zeros included
+fn contents() -> Vec<u8> {
+    let mut contents: Vec<u8> = (1..=10).collect();
+    contents.extend_from_slice(&[0; 6]);
+    contents.extend(101..=120);
+    contents
+}
+
+fn pieces() -> Vec<Vec<u8>> {
+    contents()
+        .chunks(PIECE_LENGTH as usize)
+        .map(<[u8]>::to_vec)
+        .collect()
+}
+
+fn write_everything(storage: &dyn Storage) {
+    for (index, piece) in pieces().iter().enumerate() {
+        storage.write_block(index as u32, 0, piece).unwrap();
+    }
+}
+
+// Every backend must behave the same
+fn backends(root: &Path) -> Vec<Box<dyn Storage>> {
+    vec![
+        Box::new(FsStorage::new(layout(root))),
+        Box::new(MemoryStorage::new(layout(root))),
+    ]
+}
+
+#[test]
+fn reads_back_blocks_across_file_boundaries() {
+    let directory = tempfile::tempdir().unwrap();
+    for storage in backends(directory.path()) {
+        write_everything(&*storage);
+
+        // Piece 1 ends a.bin and is padding after that
+        assert_eq!(
+            storage.read_block(1, 0, 8).unwrap(),
+            vec![9, 10, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            storage.read_block(2, 3, 4).unwrap(),
+            vec![104, 105, 106, 107]
+        );
+        // The last piece is short
+        assert_eq!(
+            storage.read_block(4, 0, 4).unwrap(),
+            vec![117, 118, 119, 120]
+        );
+        storage.flush().unwrap();
+    }
+
+    // Padding never reaches the disk
+    let root = directory.path();
+    assert_eq!(
+        std::fs::read(root.join("a.bin")).unwrap(),
+        (1..=10).collect::<Vec<u8>>()
+    );
+    assert_eq!(
+        std::fs::read(root.join("b").join("c.bin")).unwrap(),
+        (101..=120).collect::<Vec<u8>>()
+    );
+    assert!(!root.join(".pad").exists());
+}
+
+#[test]
+fn blocks_can_arrive_in_any_order() {
+    let directory = tempfile::tempdir().unwrap();
+    for storage in backends(directory.path()) {
+        let pieces = pieces();
+        for index in [4, 2, 0, 3, 1] {
+            storage
+                .write_block(index, 4, &pieces[index as usize][4..])
+                .unwrap();
+            storage
+                .write_block(index, 0, &pieces[index as usize][..4])
+                .unwrap();
+        }
+        assert_eq!(storage.read_block(0, 0, 8).unwrap(), pieces[0]);
+        assert_eq!(storage.read_block(3, 0, 8).unwrap(), pieces[3]);
+    }
+}
+
+#[test]
+fn missing_blocks_are_errors() {
+    let directory = tempfile::tempdir().unwrap();
+    for storage in backends(directory.path()) {
+        assert_eq!(
+            storage.read_block(0, 0, 8).unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+
+        storage.write_block(2, 0, &pieces()[2]).unwrap();
+        assert_eq!(
+            storage.read_block(3, 0, 8).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+}
+
+#[test]
+fn rejects_blocks_outside_their_piece() {
+    let directory = tempfile::tempdir().unwrap();
+    for storage in backends(directory.path()) {
+        assert_eq!(
+            storage.write_block(0, 4, &[0; 8]).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            storage.read_block(4, 0, 8).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+}
+
+#[test]
+fn file_sizes_match_once_everything_is_written() {
+    let directory = tempfile::tempdir().unwrap();
+    for storage in backends(directory.path()) {
+        assert!(!storage.check_file_sizes().unwrap());
+        storage.write_block(0, 0, &pieces()[0]).unwrap();
+        assert!(!storage.check_file_sizes().unwrap());
+        write_everything(&*storage);
+        assert!(storage.check_file_sizes().unwrap());
+    }
+}
+
+#[test]
+fn moves_and_deletes_the_files() {
+    let directory = tempfile::tempdir().unwrap();
+    let from = directory.path().join("from");
+    let to = directory.path().join("to");
+    for storage in backends(&from) {
+        write_everything(&*storage);
+        let mut progress = (0, 0);
+        storage
+            .move_storage(&layout(&to), MoveConflict::Fail, &mut |done, total| {
+                progress = (done, total)
+            })
+            .unwrap();
+        // Padding isn't moved
+        assert_eq!(progress, (30, 30));
+
+        assert_eq!(storage.layout(), layout(&to));
+        assert_eq!(storage.read_block(2, 0, 8).unwrap(), pieces()[2]);
+        assert!(storage.check_file_sizes().unwrap());
+
+        storage.delete().unwrap();
+        assert!(!storage.check_file_sizes().unwrap());
+        // Deleting twice is fine
+        storage.delete().unwrap();
+    }
+    // Emptied directories are removed
+    assert!(!from.join("b").exists());
+    assert!(!to.join("b").exists());
+}
+
+#[test]
+fn sparse_and_full_files_start_at_their_final_size() {
+    let directory = tempfile::tempdir().unwrap();
+    for mode in [AllocationMode::Sparse, AllocationMode::Full] {
+        for storage in backends(&directory.path().join(format!("{:?}", mode))) {
+            storage.allocate(mode).unwrap();
+            assert!(storage.check_file_sizes().unwrap());
+
+            write_everything(&*storage);
+            assert_eq!(storage.read_block(2, 0, 8).unwrap(), pieces()[2]);
+            // Allocating again keeps what was written
+            storage.allocate(mode).unwrap();
+            assert_eq!(storage.read_block(2, 0, 8).unwrap(), pieces()[2]);
+        }
+    }
+
+    let file = std::fs::File::open(directory.path().join("Full").join("b").join("c.bin")).unwrap();
+    assert!(file.allocated_size().unwrap() >= 20);
+}
+
+#[test]
+fn compact_files_grow_as_pieces_arrive() {
+    let directory = tempfile::tempdir().unwrap();
+    for storage in backends(directory.path()) {
+        storage.allocate(AllocationMode::Compact).unwrap();
+        assert!(!storage.check_file_sizes().unwrap());
+
+        storage.write_block(0, 0, &pieces()[0]).unwrap();
+        assert!(!storage.check_file_sizes().unwrap());
+    }
+    assert_eq!(
+        std::fs::metadata(directory.path().join("a.bin"))
+            .unwrap()
+            .len(),
+        8
+    );
+    assert!(!directory.path().join("b").join("c.bin").exists());
+}
+
+#[test]
+fn refuses_to_allocate_more_than_the_disk_holds() {
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("huge.bin");
+    let length = 1 << 60;
+    let storage = FsStorage::new(FileLayout {
+        root: path.clone(),
+        is_multi_file: false,
+        files: vec![FileEntry {
+            path: path.clone(),
+            length,
+            offset: 0,
+            padding: false,
+        }],
+        piece_length: 1 << 20,
+        total_length: length,
+    });
+
+    for mode in [
+        AllocationMode::Sparse,
+        AllocationMode::Full,
+        AllocationMode::Compact,
+    ] {
+        assert_eq!(
+            storage.allocate(mode).unwrap_err().kind(),
+            ErrorKind::StorageFull
+        );
+    }
+    assert!(!path.exists());
+}
+
+// a.bin is already at the destination, with other contents
+fn conflicting_move(directory: &Path, conflict: MoveConflict) -> std::io::Result<FsStorage> {
+    let from = directory.join("from");
+    let to = directory.join("to");
+    let storage = FsStorage::new(layout(&from));
+    write_everything(&storage);
+    std::fs::create_dir_all(&to).unwrap();
+    std::fs::write(to.join("a.bin"), b"theirs").unwrap();
+    storage
+        .move_storage(&layout(&to), conflict, &mut |_, _| ())
+        .map(|()| storage)
+}
+
+#[test]
+fn handles_files_already_at_the_destination() {
+    let directory = tempfile::tempdir().unwrap();
+    let (from, to) = (directory.path().join("from"), directory.path().join("to"));
+
+    let refused = conflicting_move(directory.path(), MoveConflict::Fail).err();
+    assert_eq!(refused.map(|e| e.kind()), Some(ErrorKind::AlreadyExists));
+    assert!(from.join("b").join("c.bin").exists());
+    assert!(!to.join("b").exists());
+    std::fs::remove_dir_all(&to).unwrap();
+
+    let storage = conflicting_move(directory.path(), MoveConflict::KeepExisting).unwrap();
+    assert_eq!(std::fs::read(to.join("a.bin")).unwrap(), b"theirs");
+    assert_eq!(
+        std::fs::read(from.join("a.bin")).unwrap(),
+        (1..=10).collect::<Vec<u8>>()
+    );
+    assert_eq!(storage.read_block(2, 0, 8).unwrap(), pieces()[2]);
+    storage.delete().unwrap();
+    std::fs::remove_dir_all(&from).unwrap();
+
+    let storage = conflicting_move(directory.path(), MoveConflict::Replace).unwrap();
+    assert_eq!(storage.read_block(0, 0, 8).unwrap(), pieces()[0]);
+    assert!(!from.exists());
+}
+
+#[test]
+fn puts_moved_files_back_when_the_move_fails() {
+    let directory = tempfile::tempdir().unwrap();
+    let (from, to) = (directory.path().join("from"), directory.path().join("to"));
+    let storage = FsStorage::new(layout(&from));
+    write_everything(&storage);
+    // a.bin moves fine, b/c.bin can't replace a directory
+    std::fs::create_dir_all(to.join("b").join("c.bin")).unwrap();
+
+    assert!(storage
+        .move_storage(&layout(&to), MoveConflict::Replace, &mut |_, _| ())
+        .is_err());
+    assert_eq!(storage.layout(), layout(&from));
+    assert!(!to.join("a.bin").exists());
+    assert_eq!(storage.read_block(0, 0, 8).unwrap(), pieces()[0]);
+    assert_eq!(storage.read_block(2, 0, 8).unwrap(), pieces()[2]);
+}
+
+#[test]
+fn renames_part_files_in_place() {
+    let directory = tempfile::tempdir().unwrap();
+    let root = directory.path();
+    let mut downloading = layout(root);
+    for file in &mut downloading.files {
+        file.path.set_extension("bin.part");
+    }
+    let storage = FsStorage::new(downloading);
+    write_everything(&storage);
+    assert!(root.join("b").join("c.bin.part").exists());
+
+    storage
+        .move_storage(&layout(root), MoveConflict::Fail, &mut |_, _| ())
+        .unwrap();
+    assert!(!root.join("b").join("c.bin.part").exists());
+    assert_eq!(storage.read_block(2, 0, 8).unwrap(), pieces()[2]);
+    assert!(storage.check_file_sizes().unwrap());
+}
+
+#[test]
+fn memory_storage_keeps_files_by_path() {
+    let root = Path::new("/nowhere");
+    let storage = MemoryStorage::new(layout(root));
+    write_everything(&storage);
+
+    assert_eq!(
+        storage.file(&root.join("a.bin")),
+        Some((1..=10).collect::<Vec<u8>>())
+    );
+    assert_eq!(storage.file(&root.join(".pad").join("6")), None);
+}
+
+#[tokio::test]
+async fn verifies_pieces_from_any_storage() {
+    let storage = Arc::new(MemoryStorage::new(layout(Path::new("/nowhere"))));
+    let pieces = pieces();
+    for index in [0, 1, 3] {
+        storage
+            .write_block(index, 0, &pieces[index as usize])
+            .unwrap();
+    }
+    // Piece 3 is corrupt
+    storage.write_block(3, 0, &[0xff]).unwrap();
+
+    let piece_hashes = PieceHashes {
+        v1: pieces
+            .iter()
+            .map(|piece| Sha1::digest(piece).into())
+            .collect(),
+        v2: Vec::new(),
+    };
+    let storage: SharedStorage = storage;
+    let mut checked = 0;
+    let verified = verify_pieces(&storage, &piece_hashes, |count| checked = count)
+        .await
+        .unwrap();
+
+    assert_eq!(checked, 5);
+    let verified: Vec<bool> = verified.iter().map(|bit| *bit).collect();
+    assert_eq!(verified, vec![true, true, false, false, false]);
+}
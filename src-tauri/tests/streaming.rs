@@ -0,0 +1,120 @@
+use bitvec::prelude::{BitVec, Lsb0};
+use hyper::{body::to_bytes, header, Body, Client, Request, StatusCode};
+use pirate::{
+    config::{AllocationMode, Config},
+    events::event_bus::EventBus,
+    parsing::parser::parse_error::parse_bencoded_torrent,
+    storage::{
+        disk_io::DiskIo, file_layout::FileLayout, memory_storage::MemoryStorage,
+        piece_hashes::PieceHashes,
+    },
+    streaming::server::{start_stream_server, stream_url},
+    torrent_management::{torrent::Torrent, torrent_manager::TorrentManager},
+};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{atomic::AtomicBool, Arc},
+};
+use tokio::sync::RwLock;
+
+// Files of 10 and 20 bytes in pieces of 16, the second file starts in the first piece
+fn multi_file_torrent() -> Vec<u8> {
+    let mut torrent = b"d4:infod5:filesl\
+        d6:lengthi10e4:pathl5:a.txteed6:lengthi20e4:pathl5:b.txteee\
+        4:name3:dir12:piece lengthi16e6:pieces40:"
+        .to_vec();
+    torrent.extend([7; 40]);
+    torrent.extend(b"ee");
+    torrent
+}
+
+fn data() -> Vec<u8> {
+    (0..30).collect()
+}
+
+// A finished torrent whose data only ever went through the disk cache into memory
+async fn stream_server() -> (SocketAddr, String) {
+    let mut metadata = parse_bencoded_torrent(multi_file_torrent()).unwrap();
+    metadata.file_path = PathBuf::from("/nowhere/dir");
+    let storage = MemoryStorage::new(FileLayout::from_metadata(&metadata));
+    let info_hash: [u8; 20] = metadata.info_hash.as_slice().try_into().unwrap();
+    let piece_hashes = PieceHashes::from_metadata(&metadata).unwrap();
+    let config = Arc::new(RwLock::new(Config::default()));
+    let torrent = Torrent::new(
+        info_hash,
+        30,
+        Arc::new(RwLock::new(Vec::new())),
+        Arc::new(RwLock::new(metadata)),
+        Arc::new(RwLock::new(BitVec::<u8, Lsb0>::repeat(true, 2))),
+        Arc::new(RwLock::new(HashMap::new())),
+        Arc::new(piece_hashes),
+        AtomicBool::new(false),
+        DiskIo::new(1, 16, 1 << 20).open(Arc::new(storage)),
+        AllocationMode::Sparse,
+        EventBus::new(),
+        Arc::clone(&config),
+        None,
+    );
+    let disk = torrent.disk();
+    for (piece_index, piece) in data().chunks(16).enumerate() {
+        disk.write_block(piece_index as u32, 0, piece.to_vec())
+            .await
+            .unwrap();
+    }
+
+    let torrent_hash = hex::encode(info_hash);
+    let mut manager = TorrentManager::new(EventBus::new(), config);
+    manager
+        .add_torrent(torrent_hash.clone(), Arc::new(RwLock::new(torrent)))
+        .await
+        .unwrap();
+    let address = start_stream_server(Arc::new(RwLock::new(manager)), "127.0.0.1:0")
+        .await
+        .unwrap();
+    (address, torrent_hash)
+}
+
+async fn get(url: &str, range: Option<&str>) -> (StatusCode, Vec<u8>) {
+    let mut request = Request::get(url);
+    if let Some(range) = range {
+        request = request.header(header::RANGE, range);
+    }
+    let response = Client::new()
+        .request(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    (
+        status,
+        to_bytes(response.into_body()).await.unwrap().to_vec(),
+    )
+}
+
+#[tokio::test]
+async fn files_are_read_through_the_torrent_storage() {
+    let (address, torrent_hash) = stream_server().await;
+
+    let (status, body) = get(&stream_url(&address, &torrent_hash, 0), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, data()[..10]);
+
+    let (status, body) = get(&stream_url(&address, &torrent_hash, 1), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, data()[10..]);
+}
+
+#[tokio::test]
+async fn ranges_may_span_pieces() {
+    let (address, torrent_hash) = stream_server().await;
+
+    let url = stream_url(&address, &torrent_hash, 1);
+    let (status, body) = get(&url, Some("bytes=4-9")).await;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body, data()[14..20]);
+
+    let (status, body) = get(&url, Some("bytes=-3")).await;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body, data()[27..]);
+}
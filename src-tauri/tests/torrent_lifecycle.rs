@@ -0,0 +1,185 @@
+use pirate::{
+    app_state::AppState,
+    commands::{
+        add_torrent, list_torrents, pause_torrent, remove_torrent, resume_torrent, start_torrent,
+        stop_torrent,
+    },
+    config::Config,
+    torrent_management::torrent_status::TorrentStatus,
+};
+use std::{path::Path, time::Duration};
+use tokio::{net::UdpSocket, sync::mpsc};
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+async fn state(download_directory: &Path) -> AppState {
+    let configuration = Config {
+        listen_port: 0,
+        streaming_enabled: false,
+        utp_enabled: false,
+        lsd_enabled: false,
+        port_mapping_enabled: false,
+        auto_managed: false,
+        download_directory: Some(download_directory.to_path_buf()),
+        ..Config::default()
+    };
+    AppState::new(configuration, None).await.unwrap()
+}
+
+// Answers announces without any peers and passes on the event of each one
+async fn tracker() -> (String, mpsc::UnboundedReceiver<Option<String>>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("udp://{}/announce", socket.local_addr().unwrap());
+    let (sender, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let mut buffer = vec![0; 4096];
+        while let Ok((length, from)) = socket.recv_from(&mut buffer).await {
+            let params = String::from_utf8_lossy(&buffer[..length]).to_string();
+            let event = url::form_urlencoded::parse(params.as_bytes())
+                .find(|(name, _)| name == "event")
+                .map(|(_, value)| value.to_string());
+            let _ = sender.send(event);
+            socket
+                .send_to(b"d8:intervali1800e5:peers0:e", from)
+                .await
+                .unwrap();
+        }
+    });
+    (url, receiver)
+}
+
+async fn next_event(events: &mut mpsc::UnboundedReceiver<Option<String>>) -> Option<String> {
+    tokio::time::timeout(TEST_TIMEOUT, events.recv())
+        .await
+        .expect("The tracker never heard from us")
+        .unwrap()
+}
+
+// Adds a single file torrent `name` announcing to `announce`, its file already in place
+async fn add(state: &AppState, directory: &Path, name: &str, announce: &str) -> String {
+    std::fs::write(directory.join(name), [1; 16]).unwrap();
+    let mut torrent = format!(
+        "d8:announce{}:{}4:infod6:lengthi16e4:name{}:{}12:piece lengthi16e6:pieces20:",
+        announce.len(),
+        announce,
+        name.len(),
+        name
+    )
+    .into_bytes();
+    torrent.extend([0; 20]);
+    torrent.extend(b"ee");
+    let torrent_file = directory.join(format!("{}.torrent", name));
+    std::fs::write(&torrent_file, torrent).unwrap();
+    add_torrent::handle(
+        state,
+        torrent_file.to_string_lossy().to_string(),
+        Default::default(),
+    )
+    .await
+    .unwrap()
+}
+
+async fn status(state: &AppState, torrent_hash: &str) -> TorrentStatus {
+    list_torrents::handle(state)
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|torrent| torrent.info_hash == torrent_hash)
+        .expect("The torrent is not listed")
+        .status
+}
+
+#[tokio::test]
+async fn pausing_and_stopping_leave_the_swarm() {
+    let directory = tempfile::tempdir().unwrap();
+    let state = state(directory.path()).await;
+    let (url, mut events) = tracker().await;
+    let torrent_hash = add(&state, directory.path(), "file.bin", &url).await;
+
+    start_torrent::handle(&state, torrent_hash.clone())
+        .await
+        .unwrap();
+    assert_eq!(next_event(&mut events).await.as_deref(), Some("started"));
+    assert_eq!(
+        resume_torrent::handle(&state, torrent_hash.clone())
+            .await
+            .unwrap_err(),
+        "Torrent is not paused or stopped"
+    );
+
+    pause_torrent::handle(&state, torrent_hash.clone())
+        .await
+        .unwrap();
+    assert_eq!(status(&state, &torrent_hash).await, TorrentStatus::Paused);
+    assert_eq!(next_event(&mut events).await.as_deref(), Some("stopped"));
+
+    resume_torrent::handle(&state, torrent_hash.clone())
+        .await
+        .unwrap();
+    assert_ne!(status(&state, &torrent_hash).await, TorrentStatus::Paused);
+    assert_eq!(next_event(&mut events).await.as_deref(), Some("started"));
+
+    stop_torrent::handle(&state, torrent_hash.clone())
+        .await
+        .unwrap();
+    assert_eq!(status(&state, &torrent_hash).await, TorrentStatus::Stopped);
+    assert_eq!(next_event(&mut events).await.as_deref(), Some("stopped"));
+
+    // Stopped torrents can be resumed as well
+    resume_torrent::handle(&state, torrent_hash.clone())
+        .await
+        .unwrap();
+    assert_eq!(next_event(&mut events).await.as_deref(), Some("started"));
+    stop_torrent::handle(&state, torrent_hash).await.unwrap();
+}
+
+#[tokio::test]
+async fn removing_keeps_the_data_unless_asked_to_delete_it() {
+    let directory = tempfile::tempdir().unwrap();
+    let state = state(directory.path()).await;
+    let (url, mut events) = tracker().await;
+    let kept = add(&state, directory.path(), "kept.bin", &url).await;
+    let deleted = add(&state, directory.path(), "deleted.bin", &url).await;
+
+    remove_torrent::handle(&state, kept.clone(), false)
+        .await
+        .unwrap();
+    assert!(directory.path().join("kept.bin").exists());
+
+    // A running torrent leaves the swarm on its way out
+    start_torrent::handle(&state, deleted.clone())
+        .await
+        .unwrap();
+    assert_eq!(next_event(&mut events).await.as_deref(), Some("started"));
+    remove_torrent::handle(&state, deleted.clone(), true)
+        .await
+        .unwrap();
+    assert_eq!(next_event(&mut events).await.as_deref(), Some("stopped"));
+    assert!(!directory.path().join("deleted.bin").exists());
+
+    assert!(list_torrents::handle(&state).await.unwrap().is_empty());
+    assert_eq!(
+        remove_torrent::handle(&state, kept, false)
+            .await
+            .unwrap_err(),
+        "Torrent not found"
+    );
+}
+
+#[tokio::test]
+async fn unknown_torrents_are_not_found() {
+    let directory = tempfile::tempdir().unwrap();
+    let state = state(directory.path()).await;
+    let torrent_hash = "00".repeat(20);
+
+    let results = [
+        start_torrent::handle(&state, torrent_hash.clone()).await,
+        pause_torrent::handle(&state, torrent_hash.clone()).await,
+        resume_torrent::handle(&state, torrent_hash.clone()).await,
+        stop_torrent::handle(&state, torrent_hash.clone()).await,
+        remove_torrent::handle(&state, torrent_hash, true).await,
+    ];
+    for result in results {
+        assert_eq!(result.unwrap_err(), "Torrent not found");
+    }
+}
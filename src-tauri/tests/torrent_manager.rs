@@ -0,0 +1,236 @@
+use bitvec::prelude::{BitVec, Lsb0};
+use pirate::{
+    config::{AllocationMode, Config, EncryptionPolicy},
+    events::event_bus::EventBus,
+    network::{peer_handshake::Handshake, peer_listener::start_peer_listener},
+    parsing::parser::parse_error::parse_bencoded_torrent,
+    storage::{
+        disk_io::DiskIo, file_layout::FileLayout, memory_storage::MemoryStorage,
+        piece_hashes::PieceHashes,
+    },
+    torrent_management::{
+        peers::Peer,
+        queue::{QueueAction, QueueMove},
+        torrent::Torrent,
+        torrent_manager::{apply_queue_plan, TorrentManager},
+        torrent_status::TorrentStatus,
+    },
+};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::RwLock,
+};
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Different names make different info hashes
+fn torrent(name: &str, config: &Arc<RwLock<Config>>) -> (String, Arc<RwLock<Torrent>>) {
+    let mut torrent_file = format!(
+        "d4:infod6:lengthi16e4:name{}:{}12:piece lengthi16e6:pieces20:",
+        name.len(),
+        name
+    )
+    .into_bytes();
+    torrent_file.extend([0; 20]);
+    torrent_file.extend(b"ee");
+    let mut metadata = parse_bencoded_torrent(torrent_file).unwrap();
+    metadata.file_path = PathBuf::from("/nowhere").join(name);
+    metadata.peer_id = "-PI0100-000000000000".to_string();
+    let info_hash: [u8; 20] = metadata.info_hash.as_slice().try_into().unwrap();
+    let storage = MemoryStorage::new(FileLayout::from_metadata(&metadata));
+    let piece_hashes = PieceHashes::from_metadata(&metadata).unwrap();
+    let torrent = Torrent::new(
+        info_hash,
+        16,
+        Arc::new(RwLock::new(Vec::new())),
+        Arc::new(RwLock::new(metadata)),
+        Arc::new(RwLock::new(BitVec::<u8, Lsb0>::repeat(false, 1))),
+        Arc::new(RwLock::new(HashMap::new())),
+        Arc::new(piece_hashes),
+        AtomicBool::new(false),
+        DiskIo::new(1, 16, 1 << 20).open(Arc::new(storage)),
+        AllocationMode::Sparse,
+        EventBus::new(),
+        Arc::clone(config),
+        None,
+    );
+    (hex::encode(info_hash), Arc::new(RwLock::new(torrent)))
+}
+
+#[tokio::test]
+async fn torrents_added_twice_are_rejected() {
+    let config = Arc::new(RwLock::new(Config::default()));
+    let mut manager = TorrentManager::new(EventBus::new(), Arc::clone(&config));
+    let (torrent_hash, first) = torrent("first", &config);
+    manager
+        .add_torrent(torrent_hash.clone(), Arc::clone(&first))
+        .await
+        .unwrap();
+
+    let (_, again) = torrent("first", &config);
+    assert!(manager
+        .add_torrent(torrent_hash.clone(), again)
+        .await
+        .is_err());
+    assert!(Arc::ptr_eq(
+        &manager.get_torrent(&torrent_hash).unwrap(),
+        &first
+    ));
+    assert_eq!(manager.list_torrents().await.len(), 1);
+
+    // The other hashes a torrent is known by count as well
+    let (other_hash, other) = torrent("other", &config);
+    manager.add_alias(other_hash.clone(), &torrent_hash);
+    assert!(manager.add_torrent(other_hash, other).await.is_err());
+    assert_eq!(first.read().await.queue_position(), 0);
+}
+
+#[tokio::test]
+async fn queue_plans_are_carried_out_without_the_manager() {
+    let config = Arc::new(RwLock::new(Config::default()));
+    let manager = Arc::new(RwLock::new(TorrentManager::new(
+        EventBus::new(),
+        Arc::clone(&config),
+    )));
+    let (torrent_hash, queued) = torrent("queued", &config);
+    {
+        let torrent_guard = queued.read().await;
+        torrent_guard.set_auto_managed(true);
+        torrent_guard.enqueue().await;
+    }
+    manager
+        .write()
+        .await
+        .add_torrent(torrent_hash.clone(), Arc::clone(&queued))
+        .await
+        .unwrap();
+
+    let (plan, events) = {
+        let manager = manager.read().await;
+        (manager.queue_plan().await, manager.events())
+    };
+    assert_eq!(plan.len(), 1);
+    assert_eq!(plan[0].0, torrent_hash);
+    assert_eq!(plan[0].2, QueueAction::Start);
+    assert!(!queued.read().await.is_active());
+
+    // Nothing is held while the torrent starts, others may change the manager meanwhile
+    let _manager = manager.write().await;
+    apply_queue_plan(plan, &events).await.unwrap();
+    let torrent_guard = queued.read().await;
+    assert!(torrent_guard.is_active());
+    assert_ne!(torrent_guard.check_status().await, TorrentStatus::Queued);
+    torrent_guard.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn starting_leaves_the_start_to_the_caller() {
+    let config = Arc::new(RwLock::new(Config::default()));
+    let mut manager = TorrentManager::new(EventBus::new(), Arc::clone(&config));
+    let (manual_hash, manual) = torrent("manual", &config);
+    let (managed_hash, managed) = torrent("managed", &config);
+    managed.read().await.set_auto_managed(true);
+    for (torrent_hash, torrent) in [(&manual_hash, &manual), (&managed_hash, &managed)] {
+        manager
+            .add_torrent(torrent_hash.clone(), Arc::clone(torrent))
+            .await
+            .unwrap();
+    }
+
+    // Torrents that aren't auto-managed are started by whoever carries out the plan
+    let plan = manager.start_torrent(&manual_hash).await.unwrap();
+    assert_eq!(plan.len(), 1);
+    assert_eq!(plan[0].2, QueueAction::Start);
+    assert!(Arc::ptr_eq(&plan[0].1, &manual));
+    assert!(!manual.read().await.is_active());
+    apply_queue_plan(plan, &manager.events()).await.unwrap();
+    assert!(manual.read().await.is_active());
+
+    // Auto-managed ones wait in the queue for their slot
+    let plan = manager.start_torrent(&managed_hash).await.unwrap();
+    assert_eq!(
+        managed.read().await.check_status().await,
+        TorrentStatus::Queued
+    );
+    assert!(!managed.read().await.is_active());
+    assert_eq!(plan.len(), 1);
+    assert_eq!(plan[0].0, managed_hash);
+    assert_eq!(plan[0].2, QueueAction::Start);
+
+    // Moving in the queue plans the same way
+    let plan = manager
+        .move_in_queue(&managed_hash, QueueMove::Top)
+        .await
+        .unwrap();
+    assert_eq!(managed.read().await.queue_position(), 0);
+    assert_eq!(plan.len(), 1);
+    assert!(!managed.read().await.is_active());
+    apply_queue_plan(plan, &manager.events()).await.unwrap();
+    assert!(managed.read().await.is_active());
+
+    assert!(manager.start_torrent("unknown").await.is_err());
+    manager.stop_all().await;
+}
+
+async fn read_handshake_hash(stream: &mut TcpStream) -> Vec<u8> {
+    let mut handshake = vec![0; 68];
+    tokio::time::timeout(TEST_TIMEOUT, stream.read_exact(&mut handshake))
+        .await
+        .expect("No handshake came")
+        .unwrap();
+    handshake[28..48].to_vec()
+}
+
+#[tokio::test]
+async fn peers_are_greeted_in_the_swarm_they_were_found_in() {
+    let config = Arc::new(RwLock::new(Config::default()));
+    config.write().await.encryption = EncryptionPolicy::Disabled;
+    let (_, torrent) = torrent("hybrid", &config);
+    let torrent = torrent.read().await.clone();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let v2_swarm = [9; 20];
+    torrent
+        .peers
+        .write()
+        .await
+        .push(Peer::new(listener.local_addr().unwrap()).in_swarm(&v2_swarm));
+
+    torrent.start().await.unwrap();
+    let (mut remote, _) = tokio::time::timeout(TEST_TIMEOUT, listener.accept())
+        .await
+        .expect("The peer was never connected to")
+        .unwrap();
+    assert_eq!(read_handshake_hash(&mut remote).await, v2_swarm.to_vec());
+    torrent.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn incoming_peers_may_use_either_swarm_hash() {
+    let config = Arc::new(RwLock::new(Config::default()));
+    let mut manager = TorrentManager::new(EventBus::new(), Arc::clone(&config));
+    let (torrent_hash, torrent) = torrent("hybrid", &config);
+    manager
+        .add_torrent(torrent_hash.clone(), Arc::clone(&torrent))
+        .await
+        .unwrap();
+    let v2_swarm = [9; 20];
+    manager.add_alias(hex::encode(v2_swarm), &torrent_hash);
+    torrent.read().await.start().await.unwrap();
+    let manager = Arc::new(RwLock::new(manager));
+    let port = start_peer_listener(manager, 0, false).await.unwrap().port();
+
+    for info_hash in [hex::decode(&torrent_hash).unwrap(), v2_swarm.to_vec()] {
+        let mut remote = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let handshake = Handshake::new(info_hash.clone(), "-qB4500-a1b2c3d4e5f6".to_string());
+        remote.write_all(&handshake.to_bytes()).await.unwrap();
+        assert_eq!(read_handshake_hash(&mut remote).await, info_hash);
+    }
+    torrent.read().await.stop().await.unwrap();
+}
@@ -0,0 +1,126 @@
+use pirate::parsing::parser::parse_error::parse_bencoded_torrent;
+use serde_bencode::value::Value;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+
+fn dict(entries: Vec<(&str, Value)>) -> Value {
+    Value::Dict(
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.as_bytes().to_vec(), value))
+            .collect::<HashMap<_, _>>(),
+    )
+}
+
+fn bytes(text: &str) -> Value {
+    Value::Bytes(text.as_bytes().to_vec())
+}
+
+fn torrent_file(info: Vec<(&str, Value)>) -> Vec<u8> {
+    serde_bencode::to_bytes(&dict(vec![("info", dict(info))])).unwrap()
+}
+
+// A multi-file torrent named `name` with one file at `path`
+fn multi_file(name: &str, path: &[&str]) -> Vec<u8> {
+    torrent_file(vec![
+        (
+            "files",
+            Value::List(vec![dict(vec![
+                ("length", Value::Int(16)),
+                (
+                    "path",
+                    Value::List(path.iter().map(|part| bytes(part)).collect()),
+                ),
+            ])]),
+        ),
+        ("name", bytes(name)),
+        ("piece length", Value::Int(16)),
+        ("pieces", Value::Bytes(vec![0; 20])),
+    ])
+}
+
+// A v2-only torrent with one file at `path` in its file tree
+fn v2_file_tree(path: &[&str]) -> Vec<u8> {
+    let mut node = dict(vec![(
+        "",
+        dict(vec![
+            ("length", Value::Int(16)),
+            ("pieces root", Value::Bytes(vec![0; 32])),
+        ]),
+    )]);
+    for part in path.iter().rev() {
+        node = dict(vec![(part, node)]);
+    }
+    torrent_file(vec![
+        ("file tree", node),
+        ("meta version", Value::Int(2)),
+        ("name", bytes("dir")),
+        ("piece length", Value::Int(16)),
+    ])
+}
+
+#[test]
+fn accepts_plain_file_names() {
+    let metadata = parse_bencoded_torrent(multi_file("dir", &["sub", "a.bin"])).unwrap();
+    assert_eq!(
+        metadata.info.files.unwrap()[0].path,
+        vec!["sub".to_string(), "a.bin".to_string()]
+    );
+    assert!(parse_bencoded_torrent(v2_file_tree(&["sub", "a.bin"])).is_ok());
+}
+
+#[test]
+fn rejects_paths_leaving_the_download_directory() {
+    for path in [
+        &["..", "..", "etc", "passwd"][..],
+        &["sub", "..", "..", "escaped"],
+        &["/etc/passwd"],
+        &["sub/../../escaped"],
+        &[""],
+        &["."],
+        &[],
+    ] {
+        assert!(
+            parse_bencoded_torrent(multi_file("dir", path)).is_err(),
+            "{:?}",
+            path
+        );
+    }
+    for name in ["..", "/tmp", "", "dir/.."] {
+        assert!(
+            parse_bencoded_torrent(multi_file(name, &["a.bin"])).is_err(),
+            "{:?}",
+            name
+        );
+    }
+}
+
+#[test]
+fn rejects_traversing_v2_file_trees() {
+    assert!(parse_bencoded_torrent(v2_file_tree(&["..", "escaped"])).is_err());
+    assert!(parse_bencoded_torrent(v2_file_tree(&["/tmp", "escaped"])).is_err());
+}
+
+#[test]
+fn the_torrent_file_cannot_pick_its_hash_or_location() {
+    let info = dict(vec![
+        ("length", Value::Int(16)),
+        ("name", bytes("file.bin")),
+        ("piece length", Value::Int(16)),
+        ("pieces", Value::Bytes(vec![0; 20])),
+    ]);
+    let torrent = dict(vec![
+        ("info", info.clone()),
+        ("info_hash", Value::Bytes(vec![7; 20])),
+        ("info_hash_v2", Value::Bytes(vec![7; 32])),
+        ("file_path", bytes("/etc/cron.d/file.bin")),
+    ]);
+
+    let metadata = parse_bencoded_torrent(serde_bencode::to_bytes(&torrent).unwrap()).unwrap();
+    assert_eq!(
+        metadata.info_hash,
+        Sha1::digest(serde_bencode::to_bytes(&info).unwrap()).to_vec()
+    );
+    assert!(metadata.info_hash_v2.is_empty());
+    assert!(metadata.file_path.as_os_str().is_empty());
+}
@@ -0,0 +1,144 @@
+use pirate::{
+    app_state::AppState,
+    commands::{add_torrent, get_torrent_details, list_torrents},
+    config::Config,
+};
+use sha1::{Digest, Sha1};
+use std::path::Path;
+
+async fn state(download_directory: &Path) -> AppState {
+    let configuration = Config {
+        listen_port: 0,
+        streaming_enabled: false,
+        utp_enabled: false,
+        lsd_enabled: false,
+        port_mapping_enabled: false,
+        download_directory: Some(download_directory.to_path_buf()),
+        ..Config::default()
+    };
+    AppState::new(configuration, None).await.unwrap()
+}
+
+fn data() -> Vec<u8> {
+    (0..30).collect()
+}
+
+// Files of 10 and 20 bytes in pieces of 16. Only the first piece's hash matches `data`.
+fn multi_file_torrent(name: &str) -> Vec<u8> {
+    let mut torrent = format!(
+        "d8:announce35:udp://tracker.example:6969/announce4:infod5:filesl\
+        d6:lengthi10e4:pathl5:a.txteed6:lengthi20e4:pathl5:b.txteee\
+        4:name{}:{}12:piece lengthi16e6:pieces40:",
+        name.len(),
+        name
+    )
+    .into_bytes();
+    torrent.extend(Sha1::digest(&data()[..16]));
+    torrent.extend([7; 20]);
+    torrent.extend(b"ee");
+    torrent
+}
+
+// Adds the torrent `name` with its files already downloaded, the second piece corrupt
+async fn add(state: &AppState, directory: &Path, name: &str) -> String {
+    let data = data();
+    std::fs::create_dir_all(directory.join(name)).unwrap();
+    std::fs::write(directory.join(name).join("a.txt"), &data[..10]).unwrap();
+    std::fs::write(directory.join(name).join("b.txt"), &data[10..]).unwrap();
+    let torrent_file = directory.join(format!("{}.torrent", name));
+    std::fs::write(&torrent_file, multi_file_torrent(name)).unwrap();
+    let options = add_torrent::AddTorrentOptions {
+        category: Some("linux".to_string()),
+        ..Default::default()
+    };
+    add_torrent::handle(state, torrent_file.to_string_lossy().to_string(), options)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn torrents_are_listed_by_name() {
+    let directory = tempfile::tempdir().unwrap();
+    let state = state(directory.path()).await;
+    let zeta = add(&state, directory.path(), "zeta").await;
+    let alpha = add(&state, directory.path(), "alpha").await;
+    let middle = add(&state, directory.path(), "middle").await;
+
+    let torrents = list_torrents::handle(&state).await.unwrap();
+    let hashes: Vec<&str> = torrents.iter().map(|t| t.info_hash.as_str()).collect();
+    assert_eq!(hashes, vec![alpha.as_str(), middle.as_str(), zeta.as_str()]);
+    let names: Vec<&str> = torrents.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(names, vec!["alpha", "middle", "zeta"]);
+}
+
+#[tokio::test]
+async fn summaries_count_the_verified_pieces() {
+    let directory = tempfile::tempdir().unwrap();
+    let state = state(directory.path()).await;
+    let torrent_hash = add(&state, directory.path(), "dir").await;
+
+    let torrents = list_torrents::handle(&state).await.unwrap();
+    let summary = &torrents[0];
+    assert_eq!(summary.info_hash, torrent_hash);
+    assert_eq!(summary.name, "dir");
+    assert_eq!(summary.total_size, 30);
+    assert_eq!(summary.completed_size, 16);
+    assert_eq!(summary.progress, 16.0 / 30.0);
+    assert_eq!((summary.downloaded, summary.uploaded), (0, 0));
+    assert_eq!((summary.download_rate, summary.upload_rate), (0, 0));
+    // Nothing is coming in, so there is no telling when it's done
+    assert_eq!(summary.eta_seconds, None);
+    assert_eq!(summary.ratio, 0.0);
+    assert_eq!(summary.connected_peers, 0);
+}
+
+#[tokio::test]
+async fn details_cover_pieces_files_and_trackers() {
+    let directory = tempfile::tempdir().unwrap();
+    let state = state(directory.path()).await;
+    let torrent_hash = add(&state, directory.path(), "dir").await;
+
+    let details = get_torrent_details::handle(&state, torrent_hash.clone())
+        .await
+        .unwrap();
+    assert_eq!(details.summary.info_hash, torrent_hash);
+    assert_eq!(details.summary.completed_size, 16);
+    assert_eq!(details.info_hash_v2, None);
+    assert_eq!(details.piece_length, 16);
+    assert_eq!(details.piece_count, 2);
+    // Only the first piece, least significant bit first
+    assert_eq!(details.pieces, "01");
+    assert_eq!(details.category.as_deref(), Some("linux"));
+    assert_eq!(
+        details.trackers,
+        vec!["udp://tracker.example:6969/announce"]
+    );
+    assert!(details.peers.is_empty());
+
+    let files: Vec<(usize, u64, u64)> = details
+        .files
+        .iter()
+        .map(|file| (file.index, file.size, file.completed_size))
+        .collect();
+    // The second file starts in the verified piece
+    assert_eq!(files, vec![(0, 10, 10), (1, 20, 6)]);
+    assert!(details.files[0].path.ends_with("a.txt"));
+    assert!(details.files[1].path.ends_with("b.txt"));
+    assert_eq!(details.files[0].progress, 1.0);
+    assert_eq!(details.files[1].progress, 0.3);
+
+    // Serialised for the frontend with the summary fields at the top level
+    let json = serde_json::to_value(&details).unwrap();
+    assert_eq!(json["info_hash"], torrent_hash);
+    assert_eq!(json["files"][1]["completed_size"], 6);
+}
+
+#[tokio::test]
+async fn unknown_torrents_are_not_found() {
+    let directory = tempfile::tempdir().unwrap();
+    let state = state(directory.path()).await;
+    assert!(list_torrents::handle(&state).await.unwrap().is_empty());
+
+    let result = get_torrent_details::handle(&state, "00".repeat(20)).await;
+    assert_eq!(result.unwrap_err(), "Torrent not found");
+}
@@ -0,0 +1,152 @@
+use bitvec::prelude::{BitVec, Lsb0};
+use pirate::{
+    config::{AllocationMode, Config},
+    events::event_bus::EventBus,
+    parsing::parser::parse_error::parse_bencoded_torrent,
+    storage::{
+        disk_io::DiskIo, file_layout::FileLayout, memory_storage::MemoryStorage,
+        piece_hashes::PieceHashes,
+    },
+    torrent_management::torrent::Torrent,
+    tracker::{build_tracker_query, AnnounceEvent, TransferStats},
+};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
+};
+use tokio::{
+    net::{TcpListener, UdpSocket},
+    sync::{mpsc, RwLock},
+};
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Two files of 10 and 20 bytes, a multi-file torrent has no top level length
+fn multi_file_torrent() -> Vec<u8> {
+    let mut torrent = b"d8:announce35:udp://tracker.example:6969/announce4:infod5:filesl\
+        d6:lengthi10e4:pathl1:aeed6:lengthi20e4:pathl1:beee\
+        4:name3:dir12:piece lengthi16e6:pieces40:"
+        .to_vec();
+    torrent.extend([7; 40]);
+    torrent.extend(b"ee");
+    torrent
+}
+
+fn query_pairs(query: &str) -> Vec<(String, String)> {
+    let (_, params) = query.split_once('?').unwrap();
+    url::form_urlencoded::parse(params.as_bytes())
+        .into_owned()
+        .collect()
+}
+
+fn value<'a>(pairs: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    pairs
+        .iter()
+        .find(|(name, _)| name == key)
+        .map(|(_, value)| value.as_str())
+}
+
+#[tokio::test]
+async fn announces_the_real_transfer() {
+    let metadata = parse_bencoded_torrent(multi_file_torrent()).unwrap();
+    let transfer = TransferStats {
+        uploaded: 1234,
+        downloaded: 16,
+        left: 14,
+    };
+    let query = build_tracker_query(
+        &metadata,
+        Some(AnnounceEvent::Stopped),
+        &transfer,
+        &Config::default(),
+    )
+    .await
+    .unwrap();
+
+    let pairs = query_pairs(&query);
+    assert_eq!(value(&pairs, "uploaded"), Some("1234"));
+    assert_eq!(value(&pairs, "downloaded"), Some("16"));
+    assert_eq!(value(&pairs, "left"), Some("14"));
+    assert_eq!(value(&pairs, "event"), Some("stopped"));
+}
+
+// Answers announces like a tracker would, with `peer` as the only peer, and passes on the event
+// of each one
+async fn tracker(peer: SocketAddr) -> (String, mpsc::UnboundedReceiver<Option<String>>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("udp://{}/announce", socket.local_addr().unwrap());
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let SocketAddr::V4(peer) = peer else {
+        panic!("Compact peers are IPv4");
+    };
+    let mut response = b"d8:intervali1800e5:peers6:".to_vec();
+    response.extend(peer.ip().octets());
+    response.extend(peer.port().to_be_bytes());
+    response.push(b'e');
+    tokio::spawn(async move {
+        let mut buffer = vec![0; 4096];
+        while let Ok((length, from)) = socket.recv_from(&mut buffer).await {
+            let params = String::from_utf8_lossy(&buffer[..length]).to_string();
+            let pairs = query_pairs(&format!("?{}", params));
+            let _ = sender.send(value(&pairs, "event").map(str::to_string));
+            socket.send_to(&response, from).await.unwrap();
+        }
+    });
+    (url, receiver)
+}
+
+fn torrent(announce: &str) -> Torrent {
+    let mut metadata = parse_bencoded_torrent(multi_file_torrent()).unwrap();
+    metadata.announce = announce.to_string();
+    metadata.file_path = PathBuf::from("/nowhere/dir");
+    let storage = MemoryStorage::new(FileLayout::from_metadata(&metadata));
+    let info_hash: [u8; 20] = metadata.info_hash.as_slice().try_into().unwrap();
+    let piece_hashes = PieceHashes::from_metadata(&metadata).unwrap();
+    Torrent::new(
+        info_hash,
+        30,
+        Arc::new(RwLock::new(Vec::new())),
+        Arc::new(RwLock::new(metadata)),
+        Arc::new(RwLock::new(BitVec::<u8, Lsb0>::repeat(false, 2))),
+        Arc::new(RwLock::new(HashMap::new())),
+        Arc::new(piece_hashes),
+        AtomicBool::new(false),
+        DiskIo::new(1, 16, 1 << 20).open(Arc::new(storage)),
+        AllocationMode::Sparse,
+        EventBus::new(),
+        Arc::new(RwLock::new(Config::default())),
+        None,
+    )
+}
+
+async fn next_event(events: &mut mpsc::UnboundedReceiver<Option<String>>) -> Option<String> {
+    tokio::time::timeout(TEST_TIMEOUT, events.recv())
+        .await
+        .expect("The tracker never heard from us")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn every_start_announces_started_and_connects_to_the_peers() {
+    let peer = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let (url, mut events) = tracker(peer.local_addr().unwrap()).await;
+    let torrent = torrent(&url);
+
+    torrent.start().await.unwrap();
+    assert_eq!(next_event(&mut events).await.as_deref(), Some("started"));
+    // The peers the tracker hands out are connected to right away
+    tokio::time::timeout(TEST_TIMEOUT, peer.accept())
+        .await
+        .expect("The tracker's peer was never connected to")
+        .unwrap();
+
+    // Resuming rejoins the swarm
+    torrent.stop().await.unwrap();
+    assert_eq!(next_event(&mut events).await.as_deref(), Some("stopped"));
+    torrent.start().await.unwrap();
+    assert_eq!(next_event(&mut events).await.as_deref(), Some("started"));
+    torrent.stop().await.unwrap();
+}
@@ -0,0 +1,138 @@
+use pirate::network::utp::socket::UtpSocket;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UdpSocket,
+    sync::Mutex,
+};
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+fn localhost() -> SocketAddr {
+    "127.0.0.1:0".parse().unwrap()
+}
+
+fn test_data(size: usize) -> Vec<u8> {
+    (0..size).map(|index| (index % 251) as u8).collect()
+}
+
+// Forwards datagrams between a client and `server`, dropping `loss` of them in each direction.
+// Returns the address clients should connect to.
+async fn lossy_relay(server: SocketAddr, loss: f64, seed: u64) -> SocketAddr {
+    let client_side = Arc::new(UdpSocket::bind(localhost()).await.unwrap());
+    let server_side = Arc::new(UdpSocket::bind(localhost()).await.unwrap());
+    let relay_address = client_side.local_addr().unwrap();
+    let client = Arc::new(Mutex::new(None::<SocketAddr>));
+    let rng = Arc::new(Mutex::new(StdRng::seed_from_u64(seed)));
+
+    {
+        let (client_side, server_side) = (client_side.clone(), server_side.clone());
+        let (client, rng) = (client.clone(), rng.clone());
+        tokio::spawn(async move {
+            let mut buffer = vec![0u8; 65535];
+            loop {
+                let (size, from) = client_side.recv_from(&mut buffer).await.unwrap();
+                *client.lock().await = Some(from);
+                if rng.lock().await.gen_bool(loss) {
+                    continue;
+                }
+                let _ = server_side.send_to(&buffer[..size], server).await;
+            }
+        });
+    }
+    tokio::spawn(async move {
+        let mut buffer = vec![0u8; 65535];
+        loop {
+            let (size, _) = server_side.recv_from(&mut buffer).await.unwrap();
+            if rng.lock().await.gen_bool(loss) {
+                continue;
+            }
+            if let Some(client) = *client.lock().await {
+                let _ = client_side.send_to(&buffer[..size], client).await;
+            }
+        }
+    });
+
+    relay_address
+}
+
+// Sends `size` bytes from a client to the server, which echoes a checksum and closes
+async fn transfer(loss: f64, size: usize) {
+    let server = UtpSocket::bind(localhost()).await.unwrap();
+    let client = UtpSocket::bind(localhost()).await.unwrap();
+    let target = if loss > 0.0 {
+        lossy_relay(server.local_addr().unwrap(), loss, 7).await
+    } else {
+        server.local_addr().unwrap()
+    };
+
+    let receiver = tokio::spawn(async move {
+        let (mut stream, _) = server.accept().await.unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).await.unwrap();
+        let sum: u64 = received.iter().map(|byte| *byte as u64).sum();
+        stream.write_all(&sum.to_be_bytes()).await.unwrap();
+        stream.shutdown().await.unwrap();
+        received
+    });
+
+    let data = test_data(size);
+    let mut stream = client.connect(target).await.unwrap();
+    stream.write_all(&data).await.unwrap();
+    stream.shutdown().await.unwrap();
+
+    let mut answer = Vec::new();
+    stream.read_to_end(&mut answer).await.unwrap();
+    let expected: u64 = data.iter().map(|byte| *byte as u64).sum();
+    assert_eq!(answer, expected.to_be_bytes());
+    assert!(receiver.await.unwrap() == data);
+}
+
+#[tokio::test]
+async fn transfers_data_between_two_endpoints() {
+    tokio::time::timeout(TEST_TIMEOUT, transfer(0.0, 4 << 20))
+        .await
+        .expect("Transfer timed out");
+}
+
+#[tokio::test]
+async fn recovers_from_packet_loss() {
+    tokio::time::timeout(TEST_TIMEOUT, transfer(0.05, 512 << 10))
+        .await
+        .expect("Transfer timed out");
+}
+
+#[tokio::test]
+async fn recovers_from_heavy_packet_loss() {
+    tokio::time::timeout(TEST_TIMEOUT, transfer(0.2, 64 << 10))
+        .await
+        .expect("Transfer timed out");
+}
+
+#[tokio::test]
+async fn connect_fails_when_nobody_listens() {
+    // A bound UDP socket that never answers
+    let silent = UdpSocket::bind(localhost()).await.unwrap();
+    let client = UtpSocket::bind(localhost()).await.unwrap();
+
+    let result = client.connect(silent.local_addr().unwrap()).await;
+    assert!(matches!(result, Err(e) if e.kind() == std::io::ErrorKind::TimedOut));
+}
+
+#[tokio::test]
+async fn connect_is_refused_by_a_reset() {
+    let server = UtpSocket::bind(localhost()).await.unwrap();
+    let client = UtpSocket::bind(localhost()).await.unwrap();
+    let address = server.local_addr().unwrap();
+
+    // Fill the accept backlog so further connections are reset
+    let mut accepted = Vec::new();
+    let result = loop {
+        match client.connect(address).await {
+            Ok(stream) => accepted.push(stream),
+            Err(e) => break e,
+        }
+    };
+    assert_eq!(result.kind(), std::io::ErrorKind::ConnectionReset);
+}
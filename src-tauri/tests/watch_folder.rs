@@ -0,0 +1,274 @@
+use pirate::{
+    app_state::AppState,
+    config::{Config, EncryptionPolicy},
+    network::{
+        extension::{EXTENDED_MESSAGE_ID, UT_METADATA_ID},
+        peer_handshake::Handshake,
+    },
+    watch::watch_folder::{settled_files, watch_folder},
+};
+use serde_bencode::value::Value;
+use sha1::{Digest, Sha1};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+};
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(10);
+const SCAN_INTERVAL: Duration = Duration::from_millis(20);
+// Our side's id for ut_metadata, what the watcher sends its requests to
+const REMOTE_UT_METADATA_ID: u8 = 3;
+
+fn info() -> Vec<u8> {
+    let mut info = b"d6:lengthi16e4:name9:movie.bin12:piece lengthi16e6:pieces20:".to_vec();
+    info.extend([0; 20]);
+    info.push(b'e');
+    info
+}
+
+fn torrent_file() -> Vec<u8> {
+    let mut torrent = b"d4:info".to_vec();
+    torrent.extend(info());
+    torrent.push(b'e');
+    torrent
+}
+
+fn info_hash() -> [u8; 20] {
+    Sha1::digest(info()).into()
+}
+
+async fn state(configure: impl FnOnce(&mut Config)) -> &'static AppState {
+    let mut configuration = Config {
+        listen_port: 0,
+        streaming_enabled: false,
+        utp_enabled: false,
+        lsd_enabled: false,
+        port_mapping_enabled: false,
+        encryption: EncryptionPolicy::Disabled,
+        ..Config::default()
+    };
+    configure(&mut configuration);
+    let state = AppState::new(configuration, None).await.unwrap();
+    // The watcher borrows the state for as long as it runs
+    Box::leak(Box::new(state))
+}
+
+fn watch(state: &'static AppState, directory: &Path, auto_start: bool) {
+    let directory = directory.to_path_buf();
+    tokio::spawn(watch_folder(state, directory, auto_start, SCAN_INTERVAL));
+}
+
+async fn wait_for(path: PathBuf) {
+    tokio::time::timeout(TEST_TIMEOUT, async {
+        while !path.exists() {
+            tokio::time::sleep(SCAN_INTERVAL).await;
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("{} never showed up", path.display()));
+}
+
+async fn loaded_torrents(state: &AppState) -> usize {
+    state
+        .torrent_manager
+        .read()
+        .await
+        .list_torrents()
+        .await
+        .len()
+}
+
+// The .torrent fetched for a magnet is written before it is added
+async fn wait_for_torrents(state: &AppState, count: usize) {
+    tokio::time::timeout(TEST_TIMEOUT, async {
+        while loaded_torrents(state).await != count {
+            tokio::time::sleep(SCAN_INTERVAL).await;
+        }
+    })
+    .await
+    .expect("The torrent was never added");
+}
+
+async fn data_path(state: &AppState) -> PathBuf {
+    let torrent = state
+        .torrent_manager
+        .read()
+        .await
+        .get_torrent(&hex::encode(info_hash()))
+        .expect("The torrent was not added");
+    let torrent = torrent.read().await;
+    let file_path = torrent.metadata.read().await.file_path.clone();
+    file_path
+}
+
+#[test]
+fn files_are_only_picked_up_once_their_size_settles() {
+    let mut pending = HashMap::new();
+    let file = PathBuf::from("watch/a.torrent");
+
+    assert!(settled_files(&mut pending, vec![(file.clone(), 10)]).is_empty());
+    // Still being copied in
+    assert!(settled_files(&mut pending, vec![(file.clone(), 20)]).is_empty());
+    assert_eq!(
+        settled_files(&mut pending, vec![(file.clone(), 20)]),
+        vec![file.clone()]
+    );
+    assert!(pending.is_empty());
+    // Gone before it settled, it starts over if it comes back
+    assert!(settled_files(&mut pending, vec![(file.clone(), 20)]).is_empty());
+    assert!(settled_files(&mut pending, Vec::new()).is_empty());
+    assert!(settled_files(&mut pending, vec![(file, 20)]).is_empty());
+}
+
+#[tokio::test]
+async fn torrents_go_to_added_and_broken_ones_to_failed() {
+    let directory = tempfile::tempdir().unwrap();
+    let state = state(|_| ()).await;
+    std::fs::write(directory.path().join("movie.torrent"), torrent_file()).unwrap();
+    std::fs::write(directory.path().join("broken.torrent"), b"not bencode").unwrap();
+    watch(state, directory.path(), false);
+
+    wait_for(directory.path().join("added/movie.torrent")).await;
+    wait_for(directory.path().join("failed/broken.torrent.error")).await;
+    assert!(directory.path().join("failed/broken.torrent").exists());
+    assert!(!directory.path().join("movie.torrent").exists());
+    assert!(!directory.path().join("broken.torrent").exists());
+    let error =
+        std::fs::read_to_string(directory.path().join("failed/broken.torrent.error")).unwrap();
+    assert!(error.contains("Failed to parse metadata"), "{}", error);
+
+    // The data goes where the torrent file was dropped, not where it was moved to
+    assert_eq!(loaded_torrents(state).await, 1);
+    assert_eq!(data_path(state).await, directory.path().join("movie.bin"));
+}
+
+#[tokio::test]
+async fn torrents_that_fail_to_start_are_not_kept() {
+    let directory = tempfile::tempdir().unwrap();
+    let blocker = tempfile::NamedTempFile::new().unwrap();
+    // Nothing can be created under a file, so allocating fails
+    let download_directory = blocker.path().join("downloads");
+    let state = state(|configuration| {
+        configuration.download_directory = Some(download_directory);
+        configuration.auto_managed = false;
+    })
+    .await;
+    std::fs::write(directory.path().join("movie.torrent"), torrent_file()).unwrap();
+    watch(state, directory.path(), true);
+
+    wait_for(directory.path().join("failed/movie.torrent.error")).await;
+    assert!(!directory.path().join("added/movie.torrent").exists());
+    assert_eq!(loaded_torrents(state).await, 0);
+}
+
+// Answers announces with `peer` as the only peer
+async fn tracker(peer: SocketAddr) -> String {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("udp://{}/announce", socket.local_addr().unwrap());
+    let SocketAddr::V4(peer) = peer else {
+        panic!("Compact peers are IPv4");
+    };
+    let mut response = b"d8:intervali1800e5:peers6:".to_vec();
+    response.extend(peer.ip().octets());
+    response.extend(peer.port().to_be_bytes());
+    response.push(b'e');
+    tokio::spawn(async move {
+        let mut buffer = vec![0; 4096];
+        while let Ok((_, from)) = socket.recv_from(&mut buffer).await {
+            socket.send_to(&response, from).await.unwrap();
+        }
+    });
+    url
+}
+
+async fn read_frame(stream: &mut TcpStream) -> Vec<u8> {
+    let length = stream.read_u32().await.unwrap();
+    let mut frame = vec![0; length as usize];
+    stream.read_exact(&mut frame).await.unwrap();
+    frame
+}
+
+async fn send_extended(stream: &mut TcpStream, id: u8, dict: Value, data: &[u8]) {
+    let mut payload = vec![EXTENDED_MESSAGE_ID, id];
+    payload.extend(serde_bencode::to_bytes(&dict).unwrap());
+    payload.extend(data);
+    stream.write_u32(payload.len() as u32).await.unwrap();
+    stream.write_all(&payload).await.unwrap();
+}
+
+fn bencode_dict(entries: Vec<(&str, Value)>) -> Value {
+    Value::Dict(
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.as_bytes().to_vec(), value))
+            .collect(),
+    )
+}
+
+// A peer that has the torrent and hands its info dictionary to everyone who asks
+async fn metadata_peer() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut handshake = vec![0; 68];
+            stream.read_exact(&mut handshake).await.unwrap();
+            let ours = Handshake::new(info_hash().to_vec(), "-qB4500-a1b2c3d4e5f6".to_string())
+                .with_extension_protocol();
+            stream.write_all(&ours.to_bytes()).await.unwrap();
+            let size = Value::Int(info().len() as i64);
+            let extensions = bencode_dict(vec![(
+                "ut_metadata",
+                Value::Int(REMOTE_UT_METADATA_ID as i64),
+            )]);
+            let extended = bencode_dict(vec![("m", extensions), ("metadata_size", size.clone())]);
+            send_extended(&mut stream, 0, extended, &[]).await;
+
+            // Skips the watcher's extended handshake
+            let request = [EXTENDED_MESSAGE_ID, REMOTE_UT_METADATA_ID];
+            while !read_frame(&mut stream).await.starts_with(&request) {}
+            let data = bencode_dict(vec![
+                ("msg_type", Value::Int(1)),
+                ("piece", Value::Int(0)),
+                ("total_size", size),
+            ]);
+            send_extended(&mut stream, UT_METADATA_ID, data, &info()).await;
+        }
+    });
+    address
+}
+
+#[tokio::test]
+async fn magnet_files_keep_the_fetched_torrent_only_when_it_is_added() {
+    let directory = tempfile::tempdir().unwrap();
+    let state = state(|_| ()).await;
+    let tracker = tracker(metadata_peer().await).await;
+    let link = format!(
+        "magnet:?xt=urn:btih:{}&tr={}\n",
+        hex::encode(info_hash()),
+        tracker
+    );
+    std::fs::write(directory.path().join("movie.magnet"), &link).unwrap();
+    watch(state, directory.path(), false);
+
+    wait_for(directory.path().join("added/movie.torrent")).await;
+    wait_for_torrents(state, 1).await;
+    assert!(directory.path().join("added/movie.magnet").exists());
+    assert_eq!(data_path(state).await, directory.path().join("movie.bin"));
+
+    // The same torrent again is fetched, then refused
+    std::fs::write(directory.path().join("again.magnet"), &link).unwrap();
+    wait_for(directory.path().join("failed/again.magnet.error")).await;
+    let error =
+        std::fs::read_to_string(directory.path().join("failed/again.magnet.error")).unwrap();
+    assert!(error.contains("already added"), "{}", error);
+    assert!(!directory.path().join("added/again.torrent").exists());
+    assert!(!directory.path().join("failed/again.torrent").exists());
+    assert_eq!(loaded_torrents(state).await, 1);
+}
@@ -0,0 +1,240 @@
+use futures::{stream, StreamExt};
+use hyper::{
+    header,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use pirate::{
+    network::web_seed::WebSeed,
+    parsing::parser::{parse_error::parse_bencoded_torrent, torrent_metadata::TorrentMetadata},
+};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+const TEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Files = Arc<HashMap<String, Vec<u8>>>;
+type Requests = Arc<Mutex<Vec<String>>>;
+
+fn content(length: usize, seed: u8) -> Vec<u8> {
+    (0..length)
+        .map(|i| (i as u8).wrapping_mul(7).wrapping_add(seed))
+        .collect()
+}
+
+// 40 bytes in pieces of 16, the last piece is short
+fn single_file_torrent() -> TorrentMetadata {
+    let mut torrent =
+        b"d4:infod6:lengthi40e4:name8:file.bin12:piece lengthi16e6:pieces60:".to_vec();
+    torrent.extend([0; 60]);
+    torrent.extend(b"ee");
+    parse_bencoded_torrent(torrent).unwrap()
+}
+
+// Files of 10 and 20 bytes with a pad file between them (BEP 47), the first piece spans all
+// three
+fn multi_file_torrent() -> TorrentMetadata {
+    let mut torrent = b"d4:infod5:filesl\
+        d6:lengthi10e4:pathl1:aee\
+        d4:attr1:p6:lengthi6e4:pathl4:.pad1:6ee\
+        d6:lengthi20e4:pathl1:bee\
+        e4:name3:dir12:piece lengthi32e6:pieces40:"
+        .to_vec();
+    torrent.extend([0; 40]);
+    torrent.extend(b"ee");
+    parse_bencoded_torrent(torrent).unwrap()
+}
+
+fn files() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("file.bin", content(40, 1)),
+        ("dir/a", content(10, 2)),
+        ("dir/b", content(20, 3)),
+    ]
+}
+
+// "bytes=start-end", inclusive
+fn range(request: &Request<Body>, length: usize) -> (usize, usize) {
+    let range = request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|range| range.to_str().ok())
+        .and_then(|range| range.strip_prefix("bytes="))
+        .and_then(|range| range.split_once('-'))
+        .map(|(start, end)| (start.parse().unwrap(), end.parse::<usize>().unwrap() + 1));
+    range.unwrap_or((0, length))
+}
+
+fn respond(status: StatusCode, body: Body) -> Response<Body> {
+    Response::builder().status(status).body(body).unwrap()
+}
+
+// The first path segment picks how the server behaves, the rest is the file
+async fn serve(
+    files: Files,
+    requests: Requests,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let path = request.uri().path().to_string();
+    requests.lock().unwrap().push(path.clone());
+    let (mode, file) = path.trim_start_matches('/').split_once('/').unwrap();
+    if mode == "moved" {
+        return Ok(Response::builder()
+            .status(StatusCode::FOUND)
+            .header(header::LOCATION, format!("/files/{}", file))
+            .body(Body::empty())
+            .unwrap());
+    }
+    let Some(data) = files.get(file) else {
+        return Ok(respond(StatusCode::NOT_FOUND, Body::empty()));
+    };
+    let (start, end) = range(&request, data.len());
+    Ok(match mode {
+        "files" => respond(
+            StatusCode::PARTIAL_CONTENT,
+            Body::from(data[start..end].to_vec()),
+        ),
+        // Ignores the range
+        "full" => respond(StatusCode::OK, Body::from(data.clone())),
+        // Ignores the range and never stops sending
+        "endless" => {
+            let zeros = stream::repeat_with(|| Ok::<_, Infallible>(vec![0u8; 1024]));
+            let body = stream::iter([Ok(data.clone())]).chain(zeros);
+            respond(StatusCode::OK, Body::wrap_stream(body))
+        }
+        "short" => respond(
+            StatusCode::PARTIAL_CONTENT,
+            Body::from(data[start..end - 1].to_vec()),
+        ),
+        _ => respond(StatusCode::NOT_FOUND, Body::empty()),
+    })
+}
+
+// Serves `files` on a local port, returning its url and the paths asked for
+fn server() -> (String, Requests) {
+    let files: Files = Arc::new(
+        files()
+            .into_iter()
+            .map(|(path, data)| (path.to_string(), data))
+            .collect(),
+    );
+    let requests: Requests = Arc::new(Mutex::new(Vec::new()));
+    let service_requests = Arc::clone(&requests);
+    let make_service = make_service_fn(move |_| {
+        let files = Arc::clone(&files);
+        let requests = Arc::clone(&service_requests);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                serve(Arc::clone(&files), Arc::clone(&requests), request)
+            }))
+        }
+    });
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+    let url = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    (url, requests)
+}
+
+async fn fetch(url: &str, metadata: &TorrentMetadata, piece_index: u32) -> Result<Vec<u8>, String> {
+    let web_seed = WebSeed::new(url).unwrap();
+    tokio::time::timeout(TEST_TIMEOUT, web_seed.fetch_piece(metadata, piece_index))
+        .await
+        .expect("The web seed never answered")
+}
+
+#[tokio::test]
+async fn fetches_pieces_of_a_single_file() {
+    let (url, _) = server();
+    let metadata = single_file_torrent();
+    let data = content(40, 1);
+
+    let file_url = format!("{}/files/file.bin", url);
+    assert_eq!(fetch(&file_url, &metadata, 0).await.unwrap(), data[..16]);
+    assert_eq!(fetch(&file_url, &metadata, 1).await.unwrap(), data[16..32]);
+    assert_eq!(fetch(&file_url, &metadata, 2).await.unwrap(), data[32..]);
+
+    // A url ending in a slash is the directory holding the file
+    let directory_url = format!("{}/files/", url);
+    assert_eq!(
+        fetch(&directory_url, &metadata, 1).await.unwrap(),
+        data[16..32]
+    );
+}
+
+#[tokio::test]
+async fn fetches_pieces_spanning_files_and_pad_files() {
+    let (url, requests) = server();
+    let metadata = multi_file_torrent();
+    let (a, b) = (content(10, 2), content(20, 3));
+
+    let base = format!("{}/files", url);
+    let expected = [a.clone(), vec![0; 6], b[..16].to_vec()].concat();
+    assert_eq!(fetch(&base, &metadata, 0).await.unwrap(), expected);
+    assert_eq!(fetch(&base, &metadata, 1).await.unwrap(), b[16..]);
+
+    // Pad files are filled in locally, servers don't have them
+    assert_eq!(
+        *requests.lock().unwrap(),
+        vec!["/files/dir/a", "/files/dir/b", "/files/dir/b"]
+    );
+}
+
+#[tokio::test]
+async fn whole_file_responses_only_keep_the_range() {
+    let (url, _) = server();
+    let data = content(40, 1);
+    let metadata = single_file_torrent();
+    let full_url = format!("{}/full/file.bin", url);
+    assert_eq!(fetch(&full_url, &metadata, 1).await.unwrap(), data[16..32]);
+    assert_eq!(fetch(&full_url, &metadata, 2).await.unwrap(), data[32..]);
+
+    // Reading stops at the end of the range, the rest of the body is never waited for
+    let endless_url = format!("{}/endless/file.bin", url);
+    assert_eq!(
+        fetch(&endless_url, &metadata, 1).await.unwrap(),
+        data[16..32]
+    );
+
+    let metadata = multi_file_torrent();
+    let expected = [content(10, 2), vec![0; 6], content(20, 3)[..16].to_vec()].concat();
+    let full_url = format!("{}/full", url);
+    assert_eq!(fetch(&full_url, &metadata, 0).await.unwrap(), expected);
+}
+
+#[tokio::test]
+async fn follows_redirects() {
+    let (url, requests) = server();
+    let metadata = single_file_torrent();
+    let moved_url = format!("{}/moved/file.bin", url);
+    assert_eq!(
+        fetch(&moved_url, &metadata, 1).await.unwrap(),
+        content(40, 1)[16..32]
+    );
+    assert_eq!(
+        *requests.lock().unwrap(),
+        vec!["/moved/file.bin", "/files/file.bin"]
+    );
+}
+
+#[tokio::test]
+async fn rejects_short_bodies_and_errors() {
+    let (url, _) = server();
+    let metadata = single_file_torrent();
+    let short_url = format!("{}/short/file.bin", url);
+    assert!(fetch(&short_url, &metadata, 1).await.is_err());
+    let missing_url = format!("{}/files/missing.bin", url);
+    assert!(fetch(&missing_url, &metadata, 1).await.is_err());
+}
+
+#[test]
+fn only_accepts_http_and_ftp() {
+    assert!(WebSeed::new("http://example.com/file.bin").is_ok());
+    assert!(WebSeed::new("ftp://example.com/file.bin").is_ok());
+    assert!(WebSeed::new("https://example.com/file.bin").is_err());
+    assert!(WebSeed::new("gopher://example.com/file.bin").is_err());
+    assert!(WebSeed::new("not a url").is_err());
+}